@@ -0,0 +1,402 @@
+pub mod sinks;
+
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::time::{SystemTime, UNIX_EPOCH};
+use futures_util::{stream, Stream};
+use serde::Deserialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::mpsc;
+use tonic::Status;
+use nca_error::NcaError;
+use crate::api::{Alert, AlertSeverity, LogFilter, LogMessage};
+use crate::fanout::{SharedEntry, SharedJournalReaders};
+use crate::filter::LogMatcher;
+use sinks::{build_sinks, AlertSink, KEY_ALERT_RULE};
+
+const ALERTS_CONFIG_FILE: &str = "journal/alerts.json";
+// Alerts a slow client or sink may fall behind before alerts are dropped
+const ALERT_BACKLOG: usize = 256;
+const DEFAULT_DEDUP_SECS: u64 = 60 * 60;
+const USEC_PER_SEC: u64 = 1_000_000;
+
+pub type AlertStream = Pin<Box<dyn Stream<Item = Result<Alert, Status>> + Send>>;
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    #[default]
+    Warning,
+    Critical,
+}
+
+impl From<Severity> for AlertSeverity {
+    fn from(severity: Severity) -> Self {
+        match severity {
+            Severity::Info => AlertSeverity::Info,
+            Severity::Warning => AlertSeverity::Warning,
+            Severity::Critical => AlertSeverity::Critical,
+        }
+    }
+}
+
+/// The criteria of a rule, see `LogFilter`
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct RuleMatch {
+    pub fields: HashMap<String, String>,
+    pub units: Vec<String>,
+    pub user_units: Vec<String>,
+    pub priority: Option<u32>,
+    pub message_contains: Option<String>,
+    pub message_regex: Option<String>,
+    pub container_names: Vec<String>,
+}
+
+impl RuleMatch {
+    fn to_filter(&self) -> LogFilter {
+        LogFilter {
+            fields: self.fields.clone(),
+            units: self.units.clone(),
+            user_units: self.user_units.clone(),
+            priority: self.priority,
+            message_contains: self.message_contains.clone(),
+            message_regex: self.message_regex.clone(),
+            container_names: self.container_names.clone(),
+            ..LogFilter::default()
+        }
+    }
+}
+
+/// The rule fires once `count` matching entries occurred within `window_secs`
+/// (0 = without time limit)
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct Threshold {
+    pub count: u32,
+    pub window_secs: u64,
+}
+
+impl Default for Threshold {
+    fn default() -> Self {
+        Threshold { count: 1, window_secs: 0 }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct AlertRule {
+    pub name: String,
+    pub summary: Option<String>,
+    #[serde(default)]
+    pub severity: Severity,
+    #[serde(default, rename = "match")]
+    pub matches: RuleMatch,
+    #[serde(default)]
+    pub threshold: Threshold,
+    /// Alerts of entries with the same values of these fields are deduplicated together
+    #[serde(default)]
+    pub dedup_fields: Vec<String>,
+    /// Further alerts with the same dedup key are suppressed for this long after an alert fired
+    #[serde(default = "default_dedup_secs")]
+    pub dedup_secs: u64,
+}
+
+fn default_dedup_secs() -> u64 {
+    DEFAULT_DEDUP_SECS
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SinkConfig {
+    /// Writes an entry with the identifier `nca-alerts` to the journal
+    Journal,
+    Webhook {
+        url: String,
+        #[serde(default)]
+        headers: HashMap<String, String>,
+    },
+    /// Plain SMTP without authentication, meant for a relay on the local network
+    Smtp {
+        host: String,
+        #[serde(default = "sinks::default_smtp_port")]
+        port: u16,
+        from: String,
+        to: Vec<String>,
+    },
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct AlertConfig {
+    #[serde(default)]
+    pub rules: Vec<AlertRule>,
+    #[serde(default)]
+    pub sinks: Vec<SinkConfig>,
+}
+
+impl AlertConfig {
+    pub fn parse(data: &str) -> Result<AlertConfig, NcaError> {
+        serde_json::from_str(data)
+            .map_err(|e| NcaError::new_server_config_error(format!("Invalid alert configuration: {e}")))
+    }
+
+    /// Loads `journal/alerts.json` from `config_path`, returns `None` if it does not exist
+    pub fn load(config_path: &str) -> Result<Option<AlertConfig>, NcaError> {
+        let path = PathBuf::from(config_path).join(ALERTS_CONFIG_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = fs::read_to_string(&path)
+            .map_err(|e| NcaError::new_io_error(format!("Failed to read {}: {e:?}", path.display())))?;
+        AlertConfig::parse(&data).map(Some)
+    }
+}
+
+struct RuleState {
+    rule: AlertRule,
+    matcher: LogMatcher,
+    // Timestamps of the matching entries within the window per dedup key
+    matches: HashMap<String, VecDeque<u64>>,
+    // Time of the last alert and number of suppressed alerts per dedup key
+    fired: HashMap<String, (u64, u32)>,
+}
+
+impl RuleState {
+    fn dedup_key(&self, msg: &LogMessage) -> String {
+        self.rule.dedup_fields.iter()
+            .map(|field| msg.fields.get(field).map(String::as_str).unwrap_or_default())
+            .fold(self.rule.name.clone(), |key, value| format!("{key}/{value}"))
+    }
+
+    fn process(&mut self, msg: &LogMessage, now: u64) -> Option<Alert> {
+        if !self.matcher.matches(&msg.fields) {
+            return None;
+        }
+        let dedup_key = self.dedup_key(msg);
+        let window = self.rule.threshold.window_secs * USEC_PER_SEC;
+        let matches = self.matches.entry(dedup_key.clone()).or_default();
+        matches.push_back(now);
+        while window > 0 && matches.front().is_some_and(|t| now.saturating_sub(*t) > window) {
+            matches.pop_front();
+        }
+        let match_count = matches.len() as u32;
+        if match_count < self.rule.threshold.count.max(1) {
+            return None;
+        }
+        matches.clear();
+
+        let dedup_window = self.rule.dedup_secs * USEC_PER_SEC;
+        let suppressed = match self.fired.get_mut(&dedup_key) {
+            Some((last, suppressed)) if now.saturating_sub(*last) < dedup_window => {
+                *suppressed += 1;
+                return None;
+            },
+            Some((_, suppressed)) => *suppressed,
+            None => 0,
+        };
+        self.fired.insert(dedup_key.clone(), (now, 0));
+        Some(Alert {
+            rule: self.rule.name.clone(),
+            severity: AlertSeverity::from(self.rule.severity).into(),
+            summary: self.rule.summary.clone().unwrap_or(self.rule.name.clone()),
+            timestamp: now,
+            match_count,
+            suppressed,
+            dedup_key,
+            entry: Some(msg.clone()),
+        })
+    }
+}
+
+/// Evaluates the alert rules on journal entries in the order they were written
+pub struct AlertEngine {
+    rules: Vec<RuleState>,
+}
+
+impl AlertEngine {
+    pub fn new(rules: &[AlertRule]) -> Result<AlertEngine, NcaError> {
+        let rules = rules.iter()
+            .map(|rule| Ok(RuleState {
+                rule: rule.clone(),
+                matcher: LogMatcher::new(&rule.matches.to_filter())
+                    .map_err(|e| NcaError::new_server_config_error(format!("Invalid alert rule '{}': {e}", rule.name)))?,
+                matches: HashMap::new(),
+                fired: HashMap::new(),
+            }))
+            .collect::<Result<Vec<RuleState>, NcaError>>()?;
+        Ok(AlertEngine { rules })
+    }
+
+    pub fn process(&mut self, msg: &LogMessage) -> Vec<Alert> {
+        // Alerts written to the journal must not fire alerts themselves
+        if msg.fields.contains_key(KEY_ALERT_RULE) {
+            return Vec::new();
+        }
+        let now = msg.realtime_timestamp.unwrap_or_else(|| {
+            SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as u64
+        });
+        self.rules.iter_mut()
+            .filter_map(|rule| rule.process(msg, now))
+            .collect()
+    }
+}
+
+async fn deliver(sinks: Vec<Box<dyn AlertSink>>, mut rx: mpsc::Receiver<Alert>) {
+    while let Some(alert) = rx.recv().await {
+        for sink in &sinks {
+            if let Err(e) = sink.send(&alert).await {
+                eprintln!("Failed to send alert '{}' to {}: {e}", alert.rule, sink.name());
+            }
+        }
+    }
+}
+
+/// Evaluates the rules of `config` on all new journal entries. Fired alerts are passed to the
+/// configured sinks and published on the returned channel.
+pub async fn start_alerting(readers: &SharedJournalReaders, config: AlertConfig) -> Result<broadcast::Sender<Alert>, NcaError> {
+    let engine = AlertEngine::new(&config.rules)?;
+    let entries = readers.subscribe(None).await?;
+    Ok(evaluate_entries(engine, entries, build_sinks(&config.sinks)))
+}
+
+fn evaluate_entries(mut engine: AlertEngine, mut entries: broadcast::Receiver<SharedEntry>, sinks: Vec<Box<dyn AlertSink>>) -> broadcast::Sender<Alert> {
+    let (alerts_tx, _) = broadcast::channel(ALERT_BACKLOG);
+    let (sink_tx, sink_rx) = mpsc::channel(ALERT_BACKLOG);
+    tokio::spawn(deliver(sinks, sink_rx));
+
+    let alerts = alerts_tx.clone();
+    tokio::spawn(async move {
+        loop {
+            match entries.recv().await {
+                Ok(Ok(msg)) => for alert in engine.process(&msg) {
+                    // Sending only fails if no client is subscribed
+                    let _ = alerts.send(alert.clone());
+                    if let Err(e) = sink_tx.try_send(alert) {
+                        eprintln!("Dropping alert, sinks are not keeping up: {e}");
+                    }
+                },
+                Err(RecvError::Lagged(count)) => eprintln!("Alerting fell behind, {count} journal entries were not evaluated"),
+                Ok(Err(status)) => {
+                    eprintln!("Journal reader failed, no more alerts will fire: {}", status.message());
+                    return;
+                },
+                Err(RecvError::Closed) => {
+                    eprintln!("Journal reader stopped, no more alerts will fire");
+                    return;
+                }
+            }
+        }
+    });
+    alerts_tx
+}
+
+/// The alerts of `rx`, limited to `rules` unless it is empty
+pub fn alert_stream(rx: broadcast::Receiver<Alert>, rules: Vec<String>) -> AlertStream {
+    Box::pin(stream::unfold((rx, rules), |(mut rx, rules)| async move {
+        loop {
+            match rx.recv().await {
+                Ok(alert) if rules.is_empty() || rules.contains(&alert.rule) => return Some((Ok(alert), (rx, rules))),
+                Ok(_) => continue,
+                Err(RecvError::Lagged(count)) => eprintln!("Alert stream fell behind, {count} alerts were dropped"),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use futures_util::StreamExt;
+    use sinks::RecordingSink;
+    use super::*;
+
+    fn entry(message: &str, unit: &str, secs: u64) -> LogMessage {
+        LogMessage {
+            fields: HashMap::from([
+                ("MESSAGE".to_string(), message.to_string()),
+                ("_SYSTEMD_UNIT".to_string(), unit.to_string()),
+            ]),
+            message: message.to_string(),
+            realtime_timestamp: Some(secs * USEC_PER_SEC),
+            ..LogMessage::default()
+        }
+    }
+
+    #[test]
+    fn test_example_config() {
+        let config = AlertConfig::parse(include_str!("../install/etc/ncatomic/journal/alerts.json")).unwrap();
+        assert_eq!(config.sinks, vec![SinkConfig::Journal]);
+        AlertEngine::new(&config.rules).unwrap();
+    }
+
+    #[test]
+    fn test_threshold_and_dedup() {
+        let config = AlertConfig::parse(r#"{"rules": [{
+            "name": "cron-failed",
+            "severity": "critical",
+            "match": {"units": ["cron@*"], "message_regex": "(?i)failed"},
+            "threshold": {"count": 2, "window_secs": 60},
+            "dedup_fields": ["_SYSTEMD_UNIT"],
+            "dedup_secs": 600
+        }]}"#).unwrap();
+        let mut engine = AlertEngine::new(&config.rules).unwrap();
+
+        assert!(engine.process(&entry("Job failed", "cron@a.service", 0)).is_empty());
+        assert!(engine.process(&entry("Job succeeded", "cron@a.service", 10)).is_empty());
+        // outside the window of the first failure
+        assert!(engine.process(&entry("Job failed", "cron@a.service", 100)).is_empty());
+        let alerts = engine.process(&entry("Job failed", "cron@a.service", 120));
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].severity(), AlertSeverity::Critical);
+        assert_eq!((alerts[0].match_count, alerts[0].dedup_key.as_str()), (2, "cron-failed/cron@a.service"));
+
+        // suppressed within the dedup window, other units are independent
+        engine.process(&entry("Job failed", "cron@a.service", 130));
+        assert!(engine.process(&entry("Job failed", "cron@a.service", 140)).is_empty());
+        engine.process(&entry("Job failed", "cron@b.service", 150));
+        assert_eq!(engine.process(&entry("Job failed", "cron@b.service", 160)).len(), 1);
+
+        engine.process(&entry("Job failed", "cron@a.service", 800));
+        let alerts = engine.process(&entry("Job failed", "cron@a.service", 810));
+        assert_eq!(alerts[0].suppressed, 1);
+    }
+
+    #[tokio::test]
+    async fn test_alerts_are_delivered() {
+        let config = AlertConfig::parse(r#"{"rules": [
+            {"name": "backup-failed", "match": {"units": ["nca-backup.service"]}, "dedup_secs": 600},
+            {"name": "oom", "match": {"message_regex": "Out of memory"}}
+        ]}"#).unwrap();
+        let sink = RecordingSink::default();
+        let (entries, rx) = broadcast::channel(16);
+        let alerts_tx = evaluate_entries(AlertEngine::new(&config.rules).unwrap(), rx, vec![Box::new(sink.clone())]);
+        let mut alerts = alert_stream(alerts_tx.subscribe(), vec!["backup-failed".to_string()]);
+        drop(alerts_tx);
+
+        let failure = entry("Fatal: unable to save snapshot", "nca-backup.service", 10);
+        for msg in [failure.clone(), entry("Out of memory: Killed process 42", "kernel", 20), failure.clone()] {
+            entries.send(Ok(msg)).unwrap();
+        }
+        // Fires again once the dedup window has passed, counting the suppressed alert
+        let mut repeated = failure.clone();
+        repeated.realtime_timestamp = Some(700 * USEC_PER_SEC);
+        entries.send(Ok(repeated)).unwrap();
+        drop(entries);
+
+        let streamed: Vec<Alert> = alerts.by_ref().map(|alert| alert.unwrap()).collect().await;
+        assert_eq!(streamed.iter().map(|a| (a.rule.as_str(), a.suppressed)).collect::<Vec<_>>(),
+                   vec![("backup-failed", 0), ("backup-failed", 1)]);
+
+        for _ in 0..100 {
+            if sink.alerts.lock().unwrap().len() == 3 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        let delivered: Vec<String> = sink.alerts.lock().unwrap().iter().map(|a| a.rule.clone()).collect();
+        assert_eq!(delivered, vec!["backup-failed", "oom", "backup-failed"]);
+    }
+}
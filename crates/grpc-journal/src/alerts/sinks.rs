@@ -0,0 +1,309 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use nca_error::NcaError;
+use crate::alerts::SinkConfig;
+use crate::api::{Alert, AlertSeverity};
+
+/// Set on the journal entries written for alerts
+pub(crate) const KEY_ALERT_RULE: &str = "NCA_ALERT_RULE";
+const SYSLOG_IDENTIFIER: &str = "nca-alerts";
+const SMTP_TIMEOUT: Duration = Duration::from_secs(30);
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub(crate) fn default_smtp_port() -> u16 {
+    25
+}
+
+#[tonic::async_trait]
+pub trait AlertSink: Send + Sync {
+    fn name(&self) -> String;
+    async fn send(&self, alert: &Alert) -> Result<(), NcaError>;
+}
+
+fn severity_name(severity: AlertSeverity) -> &'static str {
+    match severity {
+        AlertSeverity::Info => "info",
+        AlertSeverity::Warning => "warning",
+        AlertSeverity::Critical => "critical",
+    }
+}
+
+fn alert_text(alert: &Alert) -> String {
+    let entry = alert.entry.as_ref();
+    let mut text = format!("{} ({} matching entries)", alert.summary, alert.match_count);
+    if alert.suppressed > 0 {
+        text.push_str(&format!(", {} similar alerts suppressed", alert.suppressed));
+    }
+    if let Some(message) = entry.map(|e| e.message.as_str()).filter(|m| !m.is_empty()) {
+        text.push_str(&format!(": {message}"));
+    }
+    text
+}
+
+fn alert_json(alert: &Alert) -> Value {
+    let entry = alert.entry.clone().unwrap_or_default();
+    json!({
+        "rule": alert.rule,
+        "severity": severity_name(alert.severity()),
+        "summary": alert.summary,
+        "timestamp": alert.timestamp,
+        "match_count": alert.match_count,
+        "suppressed": alert.suppressed,
+        "dedup_key": alert.dedup_key,
+        "message": entry.message,
+        "unit": entry.unit,
+        "container_name": entry.container_name,
+        "cursor": entry.cursor,
+    })
+}
+
+pub struct JournalSink;
+
+#[tonic::async_trait]
+impl AlertSink for JournalSink {
+    fn name(&self) -> String {
+        "journal".to_string()
+    }
+
+    async fn send(&self, alert: &Alert) -> Result<(), NcaError> {
+        let priority = match alert.severity() {
+            AlertSeverity::Info => 6,
+            AlertSeverity::Warning => 4,
+            AlertSeverity::Critical => 2,
+        };
+        let fields = [
+            format!("MESSAGE={}", alert_text(alert)),
+            format!("PRIORITY={priority}"),
+            format!("SYSLOG_IDENTIFIER={SYSLOG_IDENTIFIER}"),
+            format!("{KEY_ALERT_RULE}={}", alert.rule),
+        ];
+        let result = systemd::journal::send(&fields.iter().map(String::as_str).collect::<Vec<&str>>());
+        match result {
+            0.. => Ok(()),
+            errno => Err(NcaError::new_io_error(format!("Failed to write to the journal (error {errno})"))),
+        }
+    }
+}
+
+pub struct WebhookSink {
+    url: String,
+    headers: HashMap<String, String>,
+    client: reqwest::Client,
+}
+
+impl WebhookSink {
+    pub fn new(url: String, headers: HashMap<String, String>) -> WebhookSink {
+        let client = reqwest::Client::builder()
+            .timeout(WEBHOOK_TIMEOUT)
+            .build()
+            .unwrap_or_default();
+        WebhookSink { url, headers, client }
+    }
+}
+
+#[tonic::async_trait]
+impl AlertSink for WebhookSink {
+    fn name(&self) -> String {
+        format!("webhook {}", self.url)
+    }
+
+    /// POSTs the alert as JSON object
+    async fn send(&self, alert: &Alert) -> Result<(), NcaError> {
+        let request = self.headers.iter()
+            .fold(self.client.post(&self.url), |request, (name, value)| request.header(name, value));
+        request.json(&alert_json(alert))
+            .send().await
+            .and_then(|response| response.error_for_status())
+            .map(|_| ())
+            .map_err(|e| NcaError::new_io_error(format!("Webhook request failed: {e}")))
+    }
+}
+
+pub struct SmtpSink {
+    address: String,
+    from: String,
+    to: Vec<String>,
+}
+
+impl SmtpSink {
+    pub fn new(host: &str, port: u16, from: String, to: Vec<String>) -> SmtpSink {
+        SmtpSink { address: format!("{host}:{port}"), from, to }
+    }
+
+    fn mail(&self, alert: &Alert) -> String {
+        let body = serde_json::to_string_pretty(&alert_json(alert)).unwrap_or_default();
+        let headers = format!(
+            "From: <{}>\r\nTo: {}\r\nSubject: [{}] {}\r\nContent-Type: text/plain; charset=utf-8\r\n",
+            self.from,
+            self.to.iter().map(|to| format!("<{to}>")).collect::<Vec<String>>().join(", "),
+            severity_name(alert.severity()),
+            alert.summary.replace(['\r', '\n'], " "),
+        );
+        let text = format!("{}\n\n{body}", alert_text(alert));
+        // Dot-stuffing, lines starting with a dot would otherwise end the DATA command early
+        let text = text.lines()
+            .map(|line| match line.starts_with('.') {
+                true => format!(".{line}"),
+                false => line.to_string(),
+            })
+            .collect::<Vec<String>>()
+            .join("\r\n");
+        format!("{headers}\r\n{text}\r\n.")
+    }
+
+    async fn transaction(&self, alert: &Alert) -> Result<(), NcaError> {
+        let (read, mut write) = TcpStream::connect(&self.address).await
+            .map_err(|e| NcaError::new_io_error(format!("Failed to connect to {}: {e:?}", self.address)))?
+            .into_split();
+        let mut reader = BufReader::new(read);
+        expect_reply(&mut reader, 2).await?;
+        smtp_command(&mut write, &mut reader, "EHLO nextcloud-atomic", 2).await?;
+        smtp_command(&mut write, &mut reader, &format!("MAIL FROM:<{}>", self.from), 2).await?;
+        for to in &self.to {
+            smtp_command(&mut write, &mut reader, &format!("RCPT TO:<{to}>"), 2).await?;
+        }
+        smtp_command(&mut write, &mut reader, "DATA", 3).await?;
+        smtp_command(&mut write, &mut reader, &self.mail(alert), 2).await?;
+        smtp_command(&mut write, &mut reader, "QUIT", 2).await
+    }
+}
+
+/// Reads a (possibly multi-line) reply and checks the class of its status code (2xx, 3xx, ...)
+async fn expect_reply(reader: &mut BufReader<OwnedReadHalf>, class: u16) -> Result<(), NcaError> {
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).await
+            .map_err(|e| NcaError::new_io_error(format!("Failed to read SMTP reply: {e:?}")))?;
+        let code = line.get(..3).and_then(|code| code.parse::<u16>().ok())
+            .ok_or(NcaError::new_io_error(format!("Invalid SMTP reply: '{}'", line.trim_end())))?;
+        if line.as_bytes().get(3) == Some(&b'-') {
+            continue;
+        }
+        return match code / 100 == class {
+            true => Ok(()),
+            false => Err(NcaError::new_io_error(format!("SMTP server replied '{}'", line.trim_end()))),
+        };
+    }
+}
+
+async fn smtp_command(write: &mut OwnedWriteHalf, reader: &mut BufReader<OwnedReadHalf>, command: &str, class: u16) -> Result<(), NcaError> {
+    write.write_all(format!("{command}\r\n").as_bytes()).await
+        .map_err(|e| NcaError::new_io_error(format!("Failed to send SMTP command: {e:?}")))?;
+    expect_reply(reader, class).await
+}
+
+#[tonic::async_trait]
+impl AlertSink for SmtpSink {
+    fn name(&self) -> String {
+        format!("smtp {}", self.address)
+    }
+
+    async fn send(&self, alert: &Alert) -> Result<(), NcaError> {
+        tokio::time::timeout(SMTP_TIMEOUT, self.transaction(alert)).await
+            .map_err(|_| NcaError::new_io_error(format!("Timeout while sending mail via {}", self.address)))?
+    }
+}
+
+/// Collects the alerts it receives, for testing rules and alert consumers
+#[cfg(test)]
+#[derive(Default, Clone)]
+pub(crate) struct RecordingSink {
+    pub alerts: std::sync::Arc<std::sync::Mutex<Vec<Alert>>>,
+}
+
+#[cfg(test)]
+#[tonic::async_trait]
+impl AlertSink for RecordingSink {
+    fn name(&self) -> String {
+        "recording".to_string()
+    }
+
+    async fn send(&self, alert: &Alert) -> Result<(), NcaError> {
+        self.alerts.lock().unwrap().push(alert.clone());
+        Ok(())
+    }
+}
+
+pub fn build_sinks(configs: &[SinkConfig]) -> Vec<Box<dyn AlertSink>> {
+    configs.iter()
+        .map(|config| -> Box<dyn AlertSink> {
+            match config {
+                SinkConfig::Journal => Box::new(JournalSink),
+                SinkConfig::Webhook { url, headers } => Box::new(WebhookSink::new(url.clone(), headers.clone())),
+                SinkConfig::Smtp { host, port, from, to } => Box::new(SmtpSink::new(host, *port, from.clone(), to.clone())),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+    use crate::api::LogMessage;
+    use super::*;
+
+    fn alert() -> Alert {
+        Alert {
+            rule: "cron-failed".to_string(),
+            severity: AlertSeverity::Critical.into(),
+            summary: "Nextcloud cron failed".to_string(),
+            match_count: 1,
+            entry: Some(LogMessage {
+                message: "cron.php failed\n.htaccess is not writable".to_string(),
+                ..LogMessage::default()
+            }),
+            ..Alert::default()
+        }
+    }
+
+    /// Accepts one SMTP session and returns everything the client sent
+    async fn fake_smtp_server(listener: TcpListener) -> String {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (read, mut write) = stream.into_split();
+        let mut reader = BufReader::new(read);
+        let mut received = String::new();
+        write.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+        let mut in_data = false;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).await.unwrap() == 0 {
+                return received;
+            }
+            received.push_str(&line);
+            let reply: &[u8] = match line.trim_end() {
+                "." if in_data => {
+                    in_data = false;
+                    b"250 queued\r\n"
+                },
+                _ if in_data => continue,
+                "EHLO nextcloud-atomic" => b"250-localhost\r\n250 8BITMIME\r\n",
+                "DATA" => {
+                    in_data = true;
+                    b"354 go ahead\r\n"
+                },
+                "QUIT" => b"221 bye\r\n",
+                _ => b"250 ok\r\n",
+            };
+            write.write_all(reply).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_smtp_sink() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(fake_smtp_server(listener));
+        let sink = SmtpSink::new("127.0.0.1", port, "nca@localhost".to_string(), vec!["admin@example.org".to_string()]);
+        sink.send(&alert()).await.unwrap();
+
+        let received = server.await.unwrap();
+        assert!(received.starts_with("EHLO nextcloud-atomic\r\nMAIL FROM:<nca@localhost>\r\nRCPT TO:<admin@example.org>\r\nDATA\r\n"));
+        assert!(received.contains("Subject: [critical] Nextcloud cron failed\r\n"));
+        assert!(received.contains("\r\nNextcloud cron failed (1 matching entries): cron.php failed\r\n..htaccess is not writable\r\n"));
+        assert!(received.ends_with(".\r\nQUIT\r\n"));
+    }
+}
@@ -4,9 +4,8 @@ use tonic::codegen::http::HeaderName;
 use tonic::transport::Server;
 use tower_http::cors::{AllowHeaders, AllowMethods, AllowOrigin, CorsLayer, ExposeHeaders};
 
-use grpc_journal::journal_stream::*;
-use api::journal_log_stream_server::JournalLogStreamServer;
-use grpc_journal::api;
+use grpc_journal::api::journal_log_stream_server::JournalLogStreamServer;
+use grpc_journal::server::JournalLogStreamService;
 
 const DEFAULT_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
 const DEFAULT_EXPOSED_HEADERS: [HeaderName; 3] = [
@@ -1,22 +1,116 @@
 use std::collections::HashMap;
+use std::io::IsTerminal;
 use std::process::exit;
-use grpc_journal::client::stream_logs;
+use grpc_journal::client::{query_logs, stream_logs};
 use clap::{CommandFactory, Parser};
 use clap::error::ErrorKind;
 use futures_util::StreamExt;
-use grpc_journal::api::{LogFilter};
+use grpc_journal::api::{Direction, LogFilter, LogMessage, LogQuery, Priority};
+use grpc_journal::output::{format_message, parse_priority, OutputMode};
+use grpc_journal::timespec::parse_time_spec;
+
+const EXIT_NO_ENTRIES: i32 = 1;
+const EXIT_TRANSPORT_ERROR: i32 = 3;
+// Maximum number of entries the server returns per query
+const QUERY_PAGE_SIZE: u32 = 10000;
 
 #[derive(Parser)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, after_help = "\
+Exit codes:
+  0  entries were printed
+  1  no entries matched (--no-follow only)
+  2  invalid arguments
+  3  the journal service could not be reached or failed")]
 struct Cli {
     namespace: Option<String>,
     filters: Vec<String>,
+    #[arg(short, long, value_enum, default_value_t = OutputMode::Short)]
+    output: OutputMode,
+    /// Print the matching entries and exit instead of waiting for new ones
+    #[arg(long)]
+    no_follow: bool,
+    /// Show entries from this time on, e.g. "2025-10-01 08:00", "yesterday" or "-2h"
+    #[arg(long)]
+    since: Option<String>,
+    /// Show entries up to this time (implies --no-follow)
+    #[arg(long)]
+    until: Option<String>,
+    /// Show entries of this systemd unit (can be given multiple times)
+    #[arg(short, long)]
+    unit: Vec<String>,
+    /// Show entries with this or a more important priority (name or 0-7)
+    #[arg(short, long, value_parser = parse_priority)]
+    priority: Option<Priority>,
+    /// Show the most recent N entries
+    #[arg(short = 'n', long)]
+    lines: Option<u32>,
+    /// Show entries whose message matches this regular expression
+    #[arg(long)]
+    grep: Option<String>,
+}
+
+fn fail(message: impl std::fmt::Display) -> ! {
+    eprintln!("{message}");
+    exit(EXIT_TRANSPORT_ERROR);
+}
+
+async fn query_page(socket_path: &str, query: LogQuery) -> Vec<LogMessage> {
+    let stream = query_logs(socket_path.to_string(), query).await
+        .unwrap_or_else(|e| fail(e));
+    let mut entries = Vec::new();
+    let mut stream = stream.into_inner();
+    while let Some(entry) = stream.next().await {
+        match entry {
+            Ok(entry) => entries.push(entry),
+            Err(e) => fail(format!("Error receiving message ({}): {}", e.code(), e.message())),
+        }
+    }
+    entries
 }
 
+/// Prints the entries of the requested time range (or the last `lines` entries of it) and returns
+/// how many were printed and the cursor of the most recent one
+async fn print_history(socket_path: &str, filter: &LogFilter, since: Option<u64>, until: Option<u64>, lines: Option<u32>, print: impl Fn(&LogMessage)) -> (usize, Option<String>) {
+    if let Some(lines) = lines {
+        let mut entries = query_page(socket_path, LogQuery {
+            filter: Some(filter.clone()),
+            since,
+            until,
+            max_entries: Some(lines),
+            direction: Direction::Backward.into(),
+            ..LogQuery::default()
+        }).await;
+        let last_cursor = entries.first().and_then(|e| e.cursor.clone());
+        entries.reverse();
+        entries.iter().for_each(&print);
+        return (entries.len(), last_cursor);
+    }
+
+    let mut count = 0;
+    let mut cursor = None;
+    loop {
+        let entries = query_page(socket_path, LogQuery {
+            filter: Some(filter.clone()),
+            since,
+            until,
+            cursor: cursor.clone(),
+            max_entries: Some(QUERY_PAGE_SIZE),
+            direction: Direction::Forward.into(),
+        }).await;
+        entries.iter().for_each(&print);
+        count += entries.len();
+        if let Some(last) = entries.last() {
+            cursor = last.cursor.clone();
+        }
+        if entries.len() < QUERY_PAGE_SIZE as usize || cursor.is_none() {
+            return (count, cursor);
+        }
+    }
+}
 
 #[tokio::main]
 async fn main() {
-    
+
     let cli = Cli::parse();
     if let Some(invalid) = cli.filters.iter().find(|f| !f.contains("=")) {
         let mut cmd = Cli::command();
@@ -26,53 +120,52 @@ async fn main() {
                 format!("--filter arguments must container a '=' (but '--filter {invalid}' does not)"))
             .exit();
     }
+    let now = chrono::Local::now();
+    let parse_time = |arg: &str, spec: &Option<String>| spec.as_deref()
+        .map(|spec| parse_time_spec(spec, now))
+        .transpose()
+        .unwrap_or_else(|e| Cli::command().error(ErrorKind::InvalidValue, format!("--{arg}: {e}")).exit());
+    let since = parse_time("since", &cli.since);
+    let until = parse_time("until", &cli.until);
+
     let filters: HashMap<String, String> = cli.filters.into_iter()
         .map(|f| {
             let (k, v) = f.split_once("=").unwrap();
             (k.to_string(), v.to_string())
         })
         .collect();
-    let namespace = cli.namespace;
+    let output = cli.output;
+    let colors = std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none();
+    let print = |msg: &LogMessage| println!("{}", format_message(msg, output, colors));
+    let follow = !cli.no_follow && until.is_none();
 
     let socket_path = std::env::var("GRPC_JOURNAL_SOCKET_PATH")
         .expect("$GRPC_JOURNAL_SOCKET is not set");
 
-    let stream_result = stream_logs(socket_path, LogFilter {
-        namespace,
-        fields: filters
-    }).await;
-    
-    match stream_result {
-        Err(e) => {
-            eprintln!("{}", e);
-            exit(1);
-        },
-        Ok(stream) => {
-            stream.into_inner().for_each(|log| async {
-                match log {
-                    Err(e) => {
-                        match e.code() {
-                            tonic::Code::Ok => exit(0),
-                            tonic::Code::Cancelled => {
-                                eprintln!("Stream cancelled.");
-                                exit(1)
-                            },
-                            tonic::Code::Aborted => {
-                                eprintln!("Stream was aborted: {}", e.message());
-                                exit(2);
-                            },
-                            _ => {
-                                eprintln!("Error receiving message ({}): {}", e.code().to_string(), e.message());
-                                exit(3);
-                            }
-                        };
-                    },
-                    Ok(msg) => {
-                        println!("{}", msg.message);
-                    }
-                }
-            }).await;
-        }
+    let mut filter = LogFilter {
+        namespace: cli.namespace,
+        fields: filters,
+        units: cli.unit,
+        priority: cli.priority.map(|p| p as u32),
+        message_regex: cli.grep,
+        ..LogFilter::default()
     };
-    
-}
\ No newline at end of file
+
+    if !follow || since.is_some() || cli.lines.is_some() {
+        let (count, last_cursor) = print_history(&socket_path, &filter, since, until, cli.lines, print).await;
+        if !follow {
+            exit(if count == 0 { EXIT_NO_ENTRIES } else { 0 });
+        }
+        filter.after_cursor = last_cursor;
+    }
+
+    let stream = stream_logs(socket_path, filter).await
+        .unwrap_or_else(|e| fail(e));
+    stream.into_inner().for_each(|log| async {
+        match log {
+            Err(e) => fail(format!("Error receiving message ({}): {}", e.code(), e.message())),
+            Ok(msg) => print(&msg),
+        }
+    }).await;
+
+}
@@ -13,9 +13,12 @@ const DEFAULT_EXPOSED_HEADERS: [HeaderName; 3] = [
     HeaderName::from_static("grpc-message"),
     HeaderName::from_static("grpc-status-details-bin"),
 ];
+// Export and Query serve the journal unredacted, so only local processes like nca-backend may connect
+const LISTEN_ADDRESS: &str = "127.0.0.1:50051";
 
 async fn run_logstream_backend(user_logs: bool, system_logs: bool) -> Result<(), String> {
-    let service = JournalLogStreamService::new(user_logs, system_logs);
+    let mut service = JournalLogStreamService::new(user_logs, system_logs);
+    service.enable_configured_alerts().await;
     Server::builder()
         .accept_http1(true)
         .layer(CorsLayer::new()
@@ -23,7 +26,7 @@ async fn run_logstream_backend(user_logs: bool, system_logs: bool) -> Result<(),
             .max_age(DEFAULT_MAX_AGE)
             .expose_headers(ExposeHeaders::from(DEFAULT_EXPOSED_HEADERS)))
         .add_service(tonic_web::enable(JournalLogStreamServer::new(service)))
-        .serve("0.0.0.0:50051".to_socket_addrs().unwrap().next().unwrap())
+        .serve(LISTEN_ADDRESS.to_socket_addrs().unwrap().next().unwrap())
         .await
         .map_err(|e| e.to_string())?;
     Ok(())
@@ -3,15 +3,24 @@ use tonic::{Request, Response, Streaming};
 use grpc_common::client::get_socket_channel;
 use nca_error::NcaError;
 use crate::api::journal_log_stream_client::JournalLogStreamClient;
-use crate::api::LogFilter;
+use crate::api::{LogFilter, LogQuery};
 
-pub async fn stream_logs(socket_path: String, filter: LogFilter) -> Result<Response<Streaming<crate::api::LogMessage>>, NcaError> {
+async fn journal_client(socket_path: String) -> Result<JournalLogStreamClient<tonic::transport::Channel>, NcaError> {
     let channel = get_socket_channel(
         PathBuf::from(socket_path),
         "http://occ.nextcloudatomic.local".to_string()
     ).await?;
-    
-    let mut client = JournalLogStreamClient::new(channel);
+    Ok(JournalLogStreamClient::new(channel))
+}
+
+pub async fn query_logs(socket_path: String, query: LogQuery) -> Result<Response<Streaming<crate::api::LogMessage>>, NcaError> {
+    journal_client(socket_path).await?
+        .query(Request::new(query)).await
+        .map_err(|status| NcaError::new_io_error(status.message()))
+}
+
+pub async fn stream_logs(socket_path: String, filter: LogFilter) -> Result<Response<Streaming<crate::api::LogMessage>>, NcaError> {
+    let mut client = journal_client(socket_path).await?;
     
     client.tail(Request::new(filter)).await
         .map_err(|status| NcaError::new_io_error(status.message()))
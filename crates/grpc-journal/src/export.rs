@@ -0,0 +1,85 @@
+//! Reading and writing the journal export format (`journalctl -o export`), see
+//! https://systemd.io/JOURNAL_EXPORT_FORMATS/
+use std::collections::BTreeMap;
+use nca_error::NcaError;
+
+pub type ExportEntry = BTreeMap<String, String>;
+
+/// Appends `entry` to `out`. Values containing control characters are written in the binary
+/// encoding.
+pub fn write_entry(out: &mut Vec<u8>, entry: &ExportEntry) {
+    for (name, value) in entry {
+        out.extend_from_slice(name.as_bytes());
+        if value.chars().any(|c| c.is_control()) {
+            out.push(b'\n');
+            out.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            out.push(b'=');
+        }
+        out.extend_from_slice(value.as_bytes());
+        out.push(b'\n');
+    }
+    out.push(b'\n');
+}
+
+pub fn parse_entries(data: &[u8]) -> Result<Vec<ExportEntry>, NcaError> {
+    let mut entries = Vec::new();
+    let mut entry = ExportEntry::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        let line_end = rest.iter().position(|b| *b == b'\n').unwrap_or(rest.len());
+        let line = &rest[..line_end];
+        rest = &rest[(line_end + 1).min(rest.len())..];
+        if line.is_empty() {
+            if !entry.is_empty() {
+                entries.push(std::mem::take(&mut entry));
+            }
+            continue;
+        }
+        match line.iter().position(|b| *b == b'=') {
+            Some(eq) => {
+                entry.insert(String::from_utf8_lossy(&line[..eq]).to_string(),
+                             String::from_utf8_lossy(&line[eq + 1..]).to_string());
+            },
+            // binary field: the name is followed by the size as little endian u64 and the data
+            None => {
+                let name = String::from_utf8_lossy(line).to_string();
+                let size = rest.get(..8)
+                    .map(|size| u64::from_le_bytes(size.try_into().unwrap()) as usize)
+                    .ok_or_else(|| NcaError::new_io_error(format!("Truncated binary field '{name}'")))?;
+                let value = rest.get(8..8 + size)
+                    .ok_or_else(|| NcaError::new_io_error(format!("Truncated binary field '{name}'")))?;
+                entry.insert(name, String::from_utf8_lossy(value).to_string());
+                rest = &rest[(8 + size + 1).min(rest.len())..];
+            },
+        }
+    }
+    if !entry.is_empty() {
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let entries = vec![
+            ExportEntry::from([
+                ("__CURSOR".to_string(), "s=1;i=2".to_string()),
+                ("MESSAGE".to_string(), "hello = world".to_string()),
+            ]),
+            ExportEntry::from([
+                ("MESSAGE".to_string(), "two\nlines".to_string()),
+                ("PRIORITY".to_string(), "3".to_string()),
+            ]),
+        ];
+        let mut out = Vec::new();
+        entries.iter().for_each(|e| write_entry(&mut out, e));
+        assert!(out.starts_with(b"MESSAGE=hello = world\n__CURSOR=s=1;i=2\n\nMESSAGE\n\x09\0\0\0\0\0\0\0two\nlines\n"));
+        assert_eq!(parse_entries(&out).unwrap(), entries);
+        assert!(parse_entries(b"MESSAGE\n\x09\0\0").is_err());
+    }
+}
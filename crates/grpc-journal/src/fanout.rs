@@ -0,0 +1,290 @@
+use std::collections::{HashMap, VecDeque};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use futures_util::{stream, Stream, StreamExt};
+use systemd::journal::{Journal, JournalSeek};
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tonic::Status;
+use nca_error::NcaError;
+use crate::api::LogMessage;
+use crate::filter::LogMatcher;
+use crate::server::{open_journal, to_log_message};
+
+// Entries a subscriber may fall behind before it skips to the most recent ones
+const SUBSCRIBER_BACKLOG: usize = 1024;
+// How often an idle reader checks whether it still has subscribers
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+pub type LogMessageStream = Pin<Box<dyn Stream<Item = Result<LogMessage, Status>> + Send>>;
+
+/// Entries of the journal, or the error that stopped the reader
+pub type SharedEntry = Result<LogMessage, Status>;
+
+type Readers = Arc<Mutex<HashMap<Option<String>, broadcast::Sender<SharedEntry>>>>;
+
+/// One journal reader per namespace that is shared by all tailing clients. The reader thread is
+/// started by the first subscriber and stops once the last one has disconnected.
+#[derive(Debug, Default, Clone)]
+pub struct SharedJournalReaders {
+    current_user: bool,
+    system: bool,
+    readers: Readers,
+}
+
+impl SharedJournalReaders {
+    pub fn new(current_user: bool, system: bool) -> SharedJournalReaders {
+        SharedJournalReaders { current_user, system, readers: Readers::default() }
+    }
+
+    pub async fn subscribe(&self, namespace: Option<String>) -> Result<broadcast::Receiver<SharedEntry>, NcaError> {
+        let mut readers = self.readers.lock().await;
+        if let Some(tx) = readers.get(&namespace) {
+            return Ok(tx.subscribe());
+        }
+
+        let (tx, rx) = broadcast::channel(SUBSCRIBER_BACKLOG);
+        // The journal can not be moved between threads, so it is opened by the reader thread
+        let (opened_tx, opened_rx) = oneshot::channel();
+        let (current_user, system) = (self.current_user, self.system);
+        let (reader_tx, reader_namespace, reader_registry) = (tx.clone(), namespace.clone(), self.readers.clone());
+        thread::spawn(move || {
+            let reader = open_journal(current_user, system, &reader_namespace)
+                .and_then(|mut reader| {
+                    reader.seek(JournalSeek::Tail)
+                        .and_then(|_| reader.previous())
+                        .map_err(|e| NcaError::new_io_error(format!("Couldn't seek to end of journal: {e:?}")))?;
+                    Ok(reader)
+                });
+            match reader {
+                Err(e) => {
+                    let _ = opened_tx.send(Err(e));
+                },
+                Ok(reader) => {
+                    let _ = opened_tx.send(Ok(()));
+                    run_shared_reader(reader, reader_tx, reader_namespace, reader_registry);
+                }
+            }
+        });
+        opened_rx.await
+            .map_err(|e| NcaError::new_unexpected_error(format!("Journal reader thread exited: {e:?}")))??;
+        readers.insert(namespace, tx);
+        Ok(rx)
+    }
+}
+
+/// Removes the reader from the registry if nobody is subscribed. Subscribing happens while holding
+/// the registry lock, so no subscriber can be lost in between.
+fn stop_if_unused(readers: &Readers, namespace: &Option<String>, tx: &broadcast::Sender<SharedEntry>) -> bool {
+    let mut readers = readers.blocking_lock();
+    if tx.receiver_count() > 0 {
+        return false;
+    }
+    readers.remove(namespace);
+    true
+}
+
+/// Runs on its own thread, the registry is locked with `blocking_lock`
+fn run_shared_reader(mut reader: Journal, tx: broadcast::Sender<SharedEntry>, namespace: Option<String>, readers: Readers) {
+    loop {
+        match reader.next_entry() {
+            Err(e) => {
+                eprintln!("Unexpected error while reading journal, closing all streams: {e:?}");
+                let mut readers = readers.blocking_lock();
+                readers.remove(&namespace);
+                // Subscribers receive the error before their stream ends
+                let _ = tx.send(Err(Status::internal(format!("Error while reading the journal: {e}"))));
+                return;
+            },
+            Ok(Some(record)) => {
+                // Sending only fails if there are no subscribers
+                if tx.send(Ok(to_log_message(&reader, record, &namespace))).is_err()
+                    && stop_if_unused(&readers, &namespace, &tx) {
+                    return;
+                }
+            },
+            Ok(None) => {
+                if stop_if_unused(&readers, &namespace, &tx) {
+                    return;
+                }
+                if let Err(e) = reader.wait(Some(IDLE_CHECK_INTERVAL)) {
+                    eprintln!("Unexpected error while waiting for journal entries: {e:?}");
+                }
+            },
+        }
+    }
+}
+
+fn skipped_entries_message(count: u64, namespace: &Option<String>) -> LogMessage {
+    LogMessage {
+        message: format!("{count} entries skipped"),
+        namespace: namespace.clone(),
+        skipped_entries: Some(count),
+        ..LogMessage::default()
+    }
+}
+
+/// The entries of `rx` matching `matcher`. If the client reads too slowly to keep up, the
+/// entries it missed are replaced by a single message with `skipped_entries` set.
+pub fn subscriber_stream(rx: broadcast::Receiver<SharedEntry>, matcher: LogMatcher, namespace: Option<String>) -> LogMessageStream {
+    Box::pin(stream::unfold((rx, matcher, namespace), |(mut rx, matcher, namespace)| async move {
+        loop {
+            match rx.recv().await {
+                Ok(Ok(msg)) if matcher.matches(&msg.fields) => return Some((Ok(msg), (rx, matcher, namespace))),
+                Ok(Ok(_)) => continue,
+                Ok(Err(status)) => return Some((Err(status), (rx, matcher, namespace))),
+                Err(RecvError::Lagged(count)) => {
+                    let msg = skipped_entries_message(count, &namespace);
+                    return Some((Ok(msg), (rx, matcher, namespace)));
+                },
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }))
+}
+
+struct Resumption {
+    replay: Option<mpsc::Receiver<Result<LogMessage, Status>>>,
+    /// Cursors of the most recently replayed entries, which the subscriber may receive as well
+    replayed: VecDeque<String>,
+    live: Option<LogMessageStream>,
+}
+
+/// The entries of `replay`, followed by those of `rx` that were not replayed already. `rx` has to
+/// be subscribed before the replay starts, so no entry is missed in between. The stream ends after
+/// an error of the replay, e.g. for an unknown cursor.
+pub fn resumed_stream(replay: mpsc::Receiver<Result<LogMessage, Status>>, rx: broadcast::Receiver<SharedEntry>, matcher: LogMatcher, namespace: Option<String>) -> LogMessageStream {
+    let state = Resumption {
+        replay: Some(replay),
+        replayed: VecDeque::new(),
+        live: Some(subscriber_stream(rx, matcher, namespace)),
+    };
+    Box::pin(stream::unfold(state, |mut state| async move {
+        if let Some(replay) = state.replay.as_mut() {
+            match replay.recv().await {
+                Some(Ok(msg)) => {
+                    // Entries logged while the subscriber was already receiving are in its backlog
+                    if state.replayed.len() == SUBSCRIBER_BACKLOG {
+                        state.replayed.pop_front();
+                    }
+                    state.replayed.extend(msg.cursor.clone());
+                    return Some((Ok(msg), state));
+                },
+                Some(Err(status)) => {
+                    state.live = None;
+                    state.replay = None;
+                    return Some((Err(status), state));
+                },
+                None => state.replay = None,
+            }
+        }
+        loop {
+            let msg = state.live.as_mut()?.next().await?;
+            let cursor = msg.as_ref().ok().and_then(|msg| msg.cursor.as_ref());
+            match cursor {
+                Some(cursor) if state.replayed.contains(cursor) => continue,
+                // Anything after the first new entry has not been replayed either
+                Some(_) => state.replayed.clear(),
+                None => {},
+            }
+            return Some((msg, state));
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::LogFilter;
+    use super::*;
+
+    fn message(text: &str) -> LogMessage {
+        LogMessage {
+            fields: HashMap::from([("MESSAGE".to_string(), text.to_string())]),
+            message: text.to_string(),
+            ..LogMessage::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_subscriber_stream_skips_lagged_entries() {
+        let (tx, rx) = broadcast::channel(4);
+        let matcher = LogMatcher::new(&LogFilter {
+            message_contains: Some("entry".to_string()),
+            ..LogFilter::default()
+        }).unwrap();
+        let mut stream = subscriber_stream(rx, matcher, None);
+        for i in 0..10 {
+            tx.send(Ok(message(&format!("entry {i}")))).unwrap();
+        }
+        tx.send(Ok(message("filtered"))).unwrap();
+        tx.send(Ok(message("entry 10"))).unwrap();
+        drop(tx);
+
+        let received: Vec<LogMessage> = stream.by_ref().map(|msg| msg.unwrap()).collect().await;
+        assert_eq!(received[0].skipped_entries, Some(8));
+        let messages: Vec<&str> = received[1..].iter().map(|m| m.message.as_str()).collect();
+        assert_eq!(messages, vec!["entry 8", "entry 9", "entry 10"]);
+    }
+
+    fn entry(cursor: &str) -> LogMessage {
+        LogMessage { cursor: Some(cursor.to_string()), ..message(cursor) }
+    }
+
+    #[tokio::test]
+    async fn test_resumed_stream_skips_replayed_entries() {
+        let (replay_tx, replay) = mpsc::channel(4);
+        let (tx, rx) = broadcast::channel(8);
+        let stream = resumed_stream(replay, rx, LogMatcher::default(), None);
+        // c and d were logged after subscribing and before the replay reached the end of the journal
+        for cursor in ["c", "d", "e"] {
+            tx.send(Ok(entry(cursor))).unwrap();
+        }
+        drop(tx);
+        for cursor in ["b", "c", "d"] {
+            replay_tx.send(Ok(entry(cursor))).await.unwrap();
+        }
+        drop(replay_tx);
+
+        let cursors: Vec<String> = stream.map(|msg| msg.unwrap().cursor.unwrap()).collect().await;
+        assert_eq!(cursors, vec!["b", "c", "d", "e"]);
+    }
+
+    #[tokio::test]
+    async fn test_resumed_stream_ends_with_replay_error() {
+        let (replay_tx, replay) = mpsc::channel(4);
+        let (tx, rx) = broadcast::channel(8);
+        let mut stream = resumed_stream(replay, rx, LogMatcher::default(), None);
+        tx.send(Ok(entry("b"))).unwrap();
+        replay_tx.send(Err(Status::invalid_argument("Invalid journal cursor"))).await.unwrap();
+
+        assert_eq!(stream.next().await.unwrap().unwrap_err().code(), tonic::Code::InvalidArgument);
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_reader_stops_without_subscribers() {
+        let readers = SharedJournalReaders::new(true, true);
+        let first = readers.subscribe(None).await.unwrap();
+        let second = readers.subscribe(None).await.unwrap();
+        assert_eq!(readers.readers.lock().await.len(), 1);
+        drop(first);
+        drop(second);
+        tokio::time::sleep(IDLE_CHECK_INTERVAL * 3).await;
+        assert!(readers.readers.lock().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_subscriber_stream_ends_with_reader_error() {
+        let (tx, rx) = broadcast::channel(4);
+        let mut stream = subscriber_stream(rx, LogMatcher::new(&LogFilter::default()).unwrap(), None);
+        tx.send(Ok(message("entry"))).unwrap();
+        tx.send(Err(Status::internal("Error while reading the journal"))).unwrap();
+        drop(tx);
+
+        assert_eq!(stream.next().await.unwrap().unwrap().message, "entry");
+        assert_eq!(stream.next().await.unwrap().unwrap_err().code(), tonic::Code::Internal);
+        assert!(stream.next().await.is_none());
+    }
+}
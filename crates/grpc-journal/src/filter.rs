@@ -0,0 +1,198 @@
+use std::collections::HashMap;
+use regex::Regex;
+use nca_error::NcaError;
+use crate::api::LogFilter;
+
+const KEY_MESSAGE: &str = "MESSAGE";
+const KEY_PRIORITY: &str = "PRIORITY";
+const KEY_CONTAINER_NAME: &str = "CONTAINER_NAME";
+// Like `journalctl -u`, also match the messages systemd logs about a unit
+const UNIT_KEYS: &[&str] = &["_SYSTEMD_UNIT", "UNIT", "OBJECT_SYSTEMD_UNIT"];
+const USER_UNIT_KEYS: &[&str] = &["_SYSTEMD_USER_UNIT", "USER_UNIT", "OBJECT_SYSTEMD_USER_UNIT"];
+
+/// Matches `value` against a glob pattern supporting `*` and `?`
+pub(crate) fn glob_match(pattern: &str, value: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let value: Vec<char> = value.chars().collect();
+    let (mut p, mut v) = (0, 0);
+    // position of the last `*` in the pattern and of the value character it has consumed up to
+    let mut backtrack: Option<(usize, usize)> = None;
+    while v < value.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, v));
+                p += 1;
+            },
+            Some(c) if *c == '?' || *c == value[v] => {
+                p += 1;
+                v += 1;
+            },
+            _ => match backtrack {
+                Some((star, consumed)) => {
+                    p = star + 1;
+                    v = consumed + 1;
+                    backtrack = Some((star, consumed + 1));
+                },
+                None => return false,
+            }
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Unit names without a type suffix refer to services, as in `journalctl -u`
+fn unit_pattern(unit: &str) -> String {
+    match unit.contains('.') || unit.ends_with('*') {
+        true => unit.to_string(),
+        false => format!("{unit}.service"),
+    }
+}
+
+/// Server side evaluation of a `LogFilter`: all criteria have to match, the field groups of
+/// `any_of` are alternatives.
+#[derive(Debug, Default, Clone)]
+pub struct LogMatcher {
+    fields: HashMap<String, String>,
+    any_of: Vec<HashMap<String, String>>,
+    units: Vec<String>,
+    user_units: Vec<String>,
+    priority: Option<u32>,
+    message_contains: Option<String>,
+    message_regex: Option<Regex>,
+    container_names: Vec<String>,
+}
+
+impl LogMatcher {
+    pub fn new(filter: &LogFilter) -> Result<LogMatcher, NcaError> {
+        let message_regex = filter.message_regex.as_deref()
+            .map(Regex::new)
+            .transpose()
+            .map_err(|e| NcaError::Generic(format!("Invalid message regex: {e}")))?;
+        Ok(LogMatcher {
+            fields: filter.fields.clone(),
+            any_of: filter.any_of.iter()
+                .map(|group| group.fields.clone())
+                .filter(|group| !group.is_empty())
+                .collect(),
+            units: filter.units.iter().map(|u| unit_pattern(u)).collect(),
+            user_units: filter.user_units.iter().map(|u| unit_pattern(u)).collect(),
+            priority: filter.priority,
+            message_contains: filter.message_contains.clone().filter(|s| !s.is_empty()),
+            message_regex,
+            container_names: filter.container_names.clone(),
+        })
+    }
+
+    fn matches_units(&self, fields: &HashMap<String, String>) -> bool {
+        if self.units.is_empty() && self.user_units.is_empty() {
+            return true;
+        }
+        let matches_any = |keys: &[&str], patterns: &[String]| keys.iter()
+            .filter_map(|key| fields.get(*key))
+            .any(|unit| patterns.iter().any(|pattern| glob_match(pattern, unit)));
+        matches_any(UNIT_KEYS, &self.units) || matches_any(USER_UNIT_KEYS, &self.user_units)
+    }
+
+    pub fn matches(&self, fields: &HashMap<String, String>) -> bool {
+        let matches_group = |group: &HashMap<String, String>| group.iter()
+            .all(|(k, v)| fields.get(k) == Some(v));
+        if !matches_group(&self.fields) {
+            return false;
+        }
+        if !self.any_of.is_empty() && !self.any_of.iter().any(matches_group) {
+            return false;
+        }
+        if !self.matches_units(fields) {
+            return false;
+        }
+        if let Some(max_priority) = self.priority {
+            let priority = fields.get(KEY_PRIORITY).and_then(|p| p.parse::<u32>().ok());
+            if priority.is_none_or(|p| p > max_priority) {
+                return false;
+            }
+        }
+        let message = fields.get(KEY_MESSAGE).map(String::as_str).unwrap_or_default();
+        if self.message_contains.as_ref().is_some_and(|s| !message.contains(s.as_str())) {
+            return false;
+        }
+        if self.message_regex.as_ref().is_some_and(|re| !re.is_match(message)) {
+            return false;
+        }
+        if !self.container_names.is_empty() {
+            let container = fields.get(KEY_CONTAINER_NAME);
+            if !container.is_some_and(|c| self.container_names.iter().any(|pattern| glob_match(pattern, c))) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api::FieldMatch;
+    use crate::export::parse_entries;
+    use super::*;
+
+    fn matching_cursors(filter: LogFilter) -> Vec<String> {
+        let matcher = LogMatcher::new(&filter).unwrap();
+        parse_entries(include_bytes!("../tests/fixtures/journal.export")).unwrap().into_iter()
+            .map(|entry| entry.into_iter().collect::<HashMap<_, _>>())
+            .filter(|entry| matcher.matches(entry))
+            .map(|entry| entry["__CURSOR"].clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("nextcloud-aio-*", "nextcloud-aio-apache"));
+        assert!(glob_match("*aio*", "nextcloud-aio-apache"));
+        assert!(glob_match("nextcloud-aio-???", "nextcloud-aio-php"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("nextcloud-aio-*", "caddy"));
+        assert!(!glob_match("nextcloud-aio-???", "nextcloud-aio-apache"));
+        assert!(glob_match("a*b*c", "aXbYbZc"));
+    }
+
+    #[test]
+    fn test_fixture_filters() {
+        assert_eq!(matching_cursors(LogFilter::default()).len(), 7);
+
+        // AND within a group, OR across groups
+        assert_eq!(matching_cursors(LogFilter {
+            fields: HashMap::from([("_TRANSPORT".to_string(), "journal".to_string())]),
+            any_of: vec![
+                FieldMatch { fields: HashMap::from([("SYSLOG_IDENTIFIER".to_string(), "nca-system".to_string())]) },
+                FieldMatch { fields: HashMap::from([
+                    ("SYSLOG_IDENTIFIER".to_string(), "conmon".to_string()),
+                    ("CONTAINER_NAME".to_string(), "nextcloud-aio-apache".to_string()),
+                ]) },
+            ],
+            ..LogFilter::default()
+        }), vec!["c1", "c4"]);
+
+        assert_eq!(matching_cursors(LogFilter { units: vec!["nca-system".to_string()], ..LogFilter::default() }),
+                   vec!["c1", "c2", "c3"]);
+        assert_eq!(matching_cursors(LogFilter {
+            units: vec!["caddy.service".to_string()],
+            user_units: vec!["podman-*.scope".to_string()],
+            ..LogFilter::default()
+        }), vec!["c4", "c5", "c6"]);
+        assert_eq!(matching_cursors(LogFilter { priority: Some(3), ..LogFilter::default() }),
+                   vec!["c2", "c7"]);
+        assert_eq!(matching_cursors(LogFilter { message_contains: Some("disk".to_string()), ..LogFilter::default() }),
+                   vec!["c2", "c3"]);
+        assert_eq!(matching_cursors(LogFilter { message_regex: Some(r"^GET /\S+ 50\d".to_string()), ..LogFilter::default() }),
+                   vec!["c5"]);
+        assert_eq!(matching_cursors(LogFilter { container_names: vec!["nextcloud-aio-*".to_string()], ..LogFilter::default() }),
+                   vec!["c4", "c5"]);
+        // binary encoded fields are matched as well
+        assert_eq!(matching_cursors(LogFilter { message_contains: Some("multi".to_string()), ..LogFilter::default() }),
+                   vec!["c7"]);
+    }
+
+    #[test]
+    fn test_invalid_regex() {
+        assert!(LogMatcher::new(&LogFilter { message_regex: Some("(".to_string()), ..LogFilter::default() }).is_err());
+    }
+}
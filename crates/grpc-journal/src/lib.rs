@@ -1,8 +1,22 @@
 #[cfg(feature = "api")]
 pub mod server;
+#[cfg(feature = "api")]
+pub mod filter;
+#[cfg(feature = "api")]
+pub mod fanout;
+#[cfg(feature = "api")]
+pub mod alerts;
+#[cfg(feature = "mock")]
+pub mod replay;
+pub mod export;
+pub mod message;
 #[cfg(feature = "client")]
 
 pub mod client;
+#[cfg(feature = "client")]
+pub mod output;
+#[cfg(feature = "client")]
+pub mod timespec;
 
 pub mod api {
     tonic::include_proto!("api");
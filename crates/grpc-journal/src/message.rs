@@ -0,0 +1,64 @@
+use std::collections::HashMap;
+use crate::api::{LogMessage, Priority};
+
+const KEY_MESSAGE: &str = "MESSAGE";
+
+fn field(fields: &HashMap<String, String>, key: &str) -> Option<String> {
+    fields.get(key).cloned()
+}
+
+fn number(fields: &HashMap<String, String>, key: &str) -> Option<u64> {
+    fields.get(key).and_then(|v| v.parse().ok())
+}
+
+impl LogMessage {
+    /// Creates a message from the fields of a journal entry. The address fields (`__CURSOR`,
+    /// `__REALTIME_TIMESTAMP`, ...) are only part of exported entries, a live reader has to set
+    /// them separately.
+    pub fn from_fields(fields: HashMap<String, String>, namespace: Option<String>) -> LogMessage {
+        LogMessage {
+            message: field(&fields, KEY_MESSAGE).unwrap_or("<no message>".to_string()),
+            namespace,
+            cursor: field(&fields, "__CURSOR"),
+            skipped_entries: None,
+            realtime_timestamp: number(&fields, "__REALTIME_TIMESTAMP"),
+            monotonic_timestamp: number(&fields, "__MONOTONIC_TIMESTAMP"),
+            priority: number(&fields, "PRIORITY")
+                .and_then(|p| Priority::try_from(p as i32).ok())
+                .map(i32::from),
+            unit: field(&fields, "_SYSTEMD_UNIT"),
+            user_unit: field(&fields, "_SYSTEMD_USER_UNIT"),
+            syslog_identifier: field(&fields, "SYSLOG_IDENTIFIER"),
+            container_name: field(&fields, "CONTAINER_NAME"),
+            boot_id: field(&fields, "_BOOT_ID"),
+            fields,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::export::parse_entries;
+    use super::*;
+
+    #[test]
+    fn test_from_fields() {
+        let entries = parse_entries(include_bytes!("../tests/fixtures/journal.export")).unwrap();
+        let messages: Vec<LogMessage> = entries.into_iter()
+            .map(|entry| LogMessage::from_fields(entry.into_iter().collect(), None))
+            .collect();
+        assert_eq!(messages[0].message, "Storage service started");
+        assert_eq!(messages[0].priority(), Priority::Info);
+        assert_eq!(messages[0].unit.as_deref(), Some("nca-system.service"));
+        assert_eq!(messages[0].realtime_timestamp, Some(1760780000000001));
+        assert_eq!(messages[0].cursor.as_deref(), Some("c1"));
+        assert_eq!(messages[3].user_unit.as_deref(), Some("podman-1234.scope"));
+        assert_eq!(messages[3].container_name.as_deref(), Some("nextcloud-aio-apache"));
+        assert_eq!(messages[6].priority(), Priority::Crit);
+        assert_eq!(messages[6].syslog_identifier.as_deref(), Some("sshd"));
+
+        let message = LogMessage::from_fields(HashMap::from([("PRIORITY".to_string(), "9".to_string())]), None);
+        assert_eq!(message.priority, None);
+        assert_eq!(message.message, "<no message>");
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::BTreeMap;
+use chrono::{DateTime, Local};
+use clap::ValueEnum;
+use serde_json::{json, Value};
+use crate::api::{LogMessage, Priority};
+
+const RESET: &str = "\x1b[0m";
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum OutputMode {
+    /// One line per entry, like `journalctl -o short`
+    Short,
+    /// All fields of each entry
+    Verbose,
+    /// One JSON object per line
+    Json,
+}
+
+fn priority_name(priority: Priority) -> &'static str {
+    match priority {
+        Priority::Emerg => "emerg",
+        Priority::Alert => "alert",
+        Priority::Crit => "crit",
+        Priority::Err => "err",
+        Priority::Warning => "warning",
+        Priority::Notice => "notice",
+        Priority::Info => "info",
+        Priority::Debug => "debug",
+    }
+}
+
+/// Parses a priority given by name or number, e.g. for `--priority`
+pub fn parse_priority(value: &str) -> Result<Priority, String> {
+    if let Ok(number) = value.parse::<i32>() {
+        return Priority::try_from(number).map_err(|_| format!("Priority must be between 0 and 7, not {number}"));
+    }
+    match value.to_lowercase().as_str() {
+        "error" => Ok(Priority::Err),
+        "warn" => Ok(Priority::Warning),
+        name => (0..8)
+            .filter_map(|p| Priority::try_from(p).ok())
+            .find(|p| priority_name(*p) == name)
+            .ok_or(format!("Unknown priority '{value}'")),
+    }
+}
+
+fn priority_color(priority: Priority) -> Option<&'static str> {
+    match priority {
+        Priority::Emerg | Priority::Alert | Priority::Crit => Some("\x1b[1;31m"),
+        Priority::Err => Some("\x1b[31m"),
+        Priority::Warning => Some("\x1b[33m"),
+        Priority::Notice => Some("\x1b[1m"),
+        Priority::Info => None,
+        Priority::Debug => Some("\x1b[2m"),
+    }
+}
+
+fn message_color(msg: &LogMessage) -> Option<&'static str> {
+    msg.priority.and_then(|_| priority_color(msg.priority()))
+}
+
+fn local_time(usec: Option<u64>) -> Option<DateTime<Local>> {
+    DateTime::from_timestamp_micros(usec? as i64).map(|t| t.with_timezone(&Local))
+}
+
+/// The source of the entry: container, unit or syslog identifier, whichever is most specific
+fn source(msg: &LogMessage) -> String {
+    let name = msg.container_name.as_ref()
+        .or(msg.syslog_identifier.as_ref())
+        .or(msg.unit.as_ref())
+        .or(msg.user_unit.as_ref())
+        .map(String::as_str)
+        .unwrap_or("unknown");
+    match msg.fields.get("_PID") {
+        Some(pid) => format!("{name}[{pid}]"),
+        None => name.to_string(),
+    }
+}
+
+fn format_short(msg: &LogMessage, colors: bool) -> String {
+    if let Some(skipped) = msg.skipped_entries {
+        return format!("-- {skipped} entries skipped --");
+    }
+    let time = local_time(msg.realtime_timestamp)
+        .map(|t| t.format("%b %d %H:%M:%S").to_string())
+        .unwrap_or("---".to_string());
+    let text = match message_color(msg) {
+        Some(color) if colors => format!("{color}{}{RESET}", msg.message),
+        _ => msg.message.clone(),
+    };
+    format!("{time} {}: {text}", source(msg))
+}
+
+fn format_verbose(msg: &LogMessage, colors: bool) -> String {
+    let time = local_time(msg.realtime_timestamp)
+        .map(|t| t.format("%a %Y-%m-%d %H:%M:%S%.6f %Z").to_string())
+        .unwrap_or("---".to_string());
+    let mut lines = vec![format!("{time} [{}]", msg.cursor.as_deref().unwrap_or_default())];
+    if let Some(skipped) = msg.skipped_entries {
+        lines.push(format!("    {skipped} entries skipped"));
+    }
+    let fields: BTreeMap<&String, &String> = msg.fields.iter().collect();
+    for (key, value) in fields {
+        let line = format!("    {key}={value}");
+        lines.push(match (key.as_str(), message_color(msg)) {
+            ("MESSAGE", Some(color)) if colors => format!("{color}{line}{RESET}"),
+            _ => line,
+        });
+    }
+    lines.join("\n")
+}
+
+fn format_json(msg: &LogMessage) -> String {
+    let mut value = json!({
+        "message": msg.message,
+        "cursor": msg.cursor,
+        "realtime_timestamp": msg.realtime_timestamp,
+        "monotonic_timestamp": msg.monotonic_timestamp,
+        "priority": msg.priority.map(|_| priority_name(msg.priority())),
+        "unit": msg.unit,
+        "user_unit": msg.user_unit,
+        "syslog_identifier": msg.syslog_identifier,
+        "container_name": msg.container_name,
+        "boot_id": msg.boot_id,
+        "namespace": msg.namespace,
+        "fields": msg.fields.iter().collect::<BTreeMap<_, _>>(),
+    });
+    if let (Some(skipped), Value::Object(obj)) = (msg.skipped_entries, &mut value) {
+        obj.insert("skipped_entries".to_string(), Value::from(skipped));
+    }
+    value.to_string()
+}
+
+pub fn format_message(msg: &LogMessage, mode: OutputMode, colors: bool) -> String {
+    match mode {
+        OutputMode::Short => format_short(msg, colors),
+        OutputMode::Verbose => format_verbose(msg, colors),
+        OutputMode::Json => format_json(msg),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use super::*;
+
+    fn message() -> LogMessage {
+        LogMessage::from_fields(HashMap::from([
+            ("MESSAGE".to_string(), "Failed to open encrypted disk".to_string()),
+            ("PRIORITY".to_string(), "3".to_string()),
+            ("SYSLOG_IDENTIFIER".to_string(), "nca-system".to_string()),
+            ("_SYSTEMD_UNIT".to_string(), "nca-system.service".to_string()),
+            ("_PID".to_string(), "812".to_string()),
+            ("__CURSOR".to_string(), "s=1;i=2".to_string()),
+        ]), None)
+    }
+
+    #[test]
+    fn test_format_message() {
+        let msg = message();
+        assert_eq!(format_message(&msg, OutputMode::Short, false), "--- nca-system[812]: Failed to open encrypted disk");
+        assert_eq!(format_message(&msg, OutputMode::Short, true),
+                   "--- nca-system[812]: \x1b[31mFailed to open encrypted disk\x1b[0m");
+        let verbose = format_message(&msg, OutputMode::Verbose, false);
+        assert!(verbose.starts_with("--- [s=1;i=2]\n    MESSAGE=Failed to open encrypted disk\n    PRIORITY=3\n"));
+
+        let json: Value = serde_json::from_str(&format_message(&msg, OutputMode::Json, true)).unwrap();
+        assert_eq!(json["priority"], "err");
+        assert_eq!(json["unit"], "nca-system.service");
+        assert_eq!(json["fields"]["_PID"], "812");
+        assert!(json.get("skipped_entries").is_none());
+    }
+
+    #[test]
+    fn test_parse_priority() {
+        assert_eq!(parse_priority("3"), Ok(Priority::Err));
+        assert_eq!(parse_priority("warning"), Ok(Priority::Warning));
+        assert_eq!(parse_priority("WARN"), Ok(Priority::Warning));
+        assert!(parse_priority("8").is_err());
+        assert!(parse_priority("loud").is_err());
+    }
+}
@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use futures_util::stream;
+use serde_json::Value;
+use tokio::time::{sleep_until, Instant};
+use nca_error::NcaError;
+use crate::api::LogMessage;
+use crate::fanout::LogMessageStream;
+use crate::filter::LogMatcher;
+
+const BUNDLED_FIXTURE: &str = include_str!("../tests/fixtures/replay.jsonl");
+const CURSOR_PREFIX: &str = "replay;i=";
+// Pause between two rounds of a repeating replay
+const REPEAT_PAUSE: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+struct ReplayEntry {
+    // Microseconds since the first entry of the fixture
+    offset: u64,
+    fields: HashMap<String, String>,
+}
+
+fn field_value(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        // journalctl exports binary values as arrays of bytes
+        Value::Array(bytes) => bytes.iter()
+            .map(|b| b.as_u64().and_then(|b| u8::try_from(b).ok()))
+            .collect::<Option<Vec<u8>>>()
+            .map(|bytes| String::from_utf8_lossy(&bytes).to_string()),
+        _ => None,
+    }
+}
+
+/// A mock journal that replays recorded entries (in the JSON lines format of
+/// `journalctl -o json`) with their original timing, optionally sped up
+#[derive(Debug, Clone)]
+pub struct ReplaySource {
+    entries: Arc<Vec<ReplayEntry>>,
+    speed: f64,
+    repeat: bool,
+}
+
+impl Default for ReplaySource {
+    fn default() -> Self {
+        ReplaySource::bundled()
+    }
+}
+
+impl ReplaySource {
+    pub fn parse(json_lines: &str) -> Result<ReplaySource, NcaError> {
+        let mut first_timestamp = None;
+        let mut previous_offset = 0;
+        let entries = json_lines.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                let object: HashMap<String, Value> = serde_json::from_str(line)
+                    .map_err(|e| NcaError::new_missing_config_error(format!("Invalid replay entry in line {}: {e}", i + 1)))?;
+                let timestamp = object.get("__REALTIME_TIMESTAMP")
+                    .and_then(field_value)
+                    .and_then(|t| t.parse::<u64>().ok());
+                let offset = match timestamp {
+                    Some(t) => t.saturating_sub(*first_timestamp.get_or_insert(t)),
+                    None => previous_offset,
+                };
+                previous_offset = offset.max(previous_offset);
+                Ok(ReplayEntry {
+                    offset: previous_offset,
+                    fields: object.iter()
+                        .filter(|(key, _)| !key.starts_with("__"))
+                        .filter_map(|(key, value)| Some((key.clone(), field_value(value)?)))
+                        .collect(),
+                })
+            })
+            .collect::<Result<Vec<ReplayEntry>, NcaError>>()?;
+        Ok(ReplaySource { entries: Arc::new(entries), speed: 1.0, repeat: true })
+    }
+
+    pub fn load(path: &str) -> Result<ReplaySource, NcaError> {
+        let data = fs::read_to_string(path)
+            .map_err(|e| NcaError::new_io_error(format!("Failed to read replay fixture {path}: {e:?}")))?;
+        ReplaySource::parse(&data)
+    }
+
+    /// Nextcloud AIO container logs, unit failures and entries of all priorities, about a minute long
+    pub fn bundled() -> ReplaySource {
+        ReplaySource::parse(BUNDLED_FIXTURE).expect("bundled replay fixture is invalid")
+    }
+
+    /// Configured by `$GRPC_JOURNAL_REPLAY_FIXTURE` (defaults to the bundled fixture),
+    /// `$GRPC_JOURNAL_REPLAY_SPEED` and `$GRPC_JOURNAL_REPLAY_REPEAT`
+    pub fn from_env() -> Result<ReplaySource, NcaError> {
+        let source = match std::env::var("GRPC_JOURNAL_REPLAY_FIXTURE") {
+            Ok(path) => ReplaySource::load(&path)?,
+            Err(_) => ReplaySource::bundled(),
+        };
+        let speed = match std::env::var("GRPC_JOURNAL_REPLAY_SPEED") {
+            Ok(speed) => speed.parse::<f64>()
+                .map_err(|e| NcaError::new_missing_config_error(format!("Invalid replay speed '{speed}': {e}")))?,
+            Err(_) => 1.0,
+        };
+        let repeat = std::env::var("GRPC_JOURNAL_REPLAY_REPEAT").map(|r| r != "false" && r != "0").unwrap_or(true);
+        Ok(source.with_speed(speed).with_repeat(repeat))
+    }
+
+    /// Replays `speed` times as fast as recorded, 0 replays without any delay
+    pub fn with_speed(mut self, speed: f64) -> ReplaySource {
+        self.speed = speed.max(0.0);
+        self
+    }
+
+    /// Starts over after the last entry instead of ending the stream
+    pub fn with_repeat(mut self, repeat: bool) -> ReplaySource {
+        self.repeat = repeat;
+        self
+    }
+
+    fn delay(&self, offset: u64) -> Duration {
+        match self.speed > 0.0 {
+            true => Duration::from_secs_f64(offset as f64 / 1_000_000.0 / self.speed),
+            false => Duration::ZERO,
+        }
+    }
+
+    fn to_log_message(&self, index: usize, namespace: &Option<String>) -> LogMessage {
+        let entry = &self.entries[index];
+        let mut msg = LogMessage::from_fields(entry.fields.clone(), namespace.clone());
+        msg.cursor = Some(format!("{CURSOR_PREFIX}{index}"));
+        msg.realtime_timestamp = Some(SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as u64);
+        msg
+    }
+
+    /// The entries matching `matcher`, starting after the entry with `after_cursor` (or from
+    /// the start of the fixture). Timestamps are set to the time an entry is replayed.
+    pub fn tail(&self, matcher: LogMatcher, namespace: Option<String>, after_cursor: Option<String>) -> LogMessageStream {
+        let start = after_cursor
+            .and_then(|cursor| cursor.strip_prefix(CURSOR_PREFIX)?.parse::<usize>().ok())
+            .map(|index| index + 1)
+            .unwrap_or(0);
+        let round_start = self.entries.get(start).map(|e| e.offset).unwrap_or_default();
+        let state = (self.clone(), matcher, namespace, start, Instant::now(), round_start);
+        Box::pin(stream::unfold(state, |(source, matcher, namespace, mut index, mut started, mut round_start)| async move {
+            loop {
+                if index >= source.entries.len() {
+                    if !source.repeat || source.entries.is_empty() {
+                        return None;
+                    }
+                    tokio::time::sleep(REPEAT_PAUSE).await;
+                    (index, started, round_start) = (0, Instant::now(), 0);
+                }
+                let entry = &source.entries[index];
+                sleep_until(started + source.delay(entry.offset - round_start)).await;
+                index += 1;
+                if matcher.matches(&entry.fields) {
+                    let msg = source.to_log_message(index - 1, &namespace);
+                    return Some((Ok(msg), (source, matcher, namespace, index, started, round_start)));
+                }
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::StreamExt;
+    use crate::api::LogFilter;
+    use super::*;
+
+    #[tokio::test]
+    async fn test_replay() {
+        let source = ReplaySource::bundled().with_speed(0.0).with_repeat(false);
+        assert_eq!(source.tail(LogMatcher::default(), None, None).count().await, source.entries.len());
+
+        let matcher = LogMatcher::new(&LogFilter {
+            container_names: vec!["nextcloud-aio-nextcloud".to_string()],
+            priority: Some(3),
+            ..LogFilter::default()
+        }).unwrap();
+        let errors: Vec<LogMessage> = source.tail(matcher.clone(), None, None)
+            .map(|msg| msg.unwrap())
+            .collect().await;
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].message.starts_with("cron.php failed"));
+
+        let resumed = source.tail(matcher, None, errors[0].cursor.clone()).count().await;
+        assert_eq!(resumed, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_replay_speed() {
+        let source = ReplaySource::parse(concat!(
+            r#"{"__REALTIME_TIMESTAMP": "1000000", "MESSAGE": "first"}"#, "\n",
+            r#"{"__REALTIME_TIMESTAMP": "11000000", "MESSAGE": "second", "DATA": [104, 105]}"#, "\n",
+        )).unwrap().with_speed(10.0).with_repeat(false);
+        let started = Instant::now();
+        let messages: Vec<LogMessage> = source.tail(LogMatcher::default(), None, None)
+            .map(|msg| msg.unwrap())
+            .collect().await;
+        assert_eq!(started.elapsed().as_secs(), 1);
+        assert_eq!(messages[1].message, "second");
+        assert_eq!(messages[1].fields.get("DATA").map(String::as_str), Some("hi"));
+        assert_eq!(messages[1].cursor.as_deref(), Some("replay;i=1"));
+    }
+}
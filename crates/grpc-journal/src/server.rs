@@ -1,26 +1,81 @@
 use std::collections::HashMap;
 use std::thread;
+use std::time::UNIX_EPOCH;
+use systemd::journal::{self, Journal, JournalRecord};
 use tokio_stream::wrappers::ReceiverStream;
 use tonic::{Request, Response, Status};
 use nca_error::NcaError;
-use crate::api::{LogMessage, LogFilter, journal_log_stream_server::JournalLogStream};
-use tokio::sync::mpsc::Receiver;
+use crate::export::{write_entry, ExportEntry};
+use crate::fanout::{LogMessageStream, SharedJournalReaders};
+#[cfg(not(feature = "mock"))]
+use crate::fanout::{resumed_stream, subscriber_stream};
+use crate::filter::LogMatcher;
+#[cfg(feature = "mock")]
+use crate::replay::ReplaySource;
+use crate::alerts::{alert_stream, start_alerting, AlertConfig, AlertStream};
+use crate::api::{Alert, AlertsRequest, Direction, ExportChunk, LogMessage, LogFilter, LogQuery, journal_log_stream_server::JournalLogStream};
+use tokio::sync::mpsc::{Receiver, Sender};
 
 #[derive(Debug, Default)]
 pub struct JournalLogStreamService {
     user_logs: bool,
-    system_logs: bool
+    system_logs: bool,
+    shared_readers: SharedJournalReaders,
+    alerts: Option<tokio::sync::broadcast::Sender<Alert>>,
+    #[cfg(feature = "mock")]
+    replay: ReplaySource,
 }
 
 impl JournalLogStreamService {
     pub fn new(user_logs: bool, system_logs: bool) -> JournalLogStreamService {
-        JournalLogStreamService { user_logs, system_logs }
+        JournalLogStreamService {
+            user_logs,
+            system_logs,
+            shared_readers: SharedJournalReaders::new(user_logs, system_logs),
+            alerts: None,
+            #[cfg(feature = "mock")]
+            replay: ReplaySource::from_env().unwrap_or_else(|e| {
+                eprintln!("Failed to configure journal replay, using the bundled fixture: {e}");
+                ReplaySource::default()
+            }),
+        }
+    }
+
+    /// Serves `Tail` requests from `replay` instead of the journal
+    #[cfg(feature = "mock")]
+    pub fn with_replay(mut self, replay: ReplaySource) -> JournalLogStreamService {
+        self.replay = replay;
+        self
+    }
+
+    pub async fn enable_alerts(&mut self, config: AlertConfig) -> Result<(), NcaError> {
+        self.alerts = Some(start_alerting(&self.shared_readers, config).await?);
+        Ok(())
+    }
+
+    /// Enables the alert rules configured in `$CONFIG_PATH/journal/alerts.json`, if any. Log
+    /// streaming keeps working if they can't be loaded.
+    pub async fn enable_configured_alerts(&mut self) {
+        let config_path = std::env::var("CONFIG_PATH").unwrap_or("/etc/ncatomic".to_string());
+        let result = match AlertConfig::load(&config_path) {
+            Ok(Some(config)) => self.enable_alerts(config).await.map(Some),
+            Ok(None) => Ok(None),
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(Some(())) => println!("Alerting enabled"),
+            Ok(None) => println!("No alert rules configured"),
+            Err(e) => eprintln!("Failed to enable alerting: {e}"),
+        }
     }
 }
 
 #[tonic::async_trait]
 impl JournalLogStream for JournalLogStreamService {
-    type TailStream = ReceiverStream<Result<LogMessage, Status>>;
+    type TailStream = LogMessageStream;
+    type QueryStream = ReceiverStream<Result<LogMessage, Status>>;
+    type ExportStream = ReceiverStream<Result<ExportChunk, Status>>;
+    type AlertsStream = AlertStream;
 
     #[cfg(not(feature = "mock"))]
     async fn tail(&self, request: Request<LogFilter>) -> Result<Response<Self::TailStream>, Status> {
@@ -30,11 +85,53 @@ impl JournalLogStream for JournalLogStreamService {
         }
 
         let filter = request.into_inner();
+        let matcher = LogMatcher::new(&filter)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+
+        // Subscribing before the replay starts makes sure no entry falls in between
+        let rx = self.shared_readers.subscribe(filter.namespace.clone()).await
+            .map_err(|e| Status::internal(e.to_string()))?;
+        match filter.after_cursor {
+            Some(cursor) => {
+                let replay = replay_after_cursor(self.user_logs, self.system_logs, filter.namespace.clone(), matcher.clone(), cursor).await;
+                Ok(Response::new(resumed_stream(replay, rx, matcher, filter.namespace)))
+            },
+            None => Ok(Response::new(subscriber_stream(rx, matcher, filter.namespace))),
+        }
+    }
 
-        let rx = get_log_stream(self.user_logs, self.system_logs, filter.namespace, Some(filter.fields)).await;
+    async fn query(&self, request: Request<LogQuery>) -> Result<Response<Self::QueryStream>, Status> {
+        let query = request.into_inner();
+        if let (Some(since), Some(until)) = (query.since, query.until) {
+            if since > until {
+                return Err(Status::invalid_argument("'since' must not be after 'until'"));
+            }
+        }
+        let matcher = LogMatcher::new(&query.filter.clone().unwrap_or_default())
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let rx = query_log_stream(self.user_logs, self.system_logs, query, matcher).await;
         Ok(Response::new(ReceiverStream::new(rx)))
     }
 
+    async fn export(&self, request: Request<LogQuery>) -> Result<Response<Self::ExportStream>, Status> {
+        let query = request.into_inner();
+        if let (Some(since), Some(until)) = (query.since, query.until) {
+            if since > until {
+                return Err(Status::invalid_argument("'since' must not be after 'until'"));
+            }
+        }
+        let matcher = LogMatcher::new(&query.filter.clone().unwrap_or_default())
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        let rx = export_log_stream(self.user_logs, self.system_logs, query, matcher).await;
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn alerts(&self, request: Request<AlertsRequest>) -> Result<Response<Self::AlertsStream>, Status> {
+        let alerts = self.alerts.as_ref()
+            .ok_or(Status::failed_precondition("No alert rules are configured"))?;
+        Ok(Response::new(alert_stream(alerts.subscribe(), request.into_inner().rules)))
+    }
+
     #[cfg(feature = "mock")]
     async fn tail(&self, request: Request<LogFilter>) -> Result<Response<Self::TailStream>, Status> {
         #[cfg(debug_assertions)]
@@ -42,148 +139,252 @@ impl JournalLogStream for JournalLogStreamService {
             println!("grpc::JournalLogStream: Received tail request: {:?}", request);
         }
 
-        let (tx, rx) = tokio::sync::mpsc::channel::<Result<LogMessage, Status>>(100);
-        thread::spawn(move || {
-            loop {
-                let msg = LogMessage {
-                    fields: HashMap::from([
-                        ("foo".to_string(), "bar".to_string()),
-                        ("_MESSAGE".to_string(), "hello world".to_string()),
-                        ("_SYSTEMD_UNIT".to_string(), "mock.service".to_string()),
-                        ("CONTAINER_NAME".to_string(), "mock-container".to_string())
-                    ]),
-                    message: "hello world".to_string(),
-                    namespace: None
-                };
-                if let Err(e) = tx.blocking_send(Ok(msg)) {
-                    panic!("Error while sending message: {e:?}");
-                }
-                sleep(Duration::from_secs(5));
-            }
-        });
-        Ok(Response::new(ReceiverStream::new(rx)))
+        let filter = request.into_inner();
+        let matcher = LogMatcher::new(&filter)
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        Ok(Response::new(self.replay.tail(matcher, filter.namespace, filter.after_cursor)))
     }
 }
 
-pub async fn get_log_stream(current_user: bool, system: bool, namespace: Option<String>, filter_fields: Option<HashMap<String, String>>) -> Receiver<Result<LogMessage, Status>> {
-    println!("getting log stream");
+const DEFAULT_QUERY_ENTRIES: u32 = 1000;
+const MAX_QUERY_ENTRIES: u32 = 10000;
+const MAX_EXPORT_ENTRIES: u32 = 200_000;
+const EXPORT_CHUNK_SIZE: usize = 64 * 1024;
 
-    let (tx, rx) = tokio::sync::mpsc::channel(100);
-    let default_msg = "<no message>".to_string();
+pub(crate) fn open_journal(current_user: bool, system: bool, namespace: &Option<String>) -> Result<Journal, NcaError> {
+    let mut opts = journal::OpenOptions::default()
+        .system(system)
+        .current_user(current_user)
+        .system(system).to_owned();
+    match namespace {
+        None => opts
+            .all_namespaces(true)
+            .open(),
+        Some(ns) => opts.open_namespace(ns)
+    }.map_err(|e| NcaError::new_io_error(format!("Couldn't open journal: {e:?}")))
+}
 
-    const KEY_UNIT: &str = "_SYSTEMD_UNIT";
-    const KEY_MESSAGE: &str = "MESSAGE";
+pub(crate) fn to_log_message(reader: &Journal, record: JournalRecord, namespace: &Option<String>) -> LogMessage {
+    let mut msg = LogMessage::from_fields(record.into_iter().collect(), namespace.clone());
+    msg.cursor = reader.cursor().ok();
+    msg.realtime_timestamp = realtime_usec(reader).ok();
+    if let Ok((monotonic_usec, boot_id)) = reader.monotonic_timestamp() {
+        msg.monotonic_timestamp = Some(monotonic_usec);
+        msg.boot_id = Some(boot_id.to_string());
+    }
+    msg
+}
 
-    thread::spawn(move || {
-        use systemd::journal::{self, JournalSeek};
-        let filter_fields = filter_fields.unwrap_or_default();
-        let mut opts = journal::OpenOptions::default()
-            .system(system)
-            .current_user(current_user)
-            .system(system).to_owned();
-        let reader_result = match &namespace {
-            None => opts
-                .all_namespaces(true)
-                .open(),
-            Some(ns) => opts.open_namespace(ns)
+fn realtime_usec(reader: &Journal) -> Result<u64, NcaError> {
+    reader.timestamp()
+        .map_err(|e| NcaError::new_io_error(format!("Failed to read journal timestamp: {e:?}")))?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros() as u64)
+        .map_err(|e| NcaError::new_io_error(format!("Invalid journal timestamp: {e:?}")))
+}
+
+/// Moves the reader onto the entry with `cursor`, so that the next entry read is the one after it.
+/// If the entry does not exist anymore, the reader is left in front of the closest entry.
+fn seek_after_cursor(reader: &mut Journal, cursor: &str) -> Result<(), NcaError> {
+    reader.seek_cursor(cursor)
+        .map_err(|e| NcaError::new_io_error(format!("Invalid journal cursor '{cursor}': {e:?}")))?;
+    let moved = reader.next()
+        .map_err(|e| NcaError::new_io_error(format!("Failed to read journal: {e:?}")))?;
+    if moved > 0 && !reader.test_cursor(cursor).unwrap_or(false) {
+        reader.previous()
+            .map_err(|e| NcaError::new_io_error(format!("Failed to read journal: {e:?}")))?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, PartialEq)]
+enum RangePosition {
+    Before,
+    Within,
+    After,
+}
+
+/// Where an entry logged at `usec` is relative to the queried time range, in query direction
+fn range_position(usec: u64, since: Option<u64>, until: Option<u64>, direction: Direction) -> RangePosition {
+    let too_old = since.is_some_and(|since| usec < since);
+    let too_new = until.is_some_and(|until| usec > until);
+    match (direction, too_old, too_new) {
+        (Direction::Forward, true, _) | (Direction::Backward, _, true) => RangePosition::Before,
+        (Direction::Forward, _, true) | (Direction::Backward, true, _) => RangePosition::After,
+        _ => RangePosition::Within,
+    }
+}
+
+enum Emitted {
+    Sent,
+    Filtered,
+    Stop,
+}
+
+/// Reads the entries selected by `query` (apart from its filter) and passes them to `emit`
+fn query_journal<F>(reader: &mut Journal, query: &LogQuery, max_entries: u32, mut emit: F) -> Result<(), NcaError>
+where
+    F: FnMut(&Journal, JournalRecord) -> Emitted
+{
+    let direction = query.direction();
+    match (&query.cursor, direction) {
+        (Some(cursor), _) => reader.seek_cursor(cursor)
+            .map_err(|e| NcaError::new_io_error(format!("Invalid journal cursor '{cursor}': {e:?}")))?,
+        (None, Direction::Forward) => match query.since {
+            Some(usec) => reader.seek_realtime_usec(usec),
+            None => reader.seek_head(),
+        }.map_err(|e| NcaError::new_io_error(format!("Failed to seek in journal: {e:?}")))?,
+        (None, Direction::Backward) => match query.until {
+            Some(usec) => reader.seek_realtime_usec(usec),
+            None => reader.seek_tail(),
+        }.map_err(|e| NcaError::new_io_error(format!("Failed to seek in journal: {e:?}")))?,
+    };
+
+    let mut skip_cursor = query.cursor.as_deref();
+    let mut sent = 0;
+    while sent < max_entries {
+        let record = match direction {
+            Direction::Forward => reader.next_entry(),
+            Direction::Backward => reader.previous_entry(),
+        }.map_err(|e| NcaError::new_io_error(format!("Failed to read journal: {e:?}")))?;
+        let Some(record) = record else {
+            break;
         };
-        let mut reader = match reader_result {
-            Err(e) => {
-                eprintln!("Couldn't open journal: {e:?}");
-                return;
+        // The entry at the cursor has been returned by the previous query already
+        if skip_cursor.take().is_some_and(|cursor| reader.test_cursor(cursor).unwrap_or(false)) {
+            continue;
+        }
+        match range_position(realtime_usec(reader)?, query.since, query.until, direction) {
+            RangePosition::Before => continue,
+            RangePosition::After => break,
+            RangePosition::Within => {},
+        }
+        match emit(reader, record) {
+            Emitted::Sent => sent += 1,
+            Emitted::Filtered => {},
+            Emitted::Stop => {
+                eprintln!("Receiver has gone away, exiting ...");
+                break;
             },
-            Ok(reader) => reader
+        }
+    }
+    Ok(())
+}
+
+/// The entry with the address fields (`__CURSOR`, timestamps, boot id) added, as exported by
+/// `journalctl -o export`
+fn to_export_entry(reader: &Journal, record: JournalRecord) -> Result<ExportEntry, NcaError> {
+    let mut entry = record;
+    entry.insert("__CURSOR".to_string(), reader.cursor()
+        .map_err(|e| NcaError::new_io_error(format!("Failed to read journal cursor: {e:?}")))?);
+    entry.insert("__REALTIME_TIMESTAMP".to_string(), realtime_usec(reader)?.to_string());
+    let (monotonic_usec, boot_id) = reader.monotonic_timestamp()
+        .map_err(|e| NcaError::new_io_error(format!("Failed to read journal timestamp: {e:?}")))?;
+    entry.insert("__MONOTONIC_TIMESTAMP".to_string(), monotonic_usec.to_string());
+    entry.insert("_BOOT_ID".to_string(), boot_id.to_string());
+    Ok(entry)
+}
+
+fn send_error<T>(tx: &Sender<Result<T, Status>>, e: NcaError) {
+    eprintln!("{e}");
+    if let Err(e2) = tx.blocking_send(Err(Status::internal(e.to_string()))) {
+        eprintln!("Also, an error occurred while cancelling stream: {e2:?}");
+    }
+}
+
+pub async fn export_log_stream(current_user: bool, system: bool, query: LogQuery, matcher: LogMatcher) -> Receiver<Result<ExportChunk, Status>> {
+    let (tx, rx) = tokio::sync::mpsc::channel(16);
+    thread::spawn(move || {
+        let filter = query.filter.clone().unwrap_or_default();
+        let mut reader = match open_journal(current_user, system, &filter.namespace) {
+            Err(e) => return send_error(&tx, e),
+            Ok(reader) => reader,
         };
-        // TODO: Fix filtering by fields and reenable
-        for (k, v) in filter_fields.iter() {
-            if let Err(e) = reader.match_add(k, v.as_str()) {
-                eprintln!("Error adding filter: {e:?}");
+        let mut chunk = Vec::new();
+        let mut export_error = None;
+        let max_entries = query.max_entries.unwrap_or(MAX_EXPORT_ENTRIES).min(MAX_EXPORT_ENTRIES);
+        let result = query_journal(&mut reader, &query, max_entries, |reader, record| {
+            let fields = record.iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect::<HashMap<String, String>>();
+            if !matcher.matches(&fields) {
+                return Emitted::Filtered;
+            }
+            match to_export_entry(reader, record) {
+                Err(e) => {
+                    export_error = Some(e);
+                    return Emitted::Stop;
+                },
+                Ok(entry) => write_entry(&mut chunk, &entry),
             }
+            if chunk.len() >= EXPORT_CHUNK_SIZE && tx.blocking_send(Ok(ExportChunk { data: std::mem::take(&mut chunk) })).is_err() {
+                return Emitted::Stop;
+            }
+            Emitted::Sent
+        });
+        if let Some(e) = export_error.or(result.err()) {
+            return send_error(&tx, e);
+        }
+        if !chunk.is_empty() && tx.blocking_send(Ok(ExportChunk { data: chunk })).is_err() {
+            eprintln!("Receiver has gone away, exiting ...");
         }
+    });
+    rx
+}
+
+pub async fn query_log_stream(current_user: bool, system: bool, query: LogQuery, matcher: LogMatcher) -> Receiver<Result<LogMessage, Status>> {
+    let (tx, rx) = tokio::sync::mpsc::channel(100);
+    thread::spawn(move || {
+        let filter = query.filter.clone().unwrap_or_default();
+        let max_entries = query.max_entries.unwrap_or(DEFAULT_QUERY_ENTRIES).min(MAX_QUERY_ENTRIES);
+        let result = open_journal(current_user, system, &filter.namespace)
+            .and_then(|mut reader| query_journal(&mut reader, &query, max_entries, |reader, record| {
+                let log_msg = to_log_message(reader, record, &filter.namespace);
+                if !matcher.matches(&log_msg.fields) {
+                    return Emitted::Filtered;
+                }
+                match tx.blocking_send(Ok(log_msg)) {
+                    Ok(_) => Emitted::Sent,
+                    Err(_) => Emitted::Stop,
+                }
+            }));
+        if let Err(e) = result {
+            send_error(&tx, e);
+        }
+    });
+    rx
+}
 
-        reader.seek(JournalSeek::Tail).expect("Couldn't seek to end of journal");
-        if let Err(e) = reader.previous().map_err(|e| NcaError::IOError(e.to_string())) {
+/// The entries after `cursor` up to the current end of the journal. The thread exits at the end of
+/// the journal or as soon as the receiver is dropped.
+pub async fn replay_after_cursor(current_user: bool, system: bool, namespace: Option<String>, matcher: LogMatcher, cursor: String) -> Receiver<Result<LogMessage, Status>> {
+    let (tx, rx) = tokio::sync::mpsc::channel(100);
+    thread::spawn(move || {
+        let mut reader = match open_journal(current_user, system, &namespace) {
+            Err(e) => return send_error(&tx, e),
+            Ok(reader) => reader,
+        };
+        if let Err(e) = seek_after_cursor(&mut reader, &cursor) {
             eprintln!("{e:?}");
+            if let Err(e2) = tx.blocking_send(Err(Status::invalid_argument(e.to_string()))) {
+                eprintln!("Also, an error occurred while cancelling stream: {e2:?}");
+            }
             return;
-        };
-
-        let mut abort = false;
+        }
         loop {
-            loop {
-                match reader.next() {
-                    Ok(0) => {
-                        // if let Err(e) = tx.blocking_send(Err(Status::cancelled("no more messages found"))) {
-                        //     eprintln!("An error occurred while cancelling stream: {e:?}");
-                        // }
-                        break;
-                    },
-                    Ok(_) => {},
-                    Err(e) => {
-                        if let Err(e2) = tx.blocking_send(Err(Status::cancelled("unexpected error"))) {
-                            eprintln!("Unexpected error: {e:?}\n Also, an error occurred while cancelling stream: {e2:?}");
-                        }
-                        eprintln!("Unexpected error: {e:?}");
-                        abort = true;
-                        break;
+            match reader.next_entry() {
+                Err(e) => return send_error(&tx, NcaError::new_io_error(format!("Failed to read journal: {e:?}"))),
+                Ok(None) => return,
+                Ok(Some(record)) => {
+                    let log_msg = to_log_message(&reader, record, &namespace);
+                    if matcher.matches(&log_msg.fields) && tx.blocking_send(Ok(log_msg)).is_err() {
+                        eprintln!("Receiver has gone away, exiting ...");
+                        return;
                     }
                 }
-
-                match reader.await_next_entry(None) {
-                    Err(e) => {
-                        if let Err(e2) = tx.blocking_send(Err(Status::cancelled("Unexpected error while waiting for journal entries"))) {
-                            eprintln!("Unexpected error while waiting for journal entries: {e:?}\nAlso an error occurred while cancelling stream: {e2:?}");
-                        }
-                        eprintln!("Unexpected error while waiting for journal entries: {e:?}");
-                        abort = true;
-                        break;
-                    },
-                    Ok(None) => {
-                        break;
-                        #[allow(unreachable_code)]
-                        if let Err(e) = tx.blocking_send(Err(Status::cancelled("Unexpected end of journal"))) {
-                            eprintln!("Unexpected end of journal\nAlso, an error occurred while cancelling stream: {e:?}");
-                        }
-                        eprintln!("Unexpected end of journal");
-                        abort = true;
-                        break;
-                    },
-                    Ok(Some(record)) => {
-                        // if !filter_fields.iter().all(|(k, v)| {
-                        //     match record.get(k) {
-                        //         None => false,
-                        //         Some(val) => val == v,
-                        //     }
-                        // }) {
-                        //     continue;
-                        // }
-                        let record_map = record.into_iter().collect::<HashMap<String, String>>();
-                        let msg = record_map.get(KEY_MESSAGE).unwrap_or(&default_msg).clone();
-                        #[cfg(debug_assertions)]
-                        {
-                            println!("Sending journal msg: {msg}");
-                        }
-                        let log_msg = LogMessage {
-                            fields: record_map,
-                            message: msg,
-                            namespace: namespace.clone(),
-                        };
-                        if let Err(e) = tx.blocking_send(Ok(log_msg)) {
-                            eprintln!("Receiver has gone away, exiting ... ({e:?})");
-                            abort = true;
-                            break;
-                        }
-                    }
-                }
-            }
-            if abort {
-                break;
-            }
-            if let Err(e) = reader.wait(None) {
-                eprintln!("Unexpected error while waiting for journal entries: {e:?}");
             }
-        };
+        }
     });
-
     rx
 }
 
@@ -201,7 +402,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_get_log_stream() {
-        let mut rx = get_log_stream(true, true, None, None).await;
+        let mut rx = SharedJournalReaders::new(true, true).subscribe(None).await.unwrap();
         let default_msg = String::from("<no message>");
         let empty_string = String::new();
         for _ in 0..4 {
@@ -214,6 +415,43 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_range_position() {
+        assert_eq!(range_position(5, Some(1), Some(10), Direction::Forward), RangePosition::Within);
+        assert_eq!(range_position(5, Some(5), Some(5), Direction::Backward), RangePosition::Within);
+        assert_eq!(range_position(0, Some(1), None, Direction::Forward), RangePosition::Before);
+        assert_eq!(range_position(11, None, Some(10), Direction::Forward), RangePosition::After);
+        assert_eq!(range_position(11, None, Some(10), Direction::Backward), RangePosition::Before);
+        assert_eq!(range_position(0, Some(1), None, Direction::Backward), RangePosition::After);
+    }
+
+    #[tokio::test]
+    async fn test_query_log_stream() {
+        let mut rx = query_log_stream(true, true, LogQuery {
+            max_entries: Some(5),
+            direction: Direction::Backward.into(),
+            ..LogQuery::default()
+        }, LogMatcher::default()).await;
+        let mut cursors = Vec::new();
+        while let Some(msg) = rx.recv().await {
+            cursors.push(msg.unwrap().cursor.expect("journal entry without cursor"));
+        }
+        assert!(cursors.len() <= 5);
+
+        // paginating from the oldest entry continues without overlap
+        if let Some(oldest) = cursors.last() {
+            let mut rx = query_log_stream(true, true, LogQuery {
+                cursor: Some(oldest.clone()),
+                max_entries: Some(5),
+                direction: Direction::Backward.into(),
+                ..LogQuery::default()
+            }, LogMatcher::default()).await;
+            while let Some(msg) = rx.recv().await {
+                assert!(!cursors.contains(&msg.unwrap().cursor.unwrap()));
+            }
+        }
+    }
+
     #[tokio::test]
     async fn test_consume_log_stream_grpc() {
         let (client, server) = tokio::io::duplex(1024);
@@ -244,6 +482,7 @@ mod tests {
         let request = tonic::Request::new(LogFilter {
             fields: HashMap::from([]),
             namespace: None,
+            ..LogFilter::default()
         });
         let stream = client.tail(request).await.unwrap().into_inner();
 
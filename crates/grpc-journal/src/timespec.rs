@@ -0,0 +1,64 @@
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone};
+
+fn parse_duration(spec: &str) -> Option<Duration> {
+    let split = spec.find(|c: char| !c.is_ascii_digit())?;
+    let (amount, unit) = spec.split_at(split);
+    let amount: i64 = amount.parse().ok()?;
+    match unit.trim() {
+        "s" | "sec" | "secs" | "second" | "seconds" => Some(Duration::seconds(amount)),
+        "m" | "min" | "mins" | "minute" | "minutes" => Some(Duration::minutes(amount)),
+        "h" | "hour" | "hours" => Some(Duration::hours(amount)),
+        "d" | "day" | "days" => Some(Duration::days(amount)),
+        "w" | "week" | "weeks" => Some(Duration::weeks(amount)),
+        _ => None,
+    }
+}
+
+fn local_midnight(date: NaiveDate) -> Option<DateTime<Local>> {
+    Local.from_local_datetime(&date.and_time(NaiveTime::MIN)).earliest()
+}
+
+/// Parses a point in time like `journalctl --since` does: `now`, `today`, `yesterday`,
+/// `YYYY-MM-DD [HH:MM[:SS]]` (local time), RFC 3339, or relative to `now` as `-2h` or `30 min ago`.
+/// Returns microseconds since the epoch.
+pub fn parse_time_spec(spec: &str, now: DateTime<Local>) -> Result<u64, String> {
+    let spec = spec.trim();
+    let time = match spec {
+        "now" => Some(now),
+        "today" => local_midnight(now.date_naive()),
+        "yesterday" => now.date_naive().pred_opt().and_then(local_midnight),
+        _ => None,
+    }
+        .or_else(|| spec.strip_prefix('-')
+            .or_else(|| spec.strip_suffix("ago"))
+            .and_then(|relative| parse_duration(relative.trim()))
+            .map(|duration| now - duration))
+        .or_else(|| DateTime::parse_from_rfc3339(spec).ok().map(|t| t.with_timezone(&Local)))
+        .or_else(|| ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"].iter()
+            .find_map(|format| NaiveDateTime::parse_from_str(spec, format).ok())
+            .and_then(|t| Local.from_local_datetime(&t).earliest()))
+        .or_else(|| NaiveDate::parse_from_str(spec, "%Y-%m-%d").ok().and_then(local_midnight))
+        .ok_or(format!("Invalid time '{spec}'"))?;
+    u64::try_from(time.timestamp_micros())
+        .map_err(|_| format!("Time '{spec}' is before the epoch"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_time_spec() {
+        let now = Local.with_ymd_and_hms(2025, 10, 18, 12, 30, 0).unwrap();
+        let usec = |t: DateTime<Local>| t.timestamp_micros() as u64;
+        assert_eq!(parse_time_spec("now", now), Ok(usec(now)));
+        assert_eq!(parse_time_spec("-2h", now), Ok(usec(now - Duration::hours(2))));
+        assert_eq!(parse_time_spec("30 min ago", now), Ok(usec(now - Duration::minutes(30))));
+        assert_eq!(parse_time_spec("today", now), Ok(usec(Local.with_ymd_and_hms(2025, 10, 18, 0, 0, 0).unwrap())));
+        assert_eq!(parse_time_spec("yesterday", now), Ok(usec(Local.with_ymd_and_hms(2025, 10, 17, 0, 0, 0).unwrap())));
+        assert_eq!(parse_time_spec("2025-10-01 08:15", now), Ok(usec(Local.with_ymd_and_hms(2025, 10, 1, 8, 15, 0).unwrap())));
+        assert_eq!(parse_time_spec("2025-10-01", now), Ok(usec(Local.with_ymd_and_hms(2025, 10, 1, 0, 0, 0).unwrap())));
+        assert_eq!(parse_time_spec("2025-10-01T08:15:00Z", now), Ok(1759306500000000));
+        assert!(parse_time_spec("last tuesday", now).is_err());
+    }
+}
@@ -2,11 +2,14 @@ use clap::{Args, Parser, Subcommand};
 use tonic::Request;
 use grpc_common::client::{retrieve_grpc_channel};
 use grpc_nca_system::api;
+use grpc_nca_system::api::backup_client::BackupClient;
 use grpc_nca_system::api::credentials_client::CredentialsClient;
 use grpc_nca_system::api::Empty;
 use grpc_nca_system::api::nextcloud_client::NextcloudClient;
 use grpc_nca_system::api::storage_client::StorageClient;
 use grpc_nca_system::api::system_client::SystemClient;
+use nca_system_api::occ::apps::api::{apply_nc_apps_profile, list_nc_apps, run_nc_app_action};
+use nca_system_api::occ::apps::types::{AppAction, AppsProfile};
 
 #[derive(Parser)]
 struct Cli {
@@ -19,7 +22,8 @@ enum Commands {
     Storage (StorageArgs),
     Credentials(CredentialsArgs),
     Nextcloud(NextcloudArgs),
-    System(SystemArgs)
+    System(SystemArgs),
+    Backup(BackupArgs)
 }
 
 #[derive(Args)]
@@ -32,6 +36,60 @@ struct StorageArgs {
 enum StorageCommands {
     AddPassword {
         primary_password: String,
+    },
+    PrepareDataDisk {
+        device: String,
+        primary_password: String,
+        /// The token returned by a previous call for the same device
+        #[arg(long)]
+        confirm: Option<String>,
+        /// Mirror the data onto this device
+        #[arg(long)]
+        mirror: Vec<String>,
+    },
+    AddDataDisk {
+        device: String,
+        primary_password: String,
+        #[arg(long)]
+        confirm: Option<String>,
+    },
+    ReplaceDataDisk {
+        failed_device: String,
+        new_device: String,
+        primary_password: String,
+        #[arg(long)]
+        confirm: Option<String>,
+    },
+    PoolStatus,
+    KeySlots {
+        device: String,
+    },
+    RemoveKeySlot {
+        device: String,
+        slot: u32,
+        primary_password: String,
+    },
+    AddRecoveryKey {
+        device: String,
+        primary_password: String,
+    },
+    /// Re-enroll the TPM2 tokens of all encrypted disks against the current PCR values
+    ResealTpm2 {
+        /// Use the primary password instead of the stored credentials
+        #[arg(long)]
+        primary_password: Option<String>,
+        /// Skip disks that can still be unlocked with their TPM2 token
+        #[arg(long)]
+        if_needed: bool,
+    },
+    Status,
+    SetThresholds {
+        warning_percent: u32,
+        critical_percent: u32,
+        #[arg(long, default_value_t = 80)]
+        inodes_warning_percent: u32,
+        #[arg(long, default_value_t = 90)]
+        inodes_critical_percent: u32,
     }
 }
 
@@ -52,6 +110,10 @@ enum CredentialsCommands {
     },
     Init {
         primary_password: String
+    },
+    RotatePrimaryPassword {
+        old_password: String,
+        new_password: String
     }
 }
 
@@ -67,7 +129,45 @@ enum NextcloudCommands {
         domain: Option<String>,
         admin_password: Option<String>,
     },
-    HardReset
+    HardReset,
+    Apps(AppsArgs)
+}
+
+#[derive(Args)]
+struct AppsArgs {
+    #[command(subcommand)]
+    command: AppsCommands
+}
+
+#[derive(Subcommand)]
+enum AppsCommands {
+    List,
+    Install {
+        app: String,
+        #[arg(long)]
+        keep_disabled: bool,
+    },
+    Enable {
+        app: String,
+    },
+    Disable {
+        app: String,
+    },
+    /// Update the app, or all apps if none is given
+    Update {
+        app: Option<String>,
+    },
+    Remove {
+        app: String,
+    },
+    /// Install, enable, disable and remove apps as listed in a JSON profile
+    /// (`{"enabled": [...], "disabled": [...], "removed": [...]}`)
+    Apply {
+        profile: String,
+        /// Only show what would be done
+        #[arg(long)]
+        dry_run: bool,
+    }
 }
 
 #[derive(Args)]
@@ -81,6 +181,113 @@ enum SystemCommands {
     UnlockFromSystemCredentials
 }
 
+#[derive(Args)]
+struct BackupArgs {
+    #[command(subcommand)]
+    command: BackupCommands
+}
+
+#[derive(Subcommand)]
+enum BackupCommands {
+    Configure {
+        #[arg(long, default_value = "/var/data/ncatomic/backups")]
+        repository_path: String,
+        #[arg(long, default_value_t = 7)]
+        keep_daily: u32,
+        #[arg(long, default_value_t = 4)]
+        keep_weekly: u32,
+        #[arg(long, default_value_t = 6)]
+        keep_monthly: u32,
+    },
+    Create,
+    List,
+    Delete {
+        id: String
+    },
+    Status,
+    Restore {
+        id: String,
+        primary_password: String,
+        /// Only verify the backup and show which files would be changed
+        #[arg(long)]
+        dry_run: bool,
+        /// The salt of the instance the backup was created on (only needed during setup)
+        #[arg(long)]
+        salt: Option<String>,
+        #[arg(long)]
+        repository_path: Option<String>,
+    }
+}
+
+fn data_disk_result(devices: &str, response: api::DataDiskResponse) -> Result<String, String> {
+    match (response.prepared, response.confirmation_token) {
+        (true, _) => Ok(format!("Successfully set up {devices} as data disk (mounted at {})",
+                                response.mount_point.unwrap_or_default())),
+        (false, Some(token)) => Ok(format!("All data on {devices} will be erased! Repeat the command with '--confirm {token}' within 5 minutes to continue.")),
+        (false, None) => Err("Unexpected response from server".to_string()),
+    }
+}
+
+fn format_app_action(action: &AppAction) -> String {
+    action.occ_args().join(" ")
+}
+
+async fn run_apps_command(command: AppsCommands) -> Result<String, String> {
+    let channel = retrieve_grpc_channel(
+        "OCC_SERVER_ADDRESS",
+        "NCATOMIC_SOCKETS_PATH",
+        "/run/ncatomic",
+        "occ.sock",
+        "http://occ.nextcloudatomic.local"
+    ).await
+        .map_err(|e| e.to_string())?;
+
+    let action = match command {
+        AppsCommands::List => {
+            let apps = list_nc_apps(channel).await.map_err(|e| e.to_string())?;
+            return Ok(apps.iter()
+                .map(|app| format!("{:<30} {:<12} {}", app.id, app.version.as_deref().unwrap_or("-"),
+                                   if app.enabled { "enabled" } else { "disabled" }))
+                .collect::<Vec<_>>()
+                .join("\n"));
+        },
+        AppsCommands::Apply { profile, dry_run } => {
+            let profile: AppsProfile = std::fs::read_to_string(&profile)
+                .map_err(|e| format!("Failed to read {profile}: {e}"))
+                .and_then(|data| serde_json::from_str(&data).map_err(|e| format!("Invalid apps profile: {e}")))?;
+            let actions = apply_nc_apps_profile(channel, &profile, dry_run).await
+                .map_err(|e| e.to_string())?;
+            let actions = actions.iter().map(format_app_action).collect::<Vec<_>>();
+            return Ok(match (actions.is_empty(), dry_run) {
+                (true, _) => "All apps are as described in the profile".to_string(),
+                (false, true) => format!("Would run:\n{}", actions.join("\n")),
+                (false, false) => format!("Successfully ran:\n{}", actions.join("\n")),
+            });
+        },
+        AppsCommands::Install { app, keep_disabled: false } => AppAction::Install(app),
+        AppsCommands::Install { app, keep_disabled: true } => AppAction::InstallDisabled(app),
+        AppsCommands::Enable { app } => AppAction::Enable(app),
+        AppsCommands::Disable { app } => AppAction::Disable(app),
+        AppsCommands::Update { app } => AppAction::Update(app),
+        AppsCommands::Remove { app } => AppAction::Remove(app),
+    };
+    run_nc_app_action(channel, &action).await
+        .map_err(|e| e.to_string())
+}
+
+fn format_key_slots(slots: &api::KeySlotList) -> String {
+    slots.slots.iter()
+        .map(|s| {
+            let kind = s.r#type().as_str_name().trim_start_matches("KEY_SLOT_").to_lowercase();
+            match s.tpm2_pcrs.is_empty() {
+                true => format!("{}: {kind}", s.slot),
+                false => format!("{}: {kind} (PCRs {:?})", s.slot, s.tpm2_pcrs),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 #[tokio::main]
 async fn main() -> Result<(), String> {
     let cli = Cli::parse();
@@ -119,6 +326,16 @@ async fn main() -> Result<(), String> {
                     client.initialize_credentials(Request::new(api::PrimaryPassword{value: primary_password})).await
                         .map_err(|e| e.to_string())?;
                     Ok("Successfully initialized credentials".to_string())
+                },
+                CredentialsCommands::RotatePrimaryPassword {old_password, new_password} => {
+                    let credentials = client.rotate_primary_password(Request::new(api::PrimaryPasswordRotation{
+                        old_password,
+                        new_password
+                    })).await
+                        .map_err(|e| e.to_string())?
+                        .into_inner();
+                    Ok(format!("Successfully rotated primary password. New disk encryption recovery password: '{}'",
+                               credentials.disk_encryption_recovery_password))
                 }
             }
         },
@@ -131,6 +348,101 @@ async fn main() -> Result<(), String> {
                     let pw_result = client.add_disk_encryption_password(Request::new(api::PrimaryPassword { value: primary_password })).await
                         .map_err(|e| e.to_string())?;
                     Ok::<String, String>(format!("Successfully added disk encryption password to disks: '{}'", pw_result.into_inner().password))
+                },
+                StorageCommands::PrepareDataDisk { device, primary_password, confirm, mirror } => {
+                    let devices = std::iter::once(device.clone()).chain(mirror.clone()).collect::<Vec<_>>().join(", ");
+                    let response = client.prepare_data_disk(Request::new(api::DataDiskRequest {
+                        device,
+                        primary_password,
+                        confirmation_token: confirm,
+                        mirror_devices: mirror,
+                    })).await
+                        .map_err(|e| e.to_string())?
+                        .into_inner();
+                    data_disk_result(&devices, response)
+                },
+                StorageCommands::AddDataDisk { device, primary_password, confirm } => {
+                    let response = client.add_data_disk(Request::new(api::DataDiskRequest {
+                        device: device.clone(),
+                        primary_password,
+                        confirmation_token: confirm,
+                        mirror_devices: vec![],
+                    })).await
+                        .map_err(|e| e.to_string())?
+                        .into_inner();
+                    data_disk_result(&device, response)
+                },
+                StorageCommands::ReplaceDataDisk { failed_device, new_device, primary_password, confirm } => {
+                    let response = client.replace_data_disk(Request::new(api::ReplaceDataDiskRequest {
+                        failed_device,
+                        new_device: new_device.clone(),
+                        primary_password,
+                        confirmation_token: confirm,
+                    })).await
+                        .map_err(|e| e.to_string())?
+                        .into_inner();
+                    data_disk_result(&new_device, response)
+                },
+                StorageCommands::KeySlots { device } => {
+                    let slots = client.list_key_slots(Request::new(api::EncryptedDevice { device })).await
+                        .map_err(|e| e.to_string())?
+                        .into_inner();
+                    Ok(format_key_slots(&slots))
+                },
+                StorageCommands::RemoveKeySlot { device, slot, primary_password } => {
+                    let slots = client.remove_key_slot(Request::new(api::RemoveKeySlotRequest { device, slot, primary_password })).await
+                        .map_err(|e| e.to_string())?
+                        .into_inner();
+                    Ok(format!("Successfully removed key slot {slot}. Remaining key slots:\n{}", format_key_slots(&slots)))
+                },
+                StorageCommands::AddRecoveryKey { device, primary_password } => {
+                    let key = client.add_recovery_key(Request::new(api::RecoveryKeyRequest { device, primary_password })).await
+                        .map_err(|e| e.to_string())?
+                        .into_inner();
+                    Ok(format!("Recovery key for {}: {}\nStore it in a safe place, it will not be shown again!", key.device, key.recovery_key))
+                },
+                StorageCommands::ResealTpm2 { primary_password, if_needed } => {
+                    let report = client.reseal_tpm2(Request::new(api::ResealTpm2Request {
+                        primary_password,
+                        only_if_needed: if_needed,
+                    })).await
+                        .map_err(|e| e.to_string())?
+                        .into_inner();
+                    let lines = report.devices.iter()
+                        .map(|r| match r.outcome() {
+                            api::ResealOutcome::ResealSucceeded => format!("{}: resealed", r.device),
+                            api::ResealOutcome::ResealNotNeeded => format!("{}: unchanged", r.device),
+                            api::ResealOutcome::ResealFailed => format!("{}: FAILED ({})", r.device, r.error.as_deref().unwrap_or_default()),
+                        })
+                        .collect::<Vec<_>>()
+                        .join("\n");
+                    match report.devices.iter().any(|r| r.outcome() == api::ResealOutcome::ResealFailed) {
+                        true => Err(lines),
+                        false => Ok(lines),
+                    }
+                },
+                StorageCommands::PoolStatus => {
+                    let status = client.get_data_pool_status(Request::new(Empty{})).await
+                        .map_err(|e| e.to_string())?
+                        .into_inner();
+                    Ok(format!("{status:#?}"))
+                },
+                StorageCommands::Status => {
+                    let status = client.get_storage_status(Request::new(Empty{})).await
+                        .map_err(|e| e.to_string())?
+                        .into_inner();
+                    Ok(format!("{status:#?}"))
+                },
+                StorageCommands::SetThresholds { warning_percent, critical_percent, inodes_warning_percent, inodes_critical_percent } => {
+                    let thresholds = client.configure_storage_thresholds(Request::new(api::StorageThresholds {
+                        warning_percent,
+                        critical_percent,
+                        inodes_warning_percent,
+                        inodes_critical_percent,
+                    })).await
+                        .map_err(|e| e.to_string())?
+                        .into_inner();
+                    Ok(format!("Successfully updated storage thresholds: {thresholds:?}"))
                 }
             }
         },
@@ -155,7 +467,8 @@ async fn main() -> Result<(), String> {
                         format!("Successfully performed Nextcloud hard reset: \n{} --- \n{}",
                                 result.stdout.unwrap_or("".to_string()),
                                 result.stderr.unwrap_or("".to_string())))
-                }
+                },
+                NextcloudCommands::Apps(args) => run_apps_command(args.command).await,
             }
         },
         Commands::System(args) => {
@@ -169,6 +482,94 @@ async fn main() -> Result<(), String> {
                 }
             }
         }
+        Commands::Backup(args) => {
+            let mut client = BackupClient::new(channel);
+            match args.command {
+                BackupCommands::Configure { repository_path, keep_daily, keep_weekly, keep_monthly } => {
+                    let settings = client.configure_backup(Request::new(api::BackupSettings {
+                        repository_path,
+                        keep_daily,
+                        keep_weekly,
+                        keep_monthly
+                    })).await
+                        .map_err(|e| e.to_string())?
+                        .into_inner();
+                    Ok::<String, String>(format!("Backups will be stored at '{}' (keeping {} daily, {} weekly and {} monthly backups)",
+                                                 settings.repository_path, settings.keep_daily, settings.keep_weekly, settings.keep_monthly))
+                },
+                BackupCommands::Create => {
+                    let mut stream = client.create_backup(Request::new(Empty{})).await
+                        .map_err(|e| e.to_string())?
+                        .into_inner();
+                    let mut snapshot_id = None;
+                    while let Some(progress) = stream.message().await.map_err(|e| e.to_string())? {
+                        if let Some(msg) = &progress.message {
+                            eprintln!("{msg}");
+                        }
+                        match progress.phase() {
+                            api::BackupPhase::CreatingSnapshot => println!(
+                                "{:>5.1}% ({}/{} files)", progress.percent_done * 100.0, progress.files_done, progress.total_files),
+                            api::BackupPhase::Completed => snapshot_id = progress.snapshot_id,
+                            phase => println!("{}", phase.as_str_name()),
+                        }
+                    }
+                    Ok(format!("Successfully created backup {}", snapshot_id.unwrap_or_default()))
+                },
+                BackupCommands::List => {
+                    let backups = client.list_backups(Request::new(Empty{})).await
+                        .map_err(|e| e.to_string())?
+                        .into_inner()
+                        .backups;
+                    Ok(backups.iter()
+                        .map(|b| format!("{}  {}  {}", b.short_id, b.time, b.size.map(|s| format!("{s} bytes")).unwrap_or_default()))
+                        .collect::<Vec<_>>()
+                        .join("\n"))
+                },
+                BackupCommands::Delete { id } => {
+                    client.delete_backup(Request::new(api::BackupId { id: id.clone() })).await
+                        .map_err(|e| e.to_string())?;
+                    Ok(format!("Successfully deleted backup {id}"))
+                },
+                BackupCommands::Status => {
+                    let status = client.get_backup_status(Request::new(Empty{})).await
+                        .map_err(|e| e.to_string())?
+                        .into_inner();
+                    Ok(format!("{status:#?}"))
+                },
+                BackupCommands::Restore { id, primary_password, dry_run, salt, repository_path } => {
+                    let mut stream = client.restore_backup(Request::new(api::RestoreRequest {
+                        backup_id: id.clone(),
+                        primary_password,
+                        dry_run,
+                        salt,
+                        repository_path
+                    })).await
+                        .map_err(|e| e.to_string())?
+                        .into_inner();
+                    let mut changes = 0;
+                    while let Some(progress) = stream.message().await.map_err(|e| e.to_string())? {
+                        if let Some(msg) = &progress.message {
+                            eprintln!("{msg}");
+                        }
+                        if let Some(change) = &progress.change {
+                            changes += 1;
+                            println!("{:<10} {}", change.action, change.path);
+                            continue;
+                        }
+                        match progress.phase() {
+                            api::RestorePhase::RestoringFiles => println!(
+                                "{:>5.1}% ({}/{} files)", progress.percent_done * 100.0, progress.files_done, progress.total_files),
+                            api::RestorePhase::RestoreCompleted => {},
+                            phase => println!("{}", phase.as_str_name()),
+                        }
+                    }
+                    match dry_run {
+                        true => Ok(format!("Backup {id} verified successfully, restoring it would change {changes} files")),
+                        false => Ok(format!("Successfully restored backup {id}")),
+                    }
+                }
+            }
+        }
     }?;
 
     println!("{success_msg}");
@@ -64,6 +64,14 @@ pub fn generate_nonce() -> Nonce {
     buf
 }
 
+/// A random, base32 encoded token
+pub fn generate_token() -> String {
+    let rng = ring::rand::SystemRandom::new();
+    let mut buf = [0; 20];
+    rng.fill(&mut buf).expect("Unexpectedly failed to fill token buffer");
+    b32_encode(&buf)
+}
+
 pub fn create_key_from_pass(salt: &Salt, pass: String) -> AesKey {
     let mut buf = [0u8; KEK_LENGTH];
     pbkdf2::derive(PBKDF2_HMAC_SHA512, NonZeroU32::new(100_000).unwrap(), salt, pass.as_bytes(), &mut buf);
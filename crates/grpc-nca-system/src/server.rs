@@ -1,4 +1,9 @@
 pub mod service;
 pub mod config;
 pub mod storage;
+pub mod backup;
+pub mod disks;
+pub mod key_slots;
+pub mod data_disk;
+pub mod storage_status;
 mod util;
@@ -0,0 +1,603 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{ChildStderr, Command, ExitStatus, Output, Stdio};
+use std::thread;
+use std::time::Duration;
+use serde::Deserialize;
+use nca_error::NcaError;
+use crate::api::{BackupInfo, BackupPhase, BackupProgress, FileChange, RestorePhase, RestoreProgress};
+use crate::server::config::backup::RetentionPolicy;
+
+const RESTIC_PATH: &str = "/usr/bin/restic";
+pub(crate) const NC_AIO_DATA_PATH: &str = "/var/data/ncatomic/nc-aio";
+const DATABASE_DUMP_PATH: &str = "/var/data/ncatomic/nc-aio/database-dump/nextcloud.sql";
+const DATABASE_CONTAINER: &str = "nc-aio_nextcloud-aio-database_1";
+const DATABASE_USER: &str = "nextcloud";
+const DATABASE_NAME: &str = "nextcloud_database";
+const DATABASE_STARTUP_ATTEMPTS: u32 = 90;
+const NEXTCLOUD_CONTAINER: &str = "nc-aio_nextcloud-aio-nextcloud_1";
+// Nextcloud merges all `*.config.php` files of its config directory into its configuration
+const RESTORE_CONFIG_PATH: &str = "/var/data/ncatomic/nc-aio/nextcloud/config/ncatomic-restore.config.php";
+const RESTORE_CONFIG: &str = "<?php\n$CONFIG = array (\n  'maintenance' => true,\n);\n";
+const BACKUP_TAG: &str = "ncatomic";
+const SALT_FILE: &str = "ncatomic_salt.txt";
+// Only the end of restic's error output is kept for error messages
+const STDERR_TAIL_SIZE: usize = 16 * 1024;
+
+/// Reads `stderr` until it is closed, so the process never blocks on a full pipe, and returns the
+/// last `STDERR_TAIL_SIZE` bytes
+fn drain_stderr(mut stderr: ChildStderr) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut tail = Vec::new();
+        let mut buf = [0; 4096];
+        loop {
+            match stderr.read(&mut buf) {
+                Ok(0) => break,
+                Ok(count) => tail.extend_from_slice(&buf[..count]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    eprintln!("Failed to read restic error output: {e:?}");
+                    break;
+                },
+            }
+            if tail.len() > 2 * STDERR_TAIL_SIZE {
+                tail.drain(..tail.len() - STDERR_TAIL_SIZE);
+            }
+        }
+        tail.drain(..tail.len().saturating_sub(STDERR_TAIL_SIZE));
+        tail
+    })
+}
+
+/// Runs `cmd`, calls `on_line` for every line it prints to stdout and returns its exit status
+/// with the end of its error output
+fn stream_output<F: FnMut(&str)>(mut cmd: Command, mut on_line: F) -> Result<(ExitStatus, Vec<u8>), NcaError> {
+    let mut proc = cmd
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| NcaError::new_io_error(format!("{e:?}")))?;
+    let stderr = proc.stderr.take()
+        .map(drain_stderr)
+        .ok_or(NcaError::new_unexpected_error("Failed to capture error output"))?;
+    let stdout = proc.stdout.take()
+        .ok_or(NcaError::new_unexpected_error("Failed to capture output"))?;
+    for line in BufReader::new(stdout).lines() {
+        on_line(&line.map_err(NcaError::new_io_error)?);
+    }
+    let status = proc.wait()
+        .map_err(|e| NcaError::new_io_error(format!("Failed to wait for process: {e:?}")))?;
+    let stderr = stderr.join()
+        .map_err(|_| NcaError::new_unexpected_error("Reading the error output panicked"))?;
+    Ok((status, stderr))
+}
+
+/// A restic repository in a local directory, encrypted with the backup password
+#[derive(Clone, Debug)]
+pub struct ResticRepository {
+    path: PathBuf,
+    password: String,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(tag = "message_type", rename_all = "snake_case")]
+enum ResticBackupMessage {
+    Status {
+        #[serde(default)]
+        percent_done: f64,
+        #[serde(default)]
+        total_files: u64,
+        #[serde(default)]
+        files_done: u64,
+        #[serde(default)]
+        total_bytes: u64,
+        #[serde(default)]
+        bytes_done: u64,
+    },
+    Summary {
+        snapshot_id: String,
+    },
+    Error {
+        error: ResticError,
+        #[serde(default)]
+        item: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(tag = "message_type", rename_all = "snake_case")]
+enum ResticRestoreMessage {
+    Status {
+        #[serde(default)]
+        percent_done: f64,
+        #[serde(default)]
+        total_files: u64,
+        #[serde(default)]
+        files_restored: u64,
+        #[serde(default)]
+        total_bytes: u64,
+        #[serde(default)]
+        bytes_restored: u64,
+    },
+    VerboseStatus {
+        action: String,
+        item: String,
+        #[serde(default)]
+        size: u64,
+    },
+    Error {
+        error: ResticError,
+        #[serde(default)]
+        item: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+struct ResticError {
+    message: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct ResticSnapshot {
+    id: String,
+    short_id: String,
+    time: String,
+    #[serde(default)]
+    hostname: String,
+    #[serde(default)]
+    paths: Vec<String>,
+    summary: Option<ResticSnapshotSummary>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ResticSnapshotSummary {
+    total_bytes_processed: u64,
+}
+
+impl ResticRepository {
+    pub fn new(path: impl Into<PathBuf>, password: String) -> Self {
+        Self {
+            path: path.into(),
+            password,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn command(&self) -> Command {
+        let mut cmd = Command::new(RESTIC_PATH);
+        cmd.env("RESTIC_REPOSITORY", &self.path)
+            .env("RESTIC_PASSWORD", &self.password)
+            .stdin(Stdio::null());
+        cmd
+    }
+
+    fn run(&self, args: &[&str]) -> Result<Output, NcaError> {
+        let out = self.command()
+            .args(args)
+            .output()
+            .map_err(|e| NcaError::new_io_error(format!("Failed to run restic: {e:?}")))?;
+        if !out.status.success() {
+            return Err(NcaError::new_io_error(format!(
+                "restic {} failed: {}", args.first().unwrap_or(&""), String::from_utf8_lossy(&out.stderr))));
+        }
+        Ok(out)
+    }
+
+    pub fn is_initialized(&self) -> bool {
+        self.path.join("config").exists()
+    }
+
+    pub fn init(&self) -> Result<(), NcaError> {
+        fs::create_dir_all(&self.path)
+            .map_err(|e| NcaError::new_io_error(format!("Failed to create backup repository at {:?}: {e:?}", self.path)))?;
+        self.run(&["init"]).map(|_| ())
+    }
+
+    /// Runs restic with `args` and calls `on_line` for every line it prints to stdout
+    fn run_streaming<F: FnMut(&str)>(&self, args: &[&str], on_line: F) -> Result<(), NcaError> {
+        let mut cmd = self.command();
+        cmd.args(args);
+        let (status, stderr) = stream_output(cmd, on_line)
+            .map_err(|e| NcaError::new_io_error(format!("Failed to run restic: {e}")))?;
+        if !status.success() {
+            return Err(NcaError::new_io_error(format!(
+                "restic {} failed: {}", args.first().unwrap_or(&""), String::from_utf8_lossy(&stderr))));
+        }
+        Ok(())
+    }
+
+    /// Creates a snapshot of `paths` and returns its id. `on_progress` is called for every status
+    /// update reported by restic.
+    pub fn backup<F: FnMut(BackupProgress)>(&self, paths: &[&str], mut on_progress: F) -> Result<String, NcaError> {
+        let mut args = vec!["backup", "--json", "--tag", BACKUP_TAG];
+        args.extend_from_slice(paths);
+        let mut snapshot_id = None;
+        self.run_streaming(&args, |line| match parse_backup_message(line) {
+            Some(ResticBackupMessage::Summary { snapshot_id: id }) => snapshot_id = Some(id),
+            Some(msg) => if let Some(progress) = backup_progress(msg) {
+                on_progress(progress)
+            },
+            None => {
+                #[cfg(debug_assertions)]
+                eprintln!("Unexpected restic output: {line}");
+            }
+        })?;
+        snapshot_id.ok_or(NcaError::new_unexpected_error("restic did not report a snapshot id"))
+    }
+
+    /// Reads all data in the repository and verifies it against its checksums
+    pub fn check(&self) -> Result<(), NcaError> {
+        self.run(&["check", "--read-data"]).map(|_| ())
+    }
+
+    /// Restores the nextcloud data from snapshot `id`. Files that are not part of the snapshot are
+    /// deleted. With `dry_run`, only the changes that would be made are reported.
+    pub fn restore<F: FnMut(RestoreProgress)>(&self, id: &str, dry_run: bool, mut on_progress: F) -> Result<(), NcaError> {
+        let source = format!("{id}:{NC_AIO_DATA_PATH}");
+        let mut args = vec!["restore", "--json", "--delete", &source, "--target", NC_AIO_DATA_PATH];
+        if dry_run {
+            args.extend(["--dry-run", "--verbose=2"]);
+        }
+        self.run_streaming(&args, |line| match parse_restore_message(line) {
+            Some(msg) => if let Some(progress) = restore_progress(msg) {
+                on_progress(progress)
+            },
+            None => {
+                #[cfg(debug_assertions)]
+                eprintln!("Unexpected restic output: {line}");
+            }
+        })
+    }
+
+    /// Stores the (non-secret) salt next to the repository, so that the backup password can be
+    /// derived from the primary password when restoring onto a new instance.
+    pub fn store_salt(&self, salt_b32: &str) -> Result<(), NcaError> {
+        fs::write(self.path.join(SALT_FILE), salt_b32)
+            .map_err(|e| NcaError::new_io_error(format!("Failed to store salt in backup repository: {e:?}")))
+    }
+
+    pub fn read_salt(&self) -> Result<String, NcaError> {
+        fs::read_to_string(self.path.join(SALT_FILE))
+            .map(|salt| salt.trim().to_string())
+            .map_err(|e| NcaError::new_missing_config_error(format!("Failed to read salt from backup repository: {e:?}")))
+    }
+
+    pub fn snapshots(&self) -> Result<Vec<BackupInfo>, NcaError> {
+        let out = self.run(&["snapshots", "--json", "--tag", BACKUP_TAG])?;
+        parse_snapshots(&String::from_utf8_lossy(&out.stdout))
+    }
+
+    pub fn delete_snapshot(&self, id: &str) -> Result<(), NcaError> {
+        self.run(&["forget", "--prune", id]).map(|_| ())
+    }
+
+    pub fn apply_retention(&self, retention: &RetentionPolicy) -> Result<(), NcaError> {
+        let args = retention_args(retention);
+        let mut all_args = vec!["forget", "--prune", "--tag", BACKUP_TAG];
+        all_args.extend(args.iter().map(String::as_str));
+        self.run(&all_args).map(|_| ())
+    }
+
+    /// Re-encrypts the repository key with `new_password`
+    pub fn change_password(&mut self, new_password: String) -> Result<(), NcaError> {
+        let mut proc = self.command()
+            .args(["key", "passwd", "--new-password-file", "/dev/stdin"])
+            .stdin(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| NcaError::new_io_error(format!("Failed to run restic: {e:?}")))?;
+        proc.stdin.take()
+            .ok_or(NcaError::new_unexpected_error("Failed to open stdin of restic"))?
+            .write_all(new_password.as_bytes())
+            .map_err(NcaError::new_io_error)?;
+        let out = proc.wait_with_output()
+            .map_err(|e| NcaError::new_io_error(format!("Failed to wait for restic: {e:?}")))?;
+        if !out.status.success() {
+            return Err(NcaError::new_crypto_error(format!(
+                "Failed to change the password of the backup repository: {}", String::from_utf8_lossy(&out.stderr))));
+        }
+        self.password = new_password;
+        Ok(())
+    }
+}
+
+/// Dumps the database, snapshots the Nextcloud data and applies the retention policy.
+/// Returns the id of the new snapshot.
+pub(crate) fn run_backup<F: FnMut(BackupProgress)>(
+    repository: &ResticRepository,
+    retention: &RetentionPolicy,
+    salt_b32: &str,
+    mut on_progress: F
+) -> Result<String, NcaError> {
+    on_progress(phase(BackupPhase::Preparing));
+    if !repository.is_initialized() {
+        println!("Initializing backup repository");
+        repository.init()?;
+    }
+    repository.store_salt(salt_b32)?;
+
+    on_progress(phase(BackupPhase::DumpingDatabase));
+    let dump_path = Path::new(DATABASE_DUMP_PATH);
+    dump_database(dump_path)?;
+
+    on_progress(phase(BackupPhase::CreatingSnapshot));
+    let result = repository.backup(&[NC_AIO_DATA_PATH], &mut on_progress);
+    if let Err(e) = fs::remove_file(dump_path) {
+        eprintln!("Failed to remove database dump: {e:?}");
+    }
+    let snapshot_id = result?;
+
+    on_progress(phase(BackupPhase::ApplyingRetention));
+    repository.apply_retention(retention)?;
+    Ok(snapshot_id)
+}
+
+fn dump_database(target: &Path) -> Result<(), NcaError> {
+    if let Some(dir) = target.parent() {
+        fs::create_dir_all(dir).map_err(NcaError::new_io_error)?;
+    }
+    let file = fs::File::create(target)
+        .map_err(|e| NcaError::new_io_error(format!("Failed to create {target:?}: {e:?}")))?;
+    let out = Command::new("su")
+        .args(["-l", "-s", "/usr/bin/bash", "-c",
+            &format!("/usr/bin/podman exec {DATABASE_CONTAINER} pg_dump --clean --if-exists --username={DATABASE_USER} {DATABASE_NAME}"),
+            "aio"
+        ])
+        .stdout(file)
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(NcaError::new_io_error)?;
+    if !out.status.success() {
+        return Err(NcaError::new_io_error(format!(
+            "Failed to dump the nextcloud database: {}", String::from_utf8_lossy(&out.stderr))));
+    }
+    Ok(())
+}
+
+/// Loads the database dump of a restored backup into the (running) database container
+pub(crate) fn restore_database() -> Result<(), NcaError> {
+    let dump = fs::File::open(DATABASE_DUMP_PATH)
+        .map_err(|e| NcaError::new_io_error(format!("The backup does not contain a database dump: {e:?}")))?;
+    wait_for_database()?;
+    let out = Command::new("su")
+        .args(["-l", "-s", "/usr/bin/bash", "-c",
+            &format!("/usr/bin/podman exec -i {DATABASE_CONTAINER} psql --set ON_ERROR_STOP=1 --username={DATABASE_USER} {DATABASE_NAME}"),
+            "aio"
+        ])
+        .stdin(dump)
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(NcaError::new_io_error)?;
+    if !out.status.success() {
+        return Err(NcaError::new_io_error(format!(
+            "Failed to restore the nextcloud database: {}", String::from_utf8_lossy(&out.stderr))));
+    }
+    if let Err(e) = fs::remove_file(DATABASE_DUMP_PATH) {
+        eprintln!("Failed to remove database dump: {e:?}");
+    }
+    Ok(())
+}
+
+/// Keeps the restored Nextcloud in maintenance mode once it is started, so neither users nor cron
+/// jobs use it before its database has been restored
+pub(crate) fn enable_restore_maintenance_mode() -> Result<(), NcaError> {
+    fs::write(RESTORE_CONFIG_PATH, RESTORE_CONFIG)
+        .map_err(|e| NcaError::new_io_error(format!("Failed to enable maintenance mode: {e:?}")))
+}
+
+/// Ends the maintenance mode of [`enable_restore_maintenance_mode`]. Nextcloud may have copied the
+/// setting into its config.php while it was starting, so it is turned off with occ as well.
+pub(crate) fn disable_restore_maintenance_mode() -> Result<(), NcaError> {
+    fs::remove_file(RESTORE_CONFIG_PATH)
+        .map_err(|e| NcaError::new_io_error(format!("Failed to disable maintenance mode: {e:?}")))?;
+    let mut last_error = String::new();
+    for _ in 0..DATABASE_STARTUP_ATTEMPTS {
+        let out = Command::new("su")
+            .args(["-l", "-s", "/usr/bin/bash", "-c",
+                &format!("/usr/bin/podman exec -u www-data {NEXTCLOUD_CONTAINER} php occ maintenance:mode --off"),
+                "aio"
+            ])
+            .stdin(Stdio::null())
+            .output()
+            .map_err(NcaError::new_io_error)?;
+        if out.status.success() {
+            return Ok(());
+        }
+        last_error = String::from_utf8_lossy(&out.stderr).to_string();
+        std::thread::sleep(Duration::from_secs(2));
+    }
+    Err(NcaError::new_io_error(format!("Failed to disable maintenance mode: {last_error}")))
+}
+
+fn wait_for_database() -> Result<(), NcaError> {
+    for _ in 0..DATABASE_STARTUP_ATTEMPTS {
+        let ready = Command::new("su")
+            .args(["-l", "-s", "/usr/bin/bash", "-c",
+                &format!("/usr/bin/podman exec {DATABASE_CONTAINER} pg_isready --username={DATABASE_USER}"),
+                "aio"
+            ])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map(|status| status.success())
+            .unwrap_or(false);
+        if ready {
+            return Ok(());
+        }
+        std::thread::sleep(Duration::from_secs(2));
+    }
+    Err(NcaError::new_io_error("Timed out waiting for the nextcloud database to become ready"))
+}
+
+fn phase(phase: BackupPhase) -> BackupProgress {
+    BackupProgress {
+        phase: phase.into(),
+        ..BackupProgress::default()
+    }
+}
+
+fn parse_backup_message(line: &str) -> Option<ResticBackupMessage> {
+    serde_json::from_str(line).ok()
+}
+
+fn backup_progress(msg: ResticBackupMessage) -> Option<BackupProgress> {
+    match msg {
+        ResticBackupMessage::Status { percent_done, total_files, files_done, total_bytes, bytes_done } =>
+            Some(BackupProgress {
+                phase: BackupPhase::CreatingSnapshot.into(),
+                percent_done,
+                files_done,
+                total_files,
+                bytes_done,
+                total_bytes,
+                ..BackupProgress::default()
+            }),
+        ResticBackupMessage::Error { error, item } => Some(BackupProgress {
+            phase: BackupPhase::CreatingSnapshot.into(),
+            message: Some(format!("{item}: {}", error.message)),
+            ..BackupProgress::default()
+        }),
+        ResticBackupMessage::Summary { .. } | ResticBackupMessage::Other => None,
+    }
+}
+
+fn parse_restore_message(line: &str) -> Option<ResticRestoreMessage> {
+    serde_json::from_str(line).ok()
+}
+
+fn restore_progress(msg: ResticRestoreMessage) -> Option<RestoreProgress> {
+    match msg {
+        ResticRestoreMessage::Status { percent_done, total_files, files_restored, total_bytes, bytes_restored } =>
+            Some(RestoreProgress {
+                phase: RestorePhase::RestoringFiles.into(),
+                percent_done,
+                files_done: files_restored,
+                total_files,
+                bytes_done: bytes_restored,
+                total_bytes,
+                ..RestoreProgress::default()
+            }),
+        // Files that are already up to date are not worth reporting
+        ResticRestoreMessage::VerboseStatus { action, .. } if action == "unchanged" => None,
+        ResticRestoreMessage::VerboseStatus { action, item, size } => Some(RestoreProgress {
+            phase: RestorePhase::RestoringFiles.into(),
+            change: Some(FileChange { path: item, action, size }),
+            ..RestoreProgress::default()
+        }),
+        ResticRestoreMessage::Error { error, item } => Some(RestoreProgress {
+            phase: RestorePhase::RestoringFiles.into(),
+            message: Some(format!("{item}: {}", error.message)),
+            ..RestoreProgress::default()
+        }),
+        ResticRestoreMessage::Other => None,
+    }
+}
+
+fn parse_snapshots(data: &str) -> Result<Vec<BackupInfo>, NcaError> {
+    let snapshots: Vec<ResticSnapshot> = serde_json::from_str(data)
+        .map_err(|e| NcaError::new_io_error(format!("Failed to parse restic snapshots: {e:?}")))?;
+    Ok(snapshots.into_iter()
+        .map(|s| BackupInfo {
+            id: s.id,
+            short_id: s.short_id,
+            time: s.time,
+            hostname: s.hostname,
+            paths: s.paths,
+            size: s.summary.map(|summary| summary.total_bytes_processed),
+        })
+        .collect())
+}
+
+fn retention_args(retention: &RetentionPolicy) -> Vec<String> {
+    [
+        ("--keep-daily", retention.keep_daily),
+        ("--keep-weekly", retention.keep_weekly),
+        ("--keep-monthly", retention.keep_monthly),
+    ].into_iter()
+        .filter(|(_, n)| *n > 0)
+        .flat_map(|(flag, n)| [flag.to_string(), n.to_string()])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stream_output_drains_stderr() {
+        // Far more error output than fits into a pipe buffer, before any line on stdout
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "head -c 1000000 /dev/zero | tr '\\0' x >&2; echo error end >&2; echo done; exit 3"]);
+        let mut lines = Vec::new();
+        let (status, stderr) = stream_output(cmd, |line| lines.push(line.to_string())).unwrap();
+        assert_eq!(status.code(), Some(3));
+        assert_eq!(lines, vec!["done"]);
+        assert_eq!(stderr.len(), STDERR_TAIL_SIZE);
+        assert!(stderr.ends_with(b"xxerror end\n"));
+    }
+
+    #[test]
+    fn test_parse_backup_messages() {
+        let status = r#"{"message_type":"status","percent_done":0.5,"total_files":10,"files_done":5,"total_bytes":2048,"bytes_done":1024,"current_files":["/a"]}"#;
+        let progress = backup_progress(parse_backup_message(status).unwrap()).unwrap();
+        assert_eq!(progress.phase(), BackupPhase::CreatingSnapshot);
+        assert_eq!(progress.percent_done, 0.5);
+        assert_eq!(progress.files_done, 5);
+        assert_eq!(progress.bytes_done, 1024);
+
+        let summary = r#"{"message_type":"summary","files_new":10,"snapshot_id":"abcdef","total_bytes_processed":2048}"#;
+        assert_eq!(parse_backup_message(summary), Some(ResticBackupMessage::Summary { snapshot_id: "abcdef".to_string() }));
+
+        let error = r#"{"message_type":"error","error":{"message":"permission denied"},"during":"archival","item":"/a"}"#;
+        let progress = backup_progress(parse_backup_message(error).unwrap()).unwrap();
+        assert_eq!(progress.message.as_deref(), Some("/a: permission denied"));
+
+        assert_eq!(parse_backup_message(r#"{"message_type":"verbose_status"}"#), Some(ResticBackupMessage::Other));
+        assert_eq!(parse_backup_message("not json"), None);
+    }
+
+    #[test]
+    fn test_parse_restore_messages() {
+        let status = r#"{"message_type":"status","seconds_elapsed":1,"percent_done":0.25,"total_files":8,"files_restored":2,"total_bytes":400,"bytes_restored":100}"#;
+        let progress = restore_progress(parse_restore_message(status).unwrap()).unwrap();
+        assert_eq!(progress.files_done, 2);
+        assert_eq!(progress.bytes_done, 100);
+
+        let updated = r#"{"message_type":"verbose_status","action":"updated","item":"/var/data/ncatomic/nc-aio/nextcloud/config/config.php","size":1234}"#;
+        let change = restore_progress(parse_restore_message(updated).unwrap()).unwrap().change.unwrap();
+        assert_eq!(change.action, "updated");
+        assert_eq!(change.size, 1234);
+
+        let unchanged = r#"{"message_type":"verbose_status","action":"unchanged","item":"/a","size":1}"#;
+        assert_eq!(restore_progress(parse_restore_message(unchanged).unwrap()), None);
+        assert_eq!(parse_restore_message(r#"{"message_type":"summary","total_files":8}"#), Some(ResticRestoreMessage::Other));
+    }
+
+    #[test]
+    fn test_parse_snapshots() {
+        let data = r#"[
+            {"time":"2025-01-01T02:00:00Z","paths":["/var/data/ncatomic/nc-aio"],"hostname":"ncatomic","tags":["ncatomic"],"id":"0123abcd","short_id":"0123","summary":{"total_bytes_processed":4096}},
+            {"time":"2025-01-02T02:00:00Z","paths":["/var/data/ncatomic/nc-aio"],"hostname":"ncatomic","id":"4567ef01","short_id":"4567"}
+        ]"#;
+        let snapshots = parse_snapshots(data).unwrap();
+        assert_eq!(snapshots.len(), 2);
+        assert_eq!(snapshots[0].size, Some(4096));
+        assert_eq!(snapshots[1].id, "4567ef01");
+        assert_eq!(snapshots[1].size, None);
+    }
+
+    #[test]
+    fn test_retention_args() {
+        let args = retention_args(&RetentionPolicy { keep_daily: 7, keep_weekly: 0, keep_monthly: 12 });
+        assert_eq!(args, vec!["--keep-daily", "7", "--keep-monthly", "12"]);
+    }
+}
@@ -1,25 +1,27 @@
-mod backup;
+pub mod backup;
 pub mod credentials_config;
+pub mod data_disk;
+pub mod state;
+pub mod storage;
 
 use std::fs;
 use std::path::{PathBuf};
 use nca_error::NcaError;
-use crate::api::credentials_server::Credentials;
-use crate::crypto::{try_parse_salt, Salt};
-use crate::server::config::backup::BackupConfig;
-use crate::server::config::credentials_config::CredentialsConfig;
+use nca_system_api::systemd::api::set_systemd_credential;
+use crate::crypto::{b32_encode, try_parse_salt, Salt};
+use crate::server::config::data_disk::DataDiskMember;
+use crate::server::config::state::{State, StateFile};
 
 #[derive(Clone, Debug)]
 pub struct Config {
     pub config_path: String,
     pub salt: Option<Salt>,
-    pub backup: Option<BackupConfig>,
-    pub setup_complete: bool,
-    pub credentials_config: Option<CredentialsConfig>,
+    state: State,
+    state_file: StateFile,
 }
 
 impl Config {
-    pub fn new() -> Result<Self, NcaError> {
+    pub async fn new() -> Result<Self, NcaError> {
         let config_path = std::env::var("CONFIG_PATH").unwrap_or("/etc/ncatomic".to_string());
         let (
             salt,
@@ -32,7 +34,7 @@ impl Config {
                 let credentials_base = PathBuf::from(credentials_dir);
                 let salt_path = credentials_base.join("ncatomic_salt.txt");
                 let salt = match salt_path.exists() {
-                    false => None, 
+                    false => None,
                     true => match fs::read_to_string(&salt_path) {
                         Err(_) => None,
                         Ok(salt_str) => {
@@ -49,12 +51,68 @@ impl Config {
             }
         };
 
+        let state_file = StateFile::new(&config_path);
+        let state = match state_file.load().await? {
+            Some(state) => state,
+            // Instances that were set up before the state file was introduced only persisted the salt
+            None => State {
+                setup_complete: salt.is_some(),
+                ..State::default()
+            }
+        };
+
         Ok(Self {
             config_path,
             salt,
-            backup: None,
-            setup_complete: salt.is_some(),
-            credentials_config: None,
+            state,
+            state_file,
         })
     }
-}
\ No newline at end of file
+
+    pub async fn set_salt(&mut self, salt: Salt) -> Result<(), NcaError> {
+        set_systemd_credential(
+            b32_encode(&salt),
+            format!("{}/credentials/salt.txt", self.config_path),
+            Some("ncatomic_salt.txt".to_string()),
+            false
+        ).await?;
+        self.salt = Some(salt);
+        Ok(())
+    }
+
+    pub fn data_pool_members(&self) -> Vec<DataDiskMember> {
+        self.state.data_pool.as_ref()
+            .map(|pool| pool.members.clone())
+            .unwrap_or_default()
+    }
+
+    /// The backup password derived from the primary password. Falls back to the systemd credential
+    /// if the state does not contain credentials.
+    pub fn backup_password(&self) -> Result<String, NcaError> {
+        if let Some(credentials) = &self.state.credentials_config {
+            return Ok(credentials.backup_password.clone());
+        }
+        let credentials_dir = std::env::var("CREDENTIALS_DIRECTORY")
+            .map_err(|_| NcaError::new_missing_config_error("No backup password available: no credentials loaded"))?;
+        fs::read_to_string(PathBuf::from(credentials_dir).join("ncatomic_backup_password.txt"))
+            .map(|pw| pw.trim().to_string())
+            .map_err(|e| NcaError::new_io_error(format!("Failed to read backup password: {e:?}")))
+    }
+
+    pub fn state(&self) -> &State {
+        &self.state
+    }
+
+    /// Applies `update` to the state and persists the result. The in-memory state is only
+    /// changed if the new state was persisted successfully.
+    pub async fn update_state<F>(&mut self, update: F) -> Result<(), NcaError>
+    where
+        F: FnOnce(&mut State)
+    {
+        let mut state = self.state.clone();
+        update(&mut state);
+        self.state_file.store(&state).await?;
+        self.state = state;
+        Ok(())
+    }
+}
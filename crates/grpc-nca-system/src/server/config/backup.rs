@@ -1,4 +1,69 @@
-#[derive(Clone, Debug)]
+use serde::{Deserialize, Serialize};
+use crate::api::BackupSettings;
+
+pub const DEFAULT_REPOSITORY_PATH: &str = "/var/data/ncatomic/backups";
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct BackupConfig {
-    pub backup_key: Option<String>,
-}
\ No newline at end of file
+    pub repository_path: String,
+    pub retention: RetentionPolicy,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RetentionPolicy {
+    pub keep_daily: u32,
+    pub keep_weekly: u32,
+    pub keep_monthly: u32,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            keep_daily: 7,
+            keep_weekly: 4,
+            keep_monthly: 6,
+        }
+    }
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        Self {
+            repository_path: DEFAULT_REPOSITORY_PATH.to_string(),
+            retention: RetentionPolicy::default(),
+        }
+    }
+}
+
+impl From<BackupSettings> for BackupConfig {
+    fn from(value: BackupSettings) -> Self {
+        Self {
+            repository_path: value.repository_path,
+            retention: RetentionPolicy {
+                keep_daily: value.keep_daily,
+                keep_weekly: value.keep_weekly,
+                keep_monthly: value.keep_monthly,
+            },
+        }
+    }
+}
+
+impl From<BackupConfig> for BackupSettings {
+    fn from(value: BackupConfig) -> Self {
+        Self {
+            repository_path: value.repository_path,
+            keep_daily: value.retention.keep_daily,
+            keep_weekly: value.retention.keep_weekly,
+            keep_monthly: value.retention.keep_monthly,
+        }
+    }
+}
+
+/// Outcome of the most recent backup runs (timestamps in seconds since the unix epoch)
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct BackupHistory {
+    pub last_success: Option<i64>,
+    pub last_snapshot_id: Option<String>,
+    pub last_failure: Option<i64>,
+    pub last_error: Option<String>,
+}
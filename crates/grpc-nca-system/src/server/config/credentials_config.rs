@@ -1,5 +1,7 @@
-#[derive(Clone, Debug)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct CredentialsConfig {
     pub(crate) disk_encryption_password: String,
     pub(crate) backup_password: String,
-}
\ No newline at end of file
+}
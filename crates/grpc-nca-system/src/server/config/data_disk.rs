@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+use crate::api::DataPoolProfile;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DataDiskMember {
+    pub device: String,
+    pub luks_uuid: String,
+    pub mapper_name: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PoolProfile {
+    Single,
+    Raid1,
+}
+
+impl From<PoolProfile> for DataPoolProfile {
+    fn from(value: PoolProfile) -> Self {
+        match value {
+            PoolProfile::Single => DataPoolProfile::DataPoolSingle,
+            PoolProfile::Raid1 => DataPoolProfile::DataPoolRaid1,
+        }
+    }
+}
+
+/// The encrypted disks the nextcloud data is stored on. With more than one member, all data is
+/// mirrored (btrfs raid1).
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DataPoolConfig {
+    // UUID of the btrfs file system, unknown for pools created before mirroring was supported
+    pub fs_uuid: Option<String>,
+    pub profile: PoolProfile,
+    pub members: Vec<DataDiskMember>,
+}
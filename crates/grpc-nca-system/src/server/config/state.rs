@@ -0,0 +1,245 @@
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use nca_error::NcaError;
+use nca_system_api::systemd::api::{decrypt_systemd_credential, set_systemd_credential};
+use crate::server::config::backup::{BackupConfig, BackupHistory};
+use crate::server::config::credentials_config::CredentialsConfig;
+use crate::server::config::data_disk::DataPoolConfig;
+use crate::server::config::storage::StorageThresholdsConfig;
+
+pub const STATE_VERSION: u64 = 2;
+const STATE_CREDENTIAL_NAME: &str = "ncatomic_state.json";
+const KEY_VERSION: &str = "version";
+
+/// Upgrades a state document from version `n` to version `n + 1`
+pub type Migration = fn(Value) -> Result<Value, NcaError>;
+
+/// `MIGRATIONS[i]` upgrades a state document from version `i + 1` to version `i + 2`.
+/// Whenever `STATE_VERSION` is increased, a migration has to be appended here.
+const MIGRATIONS: &[Migration] = &[migrate_v1_data_disk_to_pool];
+const _: () = assert!(MIGRATIONS.len() as u64 + 1 == STATE_VERSION);
+
+/// Version 2 replaces the single `data_disk` by a `data_pool` of (mirrored) disks
+fn migrate_v1_data_disk_to_pool(mut doc: Value) -> Result<Value, NcaError> {
+    let obj = doc.as_object_mut()
+        .ok_or(NcaError::new_server_config_error("State is not an object"))?;
+    let data_pool = match obj.remove("data_disk") {
+        None | Some(Value::Null) => Value::Null,
+        Some(data_disk) => serde_json::json!({
+            "fs_uuid": null,
+            "profile": "single",
+            "members": [data_disk],
+        }),
+    };
+    obj.insert("data_pool".to_string(), data_pool);
+    obj.insert(KEY_VERSION.to_string(), Value::from(2));
+    Ok(doc)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct State {
+    pub setup_complete: bool,
+    pub credentials_config: Option<CredentialsConfig>,
+    pub backup: Option<BackupConfig>,
+    #[serde(default)]
+    pub backup_history: BackupHistory,
+    pub data_pool: Option<DataPoolConfig>,
+    #[serde(default)]
+    pub storage_thresholds: StorageThresholdsConfig,
+}
+
+/// The persisted state of nca-system. As it contains secrets, it is stored as an encrypted
+/// systemd credential.
+#[derive(Clone, Debug)]
+pub struct StateFile {
+    path: PathBuf,
+}
+
+impl StateFile {
+    pub fn new(config_path: &str) -> Self {
+        Self {
+            path: PathBuf::from(config_path).join("system/nca-system.state"),
+        }
+    }
+
+    pub async fn load(&self) -> Result<Option<State>, NcaError> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        let data = decrypt_systemd_credential(
+            self.path.to_string_lossy().to_string(),
+            Some(STATE_CREDENTIAL_NAME.to_string())
+        ).await?;
+        deserialize_state(&data).map(Some)
+    }
+
+    pub async fn store(&self, state: &State) -> Result<(), NcaError> {
+        let data = serialize_state(state)?;
+        let encrypted = set_systemd_credential(
+            data,
+            "-".to_string(),
+            Some(STATE_CREDENTIAL_NAME.to_string()),
+            false
+        ).await?;
+        write_atomically(&self.path, encrypted.as_bytes())
+    }
+}
+
+pub(crate) fn serialize_state(state: &State) -> Result<String, NcaError> {
+    let mut doc = serde_json::to_value(state)
+        .map_err(|e| NcaError::new_server_config_error(format!("Failed to serialize state: {e:?}")))?;
+    match doc.as_object_mut() {
+        None => return Err(NcaError::new_unexpected_error("State was not serialized to an object")),
+        Some(obj) => obj.insert(KEY_VERSION.to_string(), Value::from(STATE_VERSION)),
+    };
+    serde_json::to_string(&doc)
+        .map_err(|e| NcaError::new_server_config_error(format!("Failed to serialize state: {e:?}")))
+}
+
+pub(crate) fn deserialize_state(data: &str) -> Result<State, NcaError> {
+    deserialize_state_with_migrations(data, MIGRATIONS)
+}
+
+fn deserialize_state_with_migrations(data: &str, migrations: &[Migration]) -> Result<State, NcaError> {
+    let doc: Value = serde_json::from_str(data)
+        .map_err(|e| NcaError::new_server_config_error(format!("Failed to parse state: {e:?}")))?;
+    let mut doc = migrate(doc, migrations)?;
+    if let Some(obj) = doc.as_object_mut() {
+        obj.remove(KEY_VERSION);
+    }
+    serde_json::from_value(doc)
+        .map_err(|e| NcaError::new_server_config_error(format!("Failed to parse state: {e:?}")))
+}
+
+fn get_version(doc: &Value) -> Result<u64, NcaError> {
+    doc.get(KEY_VERSION)
+        .and_then(Value::as_u64)
+        .ok_or(NcaError::new_server_config_error("State is missing a valid schema version"))
+}
+
+fn migrate(mut doc: Value, migrations: &[Migration]) -> Result<Value, NcaError> {
+    let target_version = migrations.len() as u64 + 1;
+    loop {
+        let version = get_version(&doc)?;
+        if version == target_version {
+            return Ok(doc);
+        }
+        if version == 0 || version > target_version {
+            return Err(NcaError::new_server_config_error(format!(
+                "Unsupported state version {version} (expected a version between 1 and {target_version})")));
+        }
+        #[cfg(debug_assertions)]
+        eprintln!("Migrating state from version {version} to {}", version + 1);
+        doc = migrations[(version - 1) as usize](doc)?;
+        if get_version(&doc)? != version + 1 {
+            return Err(NcaError::new_unexpected_error(format!(
+                "Migration of state from version {version} did not update the schema version")));
+        }
+    }
+}
+
+fn write_atomically(path: &Path, data: &[u8]) -> Result<(), NcaError> {
+    let dir = path.parent()
+        .ok_or(NcaError::InvalidPath(path.to_string_lossy().to_string(), "Path has no parent directory".to_string()))?;
+    fs::create_dir_all(dir).map_err(NcaError::new_io_error)?;
+    let tmp_path = path.with_extension("tmp");
+    {
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&tmp_path)
+            .map_err(|e| NcaError::new_io_error(format!("Failed to open {tmp_path:?}: {e:?}")))?;
+        file.write_all(data)
+            .and_then(|_| file.sync_all())
+            .map_err(|e| NcaError::new_io_error(format!("Failed to write {tmp_path:?}: {e:?}")))?;
+    }
+    fs::rename(&tmp_path, path)
+        .map_err(|e| NcaError::new_io_error(format!("Failed to move {tmp_path:?} to {path:?}: {e:?}")))?;
+    fs::File::open(dir)
+        .and_then(|d| d.sync_all())
+        .map_err(|e| NcaError::new_io_error(format!("Failed to sync {dir:?}: {e:?}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::config::data_disk::{DataDiskMember, PoolProfile};
+
+    fn example_state() -> State {
+        State {
+            setup_complete: true,
+            credentials_config: Some(CredentialsConfig {
+                disk_encryption_password: "disk".to_string(),
+                backup_password: "backup".to_string(),
+            }),
+            backup: Some(BackupConfig::default()),
+            backup_history: BackupHistory::default(),
+            data_pool: None,
+            storage_thresholds: StorageThresholdsConfig::default(),
+        }
+    }
+
+    #[test]
+    fn test_state_roundtrip() {
+        let state = example_state();
+        let data = serialize_state(&state).unwrap();
+        assert_eq!(get_version(&serde_json::from_str(&data).unwrap()).unwrap(), STATE_VERSION);
+        assert_eq!(deserialize_state(&data).unwrap(), state);
+    }
+
+    #[test]
+    fn test_state_migrations() {
+        fn v1_to_v2(mut doc: Value) -> Result<Value, NcaError> {
+            let obj = doc.as_object_mut().unwrap();
+            let complete = obj.remove("complete").unwrap_or(Value::Bool(false));
+            obj.insert("setup_complete".to_string(), complete);
+            obj.insert(KEY_VERSION.to_string(), Value::from(2));
+            Ok(doc)
+        }
+        fn v2_to_v3(mut doc: Value) -> Result<Value, NcaError> {
+            doc.as_object_mut().unwrap().insert(KEY_VERSION.to_string(), Value::from(3));
+            Ok(doc)
+        }
+        let migrations: &[Migration] = &[v1_to_v2, v2_to_v3];
+
+        let state = deserialize_state_with_migrations(r#"{"version": 1, "complete": true}"#, migrations).unwrap();
+        assert!(state.setup_complete);
+        let state = deserialize_state_with_migrations(r#"{"version": 3, "setup_complete": true}"#, migrations).unwrap();
+        assert!(state.setup_complete);
+        assert!(deserialize_state_with_migrations(r#"{"version": 4}"#, migrations).is_err());
+        assert!(deserialize_state_with_migrations(r#"{"setup_complete": true}"#, migrations).is_err());
+    }
+
+    #[test]
+    fn test_migrate_data_disk_to_pool() {
+        let v1 = r#"{"version": 1, "setup_complete": true, "credentials_config": null, "backup": null,
+            "data_disk": {"device": "/dev/vdb", "luks_uuid": "1234", "mapper_name": "ncatomic-data"}}"#;
+        let pool = deserialize_state(v1).unwrap().data_pool.unwrap();
+        assert_eq!(pool.fs_uuid, None);
+        assert_eq!(pool.profile, PoolProfile::Single);
+        assert_eq!(pool.members, vec![DataDiskMember {
+            device: "/dev/vdb".to_string(),
+            luks_uuid: "1234".to_string(),
+            mapper_name: "ncatomic-data".to_string(),
+        }]);
+        let v1 = r#"{"version": 1, "setup_complete": false, "credentials_config": null, "backup": null}"#;
+        assert_eq!(deserialize_state(v1).unwrap().data_pool, None);
+    }
+
+    #[test]
+    fn test_write_atomically() {
+        let dir = std::env::temp_dir().join(format!("nca-system-state-test-{}", std::process::id()));
+        let path = dir.join("system/nca-system.state");
+        write_atomically(&path, b"first").unwrap();
+        write_atomically(&path, b"second").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+        assert!(!path.with_extension("tmp").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
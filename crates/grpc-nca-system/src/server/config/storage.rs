@@ -0,0 +1,52 @@
+use serde::{Deserialize, Serialize};
+use crate::api::StorageThresholds;
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct StorageThresholdsConfig {
+    pub warning_percent: u32,
+    pub critical_percent: u32,
+    pub inodes_warning_percent: u32,
+    pub inodes_critical_percent: u32,
+}
+
+impl Default for StorageThresholdsConfig {
+    fn default() -> Self {
+        Self {
+            warning_percent: 80,
+            critical_percent: 90,
+            inodes_warning_percent: 80,
+            inodes_critical_percent: 90,
+        }
+    }
+}
+
+impl StorageThresholdsConfig {
+    pub fn is_valid(&self) -> bool {
+        self.warning_percent <= self.critical_percent
+            && self.critical_percent <= 100
+            && self.inodes_warning_percent <= self.inodes_critical_percent
+            && self.inodes_critical_percent <= 100
+    }
+}
+
+impl From<StorageThresholds> for StorageThresholdsConfig {
+    fn from(value: StorageThresholds) -> Self {
+        Self {
+            warning_percent: value.warning_percent,
+            critical_percent: value.critical_percent,
+            inodes_warning_percent: value.inodes_warning_percent,
+            inodes_critical_percent: value.inodes_critical_percent,
+        }
+    }
+}
+
+impl From<StorageThresholdsConfig> for StorageThresholds {
+    fn from(value: StorageThresholdsConfig) -> Self {
+        Self {
+            warning_percent: value.warning_percent,
+            critical_percent: value.critical_percent,
+            inodes_warning_percent: value.inodes_warning_percent,
+            inodes_critical_percent: value.inodes_critical_percent,
+        }
+    }
+}
@@ -0,0 +1,673 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use nca_error::NcaError;
+use nca_system_api::systemd::api::{close_encrypted_disk, create_encrypted_disk, open_encrypted_disk, reload_systemd, stop_service};
+use crate::api::{DataPoolMember, DataPoolStatus};
+use crate::server::config::data_disk::{DataDiskMember, DataPoolConfig, PoolProfile};
+use crate::server::util::blocking;
+
+pub(crate) const DATA_PATH: &str = "/var/data/ncatomic";
+const DATA_MAPPER_NAME: &str = "ncatomic-data";
+const DATA_FS_TYPE: &str = "btrfs";
+const BTRFS: &str = "/usr/sbin/btrfs";
+
+/// Where the data disk is set up. Tests use temporary locations instead of the system paths.
+#[derive(Clone, Debug)]
+pub(crate) struct DataDiskLayout {
+    // Name of the first pool member's mapping, further members are named `<mapper_name>-<n>`
+    pub mapper_name: String,
+    pub data_path: PathBuf,
+    pub staging_path: PathBuf,
+    pub crypttab_path: PathBuf,
+    pub unit_dir: PathBuf,
+}
+
+impl Default for DataDiskLayout {
+    fn default() -> Self {
+        Self {
+            mapper_name: DATA_MAPPER_NAME.to_string(),
+            data_path: PathBuf::from(DATA_PATH),
+            staging_path: PathBuf::from("/run/ncatomic/data-disk"),
+            crypttab_path: PathBuf::from("/etc/crypttab"),
+            unit_dir: PathBuf::from("/etc/systemd/system"),
+        }
+    }
+}
+
+impl DataDiskLayout {
+    pub fn mount_unit_name(&self) -> String {
+        format!("{}.mount", escape_unit_path(&self.data_path))
+    }
+
+    fn is_pool_mapping(&self, name: &str) -> bool {
+        name == self.mapper_name || name.strip_prefix(&self.mapper_name)
+            .and_then(|suffix| suffix.strip_prefix('-'))
+            .is_some_and(|n| n.parse::<u32>().is_ok())
+    }
+
+    fn data_path_str(&self) -> String {
+        self.data_path.to_string_lossy().to_string()
+    }
+}
+
+fn mapper_path(mapper_name: &str) -> String {
+    format!("/dev/mapper/{mapper_name}")
+}
+
+fn run(program: &str, args: &[&str]) -> Result<String, NcaError> {
+    #[cfg(debug_assertions)]
+    eprintln!("Running {program} {}", args.join(" "));
+    let out = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|e| NcaError::new_io_error(format!("Failed to run {program}: {e:?}")))?;
+    if !out.status.success() {
+        return Err(NcaError::new_io_error(format!("{program} {} failed (exit code: {:?}): {}",
+                                                  args.join(" "),
+                                                  out.status.code().unwrap_or(-1),
+                                                  String::from_utf8_lossy(&out.stderr))));
+    }
+    Ok(String::from_utf8_lossy(&out.stdout).trim().to_string())
+}
+
+/// Escapes an absolute path like `systemd-escape --path`
+fn escape_unit_path(path: &Path) -> String {
+    let trimmed = path.to_string_lossy().trim_matches('/').to_string();
+    if trimmed.is_empty() {
+        return "-".to_string();
+    }
+    trimmed.char_indices()
+        .map(|(i, c)| match c {
+            '/' => "-".to_string(),
+            '.' if i == 0 => "\\x2e".to_string(),
+            c if c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == ':' => c.to_string(),
+            c => {
+                let mut buf = [0u8; 4];
+                c.encode_utf8(&mut buf).bytes()
+                    .map(|b| format!("\\x{b:02x}"))
+                    .collect()
+            }
+        })
+        .collect()
+}
+
+fn next_mapper_name(layout: &DataDiskLayout, members: &[DataDiskMember]) -> String {
+    let used: HashSet<&str> = members.iter().map(|m| m.mapper_name.as_str()).collect();
+    let mut n = 0;
+    loop {
+        let name = match n {
+            0 => layout.mapper_name.clone(),
+            n => format!("{}-{n}", layout.mapper_name),
+        };
+        if !used.contains(name.as_str()) {
+            return name;
+        }
+        n += 1;
+    }
+}
+
+fn crypttab_entry(member: &DataDiskMember) -> String {
+    format!("{} UUID={} none luks,discard,tpm2-device=auto\n", member.mapper_name, member.luks_uuid)
+}
+
+fn mount_unit(layout: &DataDiskLayout, fs_uuid: &str) -> String {
+    format!("[Unit]
+Description=Nextcloud Atomic data disk
+Before=nextcloud-all-in-one.service
+
+[Mount]
+What=/dev/disk/by-uuid/{fs_uuid}
+Where={}
+Type={DATA_FS_TYPE}
+Options=defaults,noatime
+
+[Install]
+WantedBy=local-fs.target
+", layout.data_path.display())
+}
+
+/// Replaces all entries of the pool's mappings in the crypttab with entries for `members`
+fn write_crypttab(layout: &DataDiskLayout, members: &[DataDiskMember]) -> Result<(), NcaError> {
+    let existing = match layout.crypttab_path.exists() {
+        false => String::new(),
+        true => fs::read_to_string(&layout.crypttab_path).map_err(NcaError::new_io_error)?,
+    };
+    let mut content: String = existing.lines()
+        .filter(|line| !line.split_whitespace().next().is_some_and(|name| layout.is_pool_mapping(name)))
+        .map(|line| format!("{line}\n"))
+        .collect();
+    content.extend(members.iter().map(crypttab_entry));
+    fs::write(&layout.crypttab_path, content)
+        .map_err(|e| NcaError::new_io_error(format!("Failed to write {:?}: {e:?}", layout.crypttab_path)))
+}
+
+fn write_mount_unit(layout: &DataDiskLayout, fs_uuid: &str) -> Result<(), NcaError> {
+    let unit_name = layout.mount_unit_name();
+    let wants_dir = layout.unit_dir.join("local-fs.target.wants");
+    fs::create_dir_all(&wants_dir).map_err(NcaError::new_io_error)?;
+    fs::write(layout.unit_dir.join(&unit_name), mount_unit(layout, fs_uuid))
+        .map_err(|e| NcaError::new_io_error(format!("Failed to write {unit_name}: {e:?}")))?;
+    let link = wants_dir.join(&unit_name);
+    if fs::symlink_metadata(&link).is_err() {
+        symlink(layout.unit_dir.join(&unit_name), &link)
+            .map_err(|e| NcaError::new_io_error(format!("Failed to enable {unit_name}: {e:?}")))?;
+    }
+    Ok(())
+}
+
+/// Removes the crypttab entries and the mount unit of the pool
+fn remove_pool_units(layout: &DataDiskLayout) -> Result<(), NcaError> {
+    write_crypttab(layout, &[])?;
+    let unit_name = layout.mount_unit_name();
+    for path in [layout.unit_dir.join("local-fs.target.wants").join(&unit_name), layout.unit_dir.join(&unit_name)] {
+        if fs::symlink_metadata(&path).is_ok() {
+            fs::remove_file(&path)
+                .map_err(|e| NcaError::new_io_error(format!("Failed to remove {path:?}: {e:?}")))?;
+        }
+    }
+    Ok(())
+}
+
+fn clear_directory(path: &Path) -> Result<(), NcaError> {
+    for entry in fs::read_dir(path).map_err(NcaError::new_io_error)? {
+        let path = entry.map_err(NcaError::new_io_error)?.path();
+        if path.is_dir() && !path.is_symlink() {
+            fs::remove_dir_all(&path)
+        } else {
+            fs::remove_file(&path)
+        }.map_err(|e| NcaError::new_io_error(format!("Failed to remove {path:?}: {e:?}")))?;
+    }
+    Ok(())
+}
+
+fn filesystem_uuid(device: &str) -> Result<String, NcaError> {
+    run("/usr/sbin/blkid", &["--match-tag", "UUID", "--output", "value", device])
+}
+
+fn make_filesystem(layout: &DataDiskLayout, members: &[DataDiskMember], profile: PoolProfile) -> Result<String, NcaError> {
+    let devices: Vec<String> = members.iter().map(|m| mapper_path(&m.mapper_name)).collect();
+    let mut args = vec!["--force", "--label", layout.mapper_name.as_str()];
+    if profile == PoolProfile::Raid1 {
+        args.extend(["--data", "raid1", "--metadata", "raid1"]);
+    }
+    args.extend(devices.iter().map(String::as_str));
+    run(&format!("/usr/sbin/mkfs.{DATA_FS_TYPE}"), &args)?;
+    filesystem_uuid(&devices[0])
+}
+
+/// Copies the current data onto the new pool. The data is kept in its old location until the pool
+/// is in use, see [`remove_shadowed_data`].
+fn copy_data(layout: &DataDiskLayout, members: &[DataDiskMember]) -> Result<(), NcaError> {
+    fs::create_dir_all(&layout.staging_path).map_err(NcaError::new_io_error)?;
+    fs::create_dir_all(&layout.data_path).map_err(NcaError::new_io_error)?;
+    let options = members.iter()
+        .map(|m| format!("device={}", mapper_path(&m.mapper_name)))
+        .collect::<Vec<_>>()
+        .join(",");
+    let staging_path = layout.staging_path.to_string_lossy().to_string();
+    run("/usr/bin/mount", &["-o", &options, &mapper_path(&members[0].mapper_name), &staging_path])?;
+    let copied = run("/usr/bin/cp", &["-a", "--reflink=auto", "--",
+        &format!("{}/.", layout.data_path.display()), &staging_path]);
+    let synced = copied.and_then(|_| run("/usr/bin/sync", &["-f", &staging_path]));
+    run("/usr/bin/umount", &[&staging_path])?;
+    synced.map(|_| ())
+}
+
+/// Removes the data that was copied onto the pool from the directory the pool is mounted on. A
+/// bind mount of the parent directory does not include the pool, so it exposes the old data.
+pub(crate) fn remove_shadowed_data(layout: &DataDiskLayout) -> Result<(), NcaError> {
+    let (Some(parent), Some(name)) = (layout.data_path.parent(), layout.data_path.file_name()) else {
+        return Err(NcaError::InvalidPath(layout.data_path_str(), "The data path has no parent directory".to_string()));
+    };
+    fs::create_dir_all(&layout.staging_path).map_err(NcaError::new_io_error)?;
+    let staging_path = layout.staging_path.to_string_lossy().to_string();
+    run("/usr/bin/mount", &["--bind", &parent.to_string_lossy(), &staging_path])?;
+    let cleared = clear_directory(&layout.staging_path.join(name));
+    run("/usr/bin/umount", &[&staging_path])?;
+    cleared
+}
+
+/// Wipes and encrypts `device` and opens it as `mapper_name`
+async fn create_member(device: &str, password: &str, mapper_name: String) -> Result<DataDiskMember, NcaError> {
+    let wiped = device.to_string();
+    blocking(move || run("/usr/sbin/wipefs", &["--all", "--force", &wiped])).await?;
+    create_encrypted_disk(password.to_string(), device.to_string()).await?;
+    let encrypted = device.to_string();
+    let luks_uuid = blocking(move || run("/usr/sbin/cryptsetup", &["luksUUID", &encrypted])).await?;
+    open_encrypted_disk(password.to_string(), device.to_string(), mapper_name.clone()).await?;
+    Ok(DataDiskMember {
+        device: device.to_string(),
+        luks_uuid,
+        mapper_name,
+    })
+}
+
+async fn close_members(members: &[DataDiskMember]) {
+    for member in members {
+        if let Err(e) = close_encrypted_disk(member.mapper_name.clone()).await {
+            eprintln!("{e}");
+        }
+    }
+}
+
+/// Wipes `devices`, encrypts them with `password`, creates the data file system on them (mirrored
+/// if there is more than one device) and copies the data in `layout.data_path` onto it. The
+/// encrypted devices are left open, the crypttab entries and mount unit that mount the pool at
+/// boot are written, but the file system is not mounted. The old data is left in place until
+/// [`remove_shadowed_data`] is called, so it can be discarded with [`discard_data_pool`].
+pub(crate) async fn prepare_data_pool(devices: &[String], password: String, layout: &DataDiskLayout) -> Result<DataPoolConfig, NcaError> {
+    println!("Preparing {} as data disk", devices.join(", "));
+    if devices.is_empty() {
+        return Err(NcaError::new_unexpected_error("No devices given for the data pool"));
+    }
+    let mut members = Vec::new();
+    for device in devices {
+        match create_member(device, &password, next_mapper_name(layout, &members)).await {
+            Ok(member) => members.push(member),
+            Err(e) => {
+                close_members(&members).await;
+                return Err(e);
+            }
+        }
+    }
+
+    let profile = match members.len() {
+        1 => PoolProfile::Single,
+        _ => PoolProfile::Raid1,
+    };
+    let created = {
+        let (layout, members) = (layout.clone(), members.clone());
+        blocking(move || {
+            let fs_uuid = make_filesystem(&layout, &members, profile)?;
+            copy_data(&layout, &members)?;
+            Ok(fs_uuid)
+        }).await
+    };
+    let fs_uuid = match created {
+        Ok(fs_uuid) => fs_uuid,
+        Err(e) => {
+            close_members(&members).await;
+            return Err(e);
+        }
+    };
+
+    if let Err(e) = write_crypttab(layout, &members).and_then(|_| write_mount_unit(layout, &fs_uuid)) {
+        if let Err(e) = remove_pool_units(layout) {
+            eprintln!("Failed to remove the units of the data pool: {e}");
+        }
+        close_members(&members).await;
+        return Err(e);
+    }
+    Ok(DataPoolConfig {
+        fs_uuid: Some(fs_uuid),
+        profile,
+        members,
+    })
+}
+
+/// Unmounts a pool created by [`prepare_data_pool`], removes its units and closes its devices. The
+/// data in its old location is still in use afterward.
+pub(crate) async fn discard_data_pool(pool: &DataPoolConfig, layout: &DataDiskLayout) {
+    if let Err(e) = stop_service(layout.mount_unit_name()).await {
+        eprintln!("Failed to unmount the data pool: {e}");
+    }
+    if let Err(e) = remove_pool_units(layout) {
+        eprintln!("Failed to remove the units of the data pool: {e}");
+    }
+    if let Err(e) = reload_systemd().await {
+        eprintln!("{e}");
+    }
+    close_members(&pool.members).await;
+}
+
+fn pool_fs_uuid(pool: &DataPoolConfig) -> Result<String, NcaError> {
+    match (&pool.fs_uuid, pool.members.first()) {
+        (Some(fs_uuid), _) => Ok(fs_uuid.clone()),
+        (None, Some(member)) => filesystem_uuid(&mapper_path(&member.mapper_name)),
+        (None, None) => Err(NcaError::new_unexpected_error("The data pool has no members")),
+    }
+}
+
+/// Adds `device` to the mounted pool. A pool with a single disk is converted to a mirror, the
+/// conversion continues in the background.
+pub(crate) async fn add_pool_member(pool: &DataPoolConfig, device: &str, password: String, layout: &DataDiskLayout) -> Result<DataPoolConfig, NcaError> {
+    println!("Adding {device} to the data pool");
+    let fs_uuid = pool_fs_uuid(pool)?;
+    let member = create_member(device, &password, next_mapper_name(layout, &pool.members)).await?;
+    let (new_path, data_path) = (mapper_path(&member.mapper_name), layout.data_path_str());
+    if let Err(e) = blocking(move || run(BTRFS, &["device", "add", &new_path, &data_path])).await {
+        close_members(&[member]).await;
+        return Err(e);
+    }
+
+    let mut pool = pool.clone();
+    pool.members.push(member);
+    pool.fs_uuid = Some(fs_uuid.clone());
+    if pool.profile == PoolProfile::Single {
+        let data_path = layout.data_path_str();
+        blocking(move || run(BTRFS, &["balance", "start", "--bg", "-dconvert=raid1", "-mconvert=raid1", &data_path])).await?;
+        pool.profile = PoolProfile::Raid1;
+    }
+    write_crypttab(layout, &pool.members)?;
+    write_mount_unit(layout, &fs_uuid)?;
+    Ok(pool)
+}
+
+/// A device of a btrfs file system as listed by `btrfs filesystem show`
+#[derive(Clone, Debug, PartialEq)]
+struct PoolDevice {
+    devid: u64,
+    path: Option<String>,
+    missing: bool,
+}
+
+fn parse_filesystem_show(output: &str) -> Vec<PoolDevice> {
+    output.lines()
+        .filter_map(|line| {
+            let tokens: Vec<&str> = line.split_whitespace().collect();
+            if tokens.first() != Some(&"devid") {
+                return None;
+            }
+            let devid = tokens.get(1)?.parse().ok()?;
+            let path_start = tokens.iter().position(|t| *t == "path")? + 1;
+            let missing = tokens.last() == Some(&"MISSING");
+            let path_end = if missing { tokens.len() - 1 } else { tokens.len() };
+            let path = tokens.get(path_start..path_end)
+                .map(|t| t.join(" "))
+                .filter(|p| !p.is_empty() && p != "<missing disk>");
+            Some(PoolDevice { devid, path, missing })
+        })
+        .collect()
+}
+
+/// Sums up the error counters of `btrfs device stats` per device (path or `devid:<n>`)
+fn parse_device_stats(output: &str) -> HashMap<String, u64> {
+    let mut errors = HashMap::new();
+    for line in output.lines() {
+        let (Some(device), Some(count)) = (
+            line.strip_prefix('[').and_then(|l| l.split_once("].")).map(|(device, _)| device),
+            line.split_whitespace().last().and_then(|c| c.parse::<u64>().ok())
+        ) else {
+            continue;
+        };
+        *errors.entry(device.to_string()).or_insert(0) += count;
+    }
+    errors
+}
+
+fn same_device(a: &str, b: &str) -> bool {
+    a == b || matches!((fs::canonicalize(a), fs::canonicalize(b)), (Ok(a), Ok(b)) if a == b)
+}
+
+fn find_pool_device<'a>(devices: &'a [PoolDevice], member: &DataDiskMember) -> Option<&'a PoolDevice> {
+    let path = mapper_path(&member.mapper_name);
+    devices.iter().find(|d| d.path.as_ref().is_some_and(|p| same_device(p, &path)))
+}
+
+/// The device `btrfs replace` should replace: the member's device if it is still available,
+/// otherwise the devid of the (only) missing device
+fn replace_source(devices: &[PoolDevice], member: &DataDiskMember) -> Option<String> {
+    if let Some(device) = find_pool_device(devices, member) {
+        return Some(match device.missing {
+            true => device.devid.to_string(),
+            false => mapper_path(&member.mapper_name),
+        });
+    }
+    match devices.iter().filter(|d| d.missing).collect::<Vec<_>>().as_slice() {
+        [device] => Some(device.devid.to_string()),
+        _ => None,
+    }
+}
+
+/// Replaces the pool member `failed` (device path or mapper name) with `new_device`. The data is
+/// copied to the new device in the background.
+pub(crate) async fn replace_pool_member(pool: &DataPoolConfig, failed: &str, new_device: &str, password: String, layout: &DataDiskLayout) -> Result<DataPoolConfig, NcaError> {
+    let index = pool.members.iter()
+        .position(|m| m.device == failed || m.mapper_name == failed)
+        .ok_or(NcaError::InvalidPath(failed.to_string(), "Not a member of the data pool".to_string()))?;
+    let failed_member = &pool.members[index];
+    println!("Replacing {} in the data pool with {new_device}", failed_member.device);
+    let fs_uuid = pool_fs_uuid(pool)?;
+    let data_path = layout.data_path_str();
+    let devices = parse_filesystem_show(&blocking(move || run(BTRFS, &["filesystem", "show", "--raw", &data_path])).await?);
+    let source = replace_source(&devices, failed_member)
+        .ok_or(NcaError::FaultySetup(format!("Unable to identify {} in the data pool", failed_member.device)))?;
+
+    let member = create_member(new_device, &password, next_mapper_name(layout, &pool.members)).await?;
+    let (new_path, data_path) = (mapper_path(&member.mapper_name), layout.data_path_str());
+    if let Err(e) = blocking(move || run(BTRFS, &["replace", "start", "-r", &source, &new_path, &data_path])).await {
+        close_members(&[member]).await;
+        return Err(e);
+    }
+
+    let mut pool = pool.clone();
+    pool.members[index] = member;
+    pool.fs_uuid = Some(fs_uuid.clone());
+    write_crypttab(layout, &pool.members)?;
+    write_mount_unit(layout, &fs_uuid)?;
+    Ok(pool)
+}
+
+fn is_balance_running(layout: &DataDiskLayout) -> bool {
+    // `btrfs balance status` exits with 1 while a balance is running
+    Command::new(BTRFS)
+        .args(["balance", "status", &layout.data_path_str()])
+        .stdin(Stdio::null())
+        .output()
+        .is_ok_and(|out| out.status.code() == Some(1))
+}
+
+pub(crate) fn pool_status(pool: &DataPoolConfig, layout: &DataDiskLayout) -> DataPoolStatus {
+    let show = run(BTRFS, &["filesystem", "show", "--raw", &layout.data_path_str()])
+        .or_else(|e| match &pool.fs_uuid {
+            None => Err(e),
+            Some(fs_uuid) => run(BTRFS, &["filesystem", "show", "--raw", fs_uuid]),
+        })
+        .inspect_err(|e| eprintln!("Failed to query data pool: {e}"))
+        .unwrap_or_default();
+    let devices = parse_filesystem_show(&show);
+    let errors = run(BTRFS, &["device", "stats", &layout.data_path_str()])
+        .map(|out| parse_device_stats(&out))
+        .unwrap_or_default();
+
+    let members: Vec<DataPoolMember> = pool.members.iter()
+        .map(|member| {
+            let device = find_pool_device(&devices, member);
+            let member_errors = errors.iter()
+                .filter(|(key, _)| match device {
+                    None => false,
+                    Some(d) => **key == format!("devid:{}", d.devid)
+                        || d.path.as_ref().is_some_and(|p| same_device(key, p)),
+                })
+                .map(|(_, count)| count)
+                .sum();
+            DataPoolMember {
+                device: member.device.clone(),
+                mapper_name: member.mapper_name.clone(),
+                luks_uuid: member.luks_uuid.clone(),
+                present: device.is_some_and(|d| !d.missing),
+                devid: device.map(|d| d.devid),
+                errors: member_errors,
+            }
+        })
+        .collect();
+    let degraded = devices.iter().any(|d| d.missing)
+        || members.iter().any(|m| !m.present || m.errors > 0);
+    let replace_status = run(BTRFS, &["replace", "status", "-1", &layout.data_path_str()]).ok()
+        .filter(|status| status != "Never started");
+
+    DataPoolStatus {
+        configured: true,
+        profile: crate::api::DataPoolProfile::from(pool.profile).into(),
+        fs_uuid: pool.fs_uuid.clone(),
+        members,
+        degraded,
+        replace_status,
+        balance_running: is_balance_running(layout),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_layout(name: &str) -> (PathBuf, DataDiskLayout) {
+        let dir = std::env::temp_dir().join(format!("nca-data-disk-{name}-{}", std::process::id()));
+        let layout = DataDiskLayout {
+            mapper_name: format!("nca-test-{name}-{}", std::process::id()),
+            data_path: dir.join("data"),
+            staging_path: dir.join("staging"),
+            crypttab_path: dir.join("crypttab"),
+            unit_dir: dir.join("units"),
+        };
+        (dir, layout)
+    }
+
+    fn member(layout: &DataDiskLayout, device: &str, suffix: &str) -> DataDiskMember {
+        DataDiskMember {
+            device: device.to_string(),
+            luks_uuid: format!("uuid-{device}"),
+            mapper_name: format!("{}{suffix}", layout.mapper_name),
+        }
+    }
+
+    #[test]
+    fn test_escape_unit_path() {
+        assert_eq!(escape_unit_path(Path::new("/var/data/ncatomic")), "var-data-ncatomic");
+        assert_eq!(escape_unit_path(Path::new("/var/data/nc-aio/")), "var-data-nc\\x2daio");
+        assert_eq!(escape_unit_path(Path::new("/")), "-");
+    }
+
+    #[test]
+    fn test_next_mapper_name() {
+        let (_, layout) = test_layout("names");
+        assert_eq!(next_mapper_name(&layout, &[]), layout.mapper_name);
+        let members = [member(&layout, "/dev/vdb", ""), member(&layout, "/dev/vdc", "-2")];
+        assert_eq!(next_mapper_name(&layout, &members), format!("{}-1", layout.mapper_name));
+        assert!(layout.is_pool_mapping(&format!("{}-12", layout.mapper_name)));
+        assert!(!layout.is_pool_mapping(&format!("{}-swap", layout.mapper_name)));
+    }
+
+    #[test]
+    fn test_write_units() {
+        let (dir, layout) = test_layout("units");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&layout.crypttab_path, format!("swap UUID=1 none\n{} UUID=old none luks\n", layout.mapper_name)).unwrap();
+
+        let members = [member(&layout, "/dev/vdb", ""), member(&layout, "/dev/vdc", "-1")];
+        write_crypttab(&layout, &members).unwrap();
+        write_mount_unit(&layout, "fs-uuid").unwrap();
+        write_mount_unit(&layout, "fs-uuid").unwrap();
+
+        let crypttab = fs::read_to_string(&layout.crypttab_path).unwrap();
+        assert_eq!(crypttab, format!("swap UUID=1 none\n\
+                                      {0} UUID=uuid-/dev/vdb none luks,discard,tpm2-device=auto\n\
+                                      {0}-1 UUID=uuid-/dev/vdc none luks,discard,tpm2-device=auto\n", layout.mapper_name));
+        write_crypttab(&layout, &members[1..]).unwrap();
+        let crypttab = fs::read_to_string(&layout.crypttab_path).unwrap();
+        assert_eq!(crypttab, format!("swap UUID=1 none\n{}-1 UUID=uuid-/dev/vdc none luks,discard,tpm2-device=auto\n", layout.mapper_name));
+
+        let unit_name = layout.mount_unit_name();
+        let unit = fs::read_to_string(layout.unit_dir.join(&unit_name)).unwrap();
+        assert!(unit.contains(&format!("Where={}", layout.data_path.display())));
+        assert!(unit.contains("What=/dev/disk/by-uuid/fs-uuid"));
+        assert!(layout.unit_dir.join("local-fs.target.wants").join(&unit_name).is_symlink());
+
+        remove_pool_units(&layout).unwrap();
+        assert_eq!(fs::read_to_string(&layout.crypttab_path).unwrap(), "swap UUID=1 none\n");
+        assert!(!layout.unit_dir.join(&unit_name).exists());
+        assert!(fs::symlink_metadata(layout.unit_dir.join("local-fs.target.wants").join(&unit_name)).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_filesystem_show() {
+        let output = "Label: 'ncatomic-data'  uuid: 5c1f6f1e-8f1a-4a4e-9a55-2b0a3c8a6d1e\n\
+                      \tTotal devices 3 FS bytes used 147456\n\
+                      \tdevid    1 size 10737418240 used 2172649472 path /dev/mapper/ncatomic-data\n\
+                      \tdevid    2 size 0 used 0 path <missing disk> MISSING\n\
+                      \tdevid    3 size 10737418240 used 0 path /dev/mapper/ncatomic-data-2 MISSING\n\
+                      \n\
+                      \t*** Some devices missing\n";
+        assert_eq!(parse_filesystem_show(output), vec![
+            PoolDevice { devid: 1, path: Some("/dev/mapper/ncatomic-data".to_string()), missing: false },
+            PoolDevice { devid: 2, path: None, missing: true },
+            PoolDevice { devid: 3, path: Some("/dev/mapper/ncatomic-data-2".to_string()), missing: true },
+        ]);
+    }
+
+    #[test]
+    fn test_replace_source() {
+        let layout = DataDiskLayout::default();
+        let first = member(&layout, "/dev/vdb", "");
+        let second = member(&layout, "/dev/vdc", "-1");
+        let devices = vec![
+            PoolDevice { devid: 1, path: Some("/dev/mapper/ncatomic-data".to_string()), missing: false },
+            PoolDevice { devid: 2, path: None, missing: true },
+        ];
+        assert_eq!(replace_source(&devices, &first).as_deref(), Some("/dev/mapper/ncatomic-data"));
+        assert_eq!(replace_source(&devices, &second).as_deref(), Some("2"));
+        let devices = vec![
+            PoolDevice { devid: 1, path: None, missing: true },
+            PoolDevice { devid: 2, path: None, missing: true },
+        ];
+        assert_eq!(replace_source(&devices, &second), None);
+    }
+
+    #[test]
+    fn test_parse_device_stats() {
+        let output = "[/dev/mapper/ncatomic-data].write_io_errs    0\n\
+                      [/dev/mapper/ncatomic-data].read_io_errs     3\n\
+                      [/dev/mapper/ncatomic-data].corruption_errs  1\n\
+                      [devid:2].write_io_errs    7\n\
+                      [devid:2].generation_errs  0\n";
+        let errors = parse_device_stats(output);
+        assert_eq!(errors.get("/dev/mapper/ncatomic-data"), Some(&4));
+        assert_eq!(errors.get("devid:2"), Some(&7));
+    }
+
+    fn loop_device(dir: &Path, name: &str) -> String {
+        let image = dir.join(name);
+        fs::File::create(&image).unwrap().set_len(256 * 1024 * 1024).unwrap();
+        run("/usr/sbin/losetup", &["--find", "--show", &image.to_string_lossy()]).unwrap()
+    }
+
+    /// Formats loop devices, so it needs root, cryptsetup and btrfs-progs.
+    /// Run with `cargo test --features api -- --ignored`
+    #[tokio::test]
+    #[ignore = "requires root privileges"]
+    async fn test_prepare_data_pool_on_loop_devices() {
+        let (dir, layout) = test_layout("loop");
+        fs::create_dir_all(layout.data_path.join("nc-aio")).unwrap();
+        fs::write(layout.data_path.join("nc-aio/file.txt"), "data").unwrap();
+        let devices = vec![loop_device(&dir, "disk1.img"), loop_device(&dir, "disk2.img")];
+
+        let result = prepare_data_pool(&devices, "test password".to_string(), &layout).await;
+
+        let staging_path = layout.staging_path.to_string_lossy().to_string();
+        let copied = result.as_ref().ok().map(|pool| {
+            run("/usr/bin/mount", &[&mapper_path(&pool.members[0].mapper_name), &staging_path]).unwrap();
+            let content = fs::read_to_string(layout.staging_path.join("nc-aio/file.txt")).ok();
+            run("/usr/bin/umount", &[&staging_path]).unwrap();
+            content
+        });
+        let kept = layout.data_path.join("nc-aio/file.txt").exists();
+        if let Ok(pool) = &result {
+            close_members(&pool.members).await;
+        }
+        for device in &devices {
+            run("/usr/sbin/losetup", &["--detach", device]).unwrap();
+        }
+        fs::remove_dir_all(&dir).unwrap();
+
+        let pool = result.unwrap();
+        assert_eq!(pool.profile, PoolProfile::Raid1);
+        assert_eq!(pool.members.iter().map(|m| m.device.clone()).collect::<Vec<_>>(), devices);
+        assert_eq!(copied, Some(Some("data".to_string())));
+        assert!(kept, "The data must stay in its old location until the pool is in use");
+    }
+}
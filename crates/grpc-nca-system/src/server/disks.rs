@@ -0,0 +1,374 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use rsblkid::cache::Cache;
+use nca_error::NcaError;
+use crate::api::BlockDevice;
+
+const SYSFS_BLOCK_PATH: &str = "/sys/class/block";
+const MOUNTS_PATH: &str = "/proc/self/mounts";
+// A disk is considered the system disk if any of these is mounted from it
+const SYSTEM_MOUNT_POINTS: &[&str] = &["/", "/sysroot", "/usr", "/boot", "/boot/efi", "/efi"];
+
+/// A block device as reported by the kernel, identified by its kernel name (e.g. `sda1`)
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct RawBlockDevice {
+    pub name: String,
+    pub device_type: String,
+    // The disk of a partition
+    pub parent: Option<String>,
+    // The devices a device mapper or md device is built on
+    pub slaves: Vec<String>,
+    pub size: u64,
+    pub model: Option<String>,
+    pub serial: Option<String>,
+    // Tags reported by blkid (TYPE, PTTYPE, UUID, LABEL, ...)
+    pub tags: HashMap<String, String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Mount {
+    // Kernel name of the mounted device
+    pub device: String,
+    pub mount_point: String,
+}
+
+pub(crate) trait BlockDeviceSource {
+    fn devices(&self) -> Result<Vec<RawBlockDevice>, NcaError>;
+    fn mounts(&self) -> Result<Vec<Mount>, NcaError>;
+}
+
+/// Reads block devices from sysfs and probes them with blkid
+pub(crate) struct SystemBlockDevices;
+
+impl BlockDeviceSource for SystemBlockDevices {
+    fn devices(&self) -> Result<Vec<RawBlockDevice>, NcaError> {
+        let tags = probe_blkid_tags()?;
+        let mut devices = Vec::new();
+        for entry in fs::read_dir(SYSFS_BLOCK_PATH).map_err(NcaError::new_io_error)? {
+            let entry = entry.map_err(NcaError::new_io_error)?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            let sys_path = entry.path();
+            // unused loop and ram devices
+            if name.starts_with("ram") || read_sysfs_u64(&sys_path.join("size")).unwrap_or(0) == 0 {
+                continue;
+            }
+            let is_partition = sys_path.join("partition").exists();
+            let parent = match is_partition {
+                false => None,
+                true => fs::canonicalize(&sys_path).ok()
+                    .and_then(|p| p.parent().and_then(|p| p.file_name()).map(|n| n.to_string_lossy().to_string())),
+            };
+            let slaves = fs::read_dir(sys_path.join("slaves"))
+                .map(|entries| entries.filter_map(|e| e.ok())
+                    .map(|e| e.file_name().to_string_lossy().to_string())
+                    .collect())
+                .unwrap_or_default();
+            let device_type = match (is_partition, name.as_str()) {
+                (true, _) => "part",
+                (false, n) if n.starts_with("loop") => "loop",
+                (false, n) if n.starts_with("dm-") => "dm",
+                (false, n) if n.starts_with("md") => "md",
+                (false, _) => "disk",
+            }.to_string();
+            devices.push(RawBlockDevice {
+                size: read_sysfs_u64(&sys_path.join("size")).unwrap_or(0) * 512,
+                model: read_sysfs_string(&sys_path.join("device/model")),
+                serial: read_sysfs_string(&sys_path.join("device/serial"))
+                    .or(read_sysfs_string(&sys_path.join("serial"))),
+                tags: tags.get(&name).cloned().unwrap_or_default(),
+                name,
+                device_type,
+                parent,
+                slaves,
+            });
+        }
+        Ok(devices)
+    }
+
+    fn mounts(&self) -> Result<Vec<Mount>, NcaError> {
+        let data = fs::read_to_string(MOUNTS_PATH)
+            .map_err(|e| NcaError::new_io_error(format!("Failed to read {MOUNTS_PATH}: {e:?}")))?;
+        Ok(parse_mounts(&data).into_iter()
+            .filter_map(|(device, mount_point)| kernel_name(Path::new(&device))
+                .map(|device| Mount { device, mount_point }))
+            .collect())
+    }
+}
+
+/// The block devices and mounts at one point in time, to avoid probing the devices repeatedly
+pub(crate) struct BlockDeviceSnapshot {
+    devices: Vec<RawBlockDevice>,
+    mounts: Vec<Mount>,
+}
+
+impl BlockDeviceSnapshot {
+    pub fn capture<S: BlockDeviceSource>(source: &S) -> Result<Self, NcaError> {
+        Ok(Self {
+            devices: source.devices()?,
+            mounts: source.mounts()?,
+        })
+    }
+}
+
+impl BlockDeviceSource for BlockDeviceSnapshot {
+    fn devices(&self) -> Result<Vec<RawBlockDevice>, NcaError> {
+        Ok(self.devices.clone())
+    }
+
+    fn mounts(&self) -> Result<Vec<Mount>, NcaError> {
+        Ok(self.mounts.clone())
+    }
+}
+
+fn read_sysfs_string(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+fn read_sysfs_u64(path: &Path) -> Option<u64> {
+    read_sysfs_string(path).and_then(|s| s.parse().ok())
+}
+
+/// Resolves device paths like `/dev/mapper/root` to the kernel name of the device (`dm-0`)
+fn kernel_name(device_path: &Path) -> Option<String> {
+    if !device_path.starts_with("/dev/") {
+        return None;
+    }
+    fs::canonicalize(device_path).ok()
+        .unwrap_or(PathBuf::from(device_path))
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+}
+
+fn probe_blkid_tags() -> Result<HashMap<String, HashMap<String, String>>, NcaError> {
+    let mut cache = Cache::builder()
+        .discard_changes_on_drop()
+        .build()
+        .map_err(|e| NcaError::new_io_error(format!("Failed create blkid cache: {e:?}")))?;
+    cache.probe_all_devices()
+        .map_err(|e| NcaError::new_io_error(format!("Failed to scan for devices: {e:?}")))?;
+    Ok(cache.iter()
+        .filter_map(|d| kernel_name(d.name()).map(|name| (
+            name,
+            d.iter().map(|t| (t.name().as_str().to_string(), t.value().to_string())).collect()
+        )))
+        .collect())
+}
+
+/// Parses `/proc/self/mounts` into (device, mount point) pairs
+fn parse_mounts(data: &str) -> Vec<(String, String)> {
+    data.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            Some((unescape_mount_field(fields.next()?), unescape_mount_field(fields.next()?)))
+        })
+        .collect()
+}
+
+// Whitespace and backslashes are octal-escaped in /proc/self/mounts
+fn unescape_mount_field(field: &str) -> String {
+    let mut result = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            let octal: String = chars.clone().take(3).collect();
+            if octal.len() == 3 && let Ok(value) = u8::from_str_radix(&octal, 8) {
+                result.push(value as char);
+                chars.nth(2);
+                continue;
+            }
+        }
+        result.push(c);
+    }
+    result
+}
+
+/// The disks (devices without parents or slaves) `name` is stored on
+fn root_disks(name: &str, devices: &HashMap<&str, &RawBlockDevice>, visited: &mut HashSet<String>) -> HashSet<String> {
+    if !visited.insert(name.to_string()) {
+        return HashSet::new();
+    }
+    match devices.get(name) {
+        None => HashSet::from([name.to_string()]),
+        Some(device) => match (&device.parent, device.slaves.is_empty()) {
+            (Some(parent), _) => root_disks(parent, devices, visited),
+            (None, false) => device.slaves.iter()
+                .flat_map(|slave| root_disks(slave, devices, visited))
+                .collect(),
+            (None, true) => HashSet::from([name.to_string()]),
+        }
+    }
+}
+
+pub(crate) fn list_block_devices<S: BlockDeviceSource>(source: &S) -> Result<Vec<BlockDevice>, NcaError> {
+    let raw_devices = source.devices()?;
+    let mounts = source.mounts()?;
+    let by_name: HashMap<&str, &RawBlockDevice> = raw_devices.iter()
+        .map(|d| (d.name.as_str(), d))
+        .collect();
+
+    let system_disks: HashSet<String> = mounts.iter()
+        .filter(|m| SYSTEM_MOUNT_POINTS.contains(&m.mount_point.as_str()))
+        .flat_map(|m| root_disks(&m.device, &by_name, &mut HashSet::new()))
+        .collect();
+
+    let mut devices: Vec<BlockDevice> = raw_devices.iter()
+        .map(|d| BlockDevice {
+            path: format!("/dev/{}", d.name),
+            name: d.name.clone(),
+            device_type: d.device_type.clone(),
+            parent: d.parent.clone(),
+            size: d.size,
+            model: d.model.clone(),
+            serial: d.serial.clone(),
+            partition_table: d.tags.get("PTTYPE").cloned(),
+            fs_type: d.tags.get("TYPE").cloned(),
+            uuid: d.tags.get("UUID").cloned(),
+            label: d.tags.get("LABEL").or(d.tags.get("PARTLABEL")).cloned(),
+            is_luks: d.tags.get("TYPE").is_some_and(|t| t == "crypto_LUKS"),
+            mount_point: mounts.iter().find(|m| m.device == d.name).map(|m| m.mount_point.clone()),
+            is_system_disk: !root_disks(&d.name, &by_name, &mut HashSet::new()).is_disjoint(&system_disks),
+        })
+        .collect();
+    devices.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(devices)
+}
+
+/// The disks that store any mounted file system
+pub(crate) fn mounted_disks<S: BlockDeviceSource>(source: &S) -> Result<HashSet<String>, NcaError> {
+    let raw_devices = source.devices()?;
+    let by_name: HashMap<&str, &RawBlockDevice> = raw_devices.iter()
+        .map(|d| (d.name.as_str(), d))
+        .collect();
+    Ok(source.mounts()?.iter()
+        .flat_map(|m| root_disks(&m.device, &by_name, &mut HashSet::new()))
+        .collect())
+}
+
+/// The mount that contains `path` and the disks it is stored on
+pub(crate) fn find_mount<S: BlockDeviceSource>(source: &S, path: &Path) -> Result<Option<(Mount, HashSet<String>)>, NcaError> {
+    let mount = source.mounts()?.into_iter()
+        .filter(|m| path.starts_with(&m.mount_point))
+        // max_by_key picks the last of several mounts at the same mount point, which is the visible one
+        .max_by_key(|m| Path::new(&m.mount_point).components().count());
+    let mount = match mount {
+        None => return Ok(None),
+        Some(mount) => mount,
+    };
+    let raw_devices = source.devices()?;
+    let by_name: HashMap<&str, &RawBlockDevice> = raw_devices.iter()
+        .map(|d| (d.name.as_str(), d))
+        .collect();
+    let disks = root_disks(&mount.device, &by_name, &mut HashSet::new());
+    Ok(Some((mount, disks)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FixtureBlockDevices {
+        devices: Vec<RawBlockDevice>,
+        mounts: Vec<Mount>,
+    }
+
+    impl BlockDeviceSource for FixtureBlockDevices {
+        fn devices(&self) -> Result<Vec<RawBlockDevice>, NcaError> {
+            Ok(self.devices.clone())
+        }
+
+        fn mounts(&self) -> Result<Vec<Mount>, NcaError> {
+            Ok(self.mounts.clone())
+        }
+    }
+
+    fn device(name: &str, device_type: &str, parent: Option<&str>, slaves: &[&str], tags: &[(&str, &str)]) -> RawBlockDevice {
+        RawBlockDevice {
+            name: name.to_string(),
+            device_type: device_type.to_string(),
+            parent: parent.map(str::to_string),
+            slaves: slaves.iter().map(|s| s.to_string()).collect(),
+            size: 1024,
+            tags: tags.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            ..RawBlockDevice::default()
+        }
+    }
+
+    fn fixture() -> FixtureBlockDevices {
+        FixtureBlockDevices {
+            devices: vec![
+                RawBlockDevice {
+                    model: Some("QEMU HARDDISK".to_string()),
+                    serial: Some("QM0001".to_string()),
+                    ..device("vda", "disk", None, &[], &[("PTTYPE", "gpt")])
+                },
+                device("vda1", "part", Some("vda"), &[], &[("TYPE", "vfat"), ("PARTLABEL", "esp")]),
+                device("vda2", "part", Some("vda"), &[], &[("TYPE", "crypto_LUKS"), ("UUID", "1234")]),
+                device("dm-0", "dm", None, &["vda2"], &[("TYPE", "btrfs")]),
+                device("vdb", "disk", None, &[], &[]),
+                device("vdc", "disk", None, &[], &[("TYPE", "crypto_LUKS")]),
+                device("dm-1", "dm", None, &["vdc"], &[("TYPE", "ext4")]),
+            ],
+            mounts: vec![
+                Mount { device: "dm-0".to_string(), mount_point: "/sysroot".to_string() },
+                Mount { device: "vda1".to_string(), mount_point: "/boot/efi".to_string() },
+                Mount { device: "dm-1".to_string(), mount_point: "/var/data/ncatomic".to_string() },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_list_block_devices() {
+        let devices = list_block_devices(&fixture()).unwrap();
+        let get = |name: &str| devices.iter().find(|d| d.name == name).unwrap();
+
+        assert_eq!(devices.len(), 7);
+        assert!(get("vda").is_system_disk);
+        assert!(get("vda2").is_system_disk);
+        assert!(get("dm-0").is_system_disk);
+        assert!(!get("vdb").is_system_disk);
+        assert!(!get("vdc").is_system_disk);
+        assert!(!get("dm-1").is_system_disk);
+
+        assert_eq!(get("vda").model.as_deref(), Some("QEMU HARDDISK"));
+        assert_eq!(get("vda").partition_table.as_deref(), Some("gpt"));
+        assert_eq!(get("vda1").label.as_deref(), Some("esp"));
+        assert_eq!(get("vda1").mount_point.as_deref(), Some("/boot/efi"));
+        assert!(get("vda2").is_luks);
+        assert!(get("vdc").is_luks);
+        assert!(!get("dm-1").is_luks);
+        assert_eq!(get("dm-1").fs_type.as_deref(), Some("ext4"));
+        assert_eq!(get("dm-1").mount_point.as_deref(), Some("/var/data/ncatomic"));
+        assert_eq!(get("vdb").path, "/dev/vdb");
+    }
+
+    #[test]
+    fn test_mounted_disks() {
+        let disks = mounted_disks(&fixture()).unwrap();
+        assert_eq!(disks, HashSet::from(["vda".to_string(), "vdc".to_string()]));
+    }
+
+    #[test]
+    fn test_find_mount() {
+        let (mount, disks) = find_mount(&fixture(), Path::new("/var/data/ncatomic/nc-aio")).unwrap().unwrap();
+        assert_eq!(mount.device, "dm-1");
+        assert_eq!(disks, HashSet::from(["vdc".to_string()]));
+        let (mount, _) = find_mount(&fixture(), Path::new("/boot/efi")).unwrap().unwrap();
+        assert_eq!(mount.device, "vda1");
+        assert!(find_mount(&fixture(), Path::new("/var")).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_parse_mounts() {
+        let data = "/dev/mapper/root /sysroot btrfs rw,relatime 0 0\n\
+                    proc /proc proc rw 0 0\n\
+                    /dev/vdb1 /mnt/my\\040disk ext4 rw 0 0\n";
+        assert_eq!(parse_mounts(data), vec![
+            ("/dev/mapper/root".to_string(), "/sysroot".to_string()),
+            ("proc".to_string(), "/proc".to_string()),
+            ("/dev/vdb1".to_string(), "/mnt/my disk".to_string()),
+        ]);
+    }
+}
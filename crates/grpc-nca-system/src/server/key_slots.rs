@@ -0,0 +1,160 @@
+use std::collections::{BTreeMap, HashSet};
+use serde::Deserialize;
+use nca_error::NcaError;
+use crate::api::{KeySlot, KeySlotType};
+
+// Alphabet of the recovery keys generated by systemd-cryptenroll
+const MODHEX: &str = "cbdefghijklnrtuv";
+
+#[derive(Deserialize)]
+struct LuksMetadata {
+    keyslots: BTreeMap<String, serde_json::Value>,
+    #[serde(default)]
+    tokens: BTreeMap<String, LuksToken>,
+}
+
+#[derive(Deserialize)]
+struct LuksToken {
+    #[serde(rename = "type")]
+    token_type: String,
+    #[serde(default)]
+    keyslots: Vec<String>,
+    #[serde(rename = "tpm2-pcrs", default)]
+    tpm2_pcrs: Vec<u32>,
+}
+
+fn key_slot_type(token_type: &str) -> KeySlotType {
+    match token_type {
+        "systemd-tpm2" => KeySlotType::KeySlotTpm2,
+        "systemd-recovery" => KeySlotType::KeySlotRecovery,
+        "systemd-fido2" => KeySlotType::KeySlotFido2,
+        "systemd-pkcs11" => KeySlotType::KeySlotPkcs11,
+        _ => KeySlotType::KeySlotOther,
+    }
+}
+
+/// Parses the output of `cryptsetup luksDump --dump-json-metadata`. Key slots without a token
+/// are passwords.
+pub(crate) fn parse_key_slots(metadata: &str) -> Result<Vec<KeySlot>, NcaError> {
+    let metadata: LuksMetadata = serde_json::from_str(metadata)
+        .map_err(|e| NcaError::new_io_error(format!("Failed to parse LUKS metadata: {e:?}")))?;
+    let mut slots = Vec::new();
+    for slot in metadata.keyslots.keys() {
+        let slot_number = slot.parse::<u32>()
+            .map_err(|e| NcaError::new_io_error(format!("Invalid key slot '{slot}': {e:?}")))?;
+        let token = metadata.tokens.iter()
+            .find(|(_, token)| token.keyslots.contains(slot));
+        slots.push(match token {
+            None => KeySlot {
+                slot: slot_number,
+                r#type: KeySlotType::KeySlotPassword.into(),
+                ..KeySlot::default()
+            },
+            Some((id, token)) => KeySlot {
+                slot: slot_number,
+                r#type: key_slot_type(&token.token_type).into(),
+                token: id.parse().ok(),
+                token_type: Some(token.token_type.clone()),
+                tpm2_pcrs: token.tpm2_pcrs.clone(),
+            },
+        });
+    }
+    slots.sort_by_key(|s| s.slot);
+    Ok(slots)
+}
+
+/// Makes sure that the device can still be unlocked after removing key slot `slot`. Only slots that
+/// were verified to unlock the device (`working_slots`) count, which is possible for passwords and
+/// TPM2 tokens. Recovery keys, FIDO2 and PKCS#11 tokens can't be tested here and never count.
+pub(crate) fn check_key_slot_removal(slots: &[KeySlot], slot: u32, working_slots: &HashSet<u32>) -> Result<(), NcaError> {
+    if !slots.iter().any(|s| s.slot == slot) {
+        return Err(NcaError::new_missing_config_error(format!("There is no key slot {slot}")));
+    }
+    let remaining_unlock_methods = slots.iter()
+        .filter(|s| s.slot != slot)
+        .filter(|s| match s.r#type() {
+            KeySlotType::KeySlotPassword | KeySlotType::KeySlotTpm2 => working_slots.contains(&s.slot),
+            _ => false,
+        })
+        .count();
+    if remaining_unlock_methods == 0 {
+        return Err(NcaError::FaultySetup(format!(
+            "Removing key slot {slot} would leave the device without a working unlock method")));
+    }
+    Ok(())
+}
+
+/// Finds the recovery key (eight blocks of eight modhex characters) in the output of
+/// `systemd-cryptenroll --recovery-key`
+pub(crate) fn parse_recovery_key(output: &str) -> Option<String> {
+    output.split_whitespace()
+        .find(|word| {
+            let blocks: Vec<&str> = word.split('-').collect();
+            blocks.len() == 8 && blocks.iter().all(|b| b.len() == 8 && b.chars().all(|c| MODHEX.contains(c)))
+        })
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METADATA: &str = r#"{
+        "keyslots": {
+            "0": {"type": "luks2", "key_size": 64},
+            "1": {"type": "luks2", "key_size": 64},
+            "2": {"type": "luks2", "key_size": 64},
+            "3": {"type": "luks2", "key_size": 64}
+        },
+        "tokens": {
+            "0": {"type": "systemd-tpm2", "keyslots": ["1"], "tpm2-pcrs": [7]},
+            "1": {"type": "systemd-recovery", "keyslots": ["2"]},
+            "2": {"type": "systemd-fido2", "keyslots": ["3"]}
+        },
+        "segments": {},
+        "digests": {},
+        "config": {"json_size": "12288", "keyslots_size": "16744448"}
+    }"#;
+
+    #[test]
+    fn test_parse_key_slots() {
+        let slots = parse_key_slots(METADATA).unwrap();
+        let types: Vec<KeySlotType> = slots.iter().map(|s| s.r#type()).collect();
+        assert_eq!(types, vec![
+            KeySlotType::KeySlotPassword,
+            KeySlotType::KeySlotTpm2,
+            KeySlotType::KeySlotRecovery,
+            KeySlotType::KeySlotFido2,
+        ]);
+        assert_eq!(slots[0].token, None);
+        assert_eq!(slots[1].token, Some(0));
+        assert_eq!(slots[1].tpm2_pcrs, vec![7]);
+        assert_eq!(slots[2].token_type.as_deref(), Some("systemd-recovery"));
+    }
+
+    #[test]
+    fn test_check_key_slot_removal() {
+        let slots = parse_key_slots(METADATA).unwrap();
+        assert!(check_key_slot_removal(&slots, 1, &HashSet::from([0])).is_ok());
+        assert!(check_key_slot_removal(&slots, 7, &HashSet::from([0])).is_err());
+        // recovery and FIDO2 slots can't be verified, so they don't count as unlock method
+        assert!(check_key_slot_removal(&slots, 0, &HashSet::new()).is_err());
+
+        let password_and_tpm2 = &slots[..2];
+        // a stale TPM2 token does not count as unlock method
+        assert!(check_key_slot_removal(password_and_tpm2, 0, &HashSet::new()).is_err());
+        assert!(check_key_slot_removal(password_and_tpm2, 0, &HashSet::from([1])).is_ok());
+        // an unverified password does not count as unlock method
+        assert!(check_key_slot_removal(password_and_tpm2, 1, &HashSet::new()).is_err());
+        assert!(check_key_slot_removal(password_and_tpm2, 1, &HashSet::from([0])).is_ok());
+        assert!(check_key_slot_removal(&slots[..1], 0, &HashSet::from([0])).is_err());
+    }
+
+    #[test]
+    fn test_parse_recovery_key() {
+        let key = "fhvrttjb-nkhhfhfv-bkcirdtv-ltlevtld-lrtrnhdi-uthgegeb-ijhthgkj-dcdhkvkn";
+        let output = format!("A secret recovery key has been generated for this volume:\n\n    \u{1f510} {key}\n\n");
+        assert_eq!(parse_recovery_key(&output).as_deref(), Some(key));
+        assert_eq!(parse_recovery_key("New recovery key enrolled as key slot 2."), None);
+    }
+}
@@ -1,4 +1,5 @@
 pub mod credentials;
 pub mod storage;
 pub mod nextcloud;
-pub mod system;
\ No newline at end of file
+pub mod system;
+pub mod backup;
\ No newline at end of file
@@ -0,0 +1,352 @@
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::Sender;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+use tonic::codegen::tokio_stream::wrappers::ReceiverStream;
+use tonic::{Request, Response, Status};
+use nca_error::NcaError;
+use nca_system_api::systemd::api::{get_service_status, start_service, stop_service};
+use nca_system_api::systemd::types::ServiceStatus;
+use crate::api::backup_server::Backup;
+use crate::api::{BackupId, BackupList, BackupPhase, BackupProgress, BackupSettings, BackupStatus, Empty, RestorePhase, RestoreProgress, RestoreRequest, StatusResponse};
+use crate::crypto::{b32_encode, try_parse_salt, Salt};
+use crate::server::backup::{disable_restore_maintenance_mode, enable_restore_maintenance_mode, restore_database, run_backup, ResticRepository};
+use crate::server::config::backup::{BackupConfig, DEFAULT_REPOSITORY_PATH};
+use crate::server::config::Config;
+use crate::server::service::credentials::{derive_credentials, verify_credentials};
+use crate::server::util::blocking;
+
+const NC_AIO_SERVICE: &str = "nextcloud-all-in-one.service";
+const SERVICE_STOP_TIMEOUT: Duration = Duration::from_secs(300);
+
+pub struct BackupService {
+    config: Arc<Mutex<Config>>,
+    // Held while a backup or restore is running
+    operation: Arc<Mutex<()>>,
+    // Progress of the currently running backup (if any)
+    progress: Arc<std::sync::Mutex<Option<BackupProgress>>>,
+}
+
+impl BackupService {
+    pub fn new(config: Arc<Mutex<Config>>) -> Self {
+        Self {
+            config,
+            operation: Arc::new(Mutex::new(())),
+            progress: Arc::new(std::sync::Mutex::new(None)),
+        }
+    }
+
+    async fn repository(&self) -> Result<(ResticRepository, BackupConfig), Status> {
+        let cfg = self.config.lock().await;
+        let backup_config = cfg.state().backup.clone()
+            .ok_or(Status::failed_precondition("Backups have not been configured yet"))?;
+        let password = cfg.backup_password()?;
+        Ok((ResticRepository::new(&backup_config.repository_path, password), backup_config))
+    }
+
+    fn start_operation(&self) -> Option<OwnedMutexGuard<()>> {
+        self.operation.clone().try_lock_owned().ok()
+    }
+
+    /// Determines the backup repository and password for a restore. On instances that have not
+    /// been set up yet, the salt is read from the repository unless it was provided explicitly.
+    /// Once set up, the primary password has to match the instance's credentials.
+    async fn restore_credentials(&self, request: &RestoreRequest) -> Result<(ResticRepository, Salt, bool), Status> {
+        let (setup_complete, salt, backup_config) = {
+            let cfg = self.config.lock().await;
+            (cfg.state().setup_complete, cfg.salt, cfg.state().backup.clone())
+        };
+        let repository_path = request.repository_path.clone()
+            .or(backup_config.map(|c| c.repository_path))
+            .unwrap_or(DEFAULT_REPOSITORY_PATH.to_string());
+        if !Path::new(&repository_path).is_absolute() {
+            return Err(NcaError::InvalidPath(repository_path, "The backup repository path must be absolute".to_string()).into());
+        }
+
+        let salt = match (&request.salt, setup_complete, salt) {
+            (Some(_), true, _) => return Err(Status::invalid_argument("The salt can only be provided before setup is complete")),
+            (Some(salt), false, _) => try_parse_salt(salt)?,
+            (None, true, Some(salt)) => salt,
+            (None, true, None) => return Err(Status::failed_precondition("salt not set")),
+            (None, false, _) => try_parse_salt(
+                &ResticRepository::new(&repository_path, String::new()).read_salt()?)?,
+        };
+        let credentials = derive_credentials(&salt, request.primary_password.clone())?;
+        if setup_complete {
+            verify_credentials(&self.config, &credentials).await?;
+        }
+        Ok((ResticRepository::new(repository_path, credentials.backup_password), salt, setup_complete))
+    }
+}
+
+fn unix_timestamp() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or_default()
+}
+
+fn is_valid_backup_id(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn restore_phase(phase: RestorePhase) -> RestoreProgress {
+    RestoreProgress {
+        phase: phase.into(),
+        ..RestoreProgress::default()
+    }
+}
+
+async fn wait_for_service_stop(name: &str) -> Result<(), NcaError> {
+    let start = Instant::now();
+    while start.elapsed() < SERVICE_STOP_TIMEOUT {
+        match get_service_status(name.to_string()).await {
+            Ok(ServiceStatus::INACTIVE | ServiceStatus::FAILED) => return Ok(()),
+            Ok(_) => {},
+            // The unit is not loaded anymore
+            Err(_) => return Ok(()),
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    Err(NcaError::SystemdError(format!("Timed out waiting for {name} to stop")))
+}
+
+/// Stops nextcloud and restores the files from the backup. Nextcloud is started in maintenance
+/// mode, which only ends once its database has been restored as well.
+async fn restore_nextcloud(
+    repository: ResticRepository,
+    backup_id: String,
+    tx: &Sender<Result<RestoreProgress, Status>>,
+) -> Result<(), NcaError> {
+    let _ = tx.send(Ok(restore_phase(RestorePhase::StoppingServices))).await;
+    stop_service(NC_AIO_SERVICE.to_string()).await?;
+    wait_for_service_stop(NC_AIO_SERVICE).await?;
+
+    let _ = tx.send(Ok(restore_phase(RestorePhase::RestoringFiles))).await;
+    let progress_tx = tx.clone();
+    let restored = blocking(move || repository.restore(&backup_id, false, |p| {
+        let _ = progress_tx.blocking_send(Ok(p));
+    })).await;
+    // Nextcloud must not run with the restored files and the old database, so it is left stopped
+    // if it can't be put into maintenance mode
+    if restored.is_ok() {
+        blocking(enable_restore_maintenance_mode).await?;
+    }
+
+    // Nextcloud is started again even if restoring the files failed, so the instance stays usable
+    let _ = tx.send(Ok(restore_phase(RestorePhase::StartingServices))).await;
+    let started = start_service(NC_AIO_SERVICE.to_string()).await;
+    restored?;
+    started?;
+
+    // The files don't match the database until it has been restored, so Nextcloud stays in
+    // maintenance mode if that fails
+    let _ = tx.send(Ok(restore_phase(RestorePhase::RestoringDatabase))).await;
+    blocking(|| {
+        restore_database()?;
+        disable_restore_maintenance_mode()
+    }).await
+}
+
+#[tonic::async_trait]
+impl Backup for BackupService {
+    async fn configure_backup(&self, request: Request<BackupSettings>) -> Result<Response<BackupSettings>, Status> {
+        let settings = request.into_inner();
+        if !Path::new(&settings.repository_path).is_absolute() {
+            return Err(NcaError::InvalidPath(settings.repository_path,
+                                             "The backup repository path must be absolute".to_string()).into());
+        }
+        let _guard = self.start_operation()
+            .ok_or(Status::failed_precondition("Another backup or restore is currently running"))?;
+        let backup_config = BackupConfig::from(settings);
+        self.config.lock().await
+            .update_state(|state| state.backup = Some(backup_config.clone()))
+            .await?;
+        Ok(Response::new(backup_config.into()))
+    }
+
+    type CreateBackupStream = ReceiverStream<Result<BackupProgress, Status>>;
+
+    async fn create_backup(&self, _request: Request<Empty>) -> Result<Response<Self::CreateBackupStream>, Status> {
+        let (repository, backup_config) = self.repository().await?;
+        let salt_b32 = {
+            self.config.lock().await.salt
+                .map(|salt| b32_encode(&salt))
+                .ok_or(Status::failed_precondition("salt not set"))?
+        };
+        let guard = self.start_operation()
+            .ok_or(Status::failed_precondition("Another backup or restore is currently running"))?;
+        if let Ok(mut progress) = self.progress.lock() {
+            *progress = Some(BackupProgress::default());
+        }
+
+        let (tx, rx) = tokio::sync::mpsc::channel(100);
+        let config = self.config.clone();
+        let progress = self.progress.clone();
+        tokio::spawn(async move {
+            let _guard = guard;
+            let report = {
+                let tx = tx.clone();
+                let progress = progress.clone();
+                move |p: BackupProgress| {
+                    if let Ok(mut current) = progress.lock() {
+                        *current = Some(p.clone());
+                    }
+                    // The backup continues if the client has disconnected
+                    let _ = tx.blocking_send(Ok(p));
+                }
+            };
+            let result = blocking(move || run_backup(&repository, &backup_config.retention, &salt_b32, report)).await;
+
+            let now = unix_timestamp();
+            let update = config.lock().await
+                .update_state(|state| match &result {
+                    Ok(snapshot_id) => {
+                        state.backup_history.last_success = Some(now);
+                        state.backup_history.last_snapshot_id = Some(snapshot_id.clone());
+                    },
+                    Err(e) => {
+                        state.backup_history.last_failure = Some(now);
+                        state.backup_history.last_error = Some(e.to_string());
+                    }
+                })
+                .await;
+            if let Err(e) = update {
+                eprintln!("Failed to record backup result: {e:?}");
+            }
+            if let Ok(mut current) = progress.lock() {
+                *current = None;
+            }
+
+            let msg = match result {
+                Ok(snapshot_id) => {
+                    println!("Backup {snapshot_id} created successfully");
+                    Ok(BackupProgress {
+                        phase: BackupPhase::Completed.into(),
+                        percent_done: 1.0,
+                        snapshot_id: Some(snapshot_id),
+                        ..BackupProgress::default()
+                    })
+                },
+                Err(e) => {
+                    eprintln!("Backup failed: {e}");
+                    Err(e.into())
+                }
+            };
+            let _ = tx.send(msg).await;
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn list_backups(&self, _request: Request<Empty>) -> Result<Response<BackupList>, Status> {
+        let (repository, _) = self.repository().await?;
+        if !repository.is_initialized() {
+            return Ok(Response::new(BackupList::default()));
+        }
+        let backups = blocking(move || repository.snapshots()).await?;
+        Ok(Response::new(BackupList { backups }))
+    }
+
+    async fn delete_backup(&self, request: Request<BackupId>) -> Result<Response<StatusResponse>, Status> {
+        let id = request.into_inner().id;
+        if !is_valid_backup_id(&id) {
+            return Err(Status::invalid_argument(format!("Invalid backup id: '{id}'")));
+        }
+        let (repository, _) = self.repository().await?;
+        let _guard = self.start_operation()
+            .ok_or(Status::failed_precondition("Another backup or restore is currently running"))?;
+        blocking(move || repository.delete_snapshot(&id)).await?;
+        Ok(Response::new(StatusResponse {
+            status: 200,
+            status_text: "Backup deleted successfully".to_string(),
+        }))
+    }
+
+    async fn get_backup_status(&self, _request: Request<Empty>) -> Result<Response<BackupStatus>, Status> {
+        let (configured, history) = {
+            let cfg = self.config.lock().await;
+            (cfg.state().backup.is_some(), cfg.state().backup_history.clone())
+        };
+        let progress = self.progress.lock()
+            .map_err(|e| NcaError::new_unexpected_error(format!("Failed to retrieve backup progress: {e:?}")))?
+            .clone();
+        Ok(Response::new(BackupStatus {
+            configured,
+            running: progress.is_some(),
+            progress,
+            last_success: history.last_success,
+            last_snapshot_id: history.last_snapshot_id,
+            last_failure: history.last_failure,
+            last_error: history.last_error,
+        }))
+    }
+
+    type RestoreBackupStream = ReceiverStream<Result<RestoreProgress, Status>>;
+
+    async fn restore_backup(&self, request: Request<RestoreRequest>) -> Result<Response<Self::RestoreBackupStream>, Status> {
+        let request = request.into_inner();
+        if !is_valid_backup_id(&request.backup_id) {
+            return Err(Status::invalid_argument(format!("Invalid backup id: '{}'", request.backup_id)));
+        }
+        let (repository, salt, setup_complete) = self.restore_credentials(&request).await?;
+        if !repository.is_initialized() {
+            return Err(Status::not_found("No backup repository found"));
+        }
+        let guard = self.start_operation()
+            .ok_or(Status::failed_precondition("Another backup or restore is currently running"))?;
+
+        let (tx, rx) = tokio::sync::mpsc::channel(100);
+        let config = self.config.clone();
+        tokio::spawn(async move {
+            let _guard = guard;
+            let result: Result<(), NcaError> = async {
+                let _ = tx.send(Ok(restore_phase(RestorePhase::Verifying))).await;
+                let verify_repository = repository.clone();
+                blocking(move || verify_repository.check()).await?;
+
+                if request.dry_run {
+                    let progress_tx = tx.clone();
+                    return blocking(move || repository.restore(&request.backup_id, true, |p| {
+                        let _ = progress_tx.blocking_send(Ok(p));
+                    })).await;
+                }
+
+                let repository_path = repository.path().to_string_lossy().to_string();
+                restore_nextcloud(repository, request.backup_id.clone(), &tx).await?;
+
+                if !setup_complete {
+                    // Restoring replaces InitializeCredentials when setting up a new instance
+                    let credentials = derive_credentials(&salt, request.primary_password)?;
+                    let mut cfg = config.lock().await;
+                    cfg.set_salt(salt).await?;
+                    cfg.update_state(|state| {
+                        state.credentials_config = Some(credentials);
+                        if state.backup.is_none() {
+                            state.backup = Some(BackupConfig {
+                                repository_path,
+                                ..BackupConfig::default()
+                            });
+                        }
+                    }).await?;
+                }
+                Ok(())
+            }.await;
+
+            let msg = match result {
+                Ok(()) => Ok(RestoreProgress {
+                    phase: RestorePhase::RestoreCompleted.into(),
+                    percent_done: 1.0,
+                    ..RestoreProgress::default()
+                }),
+                Err(e) => {
+                    eprintln!("Restore failed: {e}");
+                    Err(e.into())
+                }
+            };
+            let _ = tx.send(msg).await;
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
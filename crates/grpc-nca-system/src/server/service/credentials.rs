@@ -3,13 +3,14 @@ use tokio::sync::Mutex;
 use tonic::{Request, Response, Status};
 use nca_error::NcaError;
 // use crate::api:;
-use crate::api::{CredentialsInitResponse, Empty, StatusResponse};
-use nca_system_api::systemd::api::set_systemd_credential;
+use crate::api::{CredentialsInitResponse, Empty, PrimaryPasswordRotation, StatusResponse};
 use crate::api::credentials_server::Credentials;
 use crate::crypto::{b32_encode, create_key_from_pass, derive_key, generate_salt, Salt};
+use crate::server::backup::ResticRepository;
 use crate::server::config::credentials_config::CredentialsConfig;
-use crate::server::storage::add_fallback_password_to_encrypted_disks;
-use crate::server::util::set_systemd_credential_at_path;
+use crate::server::config::data_disk::DataDiskMember;
+use crate::server::storage::{add_fallback_password_to_encrypted_disks, enroll_disk_encryption_password_on_encrypted_disks, remove_disk_encryption_password_from_disks};
+use crate::server::util::{blocking, set_systemd_credential_at_path};
 
 pub struct CredentialsService {
     config: Arc<Mutex<crate::server::config::Config>>,
@@ -30,22 +31,139 @@ impl CredentialsService {
             None => {
                 println!("generating salt ...");
                 let salt = generate_salt();
-                let salt_b32 = b32_encode(&salt);
-                let config_path = {
-                    self.config.lock().await.config_path.clone()
-                };
-                set_systemd_credential(
-                    salt_b32,
-                    format!("{config_path}/credentials/salt.txt"),
-                    Some("ncatomic_salt.txt".to_string()),
-                    false
-                ).await?;
-                let mut cfg = self.config.lock().await;
-                cfg.salt = Some(salt);
+                self.config.lock().await.set_salt(salt).await?;
                 Ok(salt)
             }
         }
     }
+
+}
+
+/// Checks credentials derived from a primary password against the ones of this instance
+pub(crate) async fn verify_credentials(config: &Mutex<crate::server::config::Config>, credentials: &CredentialsConfig) -> Result<(), Status> {
+    let current_backup_password = {
+        config.lock().await.backup_password()
+            .map_err(|e| Status::failed_precondition(format!("Unable to verify primary password: {e}")))?
+    };
+    if current_backup_password != credentials.backup_password {
+        return Err(Status::permission_denied("The primary password is incorrect"));
+    }
+    Ok(())
+}
+
+pub(crate) fn derive_credentials(salt: &Salt, primary_password: String) -> Result<CredentialsConfig, NcaError> {
+    let primary_key = create_key_from_pass(salt, primary_password);
+    let disk_encryption_password = derive_key(&primary_key, salt, "NCA_DISK_ENCRYPTION".to_string())
+        .map_err(|e| NcaError::CryptoError(format!("Failed to derive key from password: {e:?}")))?;
+    let backup_password = derive_key(&primary_key, salt, "NCA_BACKUP_ENCRYPTION".to_string())
+        .map_err(|e| NcaError::CryptoError(format!("Failed to derive key from password: {e:?}")))?;
+    Ok(CredentialsConfig {
+        disk_encryption_password: b32_encode(&disk_encryption_password),
+        backup_password: b32_encode(&backup_password),
+    })
+}
+
+/// The steps of rotating the primary password. Each step can be undone by running it again with
+/// the passwords swapped, so a rotation that fails half-way can be rolled back.
+#[tonic::async_trait]
+trait PasswordRotation: Sync {
+    /// Enrolls `new` on every encrypted disk and returns the disks it was enrolled on
+    async fn enroll_disk_password(&self, old: &str, new: &str) -> Result<Vec<String>, Status>;
+    async fn remove_disk_password(&self, password: &str, devices: &[String]) -> Result<(), Status>;
+    async fn change_backup_password(&self, old: &str, new: &str) -> Result<(), Status>;
+    async fn store_backup_credential(&self, password: &str) -> Result<(), Status>;
+    async fn store_credentials(&self, credentials: &CredentialsConfig) -> Result<(), Status>;
+}
+
+struct SystemPasswordRotation<'a> {
+    config: &'a Mutex<crate::server::config::Config>,
+    config_path: String,
+    pool_members: Vec<DataDiskMember>,
+    repository_path: Option<String>,
+}
+
+#[tonic::async_trait]
+impl PasswordRotation for SystemPasswordRotation<'_> {
+    async fn enroll_disk_password(&self, old: &str, new: &str) -> Result<Vec<String>, Status> {
+        Ok(enroll_disk_encryption_password_on_encrypted_disks(old.to_string(), new.to_string(), &self.pool_members).await?)
+    }
+
+    async fn remove_disk_password(&self, password: &str, devices: &[String]) -> Result<(), Status> {
+        Ok(remove_disk_encryption_password_from_disks(password.to_string(), devices).await?)
+    }
+
+    async fn change_backup_password(&self, old: &str, new: &str) -> Result<(), Status> {
+        let Some(repository_path) = self.repository_path.clone() else {
+            return Ok(());
+        };
+        let (old, new) = (old.to_string(), new.to_string());
+        Ok(blocking(move || {
+            let mut repository = ResticRepository::new(repository_path, old);
+            match repository.is_initialized() {
+                true => repository.change_password(new),
+                false => Ok(()),
+            }
+        }).await?)
+    }
+
+    async fn store_backup_credential(&self, password: &str) -> Result<(), Status> {
+        set_systemd_credential_at_path(
+            password.to_string(),
+            format!("{}/credentials/backup_password.txt", self.config_path),
+            Some("ncatomic_backup_password.txt".to_string())
+        ).await.map(|_| ())
+    }
+
+    async fn store_credentials(&self, credentials: &CredentialsConfig) -> Result<(), Status> {
+        Ok(self.config.lock().await
+            .update_state(|state| state.credentials_config = Some(credentials.clone()))
+            .await?)
+    }
+}
+
+/// How far a rotation got before it failed
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+enum RotationStage {
+    DiskPasswordEnrolled,
+    BackupPasswordChanged,
+    BackupCredentialStored,
+}
+
+/// Replaces the `old` credentials with `new` ones. The old disk encryption password is only
+/// removed once everything else succeeded, if any other step fails the completed ones are undone.
+async fn rotate_credentials<R: PasswordRotation>(rotation: &R, old: &CredentialsConfig, new: &CredentialsConfig) -> Result<(), Status> {
+    let devices = rotation.enroll_disk_password(&old.disk_encryption_password, &new.disk_encryption_password).await?;
+    let steps = [
+        (RotationStage::DiskPasswordEnrolled, rotation.change_backup_password(&old.backup_password, &new.backup_password)),
+        (RotationStage::BackupPasswordChanged, rotation.store_backup_credential(&new.backup_password)),
+        (RotationStage::BackupCredentialStored, rotation.store_credentials(new)),
+    ];
+    for (completed, step) in steps {
+        if let Err(e) = step.await {
+            roll_back_rotation(rotation, old, new, &devices, completed).await;
+            return Err(e);
+        }
+    }
+
+    if let Err(e) = rotation.remove_disk_password(&old.disk_encryption_password, &devices).await {
+        eprintln!("WARNING: The old primary password still unlocks some of the encrypted disks: {}", e.message());
+    }
+    Ok(())
+}
+
+async fn roll_back_rotation<R: PasswordRotation>(rotation: &R, old: &CredentialsConfig, new: &CredentialsConfig, devices: &[String], completed: RotationStage) {
+    eprintln!("Rotating the primary password failed, rolling back");
+    if completed >= RotationStage::BackupCredentialStored
+        && let Err(e) = rotation.store_backup_credential(&old.backup_password).await {
+        eprintln!("Failed to restore the backup password credential: {}", e.message());
+    }
+    if completed >= RotationStage::BackupPasswordChanged
+        && let Err(e) = rotation.change_backup_password(&new.backup_password, &old.backup_password).await {
+        eprintln!("Failed to restore the password of the backup repository: {}", e.message());
+    }
+    if let Err(e) = rotation.remove_disk_password(&new.disk_encryption_password, devices).await {
+        eprintln!("Failed to remove the new password from the encrypted disks: {}", e.message());
+    }
 }
 
 #[tonic::async_trait]
@@ -88,33 +206,22 @@ impl Credentials for CredentialsService {
         let cfg = {
             self.config.lock().await.clone()
         };
-        if cfg.setup_complete {
+        if cfg.state().setup_complete {
             return Err(Status::failed_precondition("Instance already initialized"))
         };
         
         let salt = self.ensure_salt().await?;
         
-        let primary_password = request.into_inner().value;
         let salt_b32 = b32_encode(&salt);
-        let primary_key = create_key_from_pass(&salt, primary_password.clone());
-        let disk_encryption_password = derive_key(&primary_key, &salt, "NCA_DISK_ENCRYPTION".to_string())
-            .map_err(|e| NcaError::CryptoError(format!("Failed to derive key from password: {e:?}")))?;
-        let disk_encryption_password_b32 = b32_encode(&disk_encryption_password);
-        let backup_password = derive_key(&primary_key, &salt, "NCA_BACKUP_ENCRYPTION".to_string())
-            .map_err(|e| NcaError::CryptoError(format!("Failed to derive key from password: {e:?}")))?;
-        let backup_password_b32 = b32_encode(&backup_password);
-
-        {
-            let mut cfg = self.config.lock().await;
-            cfg.credentials_config = Some(CredentialsConfig {
-                disk_encryption_password: disk_encryption_password_b32.clone(),
-                backup_password: backup_password_b32.clone()
-            });
-        }
+        let credentials = derive_credentials(&salt, request.into_inner().value)?;
+
+        self.config.lock().await
+            .update_state(|state| state.credentials_config = Some(credentials.clone()))
+            .await?;
         
         Ok(Response::new(CredentialsInitResponse{
-            backup_password: backup_password_b32,
-            disk_encryption_recovery_password: disk_encryption_password_b32,
+            backup_password: credentials.backup_password,
+            disk_encryption_recovery_password: credentials.disk_encryption_password,
             salt: salt_b32,
         }))
     }
@@ -126,7 +233,7 @@ impl Credentials for CredentialsService {
                 None => return Err(Status::failed_precondition("salt not set")),
                 Some(salt) => salt,
             };
-            let credentials = match &cfg.credentials_config {
+            let credentials = match &cfg.state().credentials_config {
                 None => return Err(Status::failed_precondition("credentials not set")),
                 Some(creds) => creds
             };
@@ -140,7 +247,10 @@ impl Credentials for CredentialsService {
         
         let salt_b32 = b32_encode(&salt);
 
-        add_fallback_password_to_encrypted_disks(disk_encryption_password_b32).await?;
+        let pool_members = {
+            self.config.lock().await.data_pool_members()
+        };
+        add_fallback_password_to_encrypted_disks(disk_encryption_password_b32, &pool_members).await?;
 
         set_systemd_credential_at_path(
             salt_b32,
@@ -153,11 +263,166 @@ impl Credentials for CredentialsService {
             format!("{config_path}/credentials/backup_password.txt"),
             Some("ncatomic_backup_password.txt".to_string())
         ).await?;
+
+        self.config.lock().await
+            .update_state(|state| state.setup_complete = true)
+            .await?;
         
         Ok(Response::new(StatusResponse{
             status: 200,
             status_text: "Setup completed successfully".to_string(),
         }))
     }
+
+    async fn rotate_primary_password(&self, request: Request<PrimaryPasswordRotation>) -> Result<Response<CredentialsInitResponse>, Status> {
+        let (config_path, salt, setup_complete) = {
+            let cfg = self.config.lock().await;
+            (cfg.config_path.clone(), cfg.salt, cfg.state().setup_complete)
+        };
+        if !setup_complete {
+            return Err(Status::failed_precondition("Instance has not been initialized yet"))
+        }
+        let salt = match salt {
+            None => return Err(Status::failed_precondition("salt not set")),
+            Some(salt) => salt,
+        };
+
+        let rotation = request.into_inner();
+        let old_credentials = derive_credentials(&salt, rotation.old_password)?;
+        verify_credentials(&self.config, &old_credentials).await?;
+        let new_credentials = derive_credentials(&salt, rotation.new_password)?;
+
+        let (pool_members, backup_config) = {
+            let cfg = self.config.lock().await;
+            (cfg.data_pool_members(), cfg.state().backup.clone())
+        };
+        let rotation = SystemPasswordRotation {
+            config: &self.config,
+            config_path,
+            pool_members,
+            repository_path: backup_config.map(|backup| backup.repository_path),
+        };
+        rotate_credentials(&rotation, &old_credentials, &new_credentials).await?;
+
+        Ok(Response::new(CredentialsInitResponse {
+            backup_password: new_credentials.backup_password,
+            disk_encryption_recovery_password: new_credentials.disk_encryption_password,
+            salt: b32_encode(&salt),
+        }))
+    }
+
+    async fn verify_primary_password(&self, request: Request<crate::api::PrimaryPassword>) -> Result<Response<StatusResponse>, Status> {
+        let (salt, setup_complete) = {
+            let cfg = self.config.lock().await;
+            (cfg.salt, cfg.state().setup_complete)
+        };
+        if !setup_complete {
+            return Err(Status::failed_precondition("Instance has not been initialized yet"))
+        }
+        let salt = salt.ok_or(Status::failed_precondition("salt not set"))?;
+        let credentials = derive_credentials(&salt, request.into_inner().value)?;
+        verify_credentials(&self.config, &credentials).await?;
+        Ok(Response::new(StatusResponse {
+            status: 200,
+            status_text: "The primary password is correct".to_string(),
+        }))
+    }
+}
+    
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex as StdMutex;
+    use super::*;
+
+    /// Records the steps of a rotation and fails the step named `failing`
+    struct RecordingRotation {
+        failing: &'static str,
+        steps: StdMutex<Vec<String>>,
+    }
+
+    impl RecordingRotation {
+        fn new(failing: &'static str) -> Self {
+            Self { failing, steps: StdMutex::new(Vec::new()) }
+        }
+
+        fn record(&self, step: &str, detail: String) -> Result<(), Status> {
+            self.steps.lock().unwrap().push(format!("{step} {detail}"));
+            match step == self.failing {
+                true => Err(Status::internal(format!("{step} failed"))),
+                false => Ok(()),
+            }
+        }
+
+        fn steps(&self) -> Vec<String> {
+            self.steps.lock().unwrap().clone()
+        }
+    }
+
+    #[tonic::async_trait]
+    impl PasswordRotation for RecordingRotation {
+        async fn enroll_disk_password(&self, old: &str, new: &str) -> Result<Vec<String>, Status> {
+            self.record("enroll", format!("{old}->{new}"))?;
+            Ok(vec!["/dev/vda3".to_string()])
+        }
+
+        async fn remove_disk_password(&self, password: &str, _devices: &[String]) -> Result<(), Status> {
+            self.record("remove", password.to_string())
+        }
+
+        async fn change_backup_password(&self, old: &str, new: &str) -> Result<(), Status> {
+            self.record("restic", format!("{old}->{new}"))
+        }
+
+        async fn store_backup_credential(&self, password: &str) -> Result<(), Status> {
+            self.record("credential", password.to_string())
+        }
+
+        async fn store_credentials(&self, credentials: &CredentialsConfig) -> Result<(), Status> {
+            self.record("state", credentials.backup_password.clone())
+        }
+    }
+
+    fn credentials(name: &str) -> CredentialsConfig {
+        CredentialsConfig {
+            disk_encryption_password: format!("disk-{name}"),
+            backup_password: format!("backup-{name}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rotation_removes_old_disk_password_last() {
+        let rotation = RecordingRotation::new("");
+        rotate_credentials(&rotation, &credentials("old"), &credentials("new")).await.unwrap();
+        assert_eq!(rotation.steps(), [
+            "enroll disk-old->disk-new",
+            "restic backup-old->backup-new",
+            "credential backup-new",
+            "state backup-new",
+            "remove disk-old",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn test_failed_rotation_is_rolled_back() {
+        let rotation = RecordingRotation::new("state");
+        let result = rotate_credentials(&rotation, &credentials("old"), &credentials("new")).await;
+        assert_eq!(result.unwrap_err().message(), "state failed");
+        assert_eq!(rotation.steps(), [
+            "enroll disk-old->disk-new",
+            "restic backup-old->backup-new",
+            "credential backup-new",
+            "state backup-new",
+            "credential backup-old",
+            "restic backup-new->backup-old",
+            "remove disk-new",
+        ]);
+
+        let rotation = RecordingRotation::new("restic");
+        assert!(rotate_credentials(&rotation, &credentials("old"), &credentials("new")).await.is_err());
+        assert_eq!(rotation.steps(), [
+            "enroll disk-old->disk-new",
+            "restic backup-old->backup-new",
+            "remove disk-new",
+        ]);
+    }
 }
-    
\ No newline at end of file
@@ -1,24 +1,250 @@
 mod util;
 
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::Mutex;
 use tonic::{Request, Response, Status};
 use nca_error::NcaError;
-use crate::api::{PasswordResponse};
+use nca_system_api::systemd::api::{dump_luks_metadata, enroll_recovery_key, enroll_tpm2, get_service_status, journal_send_message, reload_systemd, start_service, stop_service, test_key_slot_password, test_tpm2_token_unlock, wipe_key_slot, Priority};
+use nca_system_api::systemd::types::ServiceStatus;
+use crate::api::{BlockDevice, DataDiskRequest, DataDiskResponse, DataPoolStatus, DiskList, Empty, EncryptedDevice, FilesystemStatus, KeySlot, KeySlotList, KeySlotType, PasswordResponse, RecoveryKey, RecoveryKeyRequest, RemoveKeySlotRequest, ReplaceDataDiskRequest, ResealOutcome, ResealTpm2Request, Tpm2ResealReport, StorageLevel, StorageStatus, StorageThresholds};
 use crate::api::storage_server::Storage;
-use crate::crypto::{b32_encode, create_key_from_pass, derive_key};
-use crate::server::storage::add_fallback_password_to_encrypted_disks;
+use crate::crypto::{b32_encode, create_key_from_pass, derive_key, generate_token};
+use crate::server::config::credentials_config::CredentialsConfig;
+use crate::server::config::data_disk::{DataDiskMember, DataPoolConfig};
+use crate::server::data_disk::{add_pool_member, discard_data_pool, pool_status, prepare_data_pool, remove_shadowed_data, replace_pool_member, DataDiskLayout};
+use crate::server::config::storage::StorageThresholdsConfig;
+use crate::server::disks::{list_block_devices, mounted_disks, SystemBlockDevices};
+use crate::server::service::credentials::{derive_credentials, verify_credentials};
+use crate::server::key_slots::{check_key_slot_removal, parse_key_slots, parse_recovery_key};
+use crate::server::storage::{add_fallback_password_to_encrypted_disks, encrypted_devices, reseal_tpm2_on_encrypted_disks};
+use crate::server::storage_status::{percent, storage_filesystems, system_disk_health, STORAGE_THRESHOLD_MESSAGE_ID};
+use crate::server::util::blocking;
+
+const NEXTCLOUD_SERVICE: &str = "nextcloud-all-in-one.service";
+const CONFIRMATION_TIMEOUT: Duration = Duration::from_secs(300);
+// How often the storage levels are checked when nobody asks for the storage status
+const STORAGE_CHECK_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// The operations that erase disks and need to be confirmed
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum DataDiskOperation {
+    Prepare,
+    Add,
+    Replace,
+}
+
+/// An operation that erases disks which has been requested, but not confirmed yet
+struct PendingConfirmation {
+    token: String,
+    operation: DataDiskOperation,
+    // path, size and serial of each disk
+    disks: Vec<(String, u64, Option<String>)>,
+    expires: Instant,
+}
+
+fn disk_identities(disks: &[BlockDevice]) -> Vec<(String, u64, Option<String>)> {
+    disks.iter()
+        .map(|d| (d.path.clone(), d.size, d.serial.clone()))
+        .collect()
+}
+
+impl PendingConfirmation {
+    fn confirms(&self, token: &str, operation: DataDiskOperation, disks: &[BlockDevice]) -> bool {
+        self.token == token
+            && self.operation == operation
+            && self.expires > Instant::now()
+            && self.disks == disk_identities(disks)
+    }
+}
 
 pub struct StorageService {
     config: Arc<Mutex<crate::server::config::Config>>,
+    pending_confirmation: Arc<Mutex<Option<PendingConfirmation>>>,
+    // The last reported level of each storage location, so crossing a threshold is logged once
+    storage_levels: Arc<Mutex<HashMap<String, StorageLevel>>>,
 }
 
 impl StorageService {
     pub fn new(config: Arc<Mutex<crate::server::config::Config>>) -> Self {
-        Self { config }
+        Self {
+            config,
+            pending_confirmation: Arc::new(Mutex::new(None)),
+            storage_levels: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    async fn verify_primary_password(&self, primary_password: String) -> Result<CredentialsConfig, Status> {
+        let salt = match self.config.lock().await.salt {
+            None => return Err(Status::failed_precondition("Instance salt has not been generated yet!")),
+            Some(salt) => salt,
+        };
+        let credentials = derive_credentials(&salt, primary_password)?;
+        verify_credentials(&self.config, &credentials).await?;
+        Ok(credentials)
+    }
+
+    /// Returns a new confirmation token if no `token` was given. Otherwise, fails unless `token`
+    /// was issued for erasing exactly `disks` with `operation` and has not expired yet.
+    async fn confirm_erase(&self, operation: DataDiskOperation, disks: &[BlockDevice], token: Option<String>) -> Result<Option<String>, Status> {
+        let mut pending = self.pending_confirmation.lock().await;
+        match token {
+            None => {
+                let token = generate_token();
+                *pending = Some(PendingConfirmation {
+                    token: token.clone(),
+                    operation,
+                    disks: disk_identities(disks),
+                    expires: Instant::now() + CONFIRMATION_TIMEOUT,
+                });
+                Ok(Some(token))
+            },
+            Some(token) => match pending.take().is_some_and(|p| p.confirms(&token, operation, disks)) {
+                true => Ok(None),
+                false => Err(Status::permission_denied("The confirmation token is invalid or has expired")),
+            }
+        }
+    }
+
+    async fn data_pool(&self) -> Result<DataPoolConfig, Status> {
+        self.config.lock().await.state().data_pool.clone()
+            .ok_or(Status::failed_precondition("No data disk has been set up"))
+    }
+
+    /// Enrolls the TPM2 for new pool members and persists the updated pool
+    async fn update_data_pool(&self, pool: DataPoolConfig, new_device: &str, disk_encryption_password: String) -> Result<(), NcaError> {
+        if let Err(e) = enroll_tpm2(disk_encryption_password, new_device.to_string()).await {
+            eprintln!("WARNING: {new_device} can only be unlocked with the disk encryption password: {e}");
+        }
+        reload_systemd().await?;
+        self.config.lock().await
+            .update_state(|state| state.data_pool = Some(pool))
+            .await
+    }
+
+    /// Creates the data pool on `devices`, mounts it in place of the data directory and records it
+    /// in the state. The old data is only removed once all of that succeeded, otherwise the pool
+    /// is discarded and the data directory is left as it was.
+    async fn set_up_data_pool(&self, devices: &[String], disk_encryption_password: String, layout: &DataDiskLayout) -> Result<(), NcaError> {
+        let data_pool = prepare_data_pool(devices, disk_encryption_password.clone(), layout).await?;
+        for device in devices {
+            if let Err(e) = enroll_tpm2(disk_encryption_password.clone(), device.clone()).await {
+                eprintln!("WARNING: {device} can only be unlocked with the disk encryption password: {e}");
+            }
+        }
+        let taken_into_use = async {
+            reload_systemd().await?;
+            start_service(layout.mount_unit_name()).await?;
+            self.config.lock().await
+                .update_state(|state| state.data_pool = Some(data_pool.clone()))
+                .await
+        }.await;
+        if let Err(e) = taken_into_use {
+            discard_data_pool(&data_pool, layout).await;
+            return Err(e);
+        }
+
+        let shadowed = layout.clone();
+        if let Err(e) = blocking(move || remove_shadowed_data(&shadowed)).await {
+            eprintln!("WARNING: The data copied to the data disk is still in {} on the system disk: {e}", layout.data_path.display());
+        }
+        Ok(())
+    }
+
+    /// Checks the storage levels periodically, so crossing a threshold is logged even if the
+    /// storage status is not requested
+    pub fn start_storage_monitor(&self) {
+        let config = self.config.clone();
+        let storage_levels = self.storage_levels.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(STORAGE_CHECK_INTERVAL);
+            loop {
+                interval.tick().await;
+                let thresholds = {
+                    config.lock().await.state().storage_thresholds.clone()
+                };
+                match tokio::task::spawn_blocking(move || storage_filesystems(&thresholds)).await {
+                    Ok(Ok(filesystems)) => log_storage_level_changes(&storage_levels, &filesystems).await,
+                    Ok(Err(e)) => eprintln!("Failed to check storage levels: {e}"),
+                    Err(e) => eprintln!("Failed to check storage levels: {e:?}"),
+                }
+            }
+        });
+    }
+}
+
+async fn log_storage_level_changes(storage_levels: &Mutex<HashMap<String, StorageLevel>>, filesystems: &[FilesystemStatus]) {
+    let mut levels = storage_levels.lock().await;
+    for filesystem in filesystems {
+        let level = filesystem.level();
+        let previous = levels.insert(filesystem.name.clone(), level).unwrap_or(StorageLevel::StorageOk);
+        if previous == level {
+            continue;
+        }
+        let used_percent = percent(filesystem.used_bytes, filesystem.used_bytes + filesystem.available_bytes);
+        let (priority, message) = match level {
+            StorageLevel::StorageOk => (Priority::Notice, format!("Storage usage of {} ({}) is back to normal ({used_percent}% used)", filesystem.name, filesystem.path)),
+            StorageLevel::StorageWarning => (Priority::Warning, format!("Storage of {} ({}) is almost full ({used_percent}% used)", filesystem.name, filesystem.path)),
+            StorageLevel::StorageCritical => (Priority::Critical, format!("Storage of {} ({}) is critically full ({used_percent}% used)", filesystem.name, filesystem.path)),
+        };
+        let fields = [
+            ("NCA_STORAGE_LOCATION", filesystem.name.clone()),
+            ("NCA_STORAGE_PATH", filesystem.path.clone()),
+            ("NCA_STORAGE_LEVEL", level.as_str_name().to_string()),
+            ("NCA_STORAGE_USED_PERCENT", used_percent.to_string()),
+            ("NCA_STORAGE_USED_INODES", filesystem.used_inodes.to_string()),
+        ];
+        if let Err(e) = journal_send_message(priority, STORAGE_THRESHOLD_MESSAGE_ID, &message, &fields) {
+            eprintln!("Failed to log storage level change: {e}\n{message}");
+        }
+    }
+}
+
+/// Resolves `device` to one of the encrypted devices of this instance
+async fn encrypted_device(device: String, pool_members: Vec<DataDiskMember>) -> Result<String, Status> {
+    let requested = fs::canonicalize(&device)
+        .map_err(|_| Status::not_found(format!("No device found at {device}")))?;
+    let devices = tokio::task::spawn_blocking(move || encrypted_devices(&pool_members))
+        .await
+        .map_err(|e| NcaError::new_unexpected_error(format!("Failed to list encrypted devices: {e:?}")))??;
+    match devices.iter().any(|d| fs::canonicalize(d).is_ok_and(|d| d == requested)) {
+        true => Ok(device),
+        false => Err(Status::not_found(format!("{device} is not an encrypted device of this system"))),
     }
 }
 
+async fn key_slots(device: &str) -> Result<Vec<KeySlot>, NcaError> {
+    parse_key_slots(&dump_luks_metadata(device.to_string()).await?)
+}
+
+/// Looks up `devices` and makes sure they are neither the system disk nor in use
+async fn erasable_disks(devices: Vec<String>) -> Result<Vec<BlockDevice>, Status> {
+    if devices.iter().collect::<BTreeSet<_>>().len() != devices.len() {
+        return Err(Status::invalid_argument("Each device may only be given once"));
+    }
+    let (disks, in_use) = tokio::task::spawn_blocking(|| {
+        Ok::<_, NcaError>((list_block_devices(&SystemBlockDevices)?, mounted_disks(&SystemBlockDevices)?))
+    }).await
+        .map_err(|e| NcaError::new_unexpected_error(format!("Failed to list disks: {e:?}")))??;
+    let mut erasable = Vec::new();
+    for device in &devices {
+        let disk = match disks.iter().find(|d| &d.path == device && d.device_type == "disk") {
+            None => return Err(Status::not_found(format!("No disk found at {device}"))),
+            Some(disk) => disk,
+        };
+        if disk.is_system_disk {
+            return Err(Status::failed_precondition(format!("{device} is the system disk")));
+        }
+        if in_use.contains(&disk.name) {
+            return Err(Status::failed_precondition(format!("{device} is in use")));
+        }
+        erasable.push(disk.clone());
+    }
+    Ok(erasable)
+}
+
 #[tonic::async_trait]
 impl Storage for StorageService {
 
@@ -35,11 +261,269 @@ impl Storage for StorageService {
         let disk_encryption_password = derive_key(&primary_key, &salt, "NCATOMIC_DISK_ENCRYPTION".to_string())
             .map_err(|e| NcaError::CryptoError(format!("Failed to derive key from password: {e:?}")))?;
         let disk_encryption_password_b32 = b32_encode(&disk_encryption_password);
-        add_fallback_password_to_encrypted_disks(disk_encryption_password_b32.clone()).await?;
+        let pool_members = {
+            self.config.lock().await.data_pool_members()
+        };
+        add_fallback_password_to_encrypted_disks(disk_encryption_password_b32.clone(), &pool_members).await?;
         Ok(Response::new(PasswordResponse {
             password: disk_encryption_password_b32,
             status: 200
         }))
 
     }
+
+    async fn list_disks(&self, _request: Request<Empty>) -> Result<Response<DiskList>, Status> {
+        let disks = tokio::task::spawn_blocking(|| list_block_devices(&SystemBlockDevices))
+            .await
+            .map_err(|e| NcaError::new_unexpected_error(format!("Failed to list disks: {e:?}")))??;
+        Ok(Response::new(DiskList { disks }))
+    }
+
+    async fn get_storage_status(&self, _request: Request<Empty>) -> Result<Response<StorageStatus>, Status> {
+        let thresholds = {
+            self.config.lock().await.state().storage_thresholds.clone()
+        };
+        let (filesystems, disks) = {
+            let thresholds = thresholds.clone();
+            tokio::task::spawn_blocking(move || {
+                let filesystems = storage_filesystems(&thresholds)?;
+                let disks = filesystems.iter()
+                    .flat_map(|fs| fs.disks.iter().cloned())
+                    .collect::<BTreeSet<_>>()
+                    .into_iter()
+                    .map(|name| system_disk_health(&name))
+                    .collect();
+                Ok::<_, NcaError>((filesystems, disks))
+            }).await
+                .map_err(|e| NcaError::new_unexpected_error(format!("Failed to query storage status: {e:?}")))??
+        };
+        log_storage_level_changes(&self.storage_levels, &filesystems).await;
+        Ok(Response::new(StorageStatus {
+            filesystems,
+            disks,
+            thresholds: Some(thresholds.into()),
+        }))
+    }
+
+    async fn configure_storage_thresholds(&self, request: Request<StorageThresholds>) -> Result<Response<StorageThresholds>, Status> {
+        let thresholds = StorageThresholdsConfig::from(request.into_inner());
+        if !thresholds.is_valid() {
+            return Err(Status::invalid_argument("Thresholds must be at most 100% and warnings must not exceed the critical thresholds"));
+        }
+        self.config.lock().await
+            .update_state(|state| state.storage_thresholds = thresholds.clone())
+            .await?;
+        Ok(Response::new(thresholds.into()))
+    }
+
+    async fn prepare_data_disk(&self, request: Request<DataDiskRequest>) -> Result<Response<DataDiskResponse>, Status> {
+        let request = request.into_inner();
+        let data_pool = {
+            self.config.lock().await.state().data_pool.clone()
+        };
+        if let Some(data_pool) = data_pool {
+            let devices: Vec<String> = data_pool.members.into_iter().map(|m| m.device).collect();
+            return Err(Status::already_exists(format!("{} is already used as data disk", devices.join(", "))));
+        }
+        let devices: Vec<String> = std::iter::once(request.device)
+            .chain(request.mirror_devices)
+            .collect();
+        let disks = erasable_disks(devices.clone()).await?;
+        let credentials = self.verify_primary_password(request.primary_password).await?;
+        if let Some(token) = self.confirm_erase(DataDiskOperation::Prepare, &disks, request.confirmation_token).await? {
+            return Ok(Response::new(DataDiskResponse {
+                confirmation_token: Some(token),
+                prepared: false,
+                mount_point: None,
+            }));
+        }
+
+        let layout = DataDiskLayout::default();
+        let nextcloud_was_active = get_service_status(NEXTCLOUD_SERVICE.to_string()).await? == ServiceStatus::ACTIVE;
+        if nextcloud_was_active {
+            stop_service(NEXTCLOUD_SERVICE.to_string()).await?;
+        }
+        let result = self.set_up_data_pool(&devices, credentials.disk_encryption_password.clone(), &layout).await;
+        // Nextcloud is started again even if the data disk could not be set up
+        let started = match nextcloud_was_active {
+            true => start_service(NEXTCLOUD_SERVICE.to_string()).await,
+            false => Ok(()),
+        };
+        if let (Err(e), Err(_)) = (&started, &result) {
+            eprintln!("Failed to start {NEXTCLOUD_SERVICE}: {e}");
+        }
+        result?;
+        started?;
+
+        Ok(Response::new(DataDiskResponse {
+            confirmation_token: None,
+            prepared: true,
+            mount_point: Some(layout.data_path.to_string_lossy().to_string()),
+        }))
+    }
+
+    async fn add_data_disk(&self, request: Request<DataDiskRequest>) -> Result<Response<DataDiskResponse>, Status> {
+        let request = request.into_inner();
+        if !request.mirror_devices.is_empty() {
+            return Err(Status::invalid_argument("Disks can only be added one at a time"));
+        }
+        let data_pool = self.data_pool().await?;
+        let disks = erasable_disks(vec![request.device.clone()]).await?;
+        let credentials = self.verify_primary_password(request.primary_password).await?;
+        if let Some(token) = self.confirm_erase(DataDiskOperation::Add, &disks, request.confirmation_token).await? {
+            return Ok(Response::new(DataDiskResponse {
+                confirmation_token: Some(token),
+                prepared: false,
+                mount_point: None,
+            }));
+        }
+
+        let layout = DataDiskLayout::default();
+        let data_pool = add_pool_member(&data_pool, &request.device, credentials.disk_encryption_password.clone(), &layout).await?;
+        self.update_data_pool(data_pool, &request.device, credentials.disk_encryption_password).await?;
+        Ok(Response::new(DataDiskResponse {
+            confirmation_token: None,
+            prepared: true,
+            mount_point: Some(layout.data_path.to_string_lossy().to_string()),
+        }))
+    }
+
+    async fn replace_data_disk(&self, request: Request<ReplaceDataDiskRequest>) -> Result<Response<DataDiskResponse>, Status> {
+        let request = request.into_inner();
+        let data_pool = self.data_pool().await?;
+        if !data_pool.members.iter().any(|m| m.device == request.failed_device || m.mapper_name == request.failed_device) {
+            return Err(Status::not_found(format!("{} is not a member of the data pool", request.failed_device)));
+        }
+        let disks = erasable_disks(vec![request.new_device.clone()]).await?;
+        let credentials = self.verify_primary_password(request.primary_password).await?;
+        if let Some(token) = self.confirm_erase(DataDiskOperation::Replace, &disks, request.confirmation_token).await? {
+            return Ok(Response::new(DataDiskResponse {
+                confirmation_token: Some(token),
+                prepared: false,
+                mount_point: None,
+            }));
+        }
+
+        let layout = DataDiskLayout::default();
+        let data_pool = replace_pool_member(&data_pool, &request.failed_device, &request.new_device,
+                                            credentials.disk_encryption_password.clone(), &layout).await?;
+        self.update_data_pool(data_pool, &request.new_device, credentials.disk_encryption_password).await?;
+        Ok(Response::new(DataDiskResponse {
+            confirmation_token: None,
+            prepared: true,
+            mount_point: Some(layout.data_path.to_string_lossy().to_string()),
+        }))
+    }
+
+    async fn list_key_slots(&self, request: Request<EncryptedDevice>) -> Result<Response<KeySlotList>, Status> {
+        let pool_members = {
+            self.config.lock().await.data_pool_members()
+        };
+        let device = encrypted_device(request.into_inner().device, pool_members).await?;
+        let slots = key_slots(&device).await?;
+        Ok(Response::new(KeySlotList { device, slots }))
+    }
+
+    async fn remove_key_slot(&self, request: Request<RemoveKeySlotRequest>) -> Result<Response<KeySlotList>, Status> {
+        let request = request.into_inner();
+        let pool_members = {
+            self.config.lock().await.data_pool_members()
+        };
+        let device = encrypted_device(request.device, pool_members).await?;
+        let credentials = self.verify_primary_password(request.primary_password).await?;
+        let slots = key_slots(&device).await?;
+        let mut working_slots = HashSet::new();
+        for slot in slots.iter().filter(|s| s.slot != request.slot) {
+            let works = match (slot.r#type(), slot.token) {
+                (KeySlotType::KeySlotPassword, _) =>
+                    test_key_slot_password(credentials.disk_encryption_password.clone(), device.clone(), slot.slot).await?,
+                (KeySlotType::KeySlotTpm2, Some(token)) => test_tpm2_token_unlock(device.clone(), token).await?,
+                _ => false,
+            };
+            if works {
+                working_slots.insert(slot.slot);
+            }
+        }
+        check_key_slot_removal(&slots, request.slot, &working_slots)
+            .map_err(|e| Status::failed_precondition(e.to_string()))?;
+
+        wipe_key_slot(credentials.disk_encryption_password, device.clone(), request.slot).await?;
+        let slots = key_slots(&device).await?;
+        Ok(Response::new(KeySlotList { device, slots }))
+    }
+
+    async fn add_recovery_key(&self, request: Request<RecoveryKeyRequest>) -> Result<Response<RecoveryKey>, Status> {
+        let request = request.into_inner();
+        let pool_members = {
+            self.config.lock().await.data_pool_members()
+        };
+        let device = encrypted_device(request.device, pool_members).await?;
+        let credentials = self.verify_primary_password(request.primary_password).await?;
+        let output = enroll_recovery_key(credentials.disk_encryption_password, device.clone()).await?;
+        let recovery_key = parse_recovery_key(&output)
+            .ok_or(NcaError::new_unexpected_error(format!("A recovery key was enrolled for {device}, but could not be read")))?;
+        Ok(Response::new(RecoveryKey { device, recovery_key }))
+    }
+
+    async fn reseal_tpm2(&self, request: Request<ResealTpm2Request>) -> Result<Response<Tpm2ResealReport>, Status> {
+        let request = request.into_inner();
+        let disk_encryption_password = match request.primary_password {
+            Some(primary_password) => self.verify_primary_password(primary_password).await?.disk_encryption_password,
+            None => match &self.config.lock().await.state().credentials_config {
+                None => return Err(Status::failed_precondition("No stored credentials, the primary password is required")),
+                Some(credentials) => credentials.disk_encryption_password.clone(),
+            },
+        };
+        let pool_members = {
+            self.config.lock().await.data_pool_members()
+        };
+        let devices = reseal_tpm2_on_encrypted_disks(disk_encryption_password, &pool_members, request.only_if_needed).await?;
+        for result in devices.iter().filter(|r| r.outcome() == ResealOutcome::ResealFailed) {
+            eprintln!("Failed to reseal TPM2 for {}: {}", result.device, result.error.as_deref().unwrap_or_default());
+        }
+        Ok(Response::new(Tpm2ResealReport { devices }))
+    }
+
+    async fn get_data_pool_status(&self, _request: Request<Empty>) -> Result<Response<DataPoolStatus>, Status> {
+        let data_pool = {
+            self.config.lock().await.state().data_pool.clone()
+        };
+        let status = match data_pool {
+            None => DataPoolStatus::default(),
+            Some(data_pool) => tokio::task::spawn_blocking(move || pool_status(&data_pool, &DataDiskLayout::default()))
+                .await
+                .map_err(|e| NcaError::new_unexpected_error(format!("Failed to query data pool: {e:?}")))?,
+        };
+        Ok(Response::new(status))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn disk(path: &str) -> BlockDevice {
+        BlockDevice {
+            path: path.to_string(),
+            size: 1 << 30,
+            serial: Some(format!("serial-{path}")),
+            ..BlockDevice::default()
+        }
+    }
+
+    #[test]
+    fn test_confirmation_is_bound_to_operation_and_disks() {
+        let disks = [disk("/dev/vdb")];
+        let pending = PendingConfirmation {
+            token: "token".to_string(),
+            operation: DataDiskOperation::Add,
+            disks: disk_identities(&disks),
+            expires: Instant::now() + CONFIRMATION_TIMEOUT,
+        };
+        assert!(pending.confirms("token", DataDiskOperation::Add, &disks));
+        assert!(!pending.confirms("other", DataDiskOperation::Add, &disks));
+        assert!(!pending.confirms("token", DataDiskOperation::Prepare, &disks));
+        assert!(!pending.confirms("token", DataDiskOperation::Replace, &disks));
+        assert!(!pending.confirms("token", DataDiskOperation::Add, &[disk("/dev/vdc")]));
+    }
 }
@@ -1,7 +1,12 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
 use rsblkid::cache::Cache;
 use rsblkid::device::Tag;
 use nca_error::NcaError;
-use nca_system_api::systemd::api::set_fallback_disk_encryption_password;
+use crate::server::config::data_disk::DataDiskMember;
+use nca_system_api::systemd::api::{enroll_disk_encryption_password, remove_disk_encryption_password, reseal_tpm2, set_fallback_disk_encryption_password, test_disk_encryption_password, test_tpm2_unlock};
+use crate::api::{ResealOutcome, Tpm2ResealResult};
 
 pub(super) fn get_crypto_devices() -> Result<Vec<String>, NcaError> {
 
@@ -22,12 +27,113 @@ pub(super) fn get_crypto_devices() -> Result<Vec<String>, NcaError> {
 }
 
 
-pub(super) async fn add_fallback_password_to_encrypted_disks(password: String) -> Result<(), NcaError> {
+/// All encrypted devices: the ones blkid finds and the data pool members, which may not have been
+/// picked up by blkid yet right after they were added to the pool
+pub(super) fn encrypted_devices(pool_members: &[DataDiskMember]) -> Result<Vec<String>, NcaError> {
+    let mut paths = get_crypto_devices()?;
+    let known: HashSet<PathBuf> = paths.iter()
+        .filter_map(|p| fs::canonicalize(p).ok())
+        .collect();
+    for member in pool_members {
+        let by_uuid = format!("/dev/disk/by-uuid/{}", member.luks_uuid);
+        let path = fs::canonicalize(&by_uuid)
+            .or_else(|_| fs::canonicalize(&member.device));
+        match path {
+            Ok(path) if known.contains(&path) => {},
+            Ok(path) => paths.push(path.to_string_lossy().to_string()),
+            Err(_) => eprintln!("WARNING: data pool member {} is not available", member.device),
+        }
+    }
+    Ok(paths)
+}
+
+pub(super) async fn add_fallback_password_to_encrypted_disks(password: String, pool_members: &[DataDiskMember]) -> Result<(), NcaError> {
 
-    let paths = get_crypto_devices()?;
+    let paths = encrypted_devices(pool_members)?;
     println!("paths of crypto devices: {:?}", paths);
     for device_path in paths {
         set_fallback_disk_encryption_password(password.clone(), device_path).await?;
     }
     Ok(())
-}
\ No newline at end of file
+}
+/// Enrolls `new_password` on every encrypted disk that `old_password` unlocks and returns the
+/// disks. If enrolling fails on one disk, the new password is removed from the others again. The
+/// old key slots are kept, see [`remove_disk_encryption_password_from_disks`].
+pub(super) async fn enroll_disk_encryption_password_on_encrypted_disks(old_password: String, new_password: String, pool_members: &[DataDiskMember]) -> Result<Vec<String>, NcaError> {
+
+    let paths = encrypted_devices(pool_members)?;
+    for device_path in &paths {
+        if !test_disk_encryption_password(old_password.clone(), device_path.clone()).await? {
+            return Err(NcaError::new_crypto_error(format!("The old password does not unlock {device_path}")));
+        }
+    }
+
+    let mut enrolled = Vec::new();
+    for device_path in &paths {
+        let result = match enroll_disk_encryption_password(old_password.clone(), new_password.clone(), device_path.clone()).await {
+            Err(e) => Err(e),
+            Ok(()) => {
+                enrolled.push(device_path.clone());
+                match test_disk_encryption_password(new_password.clone(), device_path.clone()).await {
+                    Ok(true) => Ok(()),
+                    Ok(false) => Err(NcaError::new_crypto_error(format!("Failed to unlock {device_path} with the newly enrolled password"))),
+                    Err(e) => Err(e),
+                }
+            }
+        };
+        if let Err(e) = result {
+            if let Err(e) = remove_disk_encryption_password_from_disks(new_password, &enrolled).await {
+                eprintln!("Failed to remove the new password again: {e}");
+            }
+            return Err(e);
+        }
+    }
+    Ok(paths)
+}
+
+/// Removes the key slot unlocked by `password` from each of `devices`. All devices are tried, the
+/// last error is returned.
+pub(super) async fn remove_disk_encryption_password_from_disks(password: String, devices: &[String]) -> Result<(), NcaError> {
+    let mut result = Ok(());
+    for device_path in devices {
+        if let Err(e) = remove_disk_encryption_password(password.clone(), device_path.clone()).await {
+            result = Err(e);
+        }
+    }
+    result
+}
+
+async fn reseal_device(password: String, device_path: String, only_if_needed: bool) -> Result<ResealOutcome, NcaError> {
+    if only_if_needed && test_tpm2_unlock(device_path.clone()).await? {
+        return Ok(ResealOutcome::ResealNotNeeded);
+    }
+    if !test_disk_encryption_password(password.clone(), device_path.clone()).await? {
+        return Err(NcaError::new_crypto_error(format!("The disk encryption password does not unlock {device_path}")));
+    }
+    reseal_tpm2(password, device_path).await?;
+    Ok(ResealOutcome::ResealSucceeded)
+}
+
+/// Re-enrolls the TPM2 token of every encrypted disk against the current PCR values. Failures are
+/// reported per device, so one broken disk does not prevent resealing the others.
+pub(super) async fn reseal_tpm2_on_encrypted_disks(password: String, pool_members: &[DataDiskMember], only_if_needed: bool) -> Result<Vec<Tpm2ResealResult>, NcaError> {
+
+    let paths = encrypted_devices(pool_members)?;
+    let mut results = Vec::new();
+    for device_path in paths {
+        let result = reseal_device(password.clone(), device_path.clone(), only_if_needed).await;
+        results.push(match result {
+            Ok(outcome) => Tpm2ResealResult {
+                device: device_path,
+                outcome: outcome.into(),
+                error: None,
+            },
+            Err(e) => Tpm2ResealResult {
+                device: device_path,
+                outcome: ResealOutcome::ResealFailed.into(),
+                error: Some(e.to_string()),
+            },
+        });
+    }
+    Ok(results)
+}
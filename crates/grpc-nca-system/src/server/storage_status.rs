@@ -0,0 +1,199 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use nix::sys::statvfs::statvfs;
+use nca_error::NcaError;
+use crate::api::{DiskHealth, FilesystemStatus, StorageLevel};
+use crate::server::backup::NC_AIO_DATA_PATH;
+use crate::server::config::storage::StorageThresholdsConfig;
+use crate::server::disks::{find_mount, BlockDeviceSnapshot, BlockDeviceSource, SystemBlockDevices};
+
+/// `MESSAGE_ID` of the journal entries logged when a file system crosses a usage threshold
+pub(crate) const STORAGE_THRESHOLD_MESSAGE_ID: &str = "8b5f0e3c2a7d4e61b9c4f1a0d3e6b728";
+const SYSFS_BLOCK_PATH: &str = "/sys/block";
+const DEFAULT_PODMAN_STORAGE_PATH: &str = "/var/lib/containers/storage";
+const ROOT_PATHS: &[&str] = &["/sysroot", "/"];
+
+/// The locations whose usage is reported, as (name, path)
+pub(crate) fn storage_locations() -> Vec<(&'static str, String)> {
+    let root = ROOT_PATHS.iter()
+        .find(|p| Path::new(p).exists())
+        .unwrap_or(&"/");
+    vec![
+        ("nextcloud_data", NC_AIO_DATA_PATH.to_string()),
+        ("podman_storage", podman_storage_path()),
+        ("root", root.to_string()),
+    ]
+}
+
+/// Nextcloud AIO runs in the rootless podman instance of the aio user
+fn podman_storage_path() -> String {
+    Command::new("/usr/bin/su")
+        .args(["-l", "-s", "/usr/bin/bash", "-c", "/usr/bin/podman info --format '{{.Store.GraphRoot}}'", "aio"])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()
+        .filter(|out| out.status.success())
+        .map(|out| String::from_utf8_lossy(&out.stdout).trim().to_string())
+        .filter(|path| !path.is_empty())
+        .unwrap_or(DEFAULT_PODMAN_STORAGE_PATH.to_string())
+}
+
+pub(crate) fn percent(used: u64, total: u64) -> u64 {
+    match total {
+        0 => 0,
+        total => (used as u128 * 100 / total as u128) as u64,
+    }
+}
+
+pub(crate) fn storage_level(filesystem: &FilesystemStatus, thresholds: &StorageThresholdsConfig) -> StorageLevel {
+    let used = percent(filesystem.used_bytes, filesystem.used_bytes + filesystem.available_bytes);
+    let inodes_used = percent(filesystem.used_inodes, filesystem.total_inodes);
+    if used >= thresholds.critical_percent as u64 || inodes_used >= thresholds.inodes_critical_percent as u64 {
+        StorageLevel::StorageCritical
+    } else if used >= thresholds.warning_percent as u64 || inodes_used >= thresholds.inodes_warning_percent as u64 {
+        StorageLevel::StorageWarning
+    } else {
+        StorageLevel::StorageOk
+    }
+}
+
+/// The status of all storage locations, locations that can't be queried are skipped
+pub(crate) fn storage_filesystems(thresholds: &StorageThresholdsConfig) -> Result<Vec<FilesystemStatus>, NcaError> {
+    let source = BlockDeviceSnapshot::capture(&SystemBlockDevices)?;
+    Ok(storage_locations().into_iter()
+        .filter_map(|(name, path)| filesystem_status(&source, name, &path, thresholds)
+            .inspect_err(|e| eprintln!("Skipping storage location {name}: {e}"))
+            .ok())
+        .collect())
+}
+
+pub(crate) fn filesystem_status<S: BlockDeviceSource>(source: &S, name: &str, path: &str, thresholds: &StorageThresholdsConfig) -> Result<FilesystemStatus, NcaError> {
+    let stat = statvfs(path)
+        .map_err(|e| NcaError::new_io_error(format!("Failed to query file system of {path}: {e:?}")))?;
+    let fragment_size = stat.fragment_size() as u64;
+    let total_bytes = stat.blocks() as u64 * fragment_size;
+    let free_bytes = stat.blocks_free() as u64 * fragment_size;
+    let mount = find_mount(source, Path::new(path))?;
+    let mut status = FilesystemStatus {
+        name: name.to_string(),
+        path: path.to_string(),
+        mount_point: mount.as_ref().map(|(m, _)| m.mount_point.clone()),
+        device: mount.as_ref().map(|(m, _)| m.device.clone()),
+        total_bytes,
+        used_bytes: total_bytes.saturating_sub(free_bytes),
+        available_bytes: stat.blocks_available() as u64 * fragment_size,
+        total_inodes: stat.files() as u64,
+        used_inodes: (stat.files() as u64).saturating_sub(stat.files_free() as u64),
+        level: StorageLevel::StorageOk.into(),
+        disks: mount.map(|(_, disks)| disks.into_iter().collect::<BTreeSet<_>>().into_iter().collect())
+            .unwrap_or_default(),
+    };
+    status.level = storage_level(&status, thresholds).into();
+    Ok(status)
+}
+
+fn read_string(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+fn parse_io_errors(value: &str) -> Option<u64> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+/// The first temperature sensor (in millidegrees celsius) of the device, e.g. provided by the
+/// nvme or drivetemp drivers
+fn read_temperature(device_path: &Path) -> Option<f64> {
+    let hwmon_dirs = [device_path.to_path_buf(), device_path.join("hwmon")];
+    hwmon_dirs.iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flat_map(|entries| entries.filter_map(|e| e.ok()))
+        .filter(|e| e.file_name().to_string_lossy().starts_with("hwmon"))
+        .filter_map(|e| read_string(&e.path().join("temp1_input")))
+        .filter_map(|t| t.parse::<f64>().ok())
+        .map(|t| t / 1000.0)
+        .next()
+}
+
+/// Health of the disk `name` as far as the kernel reports it in sysfs
+pub(crate) fn disk_health(sysfs_block_path: &Path, name: &str) -> DiskHealth {
+    let device_path = sysfs_block_path.join(name).join("device");
+    let state = read_string(&device_path.join("state"));
+    let io_errors = read_string(&device_path.join("ioerr_cnt")).and_then(|v| parse_io_errors(&v));
+    let healthy = state.as_deref().is_none_or(|s| s == "running" || s == "live")
+        && io_errors.is_none_or(|count| count == 0);
+    DiskHealth {
+        name: name.to_string(),
+        model: read_string(&device_path.join("model")),
+        state,
+        io_errors,
+        temperature_celsius: read_temperature(&device_path),
+        healthy,
+    }
+}
+
+pub(crate) fn system_disk_health(name: &str) -> DiskHealth {
+    disk_health(Path::new(SYSFS_BLOCK_PATH), name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filesystem(used_bytes: u64, available_bytes: u64, used_inodes: u64, total_inodes: u64) -> FilesystemStatus {
+        FilesystemStatus {
+            total_bytes: used_bytes + available_bytes,
+            used_bytes,
+            available_bytes,
+            used_inodes,
+            total_inodes,
+            ..FilesystemStatus::default()
+        }
+    }
+
+    #[test]
+    fn test_storage_level() {
+        let thresholds = StorageThresholdsConfig::default();
+        assert_eq!(storage_level(&filesystem(10, 90, 1, 100), &thresholds), StorageLevel::StorageOk);
+        assert_eq!(storage_level(&filesystem(80, 20, 1, 100), &thresholds), StorageLevel::StorageWarning);
+        assert_eq!(storage_level(&filesystem(10, 90, 95, 100), &thresholds), StorageLevel::StorageCritical);
+        assert_eq!(storage_level(&filesystem(0, 0, 0, 0), &thresholds), StorageLevel::StorageOk);
+    }
+
+    #[test]
+    fn test_disk_health() {
+        let dir = std::env::temp_dir().join(format!("nca-storage-status-test-{}", std::process::id()));
+        let sda = dir.join("sda/device");
+        fs::create_dir_all(sda.join("hwmon/hwmon3")).unwrap();
+        fs::write(sda.join("state"), "running\n").unwrap();
+        fs::write(sda.join("ioerr_cnt"), "0x2\n").unwrap();
+        fs::write(sda.join("model"), "WDC WD40EFRX\n").unwrap();
+        fs::write(sda.join("hwmon/hwmon3/temp1_input"), "38000\n").unwrap();
+        let nvme = dir.join("nvme0n1/device");
+        fs::create_dir_all(nvme.join("hwmon1")).unwrap();
+        fs::write(nvme.join("state"), "live\n").unwrap();
+        fs::write(nvme.join("hwmon1/temp1_input"), "41850\n").unwrap();
+        fs::create_dir_all(dir.join("vda")).unwrap();
+
+        let sda = disk_health(&dir, "sda");
+        assert_eq!(sda.model.as_deref(), Some("WDC WD40EFRX"));
+        assert_eq!(sda.io_errors, Some(2));
+        assert_eq!(sda.temperature_celsius, Some(38.0));
+        assert!(!sda.healthy);
+        let nvme = disk_health(&dir, "nvme0n1");
+        assert_eq!(nvme.state.as_deref(), Some("live"));
+        assert_eq!(nvme.temperature_celsius, Some(41.85));
+        assert!(nvme.healthy);
+        let vda = disk_health(&dir, "vda");
+        assert_eq!(vda.state, None);
+        assert!(vda.healthy);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,4 +1,5 @@
 use tonic::{Response, Status};
+use nca_error::NcaError;
 use nca_system_api::systemd::api::set_systemd_credential;
 use crate::api::StatusResponse;
 
@@ -10,4 +11,14 @@ pub(crate) async fn set_systemd_credential_at_path(value: String, path: String,
             Ok(Response::new(StatusResponse::default()))
         }
     }
-}
\ No newline at end of file
+}
+
+/// Runs `f` on the blocking thread pool, so long-running commands don't stall the runtime
+pub(crate) async fn blocking<T, F>(f: F) -> Result<T, NcaError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, NcaError> + Send + 'static
+{
+    tokio::task::spawn_blocking(f).await
+        .unwrap_or_else(|e| Err(NcaError::new_unexpected_error(format!("Background task failed: {e:?}"))))
+}
@@ -0,0 +1,83 @@
+//! Compares the output modes with sending one message per byte, using output like that of
+//! `occ files:scan --all -v` for a large instance. Run with `cargo bench -p grpc-occ`.
+use std::time::{Duration, Instant};
+use prost::Message;
+use grpc_occ::api::{CommandOutput, OutputMode, OutputType};
+use grpc_occ::output::{OutputDecoder, READ_BUFFER_SIZE};
+
+const SCANNED_FILES: usize = 200_000;
+
+fn files_scan_output() -> Vec<u8> {
+    let mut output = String::from("Starting scan for user 1 out of 1 (admin)\n");
+    for i in 0..SCANNED_FILES {
+        if i % 1000 == 0 {
+            output.push_str(&format!("Scanning folder   /admin/files/Fotos/Überblick {}/\n", i / 1000));
+        }
+        output.push_str(&format!("Scanning file     /admin/files/Fotos/Überblick {}/Straßenfest-{i:06} 🎉.jpg\n", i / 1000));
+        if i % 500 == 0 {
+            output.push_str(&format!(" {i}/{SCANNED_FILES} [▓▓▓░░░░░░░░░]\r"));
+        }
+    }
+    output.push_str("+---------+--------+--------------+\n| Folders | Files  | Elapsed time |\n+---------+--------+--------------+\n");
+    output.into_bytes()
+}
+
+struct Measurement {
+    messages: usize,
+    encoded_bytes: usize,
+    elapsed: Duration,
+}
+
+fn byte_per_message(output: &[u8]) -> Measurement {
+    let started = Instant::now();
+    let mut result = Measurement { messages: 0, encoded_bytes: 0, elapsed: Duration::ZERO };
+    for byte in output {
+        let msg = CommandOutput {
+            r#type: OutputType::Stdout.into(),
+            message: Some(String::from_utf8_lossy(&[*byte]).to_string()),
+            ..CommandOutput::default()
+        };
+        result.messages += 1;
+        result.encoded_bytes += msg.encoded_len();
+    }
+    result.elapsed = started.elapsed();
+    result
+}
+
+fn decoded(output: &[u8], mode: OutputMode) -> Measurement {
+    let started = Instant::now();
+    let mut result = Measurement { messages: 0, encoded_bytes: 0, elapsed: Duration::ZERO };
+    let mut decoder = OutputDecoder::new(mode, OutputType::Stdout);
+    let mut replayed = Vec::with_capacity(output.len());
+    let messages = output.chunks(READ_BUFFER_SIZE)
+        .flat_map(|read| decoder.push(read))
+        .collect::<Vec<CommandOutput>>()
+        .into_iter()
+        .chain(decoder.finish());
+    for msg in messages {
+        result.messages += 1;
+        result.encoded_bytes += msg.encoded_len();
+        replayed.extend(msg.data.unwrap_or_else(|| msg.message.unwrap_or_default().into_bytes()));
+    }
+    result.elapsed = started.elapsed();
+    assert_eq!(replayed, output, "{mode:?} output differs from the input");
+    result
+}
+
+fn main() {
+    let output = files_scan_output();
+    println!("{:.1} MiB of output", output.len() as f64 / 1024.0 / 1024.0);
+    let results = [
+        ("byte per message", byte_per_message(&output)),
+        ("lines", decoded(&output, OutputMode::Lines)),
+        ("chunks", decoded(&output, OutputMode::Chunks)),
+        ("raw", decoded(&output, OutputMode::Raw)),
+    ];
+    for (name, result) in results {
+        println!("{name:>16}: {:>9} messages, {:>6.1} MiB encoded, {:>8.1} ms, {:>8.1} MiB/s",
+                 result.messages,
+                 result.encoded_bytes as f64 / 1024.0 / 1024.0,
+                 result.elapsed.as_secs_f64() * 1000.0,
+                 output.len() as f64 / 1024.0 / 1024.0 / result.elapsed.as_secs_f64());
+    }
+}
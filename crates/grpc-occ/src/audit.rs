@@ -0,0 +1,107 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use serde::Serialize;
+use crate::policy::Caller;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditEvent {
+    /// The policy did not allow the command
+    Denied,
+    /// Recorded before the command is run, so it shows up even if it never finishes
+    Started,
+    Finished,
+}
+
+/// One line of the audit log
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct AuditRecord {
+    /// Microseconds since the epoch at which the command was requested
+    pub timestamp: u64,
+    pub event: AuditEvent,
+    pub caller: Caller,
+    /// With secrets redacted
+    pub arguments: Vec<String>,
+    pub interactive: bool,
+    pub allowed: bool,
+    pub duration_ms: Option<u64>,
+    pub exit_code: Option<i32>,
+}
+
+impl AuditRecord {
+    pub fn new(caller: Caller, arguments: Vec<String>, interactive: bool, allowed: bool) -> AuditRecord {
+        AuditRecord {
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as u64,
+            event: if allowed { AuditEvent::Started } else { AuditEvent::Denied },
+            caller,
+            arguments,
+            interactive,
+            allowed,
+            duration_ms: None,
+            exit_code: None,
+        }
+    }
+
+    pub fn finished(self, duration: Duration, exit_code: Option<i32>) -> AuditRecord {
+        AuditRecord { event: AuditEvent::Finished, duration_ms: Some(duration.as_millis() as u64), exit_code, ..self }
+    }
+}
+
+/// Records as JSON lines, the file is only ever appended to
+pub struct AuditLog {
+    file: Mutex<File>,
+}
+
+impl AuditLog {
+    pub fn open(path: &Path) -> io::Result<AuditLog> {
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .mode(0o600)
+            .open(path)?;
+        Ok(AuditLog { file: Mutex::new(file) })
+    }
+
+    /// Fails if the record could not be written completely, e.g. because the disk is full
+    pub fn record(&self, record: &AuditRecord) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        // A single write keeps lines intact if other processes append as well
+        let mut file = self.file.lock()
+            .map_err(|e| io::Error::other(format!("{e}")))?;
+        file.write_all(&line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record() -> AuditRecord {
+        AuditRecord::new(Caller { uid: Some(33), gid: Some(33), pid: None }, vec!["status".to_string()], false, true)
+    }
+
+    #[test]
+    fn test_record_appends_lines() {
+        let path = std::env::temp_dir().join(format!("grpc-occ-audit-{}.log", std::process::id()));
+        let audit_log = AuditLog::open(&path).unwrap();
+        audit_log.record(&record()).unwrap();
+        audit_log.record(&record().finished(Duration::from_millis(5), Some(0))).unwrap();
+        let lines = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let events: Vec<String> = lines.lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["event"].to_string())
+            .collect();
+        assert_eq!(events, vec!["\"started\"", "\"finished\""]);
+    }
+
+    #[test]
+    fn test_record_reports_write_error() {
+        let audit_log = AuditLog::open(Path::new("/dev/full")).unwrap();
+        assert!(audit_log.record(&record()).is_err());
+    }
+}
@@ -1,4 +1,6 @@
-use grpc_occ::occ::client::run_occ_client;
+use std::io::IsTerminal;
+use grpc_occ::api::OutputMode;
+use grpc_occ::occ::client::{run_occ_client, run_occ_interactive};
 use nca_error::NcaError;
 
 #[tokio::main]
@@ -10,6 +12,10 @@ async fn main() -> Result<(), NcaError> {
     let socket_path = std::env::var("OCC_SERVER_SOCKET")
         .expect("Variable OCC_SERVER_SOCKET is not set");
     let occ_args = args.collect::<Vec<String>>();
-    
-    run_occ_client(socket_path, occ_args).await
-}
\ No newline at end of file
+
+    if std::io::stdin().is_terminal() && std::io::stdout().is_terminal() {
+        run_occ_interactive(socket_path, occ_args).await
+    } else {
+        run_occ_client(socket_path, occ_args, OutputMode::Chunks).await
+    }
+}
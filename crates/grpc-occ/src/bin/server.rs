@@ -6,6 +6,7 @@ use tonic::transport::Server;
 use grpc_common::server::serve_socket_tonic;
 use grpc_occ::api::occ_server::OccServer;
 use grpc_occ::occ::server::OccService;
+use grpc_occ::policy::Policy;
 
 #[tokio::main]
 async fn main() -> Result<(), String> {
@@ -14,6 +15,17 @@ async fn main() -> Result<(), String> {
         "podman".to_string(),
         "nc-aio_nextcloud-aio-nextcloud_1".to_string());
 
+    // Refuse to start with a broken policy rather than allowing everything
+    let config_path = std::env::var("CONFIG_PATH").unwrap_or("/etc/ncatomic".to_string());
+    let service = match Policy::load(&config_path).map_err(|e| format!("Failed to load occ policy: {e}"))? {
+        Some(policy) => service.with_policy(policy)
+            .map_err(|e| format!("Failed to open occ audit log: {e:?}"))?,
+        None => {
+            println!("No occ policy configured, all commands are allowed");
+            service
+        }
+    };
+
     let socket_path = PathBuf::from(std::env::var("OCC_SOCKET_PATH")
         .expect("OCC_SOCKET_PATH must be set"));
     if socket_path.exists() {
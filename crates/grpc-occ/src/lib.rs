@@ -1,5 +1,15 @@
 #[cfg(feature = "api")]
 pub mod occ;
+#[cfg(feature = "api")]
+pub mod output;
+#[cfg(feature = "api")]
+pub mod runner;
+#[cfg(feature = "api")]
+pub mod pty;
+#[cfg(feature = "api")]
+pub mod policy;
+#[cfg(feature = "api")]
+pub mod audit;
 
 pub mod api {
     tonic::include_proto!("occ");
@@ -1,148 +1,404 @@
 
 pub mod server {
-    use std::io::Read;
-    use std::thread;
-    use tokio::sync::mpsc::Sender;
-    use tonic::{Request, Response, Status};
+    use std::collections::HashMap;
+    use std::io;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    #[cfg(not(feature = "mock"))]
+    use std::sync::Mutex;
+    use std::time::{Duration, Instant};
+    use tonic::{Request, Response, Status, Streaming};
     use tonic::codegen::tokio_stream::wrappers::ReceiverStream;
-    use crate::api::{Command, CommandOutput, OutputType};
+    use nca_error::NcaError;
+    use crate::api::{Command, CommandOutput, InteractiveInput};
+    use crate::api::interactive_input::Input;
     use crate::api::occ_server::Occ;
+    use crate::audit::{AuditLog, AuditRecord};
+    use crate::policy::{Caller, Policy};
+    #[cfg(not(feature = "mock"))]
+    use crate::policy::CommandNames;
+    use crate::runner::{grpc_timeout, run_interactive, run_streaming, Execution};
+
+    // Commands are killed after this long, unless the client requested a shorter timeout
+    const MAX_COMMAND_DURATION: Duration = Duration::from_secs(4 * 60 * 60);
+    // Used by e.g. `user:add --password-from-env`
+    const ALLOWED_ENVIRONMENT: [&str; 1] = ["OC_PASS"];
+    #[cfg(not(feature = "mock"))]
+    const LIST_COMMANDS_TIMEOUT: Duration = Duration::from_secs(60);
+    // Set on the php process of each command, to find it in the container again
+    const EXECUTION_MARKER: &str = "ncatomic.occ_execution";
+
+    fn check_environment(environment: &HashMap<String, String>) -> Result<(), String> {
+        match environment.keys().find(|name| !ALLOWED_ENVIRONMENT.contains(&name.as_str())) {
+            Some(name) => Err(format!("Passing {name} to occ is not permitted")),
+            None => Ok(()),
+        }
+    }
+
+    fn permission_denied(record: &AuditRecord) -> Status {
+        Status::permission_denied(format!("Running 'occ {}' is not permitted", record.arguments.join(" ")))
+    }
+
+    fn record_finished(audit_log: &AuditLog, record: AuditRecord, started: Instant, exit_code: Option<i32>) {
+        // The command has run already, so all that is left is to report the gap in the log
+        if let Err(e) = audit_log.record(&record.finished(started.elapsed(), exit_code)) {
+            eprintln!("Failed to write audit record: {e:?}");
+        }
+    }
 
     pub struct OccService {
         container_cmd: String,
         container_name: String,
+        policy: Policy,
+        audit_log: Option<Arc<AuditLog>>,
+        executions: AtomicU64,
+        // Refreshed whenever a command is not in it
+        #[cfg(not(feature = "mock"))]
+        commands: Mutex<CommandNames>,
     }
 
     impl OccService {
+        /// Allows every command until a policy is set
         pub fn new(container_cmd: String, container_name: String) -> Self {
-            Self { container_cmd, container_name }
+            Self {
+                container_cmd,
+                container_name,
+                policy: Policy::default(),
+                audit_log: None,
+                executions: AtomicU64::default(),
+                #[cfg(not(feature = "mock"))]
+                commands: Mutex::default(),
+            }
+        }
+
+        /// Fails if the audit log of `policy` can't be opened
+        pub fn with_policy(mut self, policy: Policy) -> io::Result<Self> {
+            self.audit_log = match &policy.audit_log {
+                Some(path) => Some(Arc::new(AuditLog::open(path)?)),
+                None => None,
+            };
+            self.policy = policy;
+            Ok(self)
         }
+
         #[cfg(not(feature = "mock"))]
-        fn base_command(&self) -> [&str; 7] {
-            ["exec", "-it", "-u", "www-data", &self.container_name, "php", "occ"]
+        async fn list_commands(&self) -> Result<CommandNames, NcaError> {
+            let execution = self.execution_id();
+            let mut cmd = self.command(vec!["list".to_string(), "--format=json".to_string()], HashMap::new(), false, &execution);
+            let output = tokio::time::timeout(LIST_COMMANDS_TIMEOUT, cmd.kill_on_drop(true).output()).await
+                .map_err(|_| NcaError::new_io_error("Listing the occ commands timed out"))?
+                .map_err(|e| NcaError::new_io_error(format!("Failed to list occ commands: {e:?}")))?;
+            if !output.status.success() {
+                return Err(NcaError::new_io_error(format!("Failed to list occ commands: {}", String::from_utf8_lossy(&output.stderr))));
+            }
+            CommandNames::parse(&String::from_utf8_lossy(&output.stdout))
+        }
+
+        /// `arguments` with the full name of the command. occ also runs commands given by an
+        /// abbreviation, which would otherwise slip past the policy.
+        #[cfg(not(feature = "mock"))]
+        async fn canonical_arguments(&self, arguments: &[String]) -> Result<Vec<String>, NcaError> {
+            let cached = self.commands.lock().expect("mutex was poisoned").canonicalize(arguments);
+            if let Some(arguments) = cached {
+                return Ok(arguments);
+            }
+            let commands = self.list_commands().await?;
+            let arguments = commands.canonicalize(arguments);
+            *self.commands.lock().expect("mutex was poisoned") = commands;
+            arguments.ok_or(NcaError::new_invalid_argument_error("Unknown or ambiguous occ command, please use its full name"))
         }
 
         #[cfg(feature = "mock")]
-        fn base_command(&self) -> [&str; 7] {
-            [&self.container_cmd, "exec", "-it", "-u", "www-data", &self.container_name, "php occ"]
+        async fn canonical_arguments(&self, arguments: &[String]) -> Result<Vec<String>, NcaError> {
+            Ok(arguments.to_vec())
         }
-    }
 
-    #[tonic::async_trait]
-    impl Occ for OccService {
-        type ExecStream = ReceiverStream<Result<CommandOutput, Status>>;
+        /// Checks the policy and records a denied command right away
+        fn authorize(&self, caller: Caller, arguments: &[String], interactive: bool) -> AuditRecord {
+            let allowed = self.policy.is_allowed(&caller, arguments);
+            let record = AuditRecord::new(caller, self.policy.redact(arguments), interactive, allowed);
 
-        async fn exec(&self, request: Request<Command>) -> Result<Response<Self::ExecStream>, Status> {
             #[cfg(debug_assertions)]
-            println!("Received exec request: {request:?}");
+            println!("Received {}exec request: {record:?}", if interactive { "interactive " } else { "" });
+
+            if !allowed {
+                if let Some(audit_log) = &self.audit_log {
+                    // The command is refused either way
+                    if let Err(e) = audit_log.record(&record) {
+                        eprintln!("Failed to write audit record: {e:?}");
+                    }
+                }
+            }
+            record
+        }
+
+        /// Records the command in the audit log before `run` starts it and again once it finished.
+        /// The command is not run if it can't be recorded.
+        fn audit(&self, record: AuditRecord, run: impl FnOnce() -> io::Result<Execution>) -> Result<ReceiverStream<Result<CommandOutput, Status>>, NcaError> {
+            if let Some(audit_log) = &self.audit_log {
+                audit_log.record(&record)
+                    .map_err(|e| NcaError::new_io_error(format!("Failed to write the audit log, the command was not run: {e:?}")))?;
+            }
+            let started = Instant::now();
+            let execution = match run() {
+                Ok(execution) => execution,
+                Err(e) => {
+                    if let Some(audit_log) = &self.audit_log {
+                        record_finished(audit_log, record, started, None);
+                    }
+                    return Err(NcaError::new_io_error(format!("Failed to start occ command: {e:?}")));
+                }
+            };
+            if let Some(audit_log) = self.audit_log.clone() {
+                tokio::spawn(async move {
+                    let exit_code = execution.exit.await.unwrap_or_default();
+                    record_finished(&audit_log, record, started, exit_code);
+                });
+            }
+            Ok(ReceiverStream::new(execution.output))
+        }
 
-            let args = request.into_inner().arguments;
+        /// Unique among the commands of this service, with a fixed length so no id is a prefix of another
+        fn execution_id(&self) -> String {
+            format!("{}-{:016x}", std::process::id(), self.executions.fetch_add(1, Ordering::Relaxed))
+        }
 
+        #[cfg(not(feature = "mock"))]
+        fn base_command(&self, marker: &str) -> Vec<String> {
+            ["exec", "-u", "www-data", &self.container_name, "php", "-d", marker, "occ"].map(String::from).to_vec()
+        }
 
-            let (tx, rx) = tokio::sync::mpsc::channel(100);
+        #[cfg(feature = "mock")]
+        fn base_command(&self, marker: &str) -> Vec<String> {
+            [&self.container_cmd, "exec", "-u", "www-data", &self.container_name, &format!("php -d {marker} occ")].map(String::from).to_vec()
+        }
 
+        /// Ends the php process of `execution` in the container, which outlives `podman exec`
+        /// when that is killed
+        fn terminate_command(&self, execution: &str) -> tokio::process::Command {
             #[cfg(not(feature = "mock"))]
-            let mut cmd = std::process::Command::new(&self.container_cmd);
+            let mut cmd = tokio::process::Command::new(&self.container_cmd);
             #[cfg(feature = "mock")]
             let mut cmd = {
-                let mut cmd = std::process::Command::new("echo");
+                let mut cmd = tokio::process::Command::new("echo");
                 cmd.args([&self.container_cmd]);
                 cmd
             };
-            cmd.args(self.base_command()).args(args);
-            
-            #[cfg(debug_assertions)]
-            eprintln!("Running {cmd:?}");
+            // The marker is followed by a space, as occ is always the next argument
+            let pattern = format!("-d {EXECUTION_MARKER}={execution} ");
+            cmd.args(["exec", "-u", "www-data", &self.container_name, "pkill", "-TERM", "-f", "--", &pattern]);
+            cmd
+        }
 
-            let mut spawn = cmd.spawn()?;
-            if let Some(stdout) = spawn.stdout.take() {
-                stream_output_to_receiver(stdout, tx.clone(), OutputType::Stdout);
+        /// Variables in `environment` are passed on to occ without showing up in the arguments
+        fn command(&self, arguments: Vec<String>, environment: HashMap<String, String>, interactive: bool, execution: &str) -> tokio::process::Command {
+            #[cfg(not(feature = "mock"))]
+            let mut cmd = tokio::process::Command::new(&self.container_cmd);
+            #[cfg(feature = "mock")]
+            let mut cmd = {
+                let mut cmd = tokio::process::Command::new("echo");
+                cmd.args([&self.container_cmd]);
+                cmd
+            };
+            let mut exec_options = Vec::new();
+            if interactive {
+                exec_options.push("-it".to_string());
             }
-            if let Some(stderr) = spawn.stderr.take() {
-                stream_output_to_receiver(stderr, tx.clone(), OutputType::Stderr);
+            for name in environment.keys() {
+                exec_options.extend(["--env".to_string(), name.clone()]);
             }
-            match spawn.wait() {
-                Err(e) => {
-                    eprintln!("Error waiting for occ command: {e:?}");
-                    tx.send(Ok(CommandOutput {
-                        r#type: OutputType::Exit.into(),
-                        message: Some(format!("Error waiting for occ command: {e:?}")),
-                        exit_code: Some(500)
-                    })).await.expect("send error");
-                },
-                Ok(status) => {
-                    tx.send(Ok(CommandOutput {
-                        r#type: OutputType::Exit.into(),
-                        message: None,
-                        exit_code: status.code(),
-                    })).await.expect("send error");
-                }
+            let mut base_command = self.base_command(&format!("{EXECUTION_MARKER}={execution}"));
+            let exec = base_command.iter().position(|arg| arg == "exec").unwrap_or_default();
+            base_command.splice(exec + 1..exec + 1, exec_options);
+            cmd.args(base_command).args(arguments).envs(environment);
+            cmd
+        }
+    }
+
+    #[tonic::async_trait]
+    impl Occ for OccService {
+        type ExecStream = ReceiverStream<Result<CommandOutput, Status>>;
+        type ExecInteractiveStream = ReceiverStream<Result<CommandOutput, Status>>;
+
+        async fn exec(&self, request: Request<Command>) -> Result<Response<Self::ExecStream>, Status> {
+            let caller = Caller::of(&request);
+            let timeout = grpc_timeout(request.metadata())
+                .map_or(MAX_COMMAND_DURATION, |timeout| timeout.min(MAX_COMMAND_DURATION));
+            let command = request.into_inner();
+            let arguments = self.canonical_arguments(&command.arguments).await?;
+            let record = self.authorize(caller, &arguments, false);
+            if !record.allowed {
+                return Err(permission_denied(&record));
             }
-            
-            #[cfg(debug_assertions)]
-            eprintln!("Command completed successfully");
+            check_environment(&command.environment).map_err(Status::invalid_argument)?;
+            let output_mode = command.output_mode();
+            let execution = self.execution_id();
+            let cmd = self.command(arguments, command.environment, false, &execution);
+            let terminate = self.terminate_command(&execution);
 
-            Ok(Response::new(ReceiverStream::new(rx)))
+            let output = self.audit(record, || run_streaming(cmd, output_mode, timeout, Some(terminate)))?;
+            Ok(Response::new(output))
         }
-    }
 
-    fn stream_output_to_receiver<R>(mut stream: R, tx: Sender<Result<CommandOutput, Status>>, output_type: OutputType)
-    where
-        R: Read + Send + 'static,
-    {
-        thread::spawn(move || loop {
-            let mut buf = [0];
-            match stream.read(&mut buf) {
-                Err(e) => {
-                    eprintln!("Error reading from stdout: {e:?}");
-                    break;
-                },
-                Ok(count) => {
-                    if count == 0 {
-                        break;
-                    } else if count == 1 {
-                        if let Err(e) = tx.blocking_send(Ok(CommandOutput {
-                            message: Some(String::from_utf8_lossy(&buf).parse().unwrap()),
-                            r#type: output_type.into(),
-                            exit_code: None,
-                        })) {
-                            eprintln!("Error sending occ output: {e:?}");
-                            break;
-                        }
-                    }
-                }
+        async fn exec_interactive(&self, request: Request<Streaming<InteractiveInput>>) -> Result<Response<Self::ExecInteractiveStream>, Status> {
+            let caller = Caller::of(&request);
+            let timeout = grpc_timeout(request.metadata())
+                .map_or(MAX_COMMAND_DURATION, |timeout| timeout.min(MAX_COMMAND_DURATION));
+            let mut input = request.into_inner();
+            let command = match input.message().await? {
+                Some(InteractiveInput { input: Some(Input::Command(command)) }) => command,
+                _ => return Err(Status::invalid_argument("The first message has to be the command")),
+            };
+            let arguments = self.canonical_arguments(&command.arguments).await?;
+            let record = self.authorize(caller, &arguments, true);
+            if !record.allowed {
+                return Err(permission_denied(&record));
             }
-        });
+
+            check_environment(&command.environment).map_err(Status::invalid_argument)?;
+
+            // The client sends the size of its terminal right after the command
+            let execution = self.execution_id();
+            let cmd = self.command(arguments, command.environment, true, &execution);
+            let terminate = self.terminate_command(&execution);
+            let output = self.audit(record, || run_interactive(cmd, None, input, timeout, Some(terminate)))?;
+            Ok(Response::new(output))
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_audit_refuses_unrecorded_command() {
+            let policy = Policy { audit_log: Some("/dev/full".into()), ..Policy::default() };
+            let service = OccService::new("podman".to_string(), "nextcloud".to_string()).with_policy(policy).unwrap();
+            let record = AuditRecord::new(Caller::default(), vec!["status".to_string()], false, true);
+            let result = service.audit(record, || panic!("the command must not be run"));
+            assert_eq!(Status::from(result.unwrap_err()).code(), tonic::Code::Internal);
+        }
     }
 }
 
 pub mod client {
+    use std::io::{Read, Write};
+    use std::os::fd::AsFd;
     use std::path::PathBuf;
+    use tokio::signal::unix::{signal, SignalKind};
+    use tokio::sync::mpsc::{self, Sender};
     use tonic::Streaming;
+    use tonic::codegen::tokio_stream::wrappers::ReceiverStream;
+    use tonic::transport::Channel;
     use grpc_common::client::get_socket_channel;
     use nca_error::NcaError;
-    use crate::api::{Command, CommandOutput, OutputType};
+    use crate::api::{Command, CommandOutput, InteractiveInput, OutputMode, OutputType, Signal};
+    use crate::api::interactive_input::Input;
     use crate::api::occ_client::OccClient;
+    use crate::pty::{window_size, RawMode};
 
-    pub async fn run_occ_client(socket_path: String, occ_args: Vec<String>) -> Result<(), NcaError> {
+    const STDIN_BUFFER_SIZE: usize = 4096;
 
-        let channel = get_socket_channel(
+    async fn connect(socket_path: String) -> Result<Channel, NcaError> {
+        get_socket_channel(
             PathBuf::from(socket_path),
             "http://occ.nextcloudatomic.local".to_string()
-        ).await?;
+        ).await
+    }
+
+    pub async fn run_occ_client(socket_path: String, occ_args: Vec<String>, output_mode: OutputMode) -> Result<(), NcaError> {
+
+        let channel = connect(socket_path).await?;
 
         // let channel = Channel::from_shared(format!("http://{}", &addr)).unwrap()
         //     .connect()
         //     .await.unwrap();
 
         let mut client = OccClient::new(channel);
-        let response = client.exec(Command{arguments: occ_args}).await
+        let response = client.exec(Command{arguments: occ_args, output_mode: output_mode.into(), ..Command::default()}).await
             .map_err(|e| NcaError::new_io_error(format!("An error occurred while running occ command: {e:?}")))?
             .into_inner();
         
         handle_occ_output(response).await
     }
     
+    fn send_input(tx: &Sender<InteractiveInput>, input: Input) -> bool {
+        tx.blocking_send(InteractiveInput { input: Some(input) }).is_ok()
+    }
+
+    fn forward_stdin(tx: Sender<InteractiveInput>) {
+        std::thread::spawn(move || {
+            let mut stdin = std::io::stdin();
+            let mut buf = [0; STDIN_BUFFER_SIZE];
+            loop {
+                match stdin.read(&mut buf) {
+                    Ok(0) | Err(_) => return,
+                    Ok(count) => if !send_input(&tx, Input::Stdin(buf[..count].to_vec())) {
+                        return;
+                    },
+                }
+            }
+        });
+    }
+
+    async fn forward_signals(tx: Sender<InteractiveInput>) -> std::io::Result<()> {
+        let mut window_change = signal(SignalKind::window_change())?;
+        let mut interrupt = signal(SignalKind::interrupt())?;
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::spawn(async move {
+            loop {
+                let input = tokio::select! {
+                    _ = window_change.recv() => match window_size(std::io::stdout().as_fd()) {
+                        Some(size) => Input::WindowSize(size),
+                        None => continue,
+                    },
+                    _ = interrupt.recv() => Input::Signal(Signal::Interrupt.into()),
+                    _ = terminate.recv() => Input::Signal(Signal::Terminate.into()),
+                };
+                if tx.send(InteractiveInput { input: Some(input) }).await.is_err() {
+                    return;
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Runs the occ command attached to a terminal, with the input and size of the local terminal
+    pub async fn run_occ_interactive(socket_path: String, occ_args: Vec<String>) -> Result<(), NcaError> {
+        let channel = connect(socket_path).await?;
+        let mut client = OccClient::new(channel);
+
+        let (tx, rx) = mpsc::channel(16);
+        let command = Input::Command(Command { arguments: occ_args, ..Command::default() });
+        tx.send(InteractiveInput { input: Some(command) }).await
+            .map_err(|e| NcaError::new_io_error(format!("Failed to send occ command: {e:?}")))?;
+        if let Some(size) = window_size(std::io::stdout().as_fd()) {
+            tx.send(InteractiveInput { input: Some(Input::WindowSize(size)) }).await
+                .map_err(|e| NcaError::new_io_error(format!("Failed to send terminal size: {e:?}")))?;
+        }
+        forward_signals(tx.clone()).await
+            .map_err(|e| NcaError::new_io_error(format!("Failed to listen for signals: {e:?}")))?;
+
+        let response = client.exec_interactive(ReceiverStream::new(rx)).await
+            .map_err(|e| NcaError::new_io_error(format!("An error occurred while running occ command: {e:?}")))?
+            .into_inner();
+        let raw_mode = RawMode::enable()
+            .map_err(|e| NcaError::new_io_error(format!("Failed to configure the terminal: {e:?}")))?;
+        forward_stdin(tx);
+
+        let result = handle_occ_output(response).await;
+        drop(raw_mode);
+        result
+    }
+
+    fn write_output(out: &mut impl Write, output: CommandOutput) {
+        let data = output.data.or(output.message.map(String::into_bytes)).unwrap_or_default();
+        // Chunks don't necessarily end with a newline
+        if let Err(e) = out.write_all(&data).and_then(|_| out.flush()) {
+            eprintln!("Failed to write occ output: {e:?}");
+        }
+    }
+
     pub async fn handle_occ_output(mut output: Streaming<CommandOutput>) -> Result<(), NcaError> {
 
         loop {
@@ -170,13 +426,9 @@ pub mod client {
                                     Err(NcaError::new_io_error("occ exited but no exit code was returned!"))
                                 }
                             } else if out.r#type == (OutputType::Stderr as i32) {
-                                if let Some(msg) = out.message {
-                                    eprint!("{msg}");
-                                }
+                                write_output(&mut std::io::stderr(), out);
                             } else if out.r#type == (OutputType::Stdout as i32) {
-                                if let Some(msg) = out.message {
-                                    print!("{msg}");
-                                }
+                                write_output(&mut std::io::stdout(), out);
                             }
                         }
                     }
@@ -0,0 +1,138 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::api::{CommandOutput, OutputMode, OutputType};
+
+pub const READ_BUFFER_SIZE: usize = 64 * 1024;
+// Partial lines longer than this are sent without waiting for the line end
+const MAX_LINE_LENGTH: usize = 64 * 1024;
+
+fn now_usec() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_micros() as u64
+}
+
+/// The length of `bytes` without an incomplete UTF-8 sequence at its end. Invalid sequences
+/// elsewhere are included, they are replaced when decoding.
+fn complete_utf8_len(bytes: &[u8]) -> usize {
+    let mut offset = 0;
+    loop {
+        match std::str::from_utf8(&bytes[offset..]) {
+            Ok(_) => return bytes.len(),
+            Err(e) => match e.error_len() {
+                Some(invalid) => offset += e.valid_up_to() + invalid,
+                None => return offset + e.valid_up_to(),
+            }
+        }
+    }
+}
+
+/// The end of the first line in `bytes` (after its terminator). A `\r` that is not part of
+/// `\r\n` ends a line as well, as used by progress bars.
+fn line_end(bytes: &[u8]) -> Option<usize> {
+    let pos = bytes.iter().position(|b| *b == b'\n' || *b == b'\r')?;
+    match (bytes[pos], bytes.get(pos + 1)) {
+        (b'\r', Some(b'\n')) => Some(pos + 2),
+        // the next read might start with \n
+        (b'\r', None) => None,
+        _ => Some(pos + 1),
+    }
+}
+
+/// Turns the output of a process, as it is read, into messages according to the `OutputMode`
+pub struct OutputDecoder {
+    mode: OutputMode,
+    output_type: OutputType,
+    pending: Vec<u8>,
+}
+
+impl OutputDecoder {
+    pub fn new(mode: OutputMode, output_type: OutputType) -> OutputDecoder {
+        OutputDecoder { mode, output_type, pending: Vec::new() }
+    }
+
+    fn text(&self, bytes: &[u8], timestamp: u64) -> CommandOutput {
+        CommandOutput {
+            r#type: self.output_type.into(),
+            message: Some(String::from_utf8_lossy(bytes).to_string()),
+            timestamp: Some(timestamp),
+            ..CommandOutput::default()
+        }
+    }
+
+    /// The messages that are complete after `data` was read
+    pub fn push(&mut self, data: &[u8]) -> Vec<CommandOutput> {
+        let timestamp = now_usec();
+        if self.mode == OutputMode::Raw {
+            return match data.is_empty() {
+                true => Vec::new(),
+                false => vec![CommandOutput {
+                    r#type: self.output_type.into(),
+                    data: Some(data.to_vec()),
+                    timestamp: Some(timestamp),
+                    ..CommandOutput::default()
+                }],
+            };
+        }
+
+        self.pending.extend_from_slice(data);
+        let mut messages = Vec::new();
+        let mut start = 0;
+        if self.mode == OutputMode::Lines {
+            while let Some(len) = line_end(&self.pending[start..]) {
+                messages.push(self.text(&self.pending[start..start + len], timestamp));
+                start += len;
+            }
+        }
+        if self.mode == OutputMode::Chunks || self.pending.len() - start > MAX_LINE_LENGTH {
+            let len = complete_utf8_len(&self.pending[start..]);
+            if len > 0 {
+                messages.push(self.text(&self.pending[start..start + len], timestamp));
+                start += len;
+            }
+        }
+        self.pending.drain(..start);
+        messages
+    }
+
+    /// The remaining output once the process closed the stream
+    pub fn finish(&mut self) -> Vec<CommandOutput> {
+        if self.pending.is_empty() {
+            return Vec::new();
+        }
+        let pending = std::mem::take(&mut self.pending);
+        vec![self.text(&pending, now_usec())]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(decoder: &mut OutputDecoder, data: &[u8]) -> Vec<String> {
+        decoder.push(data).into_iter().map(|out| out.message.unwrap()).collect()
+    }
+
+    #[test]
+    fn test_lines() {
+        let mut decoder = OutputDecoder::new(OutputMode::Lines, OutputType::Stdout);
+        assert_eq!(messages(&mut decoder, b"Scanning /Fotos/\xc3"), Vec::<String>::new());
+        assert_eq!(messages(&mut decoder, b"\x9cbersicht\nDone\r"), vec!["Scanning /Fotos/Übersicht\n"]);
+        assert_eq!(messages(&mut decoder, b"\n 1/3\r 2/3\r"), vec!["Done\r\n", " 1/3\r"]);
+        assert_eq!(messages(&mut decoder, b"\xff 3/3"), vec![" 2/3\r"]);
+        let rest = decoder.finish();
+        assert_eq!(rest[0].message.as_deref(), Some("\u{fffd} 3/3"));
+        assert_eq!(rest[0].r#type(), OutputType::Stdout);
+        assert!(rest[0].timestamp.is_some());
+    }
+
+    #[test]
+    fn test_chunks_and_raw() {
+        let mut decoder = OutputDecoder::new(OutputMode::Chunks, OutputType::Stderr);
+        assert_eq!(messages(&mut decoder, b"Continue? \xe2\x9c"), vec!["Continue? "]);
+        assert_eq!(messages(&mut decoder, b"\x94 \xff\xe2"), vec!["✔ \u{fffd}"]);
+        assert_eq!(decoder.finish()[0].message.as_deref(), Some("\u{fffd}"));
+
+        let mut decoder = OutputDecoder::new(OutputMode::Raw, OutputType::Stdout);
+        let out = decoder.push(b"\x1b[32m\xe2\x9c");
+        assert_eq!((out[0].data.as_deref(), out[0].message.as_deref()), (Some(&b"\x1b[32m\xe2\x9c"[..]), None));
+        assert!(decoder.finish().is_empty());
+    }
+}
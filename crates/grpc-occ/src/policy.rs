@@ -0,0 +1,377 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use nix::unistd::{Uid, User};
+use tonic::Request;
+use tonic::transport::server::UdsConnectInfo;
+use nca_error::NcaError;
+
+const POLICY_CONFIG_FILE: &str = "occ/policy.json";
+const REDACTED: &str = "***";
+// A positional argument following an argument containing one of these is considered a secret
+const SECRET_WORDS: [&str; 3] = ["password", "secret", "token"];
+
+fn default_secret_options() -> Vec<String> {
+    ["--password", "--value", "--secret", "--token"].map(String::from).to_vec()
+}
+
+/// The process on the other side of the socket, as reported by the kernel
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct Caller {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub pid: Option<i32>,
+}
+
+impl Caller {
+    /// Unknown unless the request was received over a Unix socket
+    pub fn of<T>(request: &Request<T>) -> Caller {
+        request.extensions().get::<UdsConnectInfo>()
+            .and_then(|info| info.peer_cred)
+            .map(|cred| Caller { uid: Some(cred.uid()), gid: Some(cred.gid()), pid: cred.pid() })
+            .unwrap_or_default()
+    }
+
+    fn user_name(&self) -> Option<String> {
+        let user = User::from_uid(Uid::from_raw(self.uid?)).ok()??;
+        Some(user.name)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    #[default]
+    Allow,
+    Deny,
+}
+
+/// Patterns may contain `*`, which matches any sequence of characters. A rule without
+/// `uids`, `gids` and `users` applies to every caller.
+#[derive(Deserialize, Debug, Clone)]
+pub struct PolicyRule {
+    pub action: Action,
+    /// The occ command, e.g. `user:*`
+    pub command: Option<String>,
+    /// Each pattern has to match one of the arguments following the command, ignoring case for
+    /// `user:*` commands
+    #[serde(default)]
+    pub arguments: Vec<String>,
+    #[serde(default)]
+    pub uids: Vec<u32>,
+    #[serde(default)]
+    pub gids: Vec<u32>,
+    /// User names, for system users whose uid differs between installations
+    #[serde(default)]
+    pub users: Vec<String>,
+}
+
+impl PolicyRule {
+    fn matches(&self, caller: &Caller, command: &str, arguments: &[String]) -> bool {
+        let caller_matches = (self.uids.is_empty() && self.gids.is_empty() && self.users.is_empty())
+            || caller.uid.is_some_and(|uid| self.uids.contains(&uid))
+            || caller.gid.is_some_and(|gid| self.gids.contains(&gid))
+            || (!self.users.is_empty() && caller.user_name().is_some_and(|name| self.users.contains(&name)));
+        caller_matches
+            && self.command.as_ref().is_none_or(|pattern| glob_match(pattern, command))
+            && self.arguments.iter().all(|pattern| arguments.iter().any(|arg| match ignores_argument_case(command) {
+                true => glob_match(&pattern.to_lowercase(), &arg.to_lowercase()),
+                false => glob_match(pattern, arg),
+            }))
+    }
+}
+
+/// Nextcloud looks up user ids ignoring case, so `ADMIN` has to match a rule for `admin`
+fn ignores_argument_case(command: &str) -> bool {
+    command.starts_with("user:")
+}
+
+/// Which occ commands may be run by whom. The first matching rule decides, `default` applies
+/// if none matches.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Policy {
+    #[serde(default)]
+    pub default: Action,
+    #[serde(default)]
+    pub rules: Vec<PolicyRule>,
+    /// Every command is appended to this file, if set
+    pub audit_log: Option<PathBuf>,
+    /// Options whose values are redacted in the audit log
+    #[serde(default = "default_secret_options")]
+    pub secret_options: Vec<String>,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            default: Action::Allow,
+            rules: Vec::new(),
+            audit_log: None,
+            secret_options: default_secret_options(),
+        }
+    }
+}
+
+fn glob_match(pattern: &str, value: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == value,
+        Some((prefix, rest)) => match value.strip_prefix(prefix) {
+            None => false,
+            Some(value) => value.char_indices().map(|(i, _)| i)
+                .chain([value.len()])
+                .any(|i| glob_match(rest, &value[i..])),
+        },
+    }
+}
+
+/// The position of the occ command, global options like `--no-interaction` may precede it
+fn command_index(arguments: &[String]) -> Option<usize> {
+    arguments.iter().position(|arg| !arg.starts_with('-'))
+}
+
+/// Whether `abbreviation` abbreviates every `:`-separated part of `name`, like `u:resetp` does
+/// for `user:resetpassword`
+fn abbreviates(abbreviation: &str, name: &str) -> bool {
+    let mut rest = name;
+    for (i, part) in abbreviation.split(':').enumerate() {
+        if i > 0 {
+            match rest.strip_prefix(':') {
+                Some(remainder) => rest = remainder,
+                None => return false,
+            }
+        }
+        match rest.strip_prefix(part) {
+            Some(remainder) => rest = &remainder[remainder.find(':').unwrap_or(remainder.len())..],
+            None => return false,
+        }
+    }
+    true
+}
+
+/// The available occ commands, as listed by `occ list --format=json`
+#[derive(Debug, Clone, Default)]
+pub struct CommandNames {
+    /// Names and aliases of each command, with the name of the command
+    names: BTreeMap<String, String>,
+}
+
+impl CommandNames {
+    pub fn parse(list: &str) -> Result<CommandNames, NcaError> {
+        let list: serde_json::Value = serde_json::from_str(list)
+            .map_err(|e| NcaError::new_io_error(format!("Failed to parse the list of occ commands: {e}")))?;
+        let commands = list.get("commands").and_then(|commands| commands.as_array())
+            .ok_or(NcaError::new_io_error("The list of occ commands contains no commands"))?;
+        let mut names = BTreeMap::new();
+        for command in commands {
+            let Some(name) = command.get("name").and_then(|name| name.as_str()) else {
+                continue;
+            };
+            let aliases = command.get("aliases").and_then(|aliases| aliases.as_array())
+                .into_iter()
+                .flatten()
+                .filter_map(|alias| alias.as_str());
+            for alias in aliases.chain([name]) {
+                names.insert(alias.to_string(), name.to_string());
+            }
+        }
+        Ok(CommandNames { names })
+    }
+
+    /// Resolves `name` the way occ (Symfony) does: an exact name or alias, otherwise the one
+    /// command that `name` abbreviates, ignoring case if no command matches otherwise.
+    /// `None` if `name` is unknown or ambiguous.
+    pub fn resolve(&self, name: &str) -> Option<&str> {
+        if let Some(command) = self.names.get(name) {
+            return Some(command);
+        }
+        let matching = |ignore_case: bool| self.names.iter()
+            .filter(|(alias, _)| match ignore_case {
+                true => abbreviates(&name.to_lowercase(), &alias.to_lowercase()),
+                false => abbreviates(name, alias),
+            })
+            .map(|(_, command)| command.as_str())
+            .collect::<BTreeSet<&str>>();
+        let mut commands = matching(false);
+        if commands.is_empty() {
+            commands = matching(true);
+        }
+        match commands.len() {
+            1 => commands.pop_first(),
+            _ => None,
+        }
+    }
+
+    /// `arguments` with the command replaced by its full name, so the policy is checked against
+    /// the command occ would run. `None` if the command is unknown or ambiguous.
+    pub fn canonicalize(&self, arguments: &[String]) -> Option<Vec<String>> {
+        let mut arguments = arguments.to_vec();
+        if let Some(i) = command_index(&arguments) {
+            arguments[i] = self.resolve(&arguments[i])?.to_string();
+        }
+        Some(arguments)
+    }
+}
+
+impl Policy {
+    pub fn parse(data: &str) -> Result<Policy, NcaError> {
+        serde_json::from_str(data)
+            .map_err(|e| NcaError::new_server_config_error(format!("Invalid occ policy: {e}")))
+    }
+
+    /// Loads `occ/policy.json` from `config_path`, returns `None` if it does not exist
+    pub fn load(config_path: &str) -> Result<Option<Policy>, NcaError> {
+        let path = PathBuf::from(config_path).join(POLICY_CONFIG_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let data = fs::read_to_string(&path)
+            .map_err(|e| NcaError::new_io_error(format!("Failed to read {}: {e:?}", path.display())))?;
+        Policy::parse(&data).map(Some)
+    }
+
+    /// `arguments` must name the command in full, see [CommandNames::canonicalize]
+    pub fn is_allowed(&self, caller: &Caller, arguments: &[String]) -> bool {
+        let (command, arguments) = match command_index(arguments) {
+            Some(i) => (arguments[i].as_str(), &arguments[i + 1..]),
+            None => ("", arguments),
+        };
+        let action = self.rules.iter()
+            .find(|rule| rule.matches(caller, command, arguments))
+            .map_or(self.default, |rule| rule.action);
+        action == Action::Allow
+    }
+
+    /// `arguments` with the values of secret options and positional secrets replaced
+    pub fn redact(&self, arguments: &[String]) -> Vec<String> {
+        let command = command_index(arguments);
+        let mut redacted = Vec::with_capacity(arguments.len());
+        let mut secret_follows = false;
+        for (i, arg) in arguments.iter().enumerate() {
+            if secret_follows {
+                secret_follows = false;
+                if !arg.starts_with('-') {
+                    redacted.push(REDACTED.to_string());
+                    continue;
+                }
+            }
+            if let Some((option, _)) = arg.split_once('=')
+                .filter(|(option, _)| self.secret_options.iter().any(|secret| secret == option)) {
+                redacted.push(format!("{option}={REDACTED}"));
+                continue;
+            }
+            let is_argument = command.is_some_and(|command| i > command);
+            secret_follows = self.secret_options.contains(arg) || (is_argument && !arg.starts_with('-')
+                && SECRET_WORDS.iter().any(|word| arg.to_lowercase().contains(word)));
+            redacted.push(arg.clone());
+        }
+        redacted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_rules() {
+        let policy = Policy::parse(r#"{
+            "default": "deny",
+            "rules": [
+                {"action": "allow", "uids": [0]},
+                {"action": "deny", "command": "user:*", "arguments": ["adm*"]},
+                {"action": "allow", "command": "user:*", "gids": [990]},
+                {"action": "allow", "command": "status"}
+            ]
+        }"#).unwrap();
+        let root = Caller { uid: Some(0), gid: Some(0), pid: Some(1) };
+        let backend = Caller { uid: Some(990), gid: Some(990), pid: Some(2) };
+        let other = Caller { uid: Some(1000), gid: Some(1000), pid: Some(3) };
+
+        assert!(policy.is_allowed(&root, &args(&["user:resetpassword", "admin"])));
+        assert!(!policy.is_allowed(&backend, &args(&["-n", "user:resetpassword", "admin"])));
+        assert!(policy.is_allowed(&backend, &args(&["user:disable", "alice"])));
+        assert!(!policy.is_allowed(&other, &args(&["user:disable", "alice"])));
+        assert!(policy.is_allowed(&other, &args(&["status", "--output=json"])));
+        assert!(!policy.is_allowed(&Caller::default(), &args(&["config:list"])));
+        assert!(Policy::default().is_allowed(&Caller::default(), &args(&["config:list"])));
+
+        let by_name = Policy::parse(r#"{
+            "default": "deny",
+            "rules": [{"action": "allow", "command": "config:*", "users": ["root"]}]
+        }"#).unwrap();
+        assert!(by_name.is_allowed(&root, &args(&["config:list"])));
+        assert!(!by_name.is_allowed(&other, &args(&["config:list"])));
+    }
+
+    #[test]
+    fn test_installed_policy() {
+        let policy = Policy::parse(include_str!("../install/etc/ncatomic/occ/policy.json")).unwrap();
+        // nca-backend runs as root
+        let backend = Caller { uid: Some(0), gid: Some(0), pid: Some(1) };
+        let other = Caller { uid: Some(1000), gid: Some(1000), pid: Some(2) };
+
+        assert!(policy.is_allowed(&backend, &args(&["user:resetpassword", "--password-from-env", "--", "alice"])));
+        assert!(!policy.is_allowed(&backend, &args(&["user:resetpassword", "--password-from-env", "--", "admin"])));
+        assert!(!policy.is_allowed(&backend, &args(&["user:resetpassword", "--password-from-env", "--", "ADMIN"])));
+        assert!(!policy.is_allowed(&other, &args(&["user:disable", "Admin"])));
+        assert!(!policy.is_allowed(&other, &args(&["user:resetpassword", "--password-from-env", "--", "alice"])));
+        assert!(!policy.is_allowed(&other, &args(&["user:delete", "admin"])));
+        assert!(policy.is_allowed(&other, &args(&["status"])));
+    }
+
+    #[test]
+    fn test_abbreviated_commands() {
+        let commands = CommandNames::parse(r#"{"commands": [
+            {"name": "list"},
+            {"name": "user:resetpassword"},
+            {"name": "user:report"},
+            {"name": "security:bruteforce:reset"},
+            {"name": "encryption:enable"},
+            {"name": "encryption:disable"},
+            {"name": "files:scan", "aliases": ["scan"]}
+        ]}"#).unwrap();
+        assert_eq!(commands.resolve("user:resetpassword"), Some("user:resetpassword"));
+        assert_eq!(commands.resolve("user:resetp"), Some("user:resetpassword"));
+        assert_eq!(commands.resolve("u:resetpassword"), Some("user:resetpassword"));
+        assert_eq!(commands.resolve("U:RESETP"), Some("user:resetpassword"));
+        assert_eq!(commands.resolve("sec:b:r"), Some("security:bruteforce:reset"));
+        assert_eq!(commands.resolve("scan"), Some("files:scan"));
+        assert_eq!(commands.resolve("user:re"), None);
+        assert_eq!(commands.resolve("enc:"), None);
+        assert_eq!(commands.resolve("user:delete"), None);
+
+        let policy = Policy::parse(r#"{
+            "rules": [
+                {"action": "deny", "command": "user:resetpassword"},
+                {"action": "deny", "command": "security:*"}
+            ]
+        }"#).unwrap();
+        let backend = Caller { uid: Some(990), gid: Some(990), pid: Some(2) };
+        for abbreviated in ["user:resetp", "u:resetpassword", "u:resetp"] {
+            let arguments = commands.canonicalize(&args(&["-n", abbreviated, "admin"])).unwrap();
+            assert_eq!(arguments, args(&["-n", "user:resetpassword", "admin"]));
+            assert!(!policy.is_allowed(&backend, &arguments));
+        }
+        assert!(!policy.is_allowed(&backend, &commands.canonicalize(&args(&["sec:"])).unwrap()));
+        assert_eq!(commands.canonicalize(&args(&["enc:"])), None);
+        assert_eq!(commands.canonicalize(&args(&["--version"])), Some(args(&["--version"])));
+    }
+
+    #[test]
+    fn test_redact() {
+        let policy = Policy::default();
+        assert_eq!(policy.redact(&args(&["config:system:set", "mail_smtppassword", "--value=hunter2"])),
+                   args(&["config:system:set", "mail_smtppassword", "--value=***"]));
+        assert_eq!(policy.redact(&args(&["ldap:set-config", "s01", "ldapAgentPassword", "hunter2"])),
+                   args(&["ldap:set-config", "s01", "ldapAgentPassword", "***"]));
+        assert_eq!(policy.redact(&args(&["user:resetpassword", "--password-from-env", "admin"])),
+                   args(&["user:resetpassword", "--password-from-env", "admin"]));
+        assert_eq!(policy.redact(&args(&["twofactorauth:enforce", "--token", "abc", "--on"])),
+                   args(&["twofactorauth:enforce", "--token", "***", "--on"]));
+    }
+}
@@ -0,0 +1,83 @@
+use std::io::{self, Stdin};
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd};
+use std::process::Stdio;
+use nix::libc;
+use nix::pty::{openpty, Winsize};
+use nix::sys::termios::{cfmakeraw, tcgetattr, tcsetattr, SetArg, Termios};
+use tokio::process::Command;
+use crate::api::WindowSize;
+
+const DEFAULT_WINDOW_SIZE: WindowSize = WindowSize { rows: 24, columns: 80 };
+
+nix::ioctl_write_ptr_bad!(set_winsize, libc::TIOCSWINSZ, Winsize);
+nix::ioctl_read_bad!(get_winsize, libc::TIOCGWINSZ, Winsize);
+
+fn to_winsize(size: &WindowSize) -> Winsize {
+    Winsize {
+        ws_row: size.rows.min(u16::MAX as u32) as u16,
+        ws_col: size.columns.min(u16::MAX as u32) as u16,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    }
+}
+
+/// Resizes the terminal of `fd`, the processes attached to it receive SIGWINCH
+pub fn set_window_size(fd: BorrowedFd, size: &WindowSize) -> io::Result<()> {
+    unsafe { set_winsize(fd.as_raw_fd(), &to_winsize(size)) }
+        .map(|_| ())
+        .map_err(io::Error::from)
+}
+
+/// The size of the terminal of `fd`, if it is one
+pub fn window_size(fd: BorrowedFd) -> Option<WindowSize> {
+    let mut size = Winsize { ws_row: 0, ws_col: 0, ws_xpixel: 0, ws_ypixel: 0 };
+    unsafe { get_winsize(fd.as_raw_fd(), &mut size) }.ok()?;
+    Some(WindowSize { rows: size.ws_row as u32, columns: size.ws_col as u32 })
+}
+
+/// Opens a pseudo terminal and makes it the controlling terminal and stdio of `cmd`.
+/// Returns the controller side.
+pub fn attach_pty(cmd: &mut Command, size: Option<&WindowSize>) -> io::Result<OwnedFd> {
+    let pty = openpty(Some(&to_winsize(size.unwrap_or(&DEFAULT_WINDOW_SIZE))), None)
+        .map_err(io::Error::from)?;
+    cmd.stdin(Stdio::from(pty.slave.try_clone()?))
+        .stdout(Stdio::from(pty.slave.try_clone()?))
+        .stderr(Stdio::from(pty.slave));
+    unsafe {
+        cmd.pre_exec(|| {
+            nix::unistd::setsid()?;
+            // stdin is the pseudo terminal at this point
+            if libc::ioctl(0, libc::TIOCSCTTY as _, 0) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    Ok(pty.master)
+}
+
+/// Switches the terminal of stdin to raw mode until it is dropped, so that all input (including
+/// Ctrl+C) is passed on unprocessed
+pub struct RawMode {
+    stdin: Stdin,
+    original: Termios,
+}
+
+impl RawMode {
+    pub fn enable() -> io::Result<RawMode> {
+        let stdin = io::stdin();
+        let original = tcgetattr(stdin.as_fd()).map_err(io::Error::from)?;
+        let mut raw = original.clone();
+        cfmakeraw(&mut raw);
+        tcsetattr(stdin.as_fd(), SetArg::TCSANOW, &raw).map_err(io::Error::from)?;
+        Ok(RawMode { stdin, original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        if let Err(e) = tcsetattr(self.stdin.as_fd(), SetArg::TCSANOW, &self.original) {
+            eprintln!("Failed to restore terminal settings: {e}");
+        }
+    }
+}
@@ -0,0 +1,311 @@
+use std::io;
+use std::os::fd::AsFd;
+use std::process::{ExitStatus, Stdio};
+use std::time::Duration;
+use nix::libc;
+use nix::sys::signal::kill;
+use nix::unistd::Pid;
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::process::{Child, Command};
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::task::JoinHandle;
+use tonic::Status;
+use tonic::codegen::tokio_stream::{Stream, StreamExt};
+use tonic::metadata::MetadataMap;
+use crate::api::{CommandOutput, InteractiveInput, OutputMode, OutputType, Signal, WindowSize};
+use crate::api::interactive_input::Input;
+use crate::output::{OutputDecoder, READ_BUFFER_SIZE};
+use crate::pty::{attach_pty, set_window_size};
+
+const OUTPUT_BACKLOG: usize = 100;
+// ETX, the terminal sends SIGINT to its foreground process when it is written
+const INTERRUPT_CHAR: u8 = 0x03;
+const TERMINATE_TIMEOUT: Duration = Duration::from_secs(10);
+
+enum Outcome {
+    Exited(io::Result<ExitStatus>),
+    Disconnected,
+    TimedOut,
+}
+
+/// The `grpc-timeout` a client has set for its request
+pub fn grpc_timeout(metadata: &MetadataMap) -> Option<Duration> {
+    let timeout = metadata.get("grpc-timeout")?.to_str().ok()?;
+    let (value, unit) = timeout.split_at(timeout.len().checked_sub(1)?);
+    let value: u64 = value.parse().ok()?;
+    match unit {
+        "H" => Some(Duration::from_secs(value * 60 * 60)),
+        "M" => Some(Duration::from_secs(value * 60)),
+        "S" => Some(Duration::from_secs(value)),
+        "m" => Some(Duration::from_millis(value)),
+        "u" => Some(Duration::from_micros(value)),
+        "n" => Some(Duration::from_nanos(value)),
+        _ => None,
+    }
+}
+
+fn read_output<R>(mut stream: R, tx: Sender<Result<CommandOutput, Status>>, output_type: OutputType, output_mode: OutputMode) -> JoinHandle<()>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut decoder = OutputDecoder::new(output_mode, output_type);
+        let mut buf = vec![0; READ_BUFFER_SIZE];
+        loop {
+            let (messages, done) = match stream.read(&mut buf).await {
+                // The controller side of a pseudo terminal can't be read anymore once the process has exited
+                Err(e) if e.raw_os_error() == Some(libc::EIO) => (decoder.finish(), true),
+                Err(e) => {
+                    eprintln!("Error reading occ output: {e:?}");
+                    (decoder.finish(), true)
+                },
+                Ok(0) => (decoder.finish(), true),
+                Ok(count) => (decoder.push(&buf[..count]), false),
+            };
+            for msg in messages {
+                // Fails only if the client is gone
+                if tx.send(Ok(msg)).await.is_err() {
+                    return;
+                }
+            }
+            if done {
+                return;
+            }
+        }
+    })
+}
+
+/// A running command
+pub struct Execution {
+    pub output: Receiver<Result<CommandOutput, Status>>,
+    /// The exit code, `None` if the command was killed or could not be waited for
+    pub exit: JoinHandle<Option<i32>>,
+}
+
+/// Runs `terminate` to end a command that killing its local process does not reach
+async fn terminate_command(mut terminate: Command) {
+    let output = terminate
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output();
+    match tokio::time::timeout(TERMINATE_TIMEOUT, output).await {
+        Ok(Ok(output)) if output.status.success() => {},
+        Ok(Ok(output)) => eprintln!("Failed to terminate occ command: {}", String::from_utf8_lossy(&output.stderr)),
+        Ok(Err(e)) => eprintln!("Failed to terminate occ command: {e:?}"),
+        Err(_) => eprintln!("Terminating occ command timed out"),
+    }
+}
+
+/// Waits for `child` to exit and sends the `EXIT` message after all output. Kills the process
+/// if the client disconnects or it runs longer than `timeout`. `terminate` is run before that,
+/// as killing e.g. `podman exec` leaves the command running in the container.
+fn supervise(mut child: Child, readers: Vec<JoinHandle<()>>, input: Option<JoinHandle<()>>, tx: Sender<Result<CommandOutput, Status>>, timeout: Duration, terminate: Option<Command>) -> JoinHandle<Option<i32>> {
+    tokio::spawn(async move {
+        let outcome = tokio::select! {
+            status = child.wait() => Outcome::Exited(status),
+            _ = tx.closed() => Outcome::Disconnected,
+            _ = tokio::time::sleep(timeout) => Outcome::TimedOut,
+        };
+        if !matches!(outcome, Outcome::Exited(_)) {
+            if let Some(terminate) = terminate {
+                terminate_command(terminate).await;
+            }
+            if let Err(e) = child.kill().await {
+                eprintln!("Failed to kill occ command: {e:?}");
+            }
+        }
+        if let Some(input) = input {
+            input.abort();
+        }
+        // The output is complete once the process has closed its output streams
+        for reader in readers {
+            let _ = reader.await;
+        }
+
+        let exit_code = match &outcome {
+            Outcome::Exited(Ok(status)) => status.code(),
+            _ => None,
+        };
+        let exit = match outcome {
+            Outcome::Disconnected => return exit_code,
+            Outcome::TimedOut => Err(Status::deadline_exceeded(format!("occ command did not finish within {timeout:?}"))),
+            Outcome::Exited(Err(e)) => Ok(CommandOutput {
+                r#type: OutputType::Exit.into(),
+                message: Some(format!("Error waiting for occ command: {e:?}")),
+                exit_code: Some(500),
+                ..CommandOutput::default()
+            }),
+            Outcome::Exited(Ok(status)) => Ok(CommandOutput {
+                r#type: OutputType::Exit.into(),
+                exit_code: status.code(),
+                ..CommandOutput::default()
+            }),
+        };
+        let _ = tx.send(exit).await;
+        exit_code
+    })
+}
+
+/// Spawns `cmd` and streams its stdout and stderr. The `EXIT` message follows all output. The
+/// process is killed if the receiver is dropped or it runs longer than `timeout`, after running
+/// `terminate` if given.
+pub fn run_streaming(mut cmd: Command, output_mode: OutputMode, timeout: Duration, terminate: Option<Command>) -> io::Result<Execution> {
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+    let (tx, rx) = mpsc::channel(OUTPUT_BACKLOG);
+    let stdout = child.stdout.take()
+        .map(|stdout| read_output(stdout, tx.clone(), OutputType::Stdout, output_mode));
+    let stderr = child.stderr.take()
+        .map(|stderr| read_output(stderr, tx.clone(), OutputType::Stderr, output_mode));
+    let exit = supervise(child, [stdout, stderr].into_iter().flatten().collect(), None, tx, timeout, terminate);
+    Ok(Execution { output: rx, exit })
+}
+
+async fn forward_input<S>(mut input: S, mut terminal: File, pid: Option<u32>)
+where
+    S: Stream<Item = Result<InteractiveInput, Status>> + Unpin + Send + 'static,
+{
+    while let Some(Ok(msg)) = input.next().await {
+        let result = match msg.input {
+            Some(Input::Stdin(data)) => terminal.write_all(&data).await
+                .and(terminal.flush().await),
+            Some(Input::WindowSize(size)) => set_window_size(terminal.as_fd(), &size),
+            Some(Input::Signal(signal)) => match Signal::try_from(signal) {
+                Ok(Signal::Interrupt) => terminal.write_all(&[INTERRUPT_CHAR]).await
+                    .and(terminal.flush().await),
+                Ok(Signal::Terminate) => match pid {
+                    Some(pid) => kill(Pid::from_raw(pid as i32), nix::sys::signal::Signal::SIGTERM)
+                        .map_err(io::Error::from),
+                    None => Ok(()),
+                },
+                Err(_) => Err(io::Error::other(format!("Unknown signal {signal}"))),
+            },
+            Some(Input::Command(_)) => Err(io::Error::other("The command is already running")),
+            None => Ok(()),
+        };
+        if let Err(e) = result {
+            eprintln!("Failed to forward input to occ command: {e:?}");
+        }
+    }
+}
+
+/// Spawns `cmd` attached to a pseudo terminal of `size` and forwards `input` to it. The output
+/// is sent as raw stdout, followed by the `EXIT` message. Ends like [`run_streaming`].
+pub fn run_interactive<S>(mut cmd: Command, size: Option<WindowSize>, input: S, timeout: Duration, terminate: Option<Command>) -> io::Result<Execution>
+where
+    S: Stream<Item = Result<InteractiveInput, Status>> + Unpin + Send + 'static,
+{
+    let terminal = attach_pty(&mut cmd, size.as_ref())?;
+    let child = cmd.kill_on_drop(true).spawn()?;
+    // The terminal reports the end of the output only once no process has it open anymore
+    drop(cmd);
+
+    let (tx, rx) = mpsc::channel(OUTPUT_BACKLOG);
+    let output = File::from(std::fs::File::from(terminal.try_clone()?));
+    let reader = read_output(output, tx.clone(), OutputType::Stdout, OutputMode::Raw);
+    let input = tokio::spawn(forward_input(input, File::from(std::fs::File::from(terminal)), child.id()));
+    let exit = supervise(child, vec![reader], Some(input), tx, timeout, terminate);
+    Ok(Execution { output: rx, exit })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+    use tonic::codegen::tokio_stream::wrappers::ReceiverStream;
+    use super::*;
+
+    fn shell(script: &str) -> Command {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", script]);
+        cmd
+    }
+
+    async fn collect(mut rx: Receiver<Result<CommandOutput, Status>>) -> Vec<Result<CommandOutput, Status>> {
+        let mut messages = Vec::new();
+        while let Some(msg) = rx.recv().await {
+            messages.push(msg);
+        }
+        messages
+    }
+
+    #[tokio::test]
+    async fn test_exit_after_output() {
+        let rx = run_streaming(shell("echo started; sleep 0.2; seq 1 2000 >&2; echo done; exit 3"), OutputMode::Lines, Duration::from_secs(10), None).unwrap().output;
+        let messages: Vec<CommandOutput> = collect(rx).await.into_iter().map(|msg| msg.unwrap()).collect();
+        let exit = messages.last().unwrap();
+        assert_eq!((exit.r#type(), exit.exit_code), (OutputType::Exit, Some(3)));
+        let stdout: Vec<&str> = messages.iter()
+            .filter(|msg| msg.r#type() == OutputType::Stdout)
+            .map(|msg| msg.message.as_deref().unwrap())
+            .collect();
+        assert_eq!(stdout, vec!["started\n", "done\n"]);
+        assert_eq!(messages.iter().filter(|msg| msg.r#type() == OutputType::Stderr).count(), 2000);
+    }
+
+    #[tokio::test]
+    async fn test_timeout_kills_process() {
+        let started = Instant::now();
+        let rx = run_streaming(shell("echo waiting; exec sleep 30"), OutputMode::Lines, Duration::from_millis(200), None).unwrap().output;
+        let messages = collect(rx).await;
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert_eq!(messages[0].as_ref().unwrap().message.as_deref(), Some("waiting\n"));
+        assert_eq!(messages[1].as_ref().unwrap_err().code(), tonic::Code::DeadlineExceeded);
+    }
+
+    fn is_running(pid: &str) -> bool {
+        // A killed process stays a zombie until its new parent reaps it
+        std::fs::read_to_string(format!("/proc/{pid}/stat"))
+            .is_ok_and(|stat| !stat.rsplit_once(')').is_some_and(|(_, rest)| rest.trim_start().starts_with('Z')))
+    }
+
+    #[tokio::test]
+    async fn test_timeout_terminates_detached_process() {
+        // Like a command in a container, the sleep is not killed along with the local process
+        let pid_file = std::env::temp_dir().join(format!("grpc-occ-test-{}.pid", std::process::id()));
+        let script = format!("sleep 30 & echo $! > '{}'; echo waiting; wait", pid_file.display());
+        let terminate = shell(&format!("kill -TERM \"$(cat '{}')\"", pid_file.display()));
+        let rx = run_streaming(shell(&script), OutputMode::Lines, Duration::from_millis(500), Some(terminate)).unwrap().output;
+        let messages = collect(rx).await;
+        assert_eq!(messages.last().unwrap().as_ref().unwrap_err().code(), tonic::Code::DeadlineExceeded);
+
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        let pid = pid.trim();
+        std::fs::remove_file(&pid_file).unwrap();
+        let started = Instant::now();
+        while is_running(pid) && started.elapsed() < Duration::from_secs(5) {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        assert!(!is_running(pid), "process {pid} is still running");
+    }
+
+    #[tokio::test]
+    async fn test_interactive_input() {
+        let (tx, rx) = mpsc::channel(10);
+        let size = WindowSize { rows: 40, columns: 120 };
+        let rx = run_interactive(shell("stty size; read answer; echo \"got $answer\"; exit 4"), Some(size), ReceiverStream::new(rx), Duration::from_secs(10), None).unwrap().output;
+        tx.send(Ok(InteractiveInput { input: Some(Input::Stdin(b"yes\n".to_vec())) })).await.unwrap();
+
+        let messages: Vec<CommandOutput> = collect(rx).await.into_iter().map(|msg| msg.unwrap()).collect();
+        let output: Vec<u8> = messages.iter().flat_map(|msg| msg.data.clone().unwrap_or_default()).collect();
+        let output = String::from_utf8(output).unwrap();
+        // The terminal echoes the input, possibly before the command printed anything
+        assert!(output.contains("40 120\r\n") && output.contains("yes\r\n"), "{output:?}");
+        assert!(output.ends_with("got yes\r\n"), "{output:?}");
+        assert_eq!(messages.last().unwrap().exit_code, Some(4));
+    }
+
+    #[test]
+    fn test_grpc_timeout() {
+        let mut metadata = MetadataMap::new();
+        assert_eq!(grpc_timeout(&metadata), None);
+        metadata.insert("grpc-timeout", "1500m".parse().unwrap());
+        assert_eq!(grpc_timeout(&metadata), Some(Duration::from_millis(1500)));
+        metadata.insert("grpc-timeout", "2H".parse().unwrap());
+        assert_eq!(grpc_timeout(&metadata), Some(Duration::from_secs(7200)));
+    }
+}
@@ -38,4 +38,119 @@ pub mod setup {
     pub struct Status {
         pub status: String
     }
+
+    #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+    pub struct Disk {
+        pub path: String,
+        pub name: String,
+        pub device_type: String,
+        pub parent: Option<String>,
+        pub size: u64,
+        pub model: Option<String>,
+        pub serial: Option<String>,
+        pub partition_table: Option<String>,
+        pub fs_type: Option<String>,
+        pub label: Option<String>,
+        pub is_luks: bool,
+        pub mount_point: Option<String>,
+        pub is_system_disk: bool,
+    }
+}
+
+pub mod storage {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+    #[serde(rename_all = "lowercase")]
+    pub enum StorageLevel {
+        Ok,
+        Warning,
+        Critical,
+    }
+
+    #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+    pub struct FilesystemStatus {
+        pub name: String,
+        pub path: String,
+        pub mount_point: Option<String>,
+        pub device: Option<String>,
+        pub total_bytes: u64,
+        pub used_bytes: u64,
+        pub available_bytes: u64,
+        pub total_inodes: u64,
+        pub used_inodes: u64,
+        pub level: StorageLevel,
+        pub disks: Vec<String>,
+    }
+
+    #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+    pub struct DiskHealth {
+        pub name: String,
+        pub model: Option<String>,
+        pub state: Option<String>,
+        pub io_errors: Option<u64>,
+        pub temperature_celsius: Option<f64>,
+        pub healthy: bool,
+    }
+
+    #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+    pub struct StorageThresholds {
+        pub warning_percent: u32,
+        pub critical_percent: u32,
+        pub inodes_warning_percent: u32,
+        pub inodes_critical_percent: u32,
+    }
+
+    #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+    pub struct StorageStatus {
+        pub filesystems: Vec<FilesystemStatus>,
+        pub disks: Vec<DiskHealth>,
+        pub thresholds: StorageThresholds,
+    }
+}
+
+pub mod auth {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Deserialize, Serialize, Clone, Debug)]
+    pub struct LoginRequest {
+        pub primary_password: String,
+    }
+}
+
+pub mod users {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Default)]
+    pub struct CreateUserRequest {
+        pub id: String,
+        pub display_name: Option<String>,
+        pub email: Option<String>,
+        #[serde(default)]
+        pub groups: Vec<String>,
+        pub quota: Option<String>,
+    }
+
+    /// Shown once to the admin, it is not stored anywhere
+    #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+    pub struct GeneratedPassword {
+        pub user_id: String,
+        pub password: String,
+    }
+
+    #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+    pub struct QuotaRequest {
+        pub quota: String,
+    }
+
+    #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+    pub struct CreateGroupRequest {
+        pub id: String,
+        pub display_name: Option<String>,
+    }
+
+    #[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+    pub struct GroupMemberRequest {
+        pub user_id: String,
+    }
 }
@@ -5,11 +5,10 @@ use axum_extra::routing::TypedPath;
 use url::Url;
 use rand::Rng;
 use serde::Deserialize;
-use grpc_occ::occ::client::handle_occ_output;
 use nca_error::NcaError;
 use nca_system_api::systemd::{types::ServiceStatus, api::get_service_status};
 use nca_caddy::{CaddyClient, config::builders};
-use nca_system_api::occ::api::{set_nc_system_config, NcConfigValue};
+use nca_system_api::occ::api::{list_nc_system_config, set_nc_system_config, NcConfig, NcConfigValue};
 use nca_api_model::{setup};
 use crate::config::Config;
 use paspio::entropy;
@@ -18,8 +17,10 @@ use grpc_nca_system::api;
 use grpc_nca_system::api::credentials_client::CredentialsClient;
 use grpc_nca_system::api::nextcloud_client::NextcloudClient;
 use grpc_nca_system::api::NextcloudConfig;
+use grpc_nca_system::api::storage_client::StorageClient;
 use grpc_nca_system::api::system_client::SystemClient;
-use nca_api_model::setup::{CredentialsInitResponse, CredentialsInitRequest, Status};
+use nca_api_model::setup::{CredentialsInitResponse, CredentialsInitRequest, Disk, Status};
+use nca_api_model::storage;
 
 #[derive(TypedPath, Deserialize)]
 #[typed_path("/api/service/*name")]
@@ -82,41 +83,61 @@ pub(crate) async fn activate_endpoint_nextcloud(Extension(config): Extension<Con
     Ok(Json(()))
 }
 
-async fn add_nc_trusted_domain(occ_channel: Channel, domain: String) -> Result<String, NcaError> {
-    use nca_system_api::occ::api::{NcConfigValue, set_nc_system_config};
-    let response = set_nc_system_config(occ_channel,
-                                            "trusted_domains".to_string(),
-                                            Some(11),
-                                            NcConfigValue::String(domain))
-        .await?;
-
-    handle_occ_output(response).await
-        .map(|_| "occ command terminated successfully.".to_string())
+/// The index at which `domain` has to be added to `trusted_domains`, `None` if it is trusted already
+fn trusted_domain_index(trusted_domains: Option<&NcConfigValue>, domain: &str) -> Option<usize> {
+    let entries = trusted_domains.map(NcConfigValue::entries).unwrap_or_default();
+    if entries.iter().any(|(_, value)| value.as_str() == Some(domain)) {
+        return None;
+    }
+    Some(entries.iter()
+        .filter_map(|(key, _)| key.parse::<usize>().ok())
+        .max()
+        .map_or(0, |max| max + 1))
 }
 
+/// Only changes what differs from the current config, so it can be repeated safely
 async fn set_nc_default_domain(occ_channel: Channel, domain: String) -> Result<String, NcaError> {
-    add_nc_trusted_domain(occ_channel.clone(), domain.clone()).await?;
-    let response = set_nc_system_config(occ_channel.clone(),
-                                        "overwrite.cli.url".to_string(),
-                                        None,
-                                        // TODO: get protocol from nc config
-                                        NcConfigValue::String(format!("https://{domain}/")))
-        .await?;
-
-    handle_occ_output(response).await?;
-    let response = set_nc_system_config(occ_channel,
-                                        "overwritehost".to_string(),
-                                        None,
-                                        NcConfigValue::String(domain.to_string()))
-        .await?;
-
-    handle_occ_output(response).await
-        .map(|_| "nextcloud was successfully configured".to_string())
+    let current = list_nc_system_config(occ_channel.clone(), false).await?;
+    if let Some(index) = trusted_domain_index(current.get("trusted_domains"), &domain) {
+        set_nc_system_config(occ_channel.clone(), "trusted_domains".to_string(), Some(index),
+                             NcConfigValue::String(domain.clone())).await?;
+    }
+
+    // TODO: get protocol from nc config
+    let desired = [
+        ("overwrite.cli.url", format!("https://{domain}/")),
+        ("overwritehost", domain),
+    ];
+    for (key, value) in desired {
+        if current.get(key).and_then(NcConfigValue::as_str) != Some(value.as_str()) {
+            set_nc_system_config(occ_channel.clone(), key.to_string(), None, NcConfigValue::String(value)).await?;
+        }
+    }
+    Ok("nextcloud was successfully configured".to_string())
 }
 
+/// The effective Nextcloud system config, with sensitive values masked. Private values are
+/// never returned.
+pub async fn nextcloud_config(Extension(config): Extension<Config>) -> Result<Json<NcConfig>, NcaError> {
 
+    #[cfg(not(feature = "mock-occ"))]
+    {
+        Ok(Json(list_nc_system_config(config.occ_channel, false).await?))
+    }
+
+    #[cfg(feature = "mock-occ")]
+    {
+        let _ = config;
+        Ok(Json(NcConfig::from([
+            ("trusted_domains".to_string(), NcConfigValue::Array(vec![NcConfigValue::String("localhost".to_string())])),
+            ("overwritehost".to_string(), NcConfigValue::String("localhost".to_string())),
+            ("maintenance".to_string(), NcConfigValue::Bool(false)),
+            ("dbpassword".to_string(), NcConfigValue::String("***REMOVED SENSITIVE VALUE***".to_string())),
+        ])))
+    }
+}
 
-fn check_is_secure_password(pw: &str) -> bool {
+pub(crate) fn check_is_secure_password(pw: &str) -> bool {
     if pw.is_empty() {
         return false;
     }
@@ -221,6 +242,100 @@ pub async fn hard_reset_nextcloud(Extension(config): Extension<Config>) -> Resul
     }
 }
 
+pub(crate) async fn fetch_disks(config: &Config) -> Result<Vec<Disk>, NcaError> {
+
+    #[cfg(not(feature = "mock-systemd"))]
+    {
+        let mut client = StorageClient::new(config.nca_system_channel.clone());
+        let disks = client.list_disks(tonic::Request::new(api::Empty{}))
+            .await?
+            .into_inner()
+            .disks;
+        Ok(disks.into_iter()
+            .map(|d| Disk {
+                path: d.path,
+                name: d.name,
+                device_type: d.device_type,
+                parent: d.parent,
+                size: d.size,
+                model: d.model,
+                serial: d.serial,
+                partition_table: d.partition_table,
+                fs_type: d.fs_type,
+                label: d.label,
+                is_luks: d.is_luks,
+                mount_point: d.mount_point,
+                is_system_disk: d.is_system_disk,
+            })
+            .collect())
+    }
+
+    #[cfg(feature = "mock-systemd")]
+    {
+        let _ = config;
+        Ok(mock::disks())
+    }
+}
+
+pub async fn list_disks(Extension(config): Extension<Config>) -> Result<Json<Vec<Disk>>, NcaError> {
+    Ok(Json(fetch_disks(&config).await?))
+}
+
+pub async fn storage_status(Extension(config): Extension<Config>) -> Result<Json<storage::StorageStatus>, NcaError> {
+
+    #[cfg(not(feature = "mock-systemd"))]
+    {
+        let mut client = StorageClient::new(config.nca_system_channel);
+        let status = client.get_storage_status(tonic::Request::new(api::Empty{}))
+            .await?
+            .into_inner();
+        let thresholds = status.thresholds.unwrap_or_default();
+        Ok(Json(storage::StorageStatus {
+            filesystems: status.filesystems.into_iter()
+                .map(|fs| storage::FilesystemStatus {
+                    level: match fs.level() {
+                        api::StorageLevel::StorageOk => storage::StorageLevel::Ok,
+                        api::StorageLevel::StorageWarning => storage::StorageLevel::Warning,
+                        api::StorageLevel::StorageCritical => storage::StorageLevel::Critical,
+                    },
+                    name: fs.name,
+                    path: fs.path,
+                    mount_point: fs.mount_point,
+                    device: fs.device,
+                    total_bytes: fs.total_bytes,
+                    used_bytes: fs.used_bytes,
+                    available_bytes: fs.available_bytes,
+                    total_inodes: fs.total_inodes,
+                    used_inodes: fs.used_inodes,
+                    disks: fs.disks,
+                })
+                .collect(),
+            disks: status.disks.into_iter()
+                .map(|d| storage::DiskHealth {
+                    name: d.name,
+                    model: d.model,
+                    state: d.state,
+                    io_errors: d.io_errors,
+                    temperature_celsius: d.temperature_celsius,
+                    healthy: d.healthy,
+                })
+                .collect(),
+            thresholds: storage::StorageThresholds {
+                warning_percent: thresholds.warning_percent,
+                critical_percent: thresholds.critical_percent,
+                inodes_warning_percent: thresholds.inodes_warning_percent,
+                inodes_critical_percent: thresholds.inodes_critical_percent,
+            },
+        }))
+    }
+
+    #[cfg(feature = "mock-systemd")]
+    {
+        let _ = config;
+        Ok(Json(mock::storage_status()))
+    }
+}
+
 #[cfg(feature = "mock-systemd")]
 pub mod mock {
     use std::collections::HashMap;
@@ -229,8 +344,66 @@ pub mod mock {
     use axum::Json;
     use nca_error::NcaError;
     use nca_system_api::systemd::types::ServiceStatus;
+    use nca_api_model::setup::Disk;
+    use nca_api_model::storage::{DiskHealth, FilesystemStatus, StorageLevel, StorageStatus, StorageThresholds};
     use crate::api_routes::ServiceName;
 
+    pub(crate) fn disks() -> Vec<Disk> {
+        let disk = |name: &str, size: u64, model: &str, is_system_disk: bool| Disk {
+            path: format!("/dev/{name}"),
+            name: name.to_string(),
+            device_type: "disk".to_string(),
+            parent: None,
+            size,
+            model: Some(model.to_string()),
+            serial: Some(format!("{name}-0001")),
+            partition_table: is_system_disk.then(|| "gpt".to_string()),
+            fs_type: None,
+            label: None,
+            is_luks: false,
+            mount_point: None,
+            is_system_disk,
+        };
+        vec![
+            disk("vda", 10_000_000_000, "Root Disk", true),
+            disk("vdb", 50_000_000_000, "My Disk 1", false),
+            disk("vdc", 100_000_000_000, "My Disk 2", false),
+        ]
+    }
+
+    pub(crate) fn storage_status() -> StorageStatus {
+        let filesystem = |name: &str, path: &str, disk: &str, total_bytes: u64, used_bytes: u64, level: StorageLevel| FilesystemStatus {
+            name: name.to_string(),
+            path: path.to_string(),
+            mount_point: Some(path.to_string()),
+            device: Some(format!("{disk}1")),
+            total_bytes,
+            used_bytes,
+            available_bytes: total_bytes - used_bytes,
+            total_inodes: 1_000_000,
+            used_inodes: 120_000,
+            level,
+            disks: vec![disk.to_string()],
+        };
+        StorageStatus {
+            filesystems: vec![
+                filesystem("nextcloud_data", "/var/data/ncatomic/nc-aio", "vdb", 50_000_000_000, 42_000_000_000, StorageLevel::Warning),
+                filesystem("podman_storage", "/var/home/aio/.local/share/containers/storage", "vda", 10_000_000_000, 4_000_000_000, StorageLevel::Ok),
+                filesystem("root", "/sysroot", "vda", 10_000_000_000, 4_000_000_000, StorageLevel::Ok),
+            ],
+            disks: vec![
+                DiskHealth { name: "vda".to_string(), model: Some("Root Disk".to_string()), state: Some("running".to_string()), io_errors: Some(0), temperature_celsius: Some(35.0), healthy: true },
+                DiskHealth { name: "vdb".to_string(), model: Some("My Disk 1".to_string()), state: Some("running".to_string()), io_errors: Some(0), temperature_celsius: None, healthy: true },
+            ],
+            thresholds: StorageThresholds {
+                warning_percent: 80,
+                critical_percent: 90,
+                inodes_warning_percent: 80,
+                inodes_critical_percent: 90,
+            },
+        }
+    }
+
 
     #[derive(Debug, Clone)]
     pub(crate) struct ServiceMockState {
@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use axum::body::Body;
+use axum::{Extension, Json};
+use axum::middleware::Next;
+use axum::response::IntoResponse;
+use http::{header, HeaderMap, Request, StatusCode};
+use rand::Rng;
+use nca_api_model::auth::LoginRequest;
+#[cfg(not(feature = "mock-systemd"))]
+use grpc_nca_system::api::credentials_client::CredentialsClient;
+use crate::config::Config;
+
+const SESSION_COOKIE: &str = "nca_session";
+const SESSION_TOKEN_LENGTH: usize = 48;
+// Sessions end after this long without a request
+const SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+// Slows down guessing the primary password, login attempts are handled one at a time
+const FAILED_LOGIN_DELAY: Duration = Duration::from_secs(2);
+
+/// Sessions of admins who logged in with the primary password. They are only kept in memory,
+/// restarting the backend ends all sessions.
+#[derive(Clone, Debug, Default)]
+pub struct Sessions {
+    expiry: Arc<Mutex<HashMap<String, Instant>>>,
+    login: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl Sessions {
+    fn create(&self) -> String {
+        let token: String = rand::rng()
+            .sample_iter(rand::distr::Alphanumeric)
+            .take(SESSION_TOKEN_LENGTH).map(char::from)
+            .collect();
+        let now = Instant::now();
+        let mut expiry = self.expiry.lock().expect("mutex was poisoned");
+        expiry.retain(|_, expires| *expires > now);
+        expiry.insert(token.clone(), now + SESSION_IDLE_TIMEOUT);
+        token
+    }
+
+    /// Extends the session, returns `false` if it doesn't exist or has expired
+    fn touch(&self, token: &str) -> bool {
+        let now = Instant::now();
+        let mut expiry = self.expiry.lock().expect("mutex was poisoned");
+        match expiry.get_mut(token) {
+            Some(expires) if *expires > now => {
+                *expires = now + SESSION_IDLE_TIMEOUT;
+                true
+            },
+            Some(_) => {
+                expiry.remove(token);
+                false
+            },
+            None => false,
+        }
+    }
+
+    fn remove(&self, token: &str) {
+        self.expiry.lock().expect("mutex was poisoned").remove(token);
+    }
+}
+
+fn session_token(headers: &HeaderMap) -> Option<String> {
+    headers.get_all(header::COOKIE).iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, token)| token.to_string())
+}
+
+fn session_cookie(token: &str, extra_attributes: &str) -> String {
+    #[cfg(not(feature = "insecure"))]
+    let secure = "; Secure";
+    #[cfg(feature = "insecure")]
+    let secure = "";
+    format!("{SESSION_COOKIE}={token}; Path=/api; HttpOnly; SameSite=Strict{secure}{extra_attributes}")
+}
+
+#[cfg(not(feature = "mock-systemd"))]
+async fn verify_primary_password(config: &Config, password: String) -> Result<(), (StatusCode, String)> {
+    let mut client = CredentialsClient::new(config.nca_system_channel.clone());
+    client.verify_primary_password(tonic::Request::new(password.into())).await
+        .map(|_| ())
+        .map_err(|status| match status.code() {
+            tonic::Code::PermissionDenied => (StatusCode::UNAUTHORIZED, "The primary password is incorrect".to_string()),
+            tonic::Code::FailedPrecondition => (StatusCode::PRECONDITION_FAILED, status.message().to_string()),
+            _ => (StatusCode::INTERNAL_SERVER_ERROR, format!("Failed to verify primary password: {}", status.message())),
+        })
+}
+
+#[cfg(feature = "mock-systemd")]
+async fn verify_primary_password(_config: &Config, password: String) -> Result<(), (StatusCode, String)> {
+    match password.is_empty() {
+        true => Err((StatusCode::UNAUTHORIZED, "The primary password is incorrect".to_string())),
+        false => Ok(()),
+    }
+}
+
+/// Only admins with a session are let through, see [login]
+pub async fn require_admin_session(Extension(config): Extension<Config>, req: Request<Body>, next: Next) -> Result<impl IntoResponse, (StatusCode, String)> {
+    match session_token(req.headers()) {
+        Some(token) if config.sessions.touch(&token) => Ok(next.run(req).await),
+        _ => Err((StatusCode::UNAUTHORIZED, "authentication required".to_string())),
+    }
+}
+
+/// Starts a session if the primary password is correct
+pub(crate) async fn login(Extension(config): Extension<Config>, Json(request): Json<LoginRequest>) -> Result<impl IntoResponse, (StatusCode, String)> {
+    let _attempt = config.sessions.login.lock().await;
+    if let Err(e) = verify_primary_password(&config, request.primary_password).await {
+        tokio::time::sleep(FAILED_LOGIN_DELAY).await;
+        return Err(e);
+    }
+    let token = config.sessions.create();
+    Ok(([(header::SET_COOKIE, session_cookie(&token, ""))], Json(())))
+}
+
+pub(crate) async fn logout(Extension(config): Extension<Config>, headers: HeaderMap) -> impl IntoResponse {
+    if let Some(token) = session_token(&headers) {
+        config.sessions.remove(&token);
+    }
+    ([(header::SET_COOKIE, session_cookie("", "; Max-Age=0"))], Json(()))
+}
+
+#[cfg(test)]
+mod tests {
+    use http::HeaderValue;
+    use super::*;
+
+    #[test]
+    fn test_sessions() {
+        let sessions = Sessions::default();
+        let token = sessions.create();
+        assert_eq!(token.len(), SESSION_TOKEN_LENGTH);
+        assert!(sessions.touch(&token));
+        assert!(!sessions.touch("guessed"));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(header::COOKIE, HeaderValue::from_str(&format!("theme=dark; {SESSION_COOKIE}={token}")).unwrap());
+        assert_eq!(session_token(&headers), Some(token.clone()));
+
+        sessions.remove(&token);
+        assert!(!sessions.touch(&token));
+    }
+}
@@ -3,6 +3,7 @@ use std::str::FromStr;
 use http::Uri;
 use tonic::transport::Channel;
 use grpc_common::client::retrieve_grpc_channel;
+use crate::auth::Sessions;
 
 #[derive(Clone, Debug)]
 pub struct Config {
@@ -10,7 +11,9 @@ pub struct Config {
     pub caddy_admin_socket: Option<String>,
     pub occ_channel: Channel,
     pub nca_system_channel: Channel,
+    pub journal_channel: Channel,
     pub config_path: String,
+    pub sessions: Sessions,
 }
 
 impl Config {
@@ -48,12 +51,21 @@ impl Config {
         #[cfg(feature = "mock-systemd")]
         let nca_system_channel = Channel::builder(Uri::from_static("http://localhost")).connect_lazy();
 
+        // Connects on first use, grpc-journal is not needed to start the backend
+        let journal_channel = Channel::from_shared(
+            std::env::var("GRPC_JOURNAL_ADDRESS").unwrap_or("http://127.0.0.1:50051".to_string())
+        )
+            .expect("Could not parse GRPC_JOURNAL_ADDRESS")
+            .connect_lazy();
+
         Config {
             address,
             caddy_admin_socket,
             occ_channel,
             nca_system_channel,
+            journal_channel,
             config_path,
+            sessions: Sessions::default(),
         }
     }
 }
@@ -0,0 +1,120 @@
+use axum::body::Body;
+use axum::middleware::Next;
+use axum::response::Response;
+use axum::Router;
+use http::{Request, StatusCode};
+use grpc_journal::api::journal_log_stream_server::JournalLogStreamServer;
+use grpc_journal::server::JournalLogStreamService;
+
+/// With `mock-journal`, log requests are served from a replayed fixture
+/// (see `GRPC_JOURNAL_REPLAY_FIXTURE` and `GRPC_JOURNAL_REPLAY_SPEED`). Alerts are only
+/// evaluated by the grpc-journal daemon, so each alert fires once.
+pub(crate) fn journal_service() -> JournalLogStreamService {
+    JournalLogStreamService::new(false, true)
+}
+
+/// The `Tail` RPC as grpc-web endpoint for the frontend. `Query` and `Export` return the journal
+/// unredacted, the support bundle serves it to admins instead.
+pub(crate) fn journal_router(service: JournalLogStreamService) -> Router {
+    tonic::service::Routes::new(tonic_web::enable(JournalLogStreamServer::new(service)))
+        .prepare()
+        .into_axum_router()
+        .route_layer(axum::middleware::from_fn(only_tail))
+}
+
+const TAIL_PATH: &str = "/api.JournalLogStream/Tail";
+
+async fn only_tail(req: Request<Body>, next: Next) -> Result<Response, StatusCode> {
+    match req.uri().path() {
+        TAIL_PATH => Ok(next.run(req).await),
+        _ => Err(StatusCode::NOT_FOUND),
+    }
+}
+
+#[cfg(all(test, feature = "mock-journal"))]
+mod tests {
+    use axum::body::{to_bytes, Body};
+    use http::header;
+    use prost::Message;
+    use tower::ServiceExt;
+    use grpc_journal::api::{LogFilter, LogMessage, LogQuery};
+    use grpc_journal::replay::ReplaySource;
+    use super::*;
+
+    const FRAME_HEADER_LEN: usize = 5;
+    const TRAILER_FLAG: u8 = 0x80;
+
+    fn grpc_web_frame(message: &impl Message) -> Vec<u8> {
+        let payload = message.encode_to_vec();
+        let mut frame = vec![0];
+        frame.extend((payload.len() as u32).to_be_bytes());
+        frame.extend(payload);
+        frame
+    }
+
+    /// Sends a grpc-web `Tail` request like the frontend does and decodes the streamed messages
+    async fn tail(router: Router, filter: LogFilter) -> (Vec<LogMessage>, String) {
+        let request = Request::post("/api.JournalLogStream/Tail")
+            .header(header::CONTENT_TYPE, "application/grpc-web+proto")
+            .header("x-grpc-web", "1")
+            .body(Body::from(grpc_web_frame(&filter)))
+            .unwrap();
+        let response = router.oneshot(request).await.unwrap();
+        // Errors before the first message are sent as trailers-only response
+        if let Some(status) = response.headers().get("grpc-status") {
+            return (Vec::new(), format!("grpc-status:{}", status.to_str().unwrap()));
+        }
+        let mut body = &to_bytes(response.into_body(), usize::MAX).await.unwrap()[..];
+
+        let mut messages = Vec::new();
+        while body.len() >= FRAME_HEADER_LEN {
+            let len = u32::from_be_bytes(body[1..FRAME_HEADER_LEN].try_into().unwrap()) as usize;
+            let payload = &body[FRAME_HEADER_LEN..FRAME_HEADER_LEN + len];
+            if body[0] & TRAILER_FLAG != 0 {
+                return (messages, String::from_utf8_lossy(payload).to_string());
+            }
+            messages.push(LogMessage::decode(payload).unwrap());
+            body = &body[FRAME_HEADER_LEN + len..];
+        }
+        panic!("grpc-web response without trailers");
+    }
+
+    #[tokio::test]
+    async fn test_only_tail_is_served() {
+        let router = journal_router(journal_service());
+        for method in ["Query", "Export", "Alerts"] {
+            let request = Request::post(format!("/api.JournalLogStream/{method}"))
+                .header(header::CONTENT_TYPE, "application/grpc-web+proto")
+                .header("x-grpc-web", "1")
+                .body(Body::from(grpc_web_frame(&LogQuery::default())))
+                .unwrap();
+            let response = router.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{method}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replayed_tail() {
+        let replay = ReplaySource::bundled().with_speed(0.0).with_repeat(false);
+        let router = journal_router(journal_service().with_replay(replay));
+
+        let (messages, trailers) = tail(router.clone(), LogFilter {
+            units: vec!["nca-backup".to_string()],
+            priority: Some(4),
+            ..LogFilter::default()
+        }).await;
+        assert!(trailers.contains("grpc-status:0"), "{trailers}");
+        let texts: Vec<&str> = messages.iter().map(|m| m.message.as_str()).collect();
+        assert_eq!(texts, vec![
+            "Fatal: unable to save snapshot: no space left on device",
+            "nca-backup.service: Failed with result 'exit-code'.",
+            "Failed to start nca-backup.service - Nextcloud Atomic backup.",
+        ]);
+
+        let (_, trailers) = tail(router, LogFilter {
+            message_regex: Some("(".to_string()),
+            ..LogFilter::default()
+        }).await;
+        assert!(trailers.contains("grpc-status:3"), "{trailers}");
+    }
+}
@@ -12,27 +12,34 @@ use {
     nca_system_api::systemd::types::ServiceStatus
 };
 use axum::Router;
-use axum::routing::post;
+use axum::routing::{post, put};
 use dioxus::prelude::*;
 
 mod config;
+mod auth;
 mod api_routes;
 mod middleware;
+mod support_bundle;
+mod journal;
+mod nextcloud_apps;
+mod nextcloud_users;
 
 use {
     axum::{extract::Extension, routing::get, ServiceExt},
     libsystemd::daemon::NotifyState,
-    grpc_journal::api::journal_log_stream_server::JournalLogStreamServer,
     nca_system_api::systemd::api::sd_notify,
     tower_http::services::ServeDir,
 };
 
 use notify::Watcher;
-use grpc_journal::server::JournalLogStreamService;
 use nca_caddy::CaddyClient;
 use nca_caddy::config::builders::create_nca_setup_server_json;
-use crate::api_routes::{activate_endpoint_nextcloud, complete_credentials_setup, configure_nextcloud_atomic, generate_credentials, hard_reset_nextcloud};
+use crate::api_routes::{activate_endpoint_nextcloud, complete_credentials_setup, configure_nextcloud_atomic, generate_credentials, hard_reset_nextcloud, list_disks, nextcloud_config, storage_status};
+use crate::journal::{journal_router, journal_service};
+use crate::nextcloud_apps::{app_action, apply_apps_profile, list_apps};
+use crate::nextcloud_users::{add_group_member, create_group, create_user, disable_user, enable_user, list_groups, list_users, reset_password, set_quota};
 use crate::middleware::require_setup_not_complete;
+use crate::auth::{login, logout, require_admin_session};
 
 #[tokio::main]
 async fn main() {
@@ -62,14 +69,26 @@ async fn main() {
         .route("/credentials/complete", get(complete_credentials_setup))
         .route("/caddy/endpoint/enable/nextcloud", post(activate_endpoint_nextcloud))
         .route("/service/*name", service_status_route)
-        .route("/nextcloud/hard-reset", get(hard_reset_nextcloud));
+        .route("/nextcloud/hard-reset", get(hard_reset_nextcloud))
+        .route("/storage/disks", get(list_disks));
 
-    let mut app = tonic::service::Routes::new(tonic_web::enable(
-        JournalLogStreamServer::new(
-            JournalLogStreamService::new(false, true))
-    ))
-        .prepare()
-        .into_axum_router();
+    // Routes that require an admin session
+    let admin_router = Router::new()
+        .route("/api/nextcloud/config", get(nextcloud_config))
+        .route("/api/nextcloud/apps", get(list_apps).post(app_action))
+        .route("/api/nextcloud/apps/profile", post(apply_apps_profile))
+        .route("/api/nextcloud/users", get(list_users).post(create_user))
+        .route("/api/nextcloud/users/:id/password", post(reset_password))
+        .route("/api/nextcloud/users/:id/enable", post(enable_user))
+        .route("/api/nextcloud/users/:id/disable", post(disable_user))
+        .route("/api/nextcloud/users/:id/quota", put(set_quota))
+        .route("/api/nextcloud/groups", get(list_groups).post(create_group))
+        .route("/api/nextcloud/groups/:id/users", post(add_group_member))
+        .route("/api/storage/status", get(storage_status))
+        .route("/api/support/bundle", get(support_bundle::support_bundle))
+        .route_layer(axum::middleware::from_fn(require_admin_session));
+
+    let mut app = journal_router(journal_service());
 
     #[cfg(feature = "insecure")]
     {
@@ -80,6 +99,9 @@ async fn main() {
     app = app
         .route_layer(axum::middleware::from_fn(require_setup_not_complete))
         .nest_service("/api/setup", setup_router)
+        .route("/api/auth/login", post(login))
+        .route("/api/auth/logout", post(logout))
+        .merge(admin_router)
         .fallback_service(ServeDir::new("public"))
         .layer(Extension(config.clone()));
 
@@ -0,0 +1,70 @@
+use axum::{Extension, Json};
+use axum::extract::Query;
+use serde::Deserialize;
+use nca_error::NcaError;
+#[cfg(not(feature = "mock-occ"))]
+use nca_system_api::occ::apps::api::{apply_nc_apps_profile, list_nc_apps, run_nc_app_action};
+use nca_system_api::occ::apps::types::{AppAction, AppsProfile, NcApp};
+use crate::config::Config;
+
+#[derive(Deserialize)]
+pub struct ApplyProfileParams {
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[cfg(feature = "mock-occ")]
+fn mock_apps() -> Vec<NcApp> {
+    let app = |id: &str, version: &str, enabled: bool| NcApp { id: id.to_string(), version: Some(version.to_string()), enabled };
+    vec![
+        app("calendar", "5.0.9", true),
+        app("contacts", "6.1.0", false),
+        app("files", "2.3.1", true),
+        app("richdocuments", "8.5.2", true),
+    ]
+}
+
+pub(crate) async fn list_apps(Extension(config): Extension<Config>) -> Result<Json<Vec<NcApp>>, NcaError> {
+
+    #[cfg(not(feature = "mock-occ"))]
+    {
+        Ok(Json(list_nc_apps(config.occ_channel).await?))
+    }
+
+    #[cfg(feature = "mock-occ")]
+    {
+        let _ = config;
+        Ok(Json(mock_apps()))
+    }
+}
+
+/// Runs the action and returns the apps afterwards
+pub(crate) async fn app_action(Extension(config): Extension<Config>, Json(action): Json<AppAction>) -> Result<Json<Vec<NcApp>>, NcaError> {
+
+    #[cfg(not(feature = "mock-occ"))]
+    {
+        run_nc_app_action(config.occ_channel.clone(), &action).await?;
+        Ok(Json(list_nc_apps(config.occ_channel).await?))
+    }
+
+    #[cfg(feature = "mock-occ")]
+    {
+        let _ = (config, action);
+        Ok(Json(mock_apps()))
+    }
+}
+
+/// Returns the actions that were (or with `dry_run` would be) necessary to apply the profile
+pub(crate) async fn apply_apps_profile(Extension(config): Extension<Config>, Query(params): Query<ApplyProfileParams>, Json(profile): Json<AppsProfile>) -> Result<Json<Vec<AppAction>>, NcaError> {
+
+    #[cfg(not(feature = "mock-occ"))]
+    {
+        Ok(Json(apply_nc_apps_profile(config.occ_channel, &profile, params.dry_run).await?))
+    }
+
+    #[cfg(feature = "mock-occ")]
+    {
+        let _ = (config, params);
+        Ok(Json(profile.plan(&mock_apps())))
+    }
+}
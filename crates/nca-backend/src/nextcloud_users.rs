@@ -0,0 +1,198 @@
+use std::collections::BTreeMap;
+use axum::{Extension, Json};
+use axum_extra::routing::TypedPath;
+use rand::Rng;
+use serde::Deserialize;
+use nca_api_model::users::{CreateGroupRequest, CreateUserRequest, GeneratedPassword, GroupMemberRequest, QuotaRequest};
+use nca_error::NcaError;
+#[cfg(not(feature = "mock-occ"))]
+use nca_system_api::occ::users::api::{add_nc_group, add_nc_group_member, add_nc_user, list_nc_groups, list_nc_users,
+                                      reset_nc_user_password, set_nc_user_enabled, set_nc_user_quota};
+use nca_system_api::occ::users::types::{NcUser, NewUser};
+use crate::api_routes::check_is_secure_password;
+use crate::config::Config;
+
+const GENERATED_PASSWORD_LENGTH: usize = 32;
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/api/nextcloud/users/:id/password")]
+pub struct UserPassword {
+    id: String,
+}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/api/nextcloud/users/:id/enable")]
+pub struct EnableUser {
+    id: String,
+}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/api/nextcloud/users/:id/disable")]
+pub struct DisableUser {
+    id: String,
+}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/api/nextcloud/users/:id/quota")]
+pub struct UserQuota {
+    id: String,
+}
+
+#[derive(TypedPath, Deserialize)]
+#[typed_path("/api/nextcloud/groups/:id/users")]
+pub struct GroupMembers {
+    id: String,
+}
+
+fn generate_password() -> String {
+    loop {
+        let password: String = rand::rng()
+            .sample_iter(rand::distr::Alphanumeric)
+            .take(GENERATED_PASSWORD_LENGTH).map(char::from)
+            .collect();
+        if check_is_secure_password(&password) {
+            return password;
+        }
+    }
+}
+
+#[cfg(feature = "mock-occ")]
+fn mock_users() -> Vec<NcUser> {
+    let user = |id: &str, display_name: &str, enabled: bool, groups: &[&str], quota: &str| NcUser {
+        id: id.to_string(),
+        display_name: Some(display_name.to_string()),
+        email: Some(format!("{id}@example.org")),
+        enabled,
+        groups: groups.iter().map(|group| group.to_string()).collect(),
+        quota: Some(quota.to_string()),
+        last_seen: None,
+    };
+    vec![
+        user("admin", "Administrator", true, &["admin"], "none"),
+        user("alice", "Alice", true, &["staff"], "10 GB"),
+        user("bob", "Bob", false, &[], "default"),
+    ]
+}
+
+pub(crate) async fn list_users(Extension(config): Extension<Config>) -> Result<Json<Vec<NcUser>>, NcaError> {
+
+    #[cfg(not(feature = "mock-occ"))]
+    {
+        Ok(Json(list_nc_users(config.occ_channel).await?))
+    }
+
+    #[cfg(feature = "mock-occ")]
+    {
+        let _ = config;
+        Ok(Json(mock_users()))
+    }
+}
+
+/// Creates the user with a generated password, which is returned
+pub(crate) async fn create_user(Extension(config): Extension<Config>, Json(request): Json<CreateUserRequest>) -> Result<Json<GeneratedPassword>, NcaError> {
+    if request.id.trim().is_empty() {
+        return Err(NcaError::MissingConfig("user id".to_string()));
+    }
+    let password = generate_password();
+    let user = NewUser {
+        id: request.id.clone(),
+        display_name: request.display_name.filter(|name| !name.is_empty()),
+        email: request.email.filter(|email| !email.is_empty()),
+        groups: request.groups,
+    };
+
+    #[cfg(not(feature = "mock-occ"))]
+    {
+        add_nc_user(config.occ_channel.clone(), &user, &password).await?;
+        if let Some(quota) = request.quota.filter(|quota| !quota.is_empty()) {
+            set_nc_user_quota(config.occ_channel, user.id, quota).await?;
+        }
+    }
+
+    #[cfg(feature = "mock-occ")]
+    let _ = (config, user);
+
+    Ok(Json(GeneratedPassword { user_id: request.id, password }))
+}
+
+/// Sets a new generated password, which is returned
+pub(crate) async fn reset_password(UserPassword { id }: UserPassword, Extension(config): Extension<Config>) -> Result<Json<GeneratedPassword>, NcaError> {
+    let password = generate_password();
+
+    #[cfg(not(feature = "mock-occ"))]
+    reset_nc_user_password(config.occ_channel, id.clone(), &password).await?;
+
+    #[cfg(feature = "mock-occ")]
+    let _ = config;
+
+    Ok(Json(GeneratedPassword { user_id: id, password }))
+}
+
+async fn set_enabled(config: Config, id: String, enabled: bool) -> Result<Json<()>, NcaError> {
+
+    #[cfg(not(feature = "mock-occ"))]
+    set_nc_user_enabled(config.occ_channel, id, enabled).await?;
+
+    #[cfg(feature = "mock-occ")]
+    let _ = (config, id, enabled);
+
+    Ok(Json(()))
+}
+
+pub(crate) async fn enable_user(EnableUser { id }: EnableUser, Extension(config): Extension<Config>) -> Result<Json<()>, NcaError> {
+    set_enabled(config, id, true).await
+}
+
+pub(crate) async fn disable_user(DisableUser { id }: DisableUser, Extension(config): Extension<Config>) -> Result<Json<()>, NcaError> {
+    set_enabled(config, id, false).await
+}
+
+pub(crate) async fn set_quota(UserQuota { id }: UserQuota, Extension(config): Extension<Config>, Json(request): Json<QuotaRequest>) -> Result<Json<()>, NcaError> {
+
+    #[cfg(not(feature = "mock-occ"))]
+    set_nc_user_quota(config.occ_channel, id, request.quota).await?;
+
+    #[cfg(feature = "mock-occ")]
+    let _ = (config, id, request);
+
+    Ok(Json(()))
+}
+
+pub(crate) async fn list_groups(Extension(config): Extension<Config>) -> Result<Json<BTreeMap<String, Vec<String>>>, NcaError> {
+
+    #[cfg(not(feature = "mock-occ"))]
+    {
+        Ok(Json(list_nc_groups(config.occ_channel).await?))
+    }
+
+    #[cfg(feature = "mock-occ")]
+    {
+        let _ = config;
+        Ok(Json(BTreeMap::from([
+            ("admin".to_string(), vec!["admin".to_string()]),
+            ("staff".to_string(), vec!["alice".to_string()]),
+        ])))
+    }
+}
+
+pub(crate) async fn create_group(Extension(config): Extension<Config>, Json(request): Json<CreateGroupRequest>) -> Result<Json<()>, NcaError> {
+
+    #[cfg(not(feature = "mock-occ"))]
+    add_nc_group(config.occ_channel, request.id, request.display_name).await?;
+
+    #[cfg(feature = "mock-occ")]
+    let _ = (config, request);
+
+    Ok(Json(()))
+}
+
+pub(crate) async fn add_group_member(GroupMembers { id }: GroupMembers, Extension(config): Extension<Config>, Json(request): Json<GroupMemberRequest>) -> Result<Json<()>, NcaError> {
+
+    #[cfg(not(feature = "mock-occ"))]
+    add_nc_group_member(config.occ_channel, id, request.user_id).await?;
+
+    #[cfg(feature = "mock-occ")]
+    let _ = (config, id, request);
+
+    Ok(Json(()))
+}
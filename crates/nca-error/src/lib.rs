@@ -19,7 +19,8 @@ pub enum NcaError {
     NotActivated(String),
     IOError(String),
     CryptoError(String),
-    NotReady(String)
+    NotReady(String),
+    InvalidArgument(String)
 }
 
 // Allow the use of "{}" format specifier
@@ -39,6 +40,7 @@ impl fmt::Display for NcaError {
             NcaError::SystemdError(_) => "Systemd Err",
             NcaError::FaultySetup(_) => "Faulty Setup Err",
             NcaError::NotReady(_) => "Not Ready Err",
+            NcaError::InvalidArgument(_) => "Invalid Argument Err",
         };
         write!(f, "{}: {}", error_prefix, cause)
     }
@@ -65,6 +67,7 @@ impl NcaError {
             NcaError::SystemdError(msg) => format!("Systemd Err: {}", msg).to_string(),
             NcaError::FaultySetup(msg) => format!("Faulty Setup Err: {}", msg).to_string(),
             NcaError::NotReady(msg) => format!("Not Ready Err: {}", msg).to_string(),
+            NcaError::InvalidArgument(msg) => msg.to_string(),
         }
     }
 
@@ -87,6 +90,10 @@ impl NcaError {
     pub fn new_missing_config_error<D: Display>(s: D) -> NcaError {
         NcaError::MissingConfig(s.to_string())
     }
+
+    pub fn new_invalid_argument_error<D: Display>(s: D) -> NcaError {
+        NcaError::InvalidArgument(s.to_string())
+    }
 }
 
 // So that errors get printed to the browser?
@@ -98,7 +105,8 @@ impl IntoResponse for NcaError {
             | NcaError::Unexpected(_) | NcaError::ServerConfiguration(_) 
             | NcaError::IOError(_) | NcaError::CryptoError(_) => (StatusCode::INTERNAL_SERVER_ERROR, self.to_string()),
             NcaError::WeakPassword(_, _) | NcaError::InvalidPath(_, _) 
-            | NcaError::NotActivated(_) | NcaError::MissingConfig(_) | NcaError::NotReady(_) => (StatusCode::BAD_REQUEST, self.to_string()),
+            | NcaError::NotActivated(_) | NcaError::MissingConfig(_) | NcaError::NotReady(_)
+            | NcaError::InvalidArgument(_) => (StatusCode::BAD_REQUEST, self.to_string()),
         }.into_response()
 
         // format!("status = {}, message = {}", status, error_message).into_response()
@@ -124,6 +132,7 @@ impl From<NcaError> for Status {
             NcaError::SystemdError(_) => Status::internal(value.to_string()),
             NcaError::FaultySetup(_) => Status::internal(value.to_string()),
             NcaError::NotReady(_) => Status::failed_precondition(value.to_string()),
+            NcaError::InvalidArgument(_) => Status::invalid_argument(value.to_string()),
         }
     }
 }
@@ -0,0 +1,66 @@
+use daisy_rsx::{Alert, AlertColor};
+use dioxus::prelude::*;
+use nca_api_model::auth::LoginRequest;
+use crate::components::form::{InputField, InputType, PasswordFieldConfig};
+#[cfg(not(feature = "mock-backend"))]
+use crate::{base_url, do_post};
+
+#[cfg(not(feature = "mock-backend"))]
+async fn login(request: LoginRequest) -> Result<(), String> {
+    let request_url = format!("{}/api/auth/login", base_url());
+    let payload = serde_json::to_string(&request).map_err(|e| e.to_string())?;
+    let response = do_post(&request_url, payload, None).await.map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("Failed to log in: {}", response.text().await?));
+    }
+    Ok(())
+}
+
+#[cfg(feature = "mock-backend")]
+async fn login(_request: LoginRequest) -> Result<(), String> {
+    Ok(())
+}
+
+/// Asks for the primary password to start an admin session
+#[component]
+pub fn AdminLogin(on_login: EventHandler<()>) -> Element {
+    let primary_password = use_signal(String::new);
+    let mut login_error: Signal<Option<String>> = use_signal(|| None);
+
+    rsx! {
+        form {
+            class: "flex flex-col max-w-xl",
+            onsubmit: move |evt| async move {
+                evt.prevent_default();
+                match login(LoginRequest { primary_password: primary_password.peek().clone() }).await {
+                    Ok(_) => {
+                        login_error.set(None);
+                        on_login.call(());
+                    },
+                    Err(msg) => login_error.set(Some(msg)),
+                }
+            },
+            if let Some(err) = login_error() {
+                Alert {
+                    alert_color: Some(AlertColor::Error),
+                    { err }
+                }
+            },
+            InputField {
+                title: "Primary password",
+                value: primary_password,
+                r#type: InputType::Password(PasswordFieldConfig {
+                    hide: true,
+                    generator: false,
+                    password_strength: None
+                })
+            },
+            button {
+                class: "btn btn-primary mt-4",
+                r#type: "submit",
+                disabled: primary_password().is_empty(),
+                "Log in"
+            }
+        }
+    }
+}
@@ -2,14 +2,77 @@ use daisy_rsx::{Alert, AlertColor};
 use dioxus::prelude::*;
 use dioxus_free_icons::Icon;
 use dioxus_free_icons::icons::ld_icons;
+#[cfg(feature = "mock-backend")]
+use {
+    http::StatusCode,
+    reqwest::Url,
+    crate::{HttpResponse, MockResponse},
+};
+use nca_api_model::setup::Disk;
 use crate::components::configure_configstep::{CfgConfigStep, ConfigStepContinueButton};
-use crate::ConfigStepStatus;
+use crate::{base_url, ConfigStepStatus};
+#[cfg(not(feature = "mock-backend"))]
+use crate::do_get;
 
-#[derive(Props, Debug, PartialEq, Clone)]
-struct Disk {
-    label: String,
-    size: u64,
-    uuid: String
+#[cfg(not(feature = "mock-backend"))]
+async fn fetch_disks() -> Result<Vec<Disk>, String> {
+    let request_url = format!("{}/api/setup/storage/disks", base_url());
+    let response = do_get(&request_url, None).await.map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("Failed to retrieve disks: {}", response.text().await?));
+    }
+    response.json().await
+}
+
+#[cfg(feature = "mock-backend")]
+async fn fetch_disks() -> Result<Vec<Disk>, String> {
+    let request_url = format!("{}/api/setup/storage/disks", base_url());
+    let disk = |name: &str, size: u64, model: &str, is_system_disk: bool| Disk {
+        path: format!("/dev/{name}"),
+        name: name.to_string(),
+        device_type: "disk".to_string(),
+        parent: None,
+        size,
+        model: Some(model.to_string()),
+        serial: None,
+        partition_table: None,
+        fs_type: None,
+        label: None,
+        is_luks: false,
+        mount_point: None,
+        is_system_disk,
+    };
+    let mock_disks = vec![
+        disk("vda", 10_000_000_000, "Root Disk", true),
+        disk("vdb", 50_000_000_000, "My Disk 1", false),
+        disk("vdc", 100_000_000_000, "My Disk 2", false),
+    ];
+    let resp = MockResponse {
+        body: serde_json::to_string(&mock_disks).map_err(|e| e.to_string())?,
+        url: Url::parse(&request_url).unwrap(),
+        status: StatusCode::OK,
+    };
+    HttpResponse::from(resp).json().await
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1000.0 && unit < UNITS.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+fn disk_label(disk: &Disk) -> String {
+    match (&disk.model, disk.is_system_disk) {
+        (Some(model), true) => format!("{model} ({}, system disk)", disk.path),
+        (Some(model), false) => format!("{model} ({})", disk.path),
+        (None, true) => format!("{} (system disk)", disk.path),
+        (None, false) => disk.path.clone(),
+    }
 }
 
 #[component]
@@ -24,21 +87,18 @@ pub fn CfgSetupStorage(
         old.with_visited(true).with_valid(true).with_completed(true)
     }));
 
-    let selected_disk = use_signal(|| 0);
+    let mut selected_disk: Signal<Option<String>> = use_signal(|| None);
 
-    let mock_disks: Vec<Disk> = vec![Disk {
-        label: "Root Disk".to_string(),
-        size: 10_000_000_000,
-        uuid: "1234-1234-1234-1234-1234-1234".to_string()
-    }, Disk {
-        label: "My Disk 1".to_string(),
-        size: 50_000_000_000,
-        uuid: "1234-1234-1234-1234-1234-1234".to_string()
-    }, Disk {
-        label: "My Disk 2".to_string(),
-        size: 100_000_000_000,
-        uuid: "1234-1234-1234-1234-1234-1234".to_string()
-    }];
+    let disks = use_resource(move || async move {
+        let disks: Vec<Disk> = fetch_disks().await?
+            .into_iter()
+            .filter(|d| d.device_type == "disk")
+            .collect();
+        if selected_disk.peek().is_none() {
+            selected_disk.set(disks.iter().find(|d| d.is_system_disk).map(|d| d.path.clone()));
+        }
+        Ok::<Vec<Disk>, String>(disks)
+    });
 
     rsx! {
         CfgConfigStep {
@@ -57,39 +117,53 @@ pub fn CfgSetupStorage(
                     class: "my-2",
                     "Choose a disk for store user data",
                 },
-                ul {
-                    class: "list join join-vertical w-full cursor-pointer bg-base-100 rounded-box shadow-md",
-                    for (i, disk) in mock_disks.iter().enumerate() {
-                        DiskOption {
-                            disk: disk.clone(),
-                            disk_id: i,
-                            selected_disk
+                match &*disks.read() {
+                    None => rsx! {
+                        span { class: "loading loading-spinner loading-md" }
+                    },
+                    Some(Err(e)) => rsx! {
+                        Alert {
+                            alert_color: Some(AlertColor::Error),
+                            { e.clone() }
                         }
-                    }
-                },
-                if selected_disk() != 0 {
-
-                    Alert {
-                        class: "mt-4",
-                        alert_color: Some(AlertColor::Warn),
-                        { format!("Disk \"{}\" will be erased, encrypted and formatted.", mock_disks[selected_disk()].label) }
                     },
+                    Some(Ok(disks)) => rsx! {
+                        ul {
+                            class: "list join join-vertical w-full cursor-pointer bg-base-100 rounded-box shadow-md",
+                            for disk in disks.iter() {
+                                DiskOption {
+                                    disk: disk.clone(),
+                                    selected_disk
+                                }
+                            }
+                        },
+                        for disk in disks.iter().filter(|d| !d.is_system_disk && selected_disk().as_ref() == Some(&d.path)) {
+                            Alert {
+                                class: "mt-4",
+                                alert_color: Some(AlertColor::Warn),
+                                { format!("Disk \"{}\" will be erased, encrypted and formatted.", disk_label(disk)) }
+                            },
+                        }
+                    }
                 }
-
             }
         }
     }
 }
 
 #[component]
-fn DiskOption(disk: Disk, disk_id: usize, selected_disk: Signal<usize>) -> Element {
-    let is_selected = use_memo(move || disk_id == selected_disk());
+fn DiskOption(disk: Disk, selected_disk: Signal<Option<String>>) -> Element {
+    let path = disk.path.clone();
+    let is_selected = use_memo(move || selected_disk().as_ref() == Some(&path));
     rsx! {
         li {
             class: "list-row flex flex-row py-2 join-item",
             class: if is_selected() { "bg-secondary text-secondary-content" },
             class: if !is_selected() { "bg-neutral text-neutral-content" },
-            onclick: move |_| selected_disk.set(disk_id),
+            onclick: {
+                let path = disk.path.clone();
+                move |_| selected_disk.set(Some(path.clone()))
+            },
             div {
                 class: "flex-none",
                 Icon {
@@ -103,11 +177,11 @@ fn DiskOption(disk: Disk, disk_id: usize, selected_disk: Signal<usize>) -> Eleme
             div {
                 class: "flex-1",
                 div {
-                    { disk.label }
+                    { disk_label(&disk) }
                 },
                 div {
                     class: "text-xs uppercase font-semibold opacity-60",
-                    { format!("{} Bytes", disk.size) }
+                    { format_size(disk.size) }
                 },
             }
 
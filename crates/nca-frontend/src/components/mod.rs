@@ -10,8 +10,11 @@ pub mod configure_storage;
 mod accordion;
 pub mod setup_progress_drawer;
 pub mod configure_credentials_backup;
+pub mod users;
+pub mod admin_login;
 
 pub use logs::Logs;
 pub use service_status::ServiceStatus;
 pub use nc_startup::NcStartup;
-pub use configure_nextcloud::ServicesConfig;
\ No newline at end of file
+pub use configure_nextcloud::ServicesConfig;
+pub use users::UserAdmin;
\ No newline at end of file
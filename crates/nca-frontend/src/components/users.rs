@@ -0,0 +1,278 @@
+use daisy_rsx::{Alert, AlertColor};
+use dioxus::prelude::*;
+#[cfg(feature = "mock-backend")]
+use {
+    http::StatusCode,
+    reqwest::Url,
+    crate::{generate_secure_password, HttpResponse, MockResponse},
+};
+use nca_api_model::users::{CreateUserRequest, GeneratedPassword};
+use nca_system_api::occ::users::types::NcUser;
+use crate::base_url;
+use crate::components::admin_login::AdminLogin;
+use crate::components::form::{InputField, InputType};
+#[cfg(not(feature = "mock-backend"))]
+use {
+    http::StatusCode,
+    crate::{do_get, do_post, HttpResponse},
+};
+
+#[cfg(not(feature = "mock-backend"))]
+async fn check_response(response: HttpResponse, action: &str) -> Result<HttpResponse, String> {
+    if !response.status().is_success() {
+        return Err(format!("Failed to {action}: {}", response.text().await?));
+    }
+    Ok(response)
+}
+
+/// `None` if there is no admin session
+#[cfg(not(feature = "mock-backend"))]
+async fn fetch_users() -> Result<Option<Vec<NcUser>>, String> {
+    let request_url = format!("{}/api/nextcloud/users", base_url());
+    let response = do_get(&request_url, None).await.map_err(|e| e.to_string())?;
+    if response.status() == StatusCode::UNAUTHORIZED {
+        return Ok(None);
+    }
+    check_response(response, "retrieve users").await?.json().await.map(Some)
+}
+
+#[cfg(not(feature = "mock-backend"))]
+async fn create_user(request: CreateUserRequest) -> Result<GeneratedPassword, String> {
+    let request_url = format!("{}/api/nextcloud/users", base_url());
+    let payload = serde_json::to_string(&request).map_err(|e| e.to_string())?;
+    let response = do_post(&request_url, payload, None).await.map_err(|e| e.to_string())?;
+    check_response(response, "create user").await?.json().await
+}
+
+#[cfg(not(feature = "mock-backend"))]
+async fn reset_password(user_id: String) -> Result<GeneratedPassword, String> {
+    let request_url = format!("{}/api/nextcloud/users/{user_id}/password", base_url());
+    let response = do_post(&request_url, "", None).await.map_err(|e| e.to_string())?;
+    check_response(response, "reset password").await?.json().await
+}
+
+#[cfg(not(feature = "mock-backend"))]
+async fn set_enabled(user_id: String, enabled: bool) -> Result<(), String> {
+    let action = if enabled { "enable" } else { "disable" };
+    let request_url = format!("{}/api/nextcloud/users/{user_id}/{action}", base_url());
+    let response = do_post(&request_url, "", None).await.map_err(|e| e.to_string())?;
+    check_response(response, &format!("{action} user")).await.map(|_| ())
+}
+
+#[cfg(feature = "mock-backend")]
+async fn fetch_users() -> Result<Option<Vec<NcUser>>, String> {
+    let request_url = format!("{}/api/nextcloud/users", base_url());
+    let user = |id: &str, display_name: &str, enabled: bool, groups: &[&str], quota: &str| NcUser {
+        id: id.to_string(),
+        display_name: Some(display_name.to_string()),
+        email: Some(format!("{id}@example.org")),
+        enabled,
+        groups: groups.iter().map(|group| group.to_string()).collect(),
+        quota: Some(quota.to_string()),
+        last_seen: None,
+    };
+    let mock_users = vec![
+        user("admin", "Administrator", true, &["admin"], "none"),
+        user("alice", "Alice", true, &["staff"], "10 GB"),
+        user("bob", "Bob", false, &[], "default"),
+    ];
+    let resp = MockResponse {
+        body: serde_json::to_string(&mock_users).map_err(|e| e.to_string())?,
+        url: Url::parse(&request_url).unwrap(),
+        status: StatusCode::OK,
+    };
+    HttpResponse::from(resp).json().await.map(Some)
+}
+
+#[cfg(feature = "mock-backend")]
+async fn mock_generated_password(user_id: String) -> Result<GeneratedPassword, String> {
+    let request_url = format!("{}/api/nextcloud/users/{user_id}/password", base_url());
+    let resp = MockResponse {
+        body: serde_json::to_string(&GeneratedPassword { user_id, password: generate_secure_password() })
+            .map_err(|e| e.to_string())?,
+        url: Url::parse(&request_url).unwrap(),
+        status: StatusCode::OK,
+    };
+    HttpResponse::from(resp).json().await
+}
+
+#[cfg(feature = "mock-backend")]
+async fn create_user(request: CreateUserRequest) -> Result<GeneratedPassword, String> {
+    mock_generated_password(request.id).await
+}
+
+#[cfg(feature = "mock-backend")]
+async fn reset_password(user_id: String) -> Result<GeneratedPassword, String> {
+    mock_generated_password(user_id).await
+}
+
+#[cfg(feature = "mock-backend")]
+async fn set_enabled(_user_id: String, _enabled: bool) -> Result<(), String> {
+    Ok(())
+}
+
+fn non_empty(value: String) -> Option<String> {
+    Some(value.trim().to_string()).filter(|value| !value.is_empty())
+}
+
+#[component]
+pub fn UserAdmin(error: Signal<Option<String>>) -> Element {
+    let mut users = use_resource(fetch_users);
+    let mut generated_password: Signal<Option<GeneratedPassword>> = use_signal(|| None);
+
+    let user_id = use_signal(String::new);
+    let display_name = use_signal(String::new);
+    let email = use_signal(String::new);
+    let groups = use_signal(String::new);
+    let quota = use_signal(String::new);
+
+    let mut on_password = move |result: Result<GeneratedPassword, String>| {
+        match result {
+            Ok(password) => {
+                error.set(None);
+                generated_password.set(Some(password));
+                users.restart();
+            },
+            Err(msg) => {
+                error.set(Some(msg));
+                // Shows the login again if the session has expired
+                users.restart();
+            },
+        }
+    };
+
+    rsx! {
+        div {
+            class: "flex-none p-2 overflow-y-auto",
+            h2 {
+                class: "my-2",
+                "Nextcloud Users",
+            },
+            if let Some(password) = generated_password() {
+                Alert {
+                    class: "mb-4",
+                    alert_color: Some(AlertColor::Success),
+                    div {
+                        "The password of \"{password.user_id}\" is "
+                        code { class: "select-all", "{password.password}" }
+                        ". It will not be shown again."
+                    }
+                }
+            },
+            match &*users.read() {
+                None => rsx! {
+                    span { class: "loading loading-spinner loading-md" }
+                },
+                Some(Err(e)) => rsx! {
+                    Alert {
+                        alert_color: Some(AlertColor::Error),
+                        { e.clone() }
+                    }
+                },
+                Some(Ok(None)) => rsx! {
+                    AdminLogin {
+                        on_login: move |_| {
+                            error.set(None);
+                            users.restart();
+                        }
+                    }
+                },
+                Some(Ok(Some(user_list))) => rsx! {
+                    table {
+                        class: "table bg-base-100 rounded-box shadow-md",
+                        thead {
+                            tr {
+                                th { "User" }
+                                th { "Email" }
+                                th { "Groups" }
+                                th { "Quota" }
+                                th {}
+                            }
+                        },
+                        tbody {
+                            for user in user_list.iter().cloned() {
+                                UserRow {
+                                    user,
+                                    on_reset_password: move |user_id| async move {
+                                        on_password(reset_password(user_id).await)
+                                    },
+                                    on_set_enabled: move |(user_id, enabled)| async move {
+                                        if let Err(msg) = set_enabled(user_id, enabled).await {
+                                            error.set(Some(msg));
+                                        }
+                                        users.restart();
+                                    }
+                                }
+                            }
+                        }
+                    },
+                    h2 {
+                        class: "mt-8 mb-2",
+                        "Create User",
+                    },
+                    form {
+                        class: "flex flex-col max-w-xl",
+                        onsubmit: move |evt| async move {
+                            evt.prevent_default();
+                            let request = CreateUserRequest {
+                                id: user_id.peek().trim().to_string(),
+                                display_name: non_empty(display_name.peek().clone()),
+                                email: non_empty(email.peek().clone()),
+                                groups: groups.peek().split(',').filter_map(|group| non_empty(group.to_string())).collect(),
+                                quota: non_empty(quota.peek().clone()),
+                            };
+                            on_password(create_user(request).await)
+                        },
+                        InputField { title: "User name", value: user_id, r#type: InputType::Text },
+                        InputField { title: "Display name", value: display_name, r#type: InputType::Text },
+                        InputField { title: "Email", value: email, r#type: InputType::Text },
+                        InputField { title: "Groups (comma separated)", value: groups, r#type: InputType::Text },
+                        InputField { title: "Quota (e.g. 5 GB)", value: quota, r#type: InputType::Text },
+                        button {
+                            class: "btn btn-primary mt-4",
+                            r#type: "submit",
+                            disabled: user_id().trim().is_empty(),
+                            "Create user with generated password"
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[component]
+fn UserRow(user: NcUser, on_reset_password: EventHandler<String>, on_set_enabled: EventHandler<(String, bool)>) -> Element {
+    rsx! {
+        tr {
+            class: if !user.enabled { "opacity-60" },
+            td {
+                div { "{user.display_name.clone().unwrap_or(user.id.clone())}" },
+                div { class: "text-xs opacity-60", "{user.id}" }
+            },
+            td { "{user.email.clone().unwrap_or_default()}" },
+            td { "{user.groups.join(\", \")}" },
+            td { "{user.quota.clone().unwrap_or_default()}" },
+            td {
+                class: "flex flex-row gap-2",
+                button {
+                    class: "btn btn-sm",
+                    onclick: {
+                        let id = user.id.clone();
+                        move |_| on_reset_password.call(id.clone())
+                    },
+                    "Reset password"
+                },
+                button {
+                    class: "btn btn-sm",
+                    onclick: {
+                        let id = user.id.clone();
+                        let enabled = user.enabled;
+                        move |_| on_set_enabled.call((id.clone(), !enabled))
+                    },
+                    if user.enabled { "Disable" } else { "Enable" }
+                }
+            }
+        }
+    }
+}
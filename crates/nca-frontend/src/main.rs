@@ -14,7 +14,7 @@ use dioxus_logger::tracing;
 use serde::{Deserialize, Serialize};
 use nca_frontend::layout::{Layout, SideBar};
 use nca_frontend::{assets, base_url, ConfigStep, ConfigStepStatus, ConfigStepWithStatus, GenericStep, ServicesConfig, ServiceStatus, do_get};
-use nca_frontend::components::{NcStartup, Logs};
+use nca_frontend::components::{NcStartup, Logs, UserAdmin};
 use web_sys::window;
 use reqwest::Client;
 use serde_json::json;
@@ -115,6 +115,11 @@ fn App() -> Element {
     };
 
 
+    // The user administration is only reachable directly, e.g. /#users
+    let show_user_admin = window()
+        .and_then(|w| w.location().hash().ok())
+        .is_some_and(|hash| hash == "#users");
+
     // let is_active_step_completed = use_memo(move || active_step().completed());
     let mut error: Signal<Option<String>> = use_signal(|| None);
 
@@ -227,45 +232,49 @@ fn App() -> Element {
                             "{err}"
                         }
                     },
-                    match active_step() {
-                        ConfigStep::Welcome => rsx!(
-                            CfgWelcome {
-                                on_continue: move |_| advance_step(),
-                                error
-                            }
-                        ),
-                        ConfigStep::Credentials => rsx!(
-                            CfgCredentials {
-                                on_continue: move |evt| complete_credentials_backup(evt),
-                                on_back: move |_| revert_step(),
-                                error,
-                                config: *creds_config,
-                                status: creds_status
-                            }
-                        ),
-                        ConfigStep::Nextcloud => rsx!(
-                            CfgNextcloud {
-                                on_continue: move |_| advance_step(),
-                                on_back: move |_| revert_step(),
-                                error,
-                                config: nc_config,
-                                status: nc_status,
-                                nc_admin_password: nc_admin_pw()
-                            }
-                        ),
-                        ConfigStep::Disks => rsx!(
-                            CfgSetupStorage {
-                                on_continue: move |_| advance_step(),
-                                on_back: move |_| revert_step(),
-                                error,
-                                status: disks_status
-                            }
-                        ),
-                        ConfigStep::Startup => rsx!(
-                            NcStartup {
-                                error
-                            }
-                        )
+                    if show_user_admin {
+                        UserAdmin { error }
+                    } else {
+                        match active_step() {
+                            ConfigStep::Welcome => rsx!(
+                                CfgWelcome {
+                                    on_continue: move |_| advance_step(),
+                                    error
+                                }
+                            ),
+                            ConfigStep::Credentials => rsx!(
+                                CfgCredentials {
+                                    on_continue: move |evt| complete_credentials_backup(evt),
+                                    on_back: move |_| revert_step(),
+                                    error,
+                                    config: *creds_config,
+                                    status: creds_status
+                                }
+                            ),
+                            ConfigStep::Nextcloud => rsx!(
+                                CfgNextcloud {
+                                    on_continue: move |_| advance_step(),
+                                    on_back: move |_| revert_step(),
+                                    error,
+                                    config: nc_config,
+                                    status: nc_status,
+                                    nc_admin_password: nc_admin_pw()
+                                }
+                            ),
+                            ConfigStep::Disks => rsx!(
+                                CfgSetupStorage {
+                                    on_continue: move |_| advance_step(),
+                                    on_back: move |_| revert_step(),
+                                    error,
+                                    status: disks_status
+                                }
+                            ),
+                            ConfigStep::Startup => rsx!(
+                                NcStartup {
+                                    error
+                                }
+                            )
+                        }
                     }
                 }
             }
@@ -1,42 +1,279 @@
+pub mod apps;
+pub mod users;
 
 #[cfg(feature = "backend")]
 pub mod api {
-    use tonic::Streaming;
+    use std::collections::{BTreeMap, HashMap};
+    use serde::{Deserialize, Serialize};
+    use serde_json::Value;
     use tonic::transport::Channel;
-    use grpc_occ::api::{Command, CommandOutput};
+    use grpc_occ::api::{Command, OutputType};
     use grpc_occ::api::occ_client::OccClient;
     use nca_error::NcaError;
 
+    /// A config value as `occ config:list --output=json` shows it. Objects and `null` are kept
+    /// as JSON, PHP arrays with non-consecutive keys (e.g. `trusted_domains` after deleting an
+    /// entry) are objects as well.
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    #[serde(untagged)]
     pub enum NcConfigValue {
-        String(String),
         Bool(bool),
-        Int(i32)
+        Int(i64),
+        Float(f64),
+        String(String),
+        Array(Vec<NcConfigValue>),
+        Json(Value),
     }
-    
+
+    pub type NcConfig = BTreeMap<String, NcConfigValue>;
+
+    // How occ masks sensitive values
+    const MASKED_VALUE: &str = "***REMOVED SENSITIVE VALUE***";
+    const SENSITIVE_KEY_WORDS: [&str; 4] = ["password", "secret", "salt", "token"];
+
     impl NcConfigValue {
-        fn get_args(self) -> (String, String) {
+        /// The `--type` and `--value` of `config:system:set`. Arrays and objects are written as
+        /// JSON in one go, so a failure can't leave them half written.
+        fn set_args(&self) -> (&'static str, String) {
+            match self {
+                NcConfigValue::Bool(v) => ("boolean", format!("{v}")),
+                NcConfigValue::Int(v) => ("integer", format!("{v}")),
+                NcConfigValue::Float(v) => ("double", format!("{v}")),
+                NcConfigValue::String(v) => ("string", v.clone()),
+                NcConfigValue::Json(Value::Null) => ("null", String::new()),
+                NcConfigValue::Json(Value::Bool(v)) => ("boolean", format!("{v}")),
+                NcConfigValue::Json(Value::Number(v)) if v.is_f64() => ("double", format!("{v}")),
+                NcConfigValue::Json(Value::Number(v)) => ("integer", format!("{v}")),
+                NcConfigValue::Json(Value::String(v)) => ("string", v.clone()),
+                NcConfigValue::Array(_) | NcConfigValue::Json(_) => ("json", serde_json::to_string(self).unwrap_or_default()),
+            }
+        }
+
+        /// The entries of an array or object with their keys
+        pub fn entries(&self) -> Vec<(String, NcConfigValue)> {
+            match self {
+                NcConfigValue::Array(values) => values.iter().cloned()
+                    .enumerate()
+                    .map(|(i, value)| (i.to_string(), value))
+                    .collect(),
+                NcConfigValue::Json(Value::Array(values)) => values.iter().cloned()
+                    .enumerate()
+                    .map(|(i, value)| (i.to_string(), NcConfigValue::from(value)))
+                    .collect(),
+                NcConfigValue::Json(Value::Object(values)) => values.iter()
+                    .map(|(key, value)| (key.clone(), NcConfigValue::from(value.clone())))
+                    .collect(),
+                _ => Vec::new(),
+            }
+        }
+
+        pub fn as_str(&self) -> Option<&str> {
+            match self {
+                NcConfigValue::String(v) => Some(v),
+                _ => None,
+            }
+        }
+
+        /// How an app config value is stored, app config only knows strings
+        fn to_app_value(&self) -> String {
             match self {
-                NcConfigValue::Bool(v) => ("bool".to_string(), format!("{v}")),
-                NcConfigValue::Int(v) => ("int".to_string(), format!("{v}")),
-                NcConfigValue::String(v) => ("string".to_string(), v),
+                NcConfigValue::String(v) => v.clone(),
+                other => serde_json::to_string(other).unwrap_or_default(),
             }
         }
     }
-    
-    pub async fn set_nc_system_config(occ_channel: Channel, key: String, index: Option<usize>, value: NcConfigValue) -> Result<Streaming<CommandOutput>, NcaError> {
-        let (type_arg, value_arg) = value.get_args();
-        let mut args: Vec<String> = vec!["config:system:set", "--type", &type_arg, "--value", &value_arg, &key]
-            .into_iter().map(String::from).collect();
-        if let Some(pos) = index {
-            args.push(format!("{pos}"))
+
+    impl From<Value> for NcConfigValue {
+        fn from(value: Value) -> Self {
+            serde_json::from_value(value.clone()).unwrap_or(NcConfigValue::Json(value))
         }
+    }
+
+    /// Runs an occ command and returns its stdout. Fails if the command exits with an error.
+    pub async fn run_occ(occ_channel: Channel, args: Vec<String>) -> Result<String, NcaError> {
+        run_occ_with_environment(occ_channel, args, HashMap::new()).await
+    }
 
+    /// Like [run_occ], with secrets like `OC_PASS` passed as environment
+    pub async fn run_occ_with_environment(occ_channel: Channel, args: Vec<String>, environment: HashMap<String, String>) -> Result<String, NcaError> {
+        let command = args.first().cloned().unwrap_or_default();
         let mut client = OccClient::new(occ_channel);
-        let response = client.exec(Command{arguments: args}).await?
+        let mut response = client.exec(Command{arguments: args, environment, ..Command::default()}).await?
             .into_inner();
-        
-        Ok(response)
+
+        let mut stdout = String::new();
+        let mut stderr = String::new();
+        while let Some(out) = response.message().await? {
+            match out.r#type() {
+                OutputType::Stdout => stdout.push_str(out.message.as_deref().unwrap_or_default()),
+                OutputType::Stderr => stderr.push_str(out.message.as_deref().unwrap_or_default()),
+                OutputType::Exit => return match out.exit_code {
+                    Some(0) => Ok(stdout),
+                    Some(exit_code) => Err(NcaError::new_io_error(format!(
+                        "occ {command} exited with exit code {exit_code}: {}",
+                        if stderr.trim().is_empty() { stdout.trim() } else { stderr.trim() }))),
+                    None => Err(NcaError::new_io_error(format!("occ {command} exited but no exit code was returned!"))),
+                },
+            }
+        }
+        Err(NcaError::new_io_error(format!("occ {command} ended without exit code")))
+    }
+
+    async fn list_config(occ_channel: Channel, section: &str, private: bool) -> Result<Value, NcaError> {
+        let mut args = vec!["config:list".to_string(), section.to_string(), "--output=json".to_string()];
+        if private {
+            args.push("--private".to_string());
+        }
+        let output = run_occ(occ_channel, args).await?;
+        serde_json::from_str(&output)
+            .map_err(|e| NcaError::new_unexpected_error(format!("Failed to parse output of occ config:list {section}: {e}")))
+    }
+
+    /// Replaces values whose key looks like a secret, at any depth
+    fn mask_sensitive(key: &str, value: Value) -> Value {
+        let lowercase_key = key.to_lowercase();
+        if SENSITIVE_KEY_WORDS.iter().any(|word| lowercase_key.contains(word)) {
+            return Value::String(MASKED_VALUE.to_string());
+        }
+        match value {
+            Value::Object(values) => Value::Object(values.into_iter()
+                .map(|(key, value)| {
+                    let value = mask_sensitive(&key, value);
+                    (key, value)
+                })
+                .collect()),
+            Value::Array(values) => Value::Array(values.into_iter().map(|value| mask_sensitive("", value)).collect()),
+            value => value,
+        }
+    }
+
+    fn to_config(value: Option<&Value>) -> NcConfig {
+        match value {
+            Some(Value::Object(values)) => values.iter()
+                .map(|(key, value)| (key.clone(), NcConfigValue::from(value.clone())))
+                .collect(),
+            _ => NcConfig::new(),
+        }
+    }
+
+    /// The system config, sensitive values (e.g. `dbpassword`) are masked unless `private`.
+    /// Values occ doesn't know to be sensitive are masked as well if their key looks like a secret.
+    pub async fn list_nc_system_config(occ_channel: Channel, private: bool) -> Result<NcConfig, NcaError> {
+        let config = list_config(occ_channel, "system", private).await?;
+        match private {
+            true => Ok(to_config(config.get("system"))),
+            false => Ok(to_config(config.get("system").cloned().map(|system| mask_sensitive("", system)).as_ref())),
+        }
+    }
+
+    pub async fn get_nc_system_config(occ_channel: Channel, key: &str) -> Result<Option<NcConfigValue>, NcaError> {
+        Ok(list_nc_system_config(occ_channel, true).await?.remove(key))
+    }
+
+    /// The value is attached to `--value`, otherwise occ mistakes values like `-1` for options
+    fn system_set_args(key: String, index: Option<usize>, value: NcConfigValue) -> Vec<String> {
+        let (type_arg, value_arg) = value.set_args();
+        let mut args = vec!["config:system:set".to_string(), "--type".to_string(), type_arg.to_string(),
+                            format!("--value={value_arg}"), key];
+        args.extend(index.map(|i| i.to_string()));
+        args
+    }
+
+    /// Sets `key`, or the entry at `index` of it. Arrays and objects replace the previous value.
+    pub async fn set_nc_system_config(occ_channel: Channel, key: String, index: Option<usize>, value: NcConfigValue) -> Result<(), NcaError> {
+        run_occ(occ_channel, system_set_args(key, index, value)).await.map(|_| ())
+    }
+
+    pub async fn delete_nc_system_config(occ_channel: Channel, key: String, index: Option<usize>) -> Result<(), NcaError> {
+        let mut args = vec!["config:system:delete".to_string(), key];
+        args.extend(index.map(|i| i.to_string()));
+        run_occ(occ_channel, args).await.map(|_| ())
+    }
+
+    pub async fn list_nc_app_config(occ_channel: Channel, app: &str, private: bool) -> Result<NcConfig, NcaError> {
+        let config = list_config(occ_channel, app, private).await?;
+        Ok(to_config(config.get("apps").and_then(|apps| apps.get(app))))
+    }
+
+    pub async fn get_nc_app_config(occ_channel: Channel, app: &str, key: &str) -> Result<Option<NcConfigValue>, NcaError> {
+        Ok(list_nc_app_config(occ_channel, app, true).await?.remove(key))
+    }
+
+    fn app_set_args(app: String, key: String, value: NcConfigValue) -> Vec<String> {
+        vec!["config:app:set".to_string(), app, key, format!("--value={}", value.to_app_value())]
+    }
+
+    /// App config values are strings, anything else is stored as JSON
+    pub async fn set_nc_app_config(occ_channel: Channel, app: String, key: String, value: NcConfigValue) -> Result<(), NcaError> {
+        run_occ(occ_channel, app_set_args(app, key, value)).await.map(|_| ())
+    }
+
+    pub async fn delete_nc_app_config(occ_channel: Channel, app: String, key: String) -> Result<(), NcaError> {
+        run_occ(occ_channel, vec!["config:app:delete".to_string(), app, key]).await.map(|_| ())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_parse_config_list() {
+            let output: Value = serde_json::from_str(r#"{"system": {
+                "trusted_domains": {"0": "localhost", "11": "cloud.example.org"},
+                "overwriteprotocol": "https",
+                "maintenance": false,
+                "memcache.local": "\\OC\\Memcache\\APCu",
+                "loglevel": 2,
+                "preview_max_scale_factor": 1.5,
+                "enabledPreviewProviders": ["OC\\Preview\\PNG", "OC\\Preview\\JPEG"],
+                "redis": {"host": "nextcloud-aio-redis", "port": 6379}
+            }}"#).unwrap();
+            let config = to_config(output.get("system"));
+
+            assert_eq!(config["maintenance"], NcConfigValue::Bool(false));
+            assert_eq!(config["loglevel"], NcConfigValue::Int(2));
+            assert_eq!(config["preview_max_scale_factor"], NcConfigValue::Float(1.5));
+            assert_eq!(config["overwriteprotocol"].as_str(), Some("https"));
+            assert!(matches!(config["enabledPreviewProviders"], NcConfigValue::Array(_)));
+            let domains: Vec<(String, NcConfigValue)> = config["trusted_domains"].entries();
+            assert_eq!(domains[1], ("11".to_string(), NcConfigValue::String("cloud.example.org".to_string())));
+            assert_eq!(config["redis"].entries()[1].1, NcConfigValue::Int(6379));
+        }
+
+        #[test]
+        fn test_set_args() {
+            let json = r#"[{"class": "OC\\Files\\ObjectStore\\S3", "arguments": {"port": 443}}]"#;
+            let value: NcConfigValue = serde_json::from_str(json).unwrap();
+            let (type_arg, value_arg) = value.set_args();
+            assert_eq!(type_arg, "json");
+            assert_eq!(serde_json::from_str::<Value>(&value_arg).unwrap(), serde_json::from_str::<Value>(json).unwrap());
+            assert_eq!(NcConfigValue::Int(2).set_args(), ("integer", "2".to_string()));
+
+            assert_eq!(system_set_args("loglevel".to_string(), None, NcConfigValue::Int(-1)),
+                       vec!["config:system:set", "--type", "integer", "--value=-1", "loglevel"]);
+            assert_eq!(system_set_args("trusted_domains".to_string(), Some(1), NcConfigValue::String("-x".to_string())),
+                       vec!["config:system:set", "--type", "string", "--value=-x", "trusted_domains", "1"]);
+            assert_eq!(app_set_args("files".to_string(), "max_chunk_size".to_string(), NcConfigValue::Float(-0.5)),
+                       vec!["config:app:set", "files", "max_chunk_size", "--value=-0.5"]);
+        }
+
+        #[test]
+        fn test_mask_sensitive() {
+            let system: Value = serde_json::from_str(r#"{
+                "dbpassword": "hunter2",
+                "mail_smtppassword": "hunter2",
+                "dbuser": "oc_admin",
+                "objectstore": {"arguments": {"bucket": "nc", "secret": "hunter2"}}
+            }"#).unwrap();
+            let config = to_config(Some(&mask_sensitive("", system)));
+            assert_eq!(config["dbpassword"].as_str(), Some(MASKED_VALUE));
+            assert_eq!(config["mail_smtppassword"].as_str(), Some(MASKED_VALUE));
+            assert_eq!(config["dbuser"].as_str(), Some("oc_admin"));
+            let arguments = &config["objectstore"].entries()[0].1;
+            assert_eq!(arguments.entries(), vec![
+                ("bucket".to_string(), NcConfigValue::String("nc".to_string())),
+                ("secret".to_string(), NcConfigValue::String(MASKED_VALUE.to_string())),
+            ]);
+        }
     }
-    
-    
-}
\ No newline at end of file
+}
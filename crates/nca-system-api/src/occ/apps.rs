@@ -0,0 +1,204 @@
+pub mod types {
+    use std::collections::BTreeMap;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+    pub struct NcApp {
+        pub id: String,
+        pub version: Option<String>,
+        pub enabled: bool,
+    }
+
+    /// The output of `occ app:list --output=json`
+    #[derive(Clone, Debug, Deserialize, Default)]
+    pub struct AppList {
+        #[serde(default)]
+        pub enabled: BTreeMap<String, Option<String>>,
+        #[serde(default)]
+        pub disabled: BTreeMap<String, Option<String>>,
+    }
+
+    impl AppList {
+        pub fn apps(self) -> Vec<NcApp> {
+            let enabled = self.enabled.into_iter().map(|(id, version)| NcApp { id, version, enabled: true });
+            let disabled = self.disabled.into_iter().map(|(id, version)| NcApp { id, version, enabled: false });
+            let mut apps: Vec<NcApp> = enabled.chain(disabled).collect();
+            apps.sort_by(|a, b| a.id.cmp(&b.id));
+            apps
+        }
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+    #[serde(tag = "action", content = "app", rename_all = "kebab-case")]
+    pub enum AppAction {
+        /// Installs and enables the app
+        Install(String),
+        InstallDisabled(String),
+        Enable(String),
+        Disable(String),
+        /// Updates the app, or all apps if none is given
+        Update(Option<String>),
+        Remove(String),
+    }
+
+    /// App ids consist of lowercase letters, digits and underscores, e.g. `end_to_end_encryption`
+    pub fn check_app_id(id: &str) -> Result<(), String> {
+        match !id.is_empty() && id.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_') {
+            true => Ok(()),
+            false => Err(format!("'{id}' is not a valid app id")),
+        }
+    }
+
+    impl AppAction {
+        pub fn app(&self) -> Option<&str> {
+            match self {
+                AppAction::Install(app) | AppAction::InstallDisabled(app) | AppAction::Enable(app)
+                | AppAction::Disable(app) | AppAction::Update(Some(app)) | AppAction::Remove(app) => Some(app),
+                AppAction::Update(None) => None,
+            }
+        }
+
+        pub fn check(&self) -> Result<(), String> {
+            self.app().map_or(Ok(()), check_app_id)
+        }
+
+        pub fn occ_args(&self) -> Vec<String> {
+            let args: Vec<&str> = match self {
+                AppAction::Install(_) => vec!["app:install"],
+                AppAction::InstallDisabled(_) => vec!["app:install", "--keep-disabled"],
+                AppAction::Enable(_) => vec!["app:enable"],
+                AppAction::Disable(_) => vec!["app:disable"],
+                AppAction::Update(Some(_)) => vec!["app:update"],
+                AppAction::Update(None) => vec!["app:update", "--all"],
+                AppAction::Remove(_) => vec!["app:remove"],
+            };
+            let mut args: Vec<String> = args.into_iter().map(String::from).collect();
+            // Ids starting with '-' must not be taken for options
+            if let Some(app) = self.app() {
+                args.extend(["--".to_string(), app.to_string()]);
+            }
+            args
+        }
+    }
+
+    /// The apps that should be installed and enabled (or disabled) or be removed. Apps that are
+    /// not mentioned are left alone.
+    #[derive(Clone, Debug, Serialize, Deserialize, Default, PartialEq)]
+    pub struct AppsProfile {
+        #[serde(default)]
+        pub enabled: Vec<String>,
+        #[serde(default)]
+        pub disabled: Vec<String>,
+        #[serde(default)]
+        pub removed: Vec<String>,
+    }
+
+    impl AppsProfile {
+        pub fn check(&self) -> Result<(), String> {
+            self.enabled.iter()
+                .chain(&self.disabled)
+                .chain(&self.removed)
+                .try_for_each(|id| check_app_id(id))
+        }
+
+        /// What has to be done to get from `installed` to the profile, nothing if the profile
+        /// was applied before
+        pub fn plan(&self, installed: &[NcApp]) -> Vec<AppAction> {
+            let find = |id: &String| installed.iter().find(|app| &app.id == id);
+            let enable = self.enabled.iter().filter_map(|id| match find(id) {
+                None => Some(AppAction::Install(id.clone())),
+                Some(app) if !app.enabled => Some(AppAction::Enable(id.clone())),
+                Some(_) => None,
+            });
+            let disable = self.disabled.iter().filter_map(|id| match find(id) {
+                None => Some(AppAction::InstallDisabled(id.clone())),
+                Some(app) if app.enabled => Some(AppAction::Disable(id.clone())),
+                Some(_) => None,
+            });
+            let remove = self.removed.iter()
+                .filter(|id| find(id).is_some())
+                .map(|id| AppAction::Remove(id.clone()));
+            enable.chain(disable).chain(remove).collect()
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_plan() {
+            let list: AppList = serde_json::from_str(r#"{
+                "enabled": {"files": "2.3.1", "calendar": "5.0.9"},
+                "disabled": {"contacts": "6.1.0", "firstrunwizard": null}
+            }"#).unwrap();
+            let installed = list.apps();
+            assert_eq!(installed[0], NcApp { id: "calendar".to_string(), version: Some("5.0.9".to_string()), enabled: true });
+
+            let profile = AppsProfile {
+                enabled: vec!["calendar".to_string(), "contacts".to_string(), "deck".to_string()],
+                disabled: vec!["files".to_string()],
+                removed: vec!["firstrunwizard".to_string(), "news".to_string()],
+            };
+            assert_eq!(profile.plan(&installed), vec![
+                AppAction::Enable("contacts".to_string()),
+                AppAction::Install("deck".to_string()),
+                AppAction::Disable("files".to_string()),
+                AppAction::Remove("firstrunwizard".to_string()),
+            ]);
+
+            let applied = vec![
+                NcApp { id: "calendar".to_string(), version: None, enabled: true },
+                NcApp { id: "contacts".to_string(), version: None, enabled: true },
+                NcApp { id: "deck".to_string(), version: None, enabled: true },
+                NcApp { id: "files".to_string(), version: None, enabled: false },
+            ];
+            assert!(profile.plan(&applied).is_empty());
+        }
+
+        #[test]
+        fn test_app_ids() {
+            assert_eq!(AppAction::InstallDisabled("deck".to_string()).occ_args(), vec!["app:install", "--keep-disabled", "--", "deck"]);
+            assert_eq!(AppAction::Update(None).occ_args(), vec!["app:update", "--all"]);
+            assert!(AppAction::Enable("end_to_end_encryption".to_string()).check().is_ok());
+            assert!(AppAction::Remove("--all".to_string()).check().is_err());
+            assert!(AppAction::Install("../deck".to_string()).check().is_err());
+            let profile = AppsProfile { removed: vec!["Deck".to_string()], ..AppsProfile::default() };
+            assert!(profile.check().is_err());
+        }
+    }
+}
+
+#[cfg(feature = "backend")]
+pub mod api {
+    use tonic::transport::Channel;
+    use nca_error::NcaError;
+    use crate::occ::api::run_occ;
+    use super::types::{AppAction, AppList, AppsProfile, NcApp};
+
+    pub async fn list_nc_apps(occ_channel: Channel) -> Result<Vec<NcApp>, NcaError> {
+        let output = run_occ(occ_channel, vec!["app:list".to_string(), "--output=json".to_string()]).await?;
+        let list: AppList = serde_json::from_str(&output)
+            .map_err(|e| NcaError::new_unexpected_error(format!("Failed to parse output of occ app:list: {e}")))?;
+        Ok(list.apps())
+    }
+
+    /// Returns the output of occ
+    pub async fn run_nc_app_action(occ_channel: Channel, action: &AppAction) -> Result<String, NcaError> {
+        action.check().map_err(NcaError::new_invalid_argument_error)?;
+        run_occ(occ_channel, action.occ_args()).await
+    }
+
+    /// Applies `profile` and returns the actions that were necessary. With `dry_run`, the
+    /// actions are only returned.
+    pub async fn apply_nc_apps_profile(occ_channel: Channel, profile: &AppsProfile, dry_run: bool) -> Result<Vec<AppAction>, NcaError> {
+        profile.check().map_err(NcaError::new_invalid_argument_error)?;
+        let actions = profile.plan(&list_nc_apps(occ_channel.clone()).await?);
+        if !dry_run {
+            for action in &actions {
+                run_nc_app_action(occ_channel.clone(), action).await?;
+            }
+        }
+        Ok(actions)
+    }
+}
@@ -0,0 +1,163 @@
+pub mod types {
+    use serde::{Deserialize, Serialize};
+
+    fn enabled_by_default() -> bool {
+        true
+    }
+
+    /// A user as shown by `occ user:list --info --output=json`
+    #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+    pub struct NcUser {
+        #[serde(alias = "user_id")]
+        pub id: String,
+        pub display_name: Option<String>,
+        pub email: Option<String>,
+        #[serde(default = "enabled_by_default")]
+        pub enabled: bool,
+        #[serde(default)]
+        pub groups: Vec<String>,
+        /// e.g. `none`, `default` or `5 GB`
+        pub quota: Option<String>,
+        pub last_seen: Option<String>,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Default)]
+    pub struct NewUser {
+        pub id: String,
+        pub display_name: Option<String>,
+        pub email: Option<String>,
+        #[serde(default)]
+        pub groups: Vec<String>,
+    }
+
+    impl NewUser {
+        /// The password is read from `OC_PASS`
+        pub fn occ_args(&self) -> Vec<String> {
+            let mut args = vec!["user:add".to_string(), "--password-from-env".to_string()];
+            if let Some(display_name) = &self.display_name {
+                args.push(format!("--display-name={display_name}"));
+            }
+            if let Some(email) = &self.email {
+                args.push(format!("--email={email}"));
+            }
+            args.extend(self.groups.iter().map(|group| format!("--group={group}")));
+            // Ids starting with '-' must not be taken for options
+            args.extend(["--".to_string(), self.id.clone()]);
+            args
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::collections::BTreeMap;
+        use super::*;
+
+        #[test]
+        fn test_parse_user_list() {
+            let users: BTreeMap<String, NcUser> = serde_json::from_str(r#"{
+                "admin": {"user_id": "admin", "display_name": "admin", "email": null, "cloud_id": "admin@localhost",
+                          "enabled": true, "groups": ["admin"], "quota": "none", "last_seen": "2026-10-01T08:12:45+00:00"},
+                "alice": {"user_id": "alice", "display_name": "Alice", "email": "alice@example.org",
+                          "enabled": false, "groups": [], "quota": "5 GB", "last_seen": "1970-01-01T00:00:00+00:00"}
+            }"#).unwrap();
+            assert_eq!(users["alice"].id, "alice");
+            assert!(!users["alice"].enabled);
+            assert_eq!(users["admin"].groups, vec!["admin"]);
+            assert_eq!(users["admin"].email, None);
+        }
+
+        #[test]
+        fn test_new_user_args() {
+            let user = NewUser {
+                id: "-bob".to_string(),
+                display_name: Some("Bob Builder".to_string()),
+                email: None,
+                groups: vec!["staff".to_string()],
+            };
+            assert_eq!(user.occ_args(), vec!["user:add", "--password-from-env", "--display-name=Bob Builder", "--group=staff", "--", "-bob"]);
+        }
+    }
+}
+
+#[cfg(feature = "backend")]
+pub mod api {
+    use std::collections::{BTreeMap, HashMap};
+    use serde::de::DeserializeOwned;
+    use tonic::transport::Channel;
+    use nca_error::NcaError;
+    use crate::occ::api::{run_occ, run_occ_with_environment};
+    use super::types::{NcUser, NewUser};
+
+    // user:list and group:list return at most this many entries at once
+    const PAGE_SIZE: usize = 500;
+
+    async fn list_all<T: DeserializeOwned>(occ_channel: Channel, args: &[&str]) -> Result<BTreeMap<String, T>, NcaError> {
+        let mut entries = BTreeMap::new();
+        loop {
+            let mut page_args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            page_args.extend([format!("--limit={PAGE_SIZE}"), format!("--offset={}", entries.len()), "--output=json".to_string()]);
+            let output = run_occ(occ_channel.clone(), page_args).await?;
+            let page: BTreeMap<String, T> = match output.trim() {
+                // An empty page is printed as empty list
+                "[]" => BTreeMap::new(),
+                output => serde_json::from_str(output)
+                    .map_err(|e| NcaError::new_unexpected_error(format!("Failed to parse output of occ {}: {e}", args[0])))?,
+            };
+            let done = page.len() < PAGE_SIZE;
+            entries.extend(page);
+            if done {
+                return Ok(entries);
+            }
+        }
+    }
+
+    fn password_environment(password: &str) -> HashMap<String, String> {
+        HashMap::from([("OC_PASS".to_string(), password.to_string())])
+    }
+
+    pub async fn list_nc_users(occ_channel: Channel) -> Result<Vec<NcUser>, NcaError> {
+        Ok(list_all(occ_channel, &["user:list", "--info"]).await?.into_values().collect())
+    }
+
+    pub async fn add_nc_user(occ_channel: Channel, user: &NewUser, password: &str) -> Result<(), NcaError> {
+        run_occ_with_environment(occ_channel, user.occ_args(), password_environment(password)).await
+            .map(|_| ())
+    }
+
+    pub async fn set_nc_user_enabled(occ_channel: Channel, user_id: String, enabled: bool) -> Result<(), NcaError> {
+        let command = if enabled { "user:enable" } else { "user:disable" };
+        run_occ(occ_channel, vec![command.to_string(), "--".to_string(), user_id]).await
+            .map(|_| ())
+    }
+
+    pub async fn reset_nc_user_password(occ_channel: Channel, user_id: String, password: &str) -> Result<(), NcaError> {
+        let args = vec!["user:resetpassword".to_string(), "--password-from-env".to_string(), "--".to_string(), user_id];
+        run_occ_with_environment(occ_channel, args, password_environment(password)).await
+            .map(|_| ())
+    }
+
+    /// `quota` is e.g. `5 GB`, `none` or `default`
+    pub async fn set_nc_user_quota(occ_channel: Channel, user_id: String, quota: String) -> Result<(), NcaError> {
+        let args = vec!["user:setting".to_string(), "--".to_string(), user_id, "files".to_string(), "quota".to_string(), quota];
+        run_occ(occ_channel, args).await
+            .map(|_| ())
+    }
+
+    /// The groups with their members
+    pub async fn list_nc_groups(occ_channel: Channel) -> Result<BTreeMap<String, Vec<String>>, NcaError> {
+        list_all(occ_channel, &["group:list"]).await
+    }
+
+    pub async fn add_nc_group(occ_channel: Channel, group_id: String, display_name: Option<String>) -> Result<(), NcaError> {
+        let mut args = vec!["group:add".to_string()];
+        args.extend(display_name.map(|name| format!("--display-name={name}")));
+        args.extend(["--".to_string(), group_id]);
+        run_occ(occ_channel, args).await
+            .map(|_| ())
+    }
+
+    pub async fn add_nc_group_member(occ_channel: Channel, group_id: String, user_id: String) -> Result<(), NcaError> {
+        run_occ(occ_channel, vec!["group:adduser".to_string(), "--".to_string(), group_id, user_id]).await
+            .map(|_| ())
+    }
+}
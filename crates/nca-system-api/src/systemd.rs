@@ -47,10 +47,14 @@ pub mod api {
     use nca_error::NcaError;
     use libsystemd::daemon;
     use libsystemd::daemon::NotifyState;
+    use libsystemd::logging;
+    pub use libsystemd::logging::Priority;
     use zbus_systemd::zbus::fdo::PropertiesProxy;
     use zbus_systemd::znames::InterfaceName;
     use super::types::*;
 
+    pub const TPM2_PCRS: &str = "7";
+
     pub async fn get_service_status(name: String) -> Result<ServiceStatus, NcaError> {
         let conn = zbus::Connection::system().await?;
         let manager = ManagerProxy::new(&conn).await?;
@@ -81,11 +85,38 @@ pub mod api {
         Ok(())
     }
 
+    pub async fn stop_service(name: String) -> Result<(), NcaError> {
+        #[cfg(debug_assertions)]
+        eprintln!("Stopping service {name} ...");
+        let conn = zbus::Connection::system().await?;
+        let manager = ManagerProxy::new(&conn).await?;
+        manager.stop_unit(name, "replace".to_string()).await
+            .map_err(|e| NcaError::SystemdError(format!("Failed to stop service: {e:?}")))?;
+        Ok(())
+    }
+
+    pub async fn reload_systemd() -> Result<(), NcaError> {
+        let conn = zbus::Connection::system().await?;
+        let manager = ManagerProxy::new(&conn).await?;
+        manager.reload().await
+            .map_err(|e| NcaError::SystemdError(format!("Failed to reload systemd: {e:?}")))?;
+        Ok(())
+    }
+
     pub fn sd_notify(state: &[NotifyState]) -> Result<(), NcaError> {
         daemon::notify(true, state)?;
         Ok(())
     }
 
+    /// Writes a structured entry to the journal. `message_id` is the 128-bit id (32 hex digits)
+    /// that identifies the kind of event, see `MESSAGE_ID=` in systemd.journal-fields(7)
+    pub fn journal_send_message(priority: Priority, message_id: &str, message: &str, fields: &[(&str, String)]) -> Result<(), NcaError> {
+        let vars = std::iter::once(("MESSAGE_ID", message_id.to_string()))
+            .chain(fields.iter().map(|(k, v)| (*k, v.clone())));
+        logging::journal_send(priority, message, vars)?;
+        Ok(())
+    }
+
     pub async fn set_systemd_credential(value: String, path: String, name: Option<String>, pretty: bool) -> Result<String, NcaError> {
         #[cfg(debug_assertions)]
         eprintln!("Creating systemd credential at path: '{path}'!");
@@ -146,6 +177,25 @@ pub mod api {
         }
     }
 
+    pub async fn decrypt_systemd_credential(path: String, name: Option<String>) -> Result<String, NcaError> {
+        let mut args = vec!["decrypt".to_string()];
+        if let Some(cred_name) = name {
+            args.push(format!("--name={cred_name}"));
+        }
+        args.append(&mut vec![path.clone(), "-".to_string()]);
+        let out = std::process::Command::new("/usr/bin/systemd-creds")
+            .args(args.as_slice())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .output()
+            .map_err(|e| NcaError::IOError(format!("Failed to run systemd-creds: {:?}", e)))?;
+        if out.status.success() {
+            String::from_utf8(out.stdout).map_err(|e| NcaError::IOError(format!("Failed to parse command output: {:?}", e)))
+        } else {
+            Err(NcaError::IOError(format!("Failed to decrypt systemd credential at '{path}' (exit code: {:?}): {}", out.status.code(), String::from_utf8_lossy(&out.stderr))))
+        }
+    }
+
     pub async fn set_fallback_disk_encryption_password(password: String, device_path: String) -> Result<(), NcaError> {
         // let password_credential = {
         //     let mut cmd = std::process::Command::new("/usr/bin/systemd-creds");
@@ -199,5 +249,252 @@ pub mod api {
         }
         
     }
-}
 
+    /// Enrolls `new_password` as an additional passphrase on the LUKS device at `device_path`,
+    /// using `unlock_password` to unlock it. Existing key slots are left untouched.
+    pub async fn enroll_disk_encryption_password(unlock_password: String, new_password: String, device_path: String) -> Result<(), NcaError> {
+        let unlock_credential = set_systemd_credential(unlock_password, "-".to_string(), Some("cryptenroll.passphrase".to_string()), true).await?;
+        let new_password_credential = set_systemd_credential(new_password, "-".to_string(), Some("cryptenroll.new-passphrase".to_string()), true).await?;
+
+        let mut cmd = std::process::Command::new("/usr/bin/systemd-run");
+        let cmd_with_args = cmd
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .args(vec!["-p", unlock_credential.replace("\\\n        ", "").replace("\n", "").as_str(),
+                       "-p", new_password_credential.replace("\\\n        ", "").replace("\n", "").as_str(),
+                       "-P", "--wait", "--slice-inherit",
+                       "/usr/bin/systemd-cryptenroll", "--password", device_path.as_str()]);
+        let out = cmd_with_args.spawn()
+            .map_err(|e| NcaError::IOError(format!("Failed to run systemd-cryptenroll: {e:?}")))?
+            .wait_with_output()
+            .map_err(|e| NcaError::IOError(format!("Failed to run systemd-cryptenroll: {e:?}")))?;
+        if out.status.success() {
+            Ok(())
+        } else {
+            let msg = format!("Failed to enroll password for encrypted disk {device_path} (exit code: {:?}): {}\n\n{}",
+                              out.status.code().unwrap_or(-1),
+                              String::from_utf8_lossy(&out.stdout),
+                              String::from_utf8_lossy(&out.stderr));
+            eprintln!("{}", &msg);
+            Err(NcaError::IOError(msg))
+        }
+    }
+
+    /// Checks whether `password` unlocks any key slot of the LUKS device at `device_path`
+    /// (without actually opening the device).
+    pub async fn test_disk_encryption_password(password: String, device_path: String) -> Result<bool, NcaError> {
+        let out = run_cryptsetup_with_key(password, vec!["open", "--test-passphrase", "--key-file=-", device_path.as_str()])?;
+        match out.status.code() {
+            Some(0) => Ok(true),
+            // cryptsetup exits with 2 if no key slot matched the passphrase
+            Some(2) => Ok(false),
+            code => Err(NcaError::IOError(format!("Failed to test passphrase for encrypted disk {device_path} (exit code: {:?}): {}",
+                                                  code.unwrap_or(-1),
+                                                  String::from_utf8_lossy(&out.stderr))))
+        }
+    }
+
+    /// Removes the key slot of the LUKS device at `device_path` that is unlocked by `password`.
+    pub async fn remove_disk_encryption_password(password: String, device_path: String) -> Result<(), NcaError> {
+        let out = run_cryptsetup_with_key(password, vec!["luksRemoveKey", "--key-file=-", device_path.as_str()])?;
+        if out.status.success() {
+            Ok(())
+        } else {
+            let msg = format!("Failed to remove password from encrypted disk {device_path} (exit code: {:?}): {}",
+                              out.status.code().unwrap_or(-1),
+                              String::from_utf8_lossy(&out.stderr));
+            eprintln!("{}", &msg);
+            Err(NcaError::IOError(msg))
+        }
+    }
+
+    /// Creates a LUKS2 container on `device_path` with `password` in its first key slot.
+    /// All data on the device is lost.
+    pub async fn create_encrypted_disk(password: String, device_path: String) -> Result<(), NcaError> {
+        let out = run_cryptsetup_with_key(password, vec!["luksFormat", "--type", "luks2", "--batch-mode", "--key-file=-", device_path.as_str()])?;
+        if out.status.success() {
+            Ok(())
+        } else {
+            Err(NcaError::IOError(format!("Failed to encrypt disk {device_path} (exit code: {:?}): {}",
+                                          out.status.code().unwrap_or(-1),
+                                          String::from_utf8_lossy(&out.stderr))))
+        }
+    }
+
+    pub async fn open_encrypted_disk(password: String, device_path: String, name: String) -> Result<(), NcaError> {
+        let out = run_cryptsetup_with_key(password, vec!["open", "--type", "luks", "--key-file=-", device_path.as_str(), name.as_str()])?;
+        if out.status.success() {
+            Ok(())
+        } else {
+            Err(NcaError::IOError(format!("Failed to open encrypted disk {device_path} (exit code: {:?}): {}",
+                                          out.status.code().unwrap_or(-1),
+                                          String::from_utf8_lossy(&out.stderr))))
+        }
+    }
+
+    pub async fn close_encrypted_disk(name: String) -> Result<(), NcaError> {
+        let out = std::process::Command::new("/usr/sbin/cryptsetup")
+            .args(["close", name.as_str()])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .output()
+            .map_err(|e| NcaError::IOError(format!("Failed to run cryptsetup: {e:?}")))?;
+        if out.status.success() {
+            Ok(())
+        } else {
+            Err(NcaError::IOError(format!("Failed to close encrypted disk {name} (exit code: {:?}): {}",
+                                          out.status.code().unwrap_or(-1),
+                                          String::from_utf8_lossy(&out.stderr))))
+        }
+    }
+
+    /// Enrolls a TPM2 token (bound to `TPM2_PCRS`) on the LUKS device at `device_path`, so it
+    /// can be unlocked automatically during boot.
+    pub async fn enroll_tpm2(unlock_password: String, device_path: String) -> Result<(), NcaError> {
+        let pcrs = format!("--tpm2-pcrs={TPM2_PCRS}");
+        let out = run_cryptenroll(unlock_password, &["--tpm2-device=auto", &pcrs, device_path.as_str()]).await?;
+        if out.status.success() {
+            Ok(())
+        } else {
+            let msg = format!("Failed to enroll TPM2 for encrypted disk {device_path} (exit code: {:?}): {}\n\n{}",
+                              out.status.code().unwrap_or(-1),
+                              String::from_utf8_lossy(&out.stdout),
+                              String::from_utf8_lossy(&out.stderr));
+            eprintln!("{}", &msg);
+            Err(NcaError::IOError(msg))
+        }
+    }
+
+    /// Replaces the TPM2 token of the LUKS device at `device_path` with one bound to the current
+    /// values of `TPM2_PCRS`
+    pub async fn reseal_tpm2(unlock_password: String, device_path: String) -> Result<(), NcaError> {
+        let pcrs = format!("--tpm2-pcrs={TPM2_PCRS}");
+        let out = run_cryptenroll(unlock_password, &["--wipe-slot=tpm2", "--tpm2-device=auto", &pcrs, device_path.as_str()]).await?;
+        if out.status.success() {
+            Ok(())
+        } else {
+            let msg = format!("Failed to reseal TPM2 for encrypted disk {device_path} (exit code: {:?}): {}",
+                              out.status.code().unwrap_or(-1),
+                              String::from_utf8_lossy(&out.stderr));
+            eprintln!("{}", &msg);
+            Err(NcaError::IOError(msg))
+        }
+    }
+
+    /// Tests whether the LUKS device at `device_path` can be unlocked with its TPM2 token
+    pub async fn test_tpm2_unlock(device_path: String) -> Result<bool, NcaError> {
+        let out = std::process::Command::new("/usr/sbin/cryptsetup")
+            .args(["open", "--test-passphrase", "--token-only", "--token-type", "systemd-tpm2", device_path.as_str()])
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| NcaError::IOError(format!("Failed to run cryptsetup: {e:?}")))?;
+        Ok(out.status.success())
+    }
+
+    /// Tests whether the LUKS device at `device_path` can be unlocked with the TPM2 token `token`
+    pub async fn test_tpm2_token_unlock(device_path: String, token: u32) -> Result<bool, NcaError> {
+        let out = std::process::Command::new("/usr/sbin/cryptsetup")
+            .args(["open", "--test-passphrase", "--token-only", "--token-type", "systemd-tpm2",
+                   "--token-id", token.to_string().as_str(), device_path.as_str()])
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| NcaError::IOError(format!("Failed to run cryptsetup: {e:?}")))?;
+        Ok(out.status.success())
+    }
+
+    /// Tests whether `password` unlocks the key slot `slot` of the LUKS device at `device_path`
+    pub async fn test_key_slot_password(password: String, device_path: String, slot: u32) -> Result<bool, NcaError> {
+        let slot = slot.to_string();
+        let out = run_cryptsetup_with_key(password, vec!["open", "--test-passphrase", "--key-slot", slot.as_str(), "--key-file=-", device_path.as_str()])?;
+        match out.status.code() {
+            Some(0) => Ok(true),
+            Some(1) | Some(2) => Ok(false),
+            code => Err(NcaError::IOError(format!("Failed to test key slot {slot} of encrypted disk {device_path} (exit code: {:?}): {}",
+                                                  code.unwrap_or(-1),
+                                                  String::from_utf8_lossy(&out.stderr))))
+        }
+    }
+
+    /// The LUKS2 header of the device at `device_path` as JSON
+    pub async fn dump_luks_metadata(device_path: String) -> Result<String, NcaError> {
+        let out = std::process::Command::new("/usr/sbin/cryptsetup")
+            .args(["luksDump", "--dump-json-metadata", device_path.as_str()])
+            .stdin(Stdio::null())
+            .output()
+            .map_err(|e| NcaError::IOError(format!("Failed to run cryptsetup: {e:?}")))?;
+        if out.status.success() {
+            Ok(String::from_utf8_lossy(&out.stdout).to_string())
+        } else {
+            Err(NcaError::IOError(format!("Failed to read LUKS header of {device_path} (exit code: {:?}): {}",
+                                          out.status.code().unwrap_or(-1),
+                                          String::from_utf8_lossy(&out.stderr))))
+        }
+    }
+
+    /// Removes key slot `slot` (and the tokens bound to it) from the LUKS device at `device_path`
+    pub async fn wipe_key_slot(unlock_password: String, device_path: String, slot: u32) -> Result<(), NcaError> {
+        let wipe_slot = format!("--wipe-slot={slot}");
+        let out = run_cryptenroll(unlock_password, &[&wipe_slot, device_path.as_str()]).await?;
+        if out.status.success() {
+            Ok(())
+        } else {
+            let msg = format!("Failed to remove key slot {slot} from encrypted disk {device_path} (exit code: {:?}): {}",
+                              out.status.code().unwrap_or(-1),
+                              String::from_utf8_lossy(&out.stderr));
+            eprintln!("{}", &msg);
+            Err(NcaError::IOError(msg))
+        }
+    }
+
+    /// Enrolls a new recovery key on the LUKS device at `device_path` and returns the output of
+    /// systemd-cryptenroll, which contains the key
+    pub async fn enroll_recovery_key(unlock_password: String, device_path: String) -> Result<String, NcaError> {
+        let out = run_cryptenroll(unlock_password, &["--recovery-key", device_path.as_str()]).await?;
+        if out.status.success() {
+            Ok(String::from_utf8_lossy(&out.stdout).to_string())
+        } else {
+            let msg = format!("Failed to enroll recovery key for encrypted disk {device_path} (exit code: {:?}): {}",
+                              out.status.code().unwrap_or(-1),
+                              String::from_utf8_lossy(&out.stderr));
+            eprintln!("{}", &msg);
+            Err(NcaError::IOError(msg))
+        }
+    }
+
+    /// Runs systemd-cryptenroll with `args`, the device is unlocked with `unlock_password`
+    async fn run_cryptenroll(unlock_password: String, args: &[&str]) -> Result<std::process::Output, NcaError> {
+        let unlock_credential = set_systemd_credential(unlock_password, "-".to_string(), Some("cryptenroll.passphrase".to_string()), true).await?;
+        let unlock_credential = unlock_credential.replace("\\\n        ", "").replace("\n", "");
+        let mut cmd_args = vec!["-p", unlock_credential.as_str(), "-P", "--wait", "--slice-inherit", "/usr/bin/systemd-cryptenroll"];
+        cmd_args.extend_from_slice(args);
+        #[cfg(debug_assertions)]
+        eprintln!("Running systemd-run like: /usr/bin/systemd-run {}", cmd_args.join(" "));
+        std::process::Command::new("/usr/bin/systemd-run")
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .args(cmd_args)
+            .spawn()
+            .map_err(|e| NcaError::IOError(format!("Failed to run systemd-cryptenroll: {e:?}")))?
+            .wait_with_output()
+            .map_err(|e| NcaError::IOError(format!("Failed to run systemd-cryptenroll: {e:?}")))
+    }
+
+    fn run_cryptsetup_with_key(key: String, args: Vec<&str>) -> Result<std::process::Output, NcaError> {
+        #[cfg(debug_assertions)]
+        eprintln!("Running cryptsetup like: /usr/sbin/cryptsetup {}", args.join(" "));
+        let mut proc = std::process::Command::new("/usr/sbin/cryptsetup")
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| NcaError::IOError(format!("Failed to run cryptsetup: {e:?}")))?;
+        match proc.stdin.as_mut() {
+            None => Err(NcaError::IOError("Failed to get stdin of cryptsetup".to_string())),
+            Some(stdin) => stdin.write_all(key.as_bytes())
+                .map_err(|e| NcaError::IOError(format!("Failed to pass key to cryptsetup: {e:?}")))
+        }?;
+        proc.wait_with_output()
+            .map_err(|e| NcaError::IOError(format!("Failed to run cryptsetup: {e:?}")))
+    }
+}